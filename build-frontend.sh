@@ -37,6 +37,12 @@ if ! command -v xz >/dev/null; then
   exit 1
 fi
 
+if ! command -v brotli >/dev/null; then
+  echo "did not find brotli, please install"
+  echo "  brew install brotli"
+  exit 1
+fi
+
 # We use a local directory, and we don't delete it after the build, so that
 # assets can be inspected.
 tarball_dir="$TOPLEVEL/web-assets"
@@ -56,8 +62,15 @@ find . -name '*.map' | "$tar" cJv \
   --remove-files \
   -f "$TOPLEVEL/sourcemaps.tar.xz"
 
+# Add brotli encoded versions of the file types that compress well, for browsers that accept brotli.  Images and
+# fonts are already compressed, so a brotli version would only take space in the wasm.
+# shellcheck disable=SC2038 # We have sane filenames, without return characters in them.
+find . -type f \( -name '*.html' -o -name '*.js' -o -name '*.mjs' -o -name '*.css' -o -name '*.json' -o -name '*.svg' -o -name '*.xml' -o -name '*.txt' -o -name '*.webmanifest' \) | xargs -I{} brotli -kf "{}"
+
+# Replace every file, other than the brotli versions, by its gzip encoded version.  The canister serves the gzip
+# encoded version to clients that accept neither encoding, so uncompressed copies are not needed.
 # shellcheck disable=SC2038 # We have sane filenames, without return characters in them.
-find . -type f | xargs -I{} gzip -fn "{}"
+find . -type f ! -name '*.br' | xargs -I{} gzip -fn "{}"
 
 # --mtime, --sort, --owner, --group, --numeric-owner and --format are all
 # there to get a tarball that's reproducible across different platforms.
@@ -1,6 +1,7 @@
 import type { NNSDappCanisterOptions } from "$lib/canisters/nns-dapp/nns-dapp.canister.types";
 import { idlFactory as certifiedIdlFactory } from "$lib/canisters/nns-dapp/nns-dapp.certified.idl";
 import {
+  AccountLimitReachedError,
   AccountNotFoundError,
   AddressNameTooLongError,
   AddressNameTooShortError,
@@ -80,8 +81,11 @@ export class NNSDappCanister {
    * @returns Promise<void>
    */
   public async addAccount(): Promise<AccountIdentifier> {
-    const identifierText = await this.certifiedService.add_account();
-    return AccountIdentifier.fromHex(identifierText);
+    const response = await this.certifiedService.add_account();
+    if ("AccountLimitReached" in response) {
+      throw new AccountLimitReachedError("error__account.account_limit_reached");
+    }
+    return AccountIdentifier.fromHex(response.Ok);
   }
 
   /**
@@ -79,6 +79,8 @@ export class DuplicateAddressNameError extends AccountTranslateError {
 
 export class SubAccountLimitExceededError extends Error {}
 
+export class AccountLimitReachedError extends AccountTranslateError {}
+
 export class NameTooLongError extends AccountTranslateError {
   constructor(message: string, substitutions?: I18nSubstitutions) {
     super(message);
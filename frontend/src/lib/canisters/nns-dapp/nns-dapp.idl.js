@@ -26,6 +26,10 @@ export const idlFactory = ({ IDL }) => {
     SubAccountLimitExceeded: IDL.Null,
   });
   const DetachCanisterRequest = IDL.Record({ canister_id: IDL.Principal });
+  const AddAccountResponse = IDL.Variant({
+    Ok: AccountIdentifier,
+    AccountLimitReached: IDL.Record({ limit: IDL.Nat64 }),
+  });
   const DetachCanisterResponse = IDL.Variant({
     Ok: IDL.Null,
     CanisterNotFound: IDL.Null,
@@ -158,7 +162,7 @@ export const idlFactory = ({ IDL }) => {
     AccountNotFound: IDL.Null,
   });
   return IDL.Service({
-    add_account: IDL.Func([], [AccountIdentifier], []),
+    add_account: IDL.Func([], [AddAccountResponse], []),
     add_stable_asset: IDL.Func([IDL.Vec(IDL.Nat8)], [], []),
     attach_canister: IDL.Func(
       [AttachCanisterRequest],
@@ -34,6 +34,9 @@ export type CreateSubAccountResponse =
 export interface DetachCanisterRequest {
   canister_id: Principal;
 }
+export type AddAccountResponse =
+  | { Ok: AccountIdentifierString }
+  | { AccountLimitReached: { limit: bigint } };
 export type DetachCanisterResponse = { Ok: null } | { CanisterNotFound: null };
 export type GetAccountResponse =
   | { Ok: AccountDetails }
@@ -155,7 +158,7 @@ export interface SubAccountDetails {
   account_identifier: AccountIdentifierString;
 }
 export default interface _SERVICE {
-  add_account: () => Promise<AccountIdentifierString>;
+  add_account: () => Promise<AddAccountResponse>;
   add_stable_asset: (arg_0: Array<number>) => Promise<undefined>;
   attach_canister: (
     arg_0: AttachCanisterRequest
@@ -1168,6 +1168,7 @@ interface I18nError__account {
   subaccount_not_found: string;
   rename_account_not_found: string;
   not_selected: string;
+  account_limit_reached: string;
 }
 
 interface I18nError__canister {
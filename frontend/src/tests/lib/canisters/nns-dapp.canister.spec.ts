@@ -1,5 +1,6 @@
 import { NNSDappCanister } from "$lib/canisters/nns-dapp/nns-dapp.canister";
 import {
+  AccountLimitReachedError,
   AccountNotFoundError,
   CanisterAlreadyAttachedError,
   CanisterLimitExceededError,
@@ -48,16 +49,29 @@ describe("NNSDapp", () => {
 
   describe("NNSDapp.addAccount", () => {
     it("returns account identifier when success", async () => {
-      const response =
+      const accountIdentifier =
         "d4685b31b51450508aff0331584df7692a84467b680326f5c5f7d30ae711682f";
       const service = mock<NNSDappService>();
-      service.add_account.mockResolvedValue(response);
+      service.add_account.mockResolvedValue({ Ok: accountIdentifier });
 
       const nnsDapp = await createNnsDapp(service);
 
       const res = await nnsDapp.addAccount();
 
-      expect(res).toEqual(AccountIdentifier.fromHex(response));
+      expect(res).toEqual(AccountIdentifier.fromHex(accountIdentifier));
+    });
+
+    it("throws error if the account limit has been reached", async () => {
+      const service = mock<NNSDappService>();
+      service.add_account.mockResolvedValue({
+        AccountLimitReached: { limit: 330_000n },
+      });
+
+      const nnsDapp = await createNnsDapp(service);
+
+      const call = () => nnsDapp.addAccount();
+
+      await expect(call).rejects.toThrow(AccountLimitReachedError);
     });
   });
 
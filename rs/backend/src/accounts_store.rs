@@ -1,55 +1,90 @@
 //! User accounts and transactions.
-use crate::state::{partitions::PartitionType, with_partitions, StableState};
+use crate::assets::hash_bytes;
+use crate::state::partitions::{PartitionType, Partitions};
+use crate::state::stable_value::StableValue;
+use crate::state::{with_partitions, StableState};
 use crate::stats::Stats;
+use accounts_db::AccountsDb;
+use backfills::{Backfill, BackfillProgress, Backfills};
 use candid::CandidType;
+use canister_index::CanisterIndex;
+use canisters_hashes::CanistersHashes;
 use dfn_candid::Candid;
+use hardware_wallet_index::HardwareWalletIndex;
 use histogram::AccountsStoreHistogram;
 use ic_base_types::{CanisterId, PrincipalId};
-use ic_stable_structures::{
-    memory_manager::VirtualMemory, storable::Bound, DefaultMemoryImpl, StableBTreeMap, Storable,
-};
+use ic_certified_map::{Hash, HashTree};
+use ic_stable_structures::{storable::Bound, Storable};
 use icp_ledger::{AccountIdentifier, BlockIndex, Subaccount};
 use icrc_ledger_types::icrc1::account::Account as Icrc1Account;
 use itertools::Itertools;
-use on_wire::{FromWire, IntoWire};
-use serde::Deserialize;
+use limits::{len_limit, Limits};
+use on_wire::FromWire;
+use principal_map::PrincipalMap;
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
 use std::str::FromStr;
 
+pub mod accounts_db;
+pub mod backfills;
+pub mod canister_index;
+pub mod canisters_hashes;
+pub mod hardware_wallet_index;
 pub mod histogram;
+pub mod limits;
+pub mod principal_map;
 
-// This limit is for DoS protection but should be increased if we get close to
-// the limit.
-// When you increase this limit, make sure to also increase the limit on the alerts.
-// See for example https://github.com/dfinity-ops/k8s/pull/731
-const ACCOUNT_LIMIT: u64 = 330_000;
+// Minimum length for named address name field (after trimming and normalizing spaces)
+const MIN_NAMED_ADDRESS_NAME_LENGTH: i32 = 3;
 
-const MAX_SUB_ACCOUNT_ID: u8 = u8::MAX - 1;
+// Maximum length for named address name field
+const MAX_NAMED_ADDRESS_NAME_LENGTH: i32 = 64;
 
-// Conservatively limit the number of imported tokens to prevent using too much memory.
-// Can be revisited if users find this too restrictive.
-const MAX_IMPORTED_TOKENS: i32 = 20;
+// ICP ledger memos are `u64`s, ICRC-1 memos are at most 32 bytes.
+const MAX_ICP_MEMO_LENGTH: i32 = 8;
+const MAX_ICRC1_MEMO_LENGTH: i32 = 32;
 
-// Conservatively limit the number of favorite projects to prevent using too much memory.
-const MAX_FAVORITE_PROJECTS: i32 = 20;
+// When fewer than this percentage of the account limit remains, the account limit is reported as near.
+const ACCOUNT_LIMIT_NEAR_PERCENT: u64 = 10;
 
-// Conservatively limit the number of named addresses to prevent using too much memory.
-const MAX_NAMED_ADDRESSES: i32 = 20;
+// The version of the account data bundle produced by `export_account_data`.
+// Increase this when the bundle format changes and keep importing older versions.
+const ACCOUNT_DATA_BUNDLE_VERSION: u32 = 1;
 
-// Minimum length for named address name field (after trimming and normalizing spaces)
-const MIN_NAMED_ADDRESS_NAME_LENGTH: i32 = 3;
+// How long the target principal has to claim an account transfer.
+const ACCOUNT_TRANSFER_EXPIRY_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
 
-// Maximum length for named address name field
-const MAX_NAMED_ADDRESS_NAME_LENGTH: i32 = 64;
+/// The number of accounts checked for a principal in one step of the principal-less accounts scan.
+pub const PRINCIPAL_LESS_ACCOUNTS_SCAN_BATCH_SIZE: u64 = 1_000;
+
+/// The label of the subtree of certified data that contains the hashes of users' canisters.
+pub const LABEL_CANISTERS: &[u8] = b"canisters";
 
 /// Accounts and related data.
 pub struct AccountsStore {
-    // TODO(NNS1-720): Use AccountIdentifier directly as the key for this HashMap
-    accounts_db: StableBTreeMap<Vec<u8>, Account, VirtualMemory<DefaultMemoryImpl>>,
-    accounts_db_stats: AccountsDbStats,
+    accounts_db: AccountsDb,
+    accounts_db_stats: StableValue<AccountsDbStats>,
+    /// Account transfers offered by the current owner, keyed by the principal of the current owner.
+    pending_account_transfers: PrincipalMap<PendingAccountTransfer>,
+    /// For every account with attached canisters, the hash of those canisters, keyed by account identifier.
+    canisters_hashes: CanistersHashes,
+    /// The limits in force.
+    limits: StableValue<Limits>,
+    /// Principals that may create an account even when the account limit has been reached.
+    account_limit_allowlist: PrincipalMap<()>,
+    /// For every hardware wallet, the accounts that have registered it.
+    hardware_wallet_index: HardwareWalletIndex,
+    /// For every attached canister, the accounts that have attached it.
+    canister_index: CanisterIndex,
+    /// The background scan counting accounts that have no principal.
+    ///
+    /// This is not persisted; the scan restarts after every upgrade.
+    principal_less_accounts_scan: PrincipalLessAccountsScan,
+    /// The progress of computing derived data, such as `canisters_hashes`, for accounts that existed before it.
+    backfills: StableValue<Backfills>,
 }
 
 impl Default for AccountsStore {
@@ -62,9 +97,10 @@ impl fmt::Debug for AccountsStore {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "AccountsStore{{accounts_db: StableBTreeMap{{.. {} entries}}, accounts_db_stats: {:?}}}",
+            "AccountsStore{{accounts_db: AccountsDb{{.. {} entries}}, accounts_db_stats: {:?}, pending_account_transfers: {{.. {} entries}}}}",
             self.accounts_db.len(),
             self.accounts_db_stats,
+            self.pending_account_transfers.len(),
         )
     }
 }
@@ -75,23 +111,74 @@ impl fmt::Debug for AccountsStore {
 #[cfg(test)]
 impl PartialEq for AccountsStore {
     fn eq(&self, other: &Self) -> bool {
-        self.accounts_db
-            .range(..)
-            .map(|entry| entry.into_pair())
-            .eq(other.accounts_db.range(..).map(|entry| entry.into_pair()))
+        self.accounts_db.iter().eq(other.accounts_db.iter())
+            && self.accounts_db_stats.get() == other.accounts_db_stats.get()
+            && self
+                .pending_account_transfers
+                .iter()
+                .eq(other.pending_account_transfers.iter())
+            && self.canisters_hashes == other.canisters_hashes
+            && self.limits.get() == other.limits.get()
+            && self.get_account_limit_allowlist() == other.get_account_limit_allowlist()
+            && self.hardware_wallet_index.keys() == other.hardware_wallet_index.keys()
+            && self.canister_index.keys() == other.canister_index.keys()
     }
 }
 #[cfg(test)]
 impl Eq for AccountsStore {}
 
-#[derive(Default, CandidType, Deserialize, Debug, Eq, PartialEq)]
+/// The progress of a scan counting the accounts that have no principal.
+///
+/// Early accounts were created without a principal, which is filled in by `add_account` the next time the user logs
+/// in.  Once no such accounts remain, the legacy path in `get_account` can be removed.
+#[derive(Default, Debug, Eq, PartialEq)]
+struct PrincipalLessAccountsScan {
+    /// The identifier of the last account scanned, if the scan is in progress.
+    cursor: Option<Vec<u8>>,
+    /// The number of accounts without a principal found so far by the scan in progress.
+    count_so_far: u64,
+    /// The number of accounts without a principal, once the scan is complete.
+    count: Option<u64>,
+}
+
+impl PrincipalLessAccountsScan {
+    /// Records that an account without a principal has been given one, or deleted.
+    fn forget(&mut self, account_identifier: &[u8]) {
+        if let Some(count) = &mut self.count {
+            *count = count.saturating_sub(1);
+        } else if self
+            .cursor
+            .as_deref()
+            .is_some_and(|cursor| account_identifier <= cursor)
+        {
+            self.count_so_far = self.count_so_far.saturating_sub(1);
+        }
+    }
+}
+
+#[derive(Default, CandidType, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct AccountsDbStats {
     pub sub_accounts_count: u64,
     pub hardware_wallet_accounts_count: u64,
 }
 
+impl Storable for AccountsDbStats {
+    const BOUND: Bound = Bound::Unbounded;
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        candid::encode_one(self)
+            .expect("Failed to serialize accounts DB stats")
+            .into()
+    }
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(&self).expect("Failed to serialize accounts DB stats")
+    }
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to parse accounts DB stats from store.")
+    }
+}
+
 /// A user's account.
-#[derive(CandidType, Deserialize, Debug, Eq, PartialEq, Clone)]
+#[derive(CandidType, Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
 pub struct Account {
     /// The user principal.
     ///
@@ -104,41 +191,88 @@ pub struct Account {
     imported_tokens: Option<ImportedTokens>,
     fav_projects: Option<FavProjects>,
     address_book: Option<AddressBook>,
+    named_icrc1_subaccounts: Option<Vec<NamedIcrc1Subaccount>>,
     // default_account_transactions: Do not reuse this field. There are still accounts in stable memor with this unused field.
 }
 
+/// The first byte of an account encoded as packed CBOR.
+///
+/// Accounts used to be stored as Candid, which starts with the magic bytes `DIDL`, so the encodings cannot be confused.
+/// Accounts still stored as Candid are decoded as such and re-encoded as CBOR the next time they are written.
+///
+/// Note: Packed CBOR identifies struct fields by position, so new fields must be added at the end of a struct, with
+/// `#[serde(default)]` so that records written before the field existed can still be decoded, and existing fields must
+/// never be reordered or removed.
+const ACCOUNT_ENCODING_PACKED_CBOR: u8 = 1;
+
+impl Account {
+    /// Encodes the account as packed CBOR, preceded by `ACCOUNT_ENCODING_PACKED_CBOR`.
+    fn encode_compact(&self) -> Vec<u8> {
+        let mut serializer = serde_cbor::ser::Serializer::new(vec![ACCOUNT_ENCODING_PACKED_CBOR]).packed_format();
+        self.serialize(&mut serializer).expect("Failed to serialize account");
+        serializer.into_inner()
+    }
+}
+
 impl Storable for Account {
     const BOUND: Bound = Bound::Unbounded;
     fn to_bytes(&self) -> Cow<'_, [u8]> {
-        candid::encode_one(self).expect("Failed to serialize account").into()
+        self.encode_compact().into()
     }
     fn into_bytes(self) -> Vec<u8> {
-        candid::encode_one(&self).expect("Failed to serialize account")
+        self.encode_compact()
     }
     fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
-        candid::decode_one(&bytes).expect("Failed to parse account from store.")
+        match bytes.split_first() {
+            Some((&ACCOUNT_ENCODING_PACKED_CBOR, cbor)) => {
+                serde_cbor::from_slice(cbor).expect("Failed to parse account from store.")
+            }
+            _ => candid::decode_one(&bytes).expect("Failed to parse account from store."),
+        }
     }
 }
 
-#[derive(CandidType, Deserialize, Debug, Eq, PartialEq, Clone)]
+#[derive(CandidType, Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
 struct NamedSubAccount {
     name: String,
     account_identifier: AccountIdentifier,
     // transactions: Do not reuse this field. There are still accounts in stable memory with this unused field.
+    /// Archived sub-accounts are hidden by default but keep their name.
+    archived: Option<bool>,
+    /// The position of the sub-account when displayed.  Sub-accounts without a position come last, sorted by name.
+    display_order: Option<u32>,
+    /// A key chosen by the front end to select a colour or icon for the sub-account.
+    style_key: Option<String>,
 }
 
-#[derive(CandidType, Deserialize, Debug, Eq, PartialEq, Clone)]
+#[derive(CandidType, Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
 struct NamedHardwareWalletAccount {
     name: String,
     principal: PrincipalId,
     // transactions: Do not reuse this field. There are still accounts in stable memor with this unused field.
+    device: Option<HardwareWalletDevice>,
+}
+
+/// Information about the device behind a hardware wallet, as last seen by the front end.
+///
+/// This lets the front end warn about outdated apps on the device.
+#[derive(CandidType, Deserialize, Serialize, Debug, Default, Eq, PartialEq, Clone)]
+pub struct HardwareWalletDevice {
+    pub model: Option<String>,
+    pub app_version: Option<String>,
+    pub last_verified_timestamp_nanos: Option<u64>,
 }
 
-#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
 pub struct NamedCanister {
     name: String,
     canister_id: CanisterId,
     block_index: Option<BlockIndex>,
+    // The fields below are optional so that canisters stored before they were added can be decoded.
+    tags: Option<Vec<String>>,
+    note: Option<String>,
+    /// The cycles balance below which the user wants to be alerted.
+    low_cycles_threshold: Option<u64>,
 }
 
 impl NamedCanister {
@@ -170,15 +304,28 @@ impl PartialOrd for NamedCanister {
     }
 }
 
-#[derive(CandidType, Clone, Copy, Default, Deserialize, Debug, Eq, PartialEq)]
+/// The hashes of the given canisters, in canister ID order.
+///
+/// Each hash is the SHA-256 of the Candid encoding of a `NamedCanister`.
+fn canister_hashes(canisters: &[NamedCanister]) -> Vec<Hash> {
+    canisters
+        .iter()
+        .sorted_unstable_by_key(|canister| canister.canister_id)
+        .map(|canister| hash_bytes(candid::encode_one(canister).expect("Failed to encode a canister")))
+        .collect()
+}
+
+#[derive(CandidType, Clone, Copy, Default, Deserialize, Serialize, Debug, Eq, PartialEq)]
 pub struct ImportedToken {
     ledger_canister_id: PrincipalId,
     index_canister_id: Option<PrincipalId>,
 }
 
-#[derive(CandidType, Clone, Default, Deserialize, Debug, Eq, PartialEq)]
+#[derive(CandidType, Clone, Default, Deserialize, Serialize, Debug, Eq, PartialEq)]
 pub struct ImportedTokens {
     imported_tokens: Vec<ImportedToken>,
+    /// See `VersionedSettings`.
+    version: Option<u64>,
 }
 
 #[derive(CandidType, Debug, PartialEq)]
@@ -186,6 +333,7 @@ pub enum SetImportedTokensResponse {
     Ok,
     AccountNotFound,
     TooManyImportedTokens { limit: i32 },
+    VersionConflict { current_version: u64 },
 }
 
 #[derive(CandidType, Debug, PartialEq)]
@@ -194,14 +342,56 @@ pub enum GetImportedTokensResponse {
     AccountNotFound,
 }
 
-#[derive(CandidType, Clone, Copy, Default, Deserialize, Debug, Eq, PartialEq)]
+/// A name given by the user to one of their ICRC-1 subaccounts on a given ledger.
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, Eq, PartialEq)]
+pub struct NamedIcrc1Subaccount {
+    pub ledger_canister_id: PrincipalId,
+    /// The 32-byte ICRC-1 subaccount.
+    pub subaccount: Vec<u8>,
+    pub name: String,
+}
+
+#[derive(CandidType, Clone, Deserialize, Debug, Eq, PartialEq)]
+pub struct RenameNamedIcrc1SubaccountRequest {
+    pub ledger_canister_id: PrincipalId,
+    pub subaccount: Vec<u8>,
+    pub new_name: String,
+}
+
+#[derive(CandidType, Clone, Default, Deserialize, Debug, Eq, PartialEq)]
+pub struct ListNamedIcrc1SubaccountsRequest {
+    /// If set, only subaccounts on this ledger are returned.
+    pub ledger_canister_id: Option<PrincipalId>,
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum ListNamedIcrc1SubaccountsResponse {
+    Ok(Vec<NamedIcrc1Subaccount>),
+    AccountNotFound,
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum SetNamedIcrc1SubaccountResponse {
+    Ok,
+    AccountNotFound,
+    SubaccountNotFound,
+    InvalidSubaccount,
+    NameTooLong,
+    NameAlreadyTaken,
+    SubaccountAlreadyNamed,
+    TooManyNamedSubaccounts { limit: i32 },
+}
+
+#[derive(CandidType, Clone, Copy, Default, Deserialize, Serialize, Debug, Eq, PartialEq)]
 pub struct FavProject {
     root_canister_id: PrincipalId,
 }
 
-#[derive(CandidType, Clone, Default, Deserialize, Debug, Eq, PartialEq)]
+#[derive(CandidType, Clone, Default, Deserialize, Serialize, Debug, Eq, PartialEq)]
 pub struct FavProjects {
     fav_projects: Vec<FavProject>,
+    /// See `VersionedSettings`.
+    version: Option<u64>,
 }
 
 #[derive(CandidType, Debug, PartialEq)]
@@ -209,6 +399,7 @@ pub enum SetFavProjectsResponse {
     Ok,
     AccountNotFound,
     TooManyFavProjects { limit: i32 },
+    VersionConflict { current_version: u64 },
 }
 
 #[derive(CandidType, Debug, PartialEq)]
@@ -217,21 +408,109 @@ pub enum GetFavProjectsResponse {
     AccountNotFound,
 }
 
-#[derive(CandidType, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, Eq, PartialEq)]
 pub enum AddressType {
     Icp(String),
     Icrc1(String),
 }
 
-#[derive(CandidType, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, Eq, PartialEq)]
 pub struct NamedAddress {
     address: AddressType,
     name: String,
+    // The fields below are optional so that address books stored before they were added can be decoded.
+    /// The memo to prefill when sending to this address.
+    default_memo: Option<Vec<u8>>,
+    /// The ICRC-1 subaccount to suggest when sending to this address.  Only for ICRC-1 addresses.
+    subaccount_hint: Option<Vec<u8>>,
+    tags: Option<Vec<String>>,
+    /// The ledger of the token this address is meant for.  Only for ICRC-1 addresses.
+    ledger_canister_id: Option<CanisterId>,
 }
 
-#[derive(CandidType, Clone, Default, Deserialize, Debug, Eq, PartialEq)]
+#[derive(CandidType, Clone, Default, Deserialize, Serialize, Debug, Eq, PartialEq)]
 pub struct AddressBook {
     named_addresses: Vec<NamedAddress>,
+    /// See `VersionedSettings`.
+    version: Option<u64>,
+}
+
+/// A settings collection stored on `Account`, versioned for optimistic concurrency.
+///
+/// The canister increments the version on every change and getters return it.  Setters take the
+/// version the change is based on as `expected_version` and reject the change if the collection
+/// has been modified since, so that concurrent edits from different devices are not silently lost.
+/// Any version set by the caller on the collection itself is ignored.
+trait VersionedSettings {
+    fn version(&self) -> Option<u64>;
+    fn set_version(&mut self, version: u64);
+}
+
+impl VersionedSettings for ImportedTokens {
+    fn version(&self) -> Option<u64> {
+        self.version
+    }
+    fn set_version(&mut self, version: u64) {
+        self.version = Some(version);
+    }
+}
+
+impl VersionedSettings for FavProjects {
+    fn version(&self) -> Option<u64> {
+        self.version
+    }
+    fn set_version(&mut self, version: u64) {
+        self.version = Some(version);
+    }
+}
+
+impl VersionedSettings for AddressBook {
+    fn version(&self) -> Option<u64> {
+        self.version
+    }
+    fn set_version(&mut self, version: u64) {
+        self.version = Some(version);
+    }
+}
+
+/// Gives `new` the version following that of the `current` settings.
+///
+/// # Errors
+/// Returns the current version if `expected_version` is provided and differs from it.
+fn advance_version<T: VersionedSettings>(
+    current: Option<&T>,
+    new: &mut T,
+    expected_version: Option<u64>,
+) -> Result<(), u64> {
+    let current_version = current.and_then(T::version).unwrap_or_default();
+    if expected_version.is_some_and(|expected_version| expected_version != current_version) {
+        return Err(current_version);
+    }
+    new.set_version(current_version + 1);
+    Ok(())
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct AddNamedAddressRequest {
+    named_address: NamedAddress,
+    /// The version of the address book that the change is based on.
+    expected_version: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct UpdateNamedAddressRequest {
+    /// The current name of the entry to replace.
+    name: String,
+    named_address: NamedAddress,
+    /// The version of the address book that the change is based on.
+    expected_version: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct RemoveNamedAddressRequest {
+    name: String,
+    /// The version of the address book that the change is based on.
+    expected_version: u64,
 }
 
 #[derive(CandidType, Debug, PartialEq)]
@@ -244,6 +523,13 @@ pub enum SetAddressBookResponse {
     AddressNameTooLong { max_length: i32 },
     InvalidIcrc1Address { error: String },
     DuplicateAddressName { name: String },
+    MemoTooLong { max_length: i32 },
+    InvalidSubaccountHint { error: String },
+    TooManyTags { limit: i32 },
+    TagTooLong { max_length: i32 },
+    LedgerCanisterIdNotSupported,
+    VersionConflict { current_version: u64 },
+    NamedAddressNotFound { name: String },
 }
 
 #[derive(CandidType, Debug, PartialEq)]
@@ -252,6 +538,152 @@ pub enum GetAddressBookResponse {
     AccountNotFound,
 }
 
+/// Everything the accounts store keeps for a user, in a form that can be
+/// restored under a different principal.
+///
+/// Sub-accounts are identified by their `Subaccount` rather than by their
+/// account identifier, as the account identifier depends on the principal.
+#[derive(CandidType, Clone, Deserialize, Debug, Eq, PartialEq)]
+pub struct AccountDataBundle {
+    version: u32,
+    sub_accounts: Vec<ExportedSubAccount>,
+    hardware_wallet_accounts: Vec<ExportedHardwareWalletAccount>,
+    canisters: Vec<NamedCanister>,
+    imported_tokens: ImportedTokens,
+    fav_projects: FavProjects,
+    address_book: AddressBook,
+    named_icrc1_subaccounts: Option<Vec<NamedIcrc1Subaccount>>,
+}
+
+#[derive(CandidType, Clone, Deserialize, Debug, Eq, PartialEq)]
+pub struct ExportedSubAccount {
+    name: String,
+    sub_account: Subaccount,
+    archived: Option<bool>,
+    display_order: Option<u32>,
+    style_key: Option<String>,
+}
+
+#[derive(CandidType, Clone, Deserialize, Debug, Eq, PartialEq)]
+pub struct ExportedHardwareWalletAccount {
+    name: String,
+    principal: PrincipalId,
+    device: Option<HardwareWalletDevice>,
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum ExportAccountDataResponse {
+    Ok(AccountDataBundle),
+    AccountNotFound,
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum ImportAccountDataResponse {
+    Ok,
+    AccountNotFound,
+    UnsupportedVersion { version: u32 },
+    InvalidSubAccount,
+    DuplicateSubAccount,
+    DuplicateHardwareWallet,
+    HardwareWalletLimitExceeded,
+    DuplicateCanister,
+    CanisterLimitExceeded,
+    NameTooLong,
+    TooManyImportedTokens { limit: i32 },
+    TooManyFavProjects { limit: i32 },
+    InvalidAddressBook { error: String },
+    InvalidCanisterMetadata { error: String },
+    InvalidNamedIcrc1Subaccounts { error: String },
+    InvalidHardwareWalletDevice { error: String },
+}
+
+/// An offer by the owner of an account to hand the account over to another principal.
+#[derive(CandidType, Clone, Deserialize, Debug, Eq, PartialEq)]
+pub struct PendingAccountTransfer {
+    to: PrincipalId,
+    expires_at_timestamp_nanos: u64,
+}
+
+impl Storable for PendingAccountTransfer {
+    const BOUND: Bound = Bound::Unbounded;
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        candid::encode_one(self)
+            .expect("Failed to serialize pending account transfer")
+            .into()
+    }
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(&self).expect("Failed to serialize pending account transfer")
+    }
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to parse pending account transfer from store.")
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct CreateAccountTransferRequest {
+    to: PrincipalId,
+}
+
+#[cfg(test)]
+impl CreateAccountTransferRequest {
+    pub fn test_data() -> Self {
+        CreateAccountTransferRequest {
+            to: PrincipalId::new_user_test_id(1_000_000),
+        }
+    }
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum CreateAccountTransferResponse {
+    Ok { expires_at_timestamp_nanos: u64 },
+    AccountNotFound,
+    CannotTransferToSelf,
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum CancelAccountTransferResponse {
+    Ok,
+    TransferNotFound,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ClaimAccountTransferRequest {
+    from: PrincipalId,
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum ClaimAccountTransferResponse {
+    Ok,
+    TransferNotFound,
+    TransferExpired,
+    AccountNotFound,
+    TargetAccountNotEmpty,
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum AddAccountResponse {
+    Ok(AccountIdentifier),
+    AccountLimitReached { limit: u64 },
+}
+
+#[derive(CandidType, Deserialize, Debug, Default, Clone, Eq, PartialEq)]
+pub struct UpdateAccountLimitAllowlistRequest {
+    pub add: Vec<PrincipalId>,
+    pub remove: Vec<PrincipalId>,
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum UpdateAccountLimitAllowlistResponse {
+    Ok,
+    AllowlistTooLarge { limit: u32 },
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum DeleteAccountResponse {
+    Ok,
+    AccountNotFound,
+}
+
 #[derive(CandidType, Debug, PartialEq)]
 pub enum CreateSubAccountResponse {
     Ok(SubAccountDetails),
@@ -278,6 +710,7 @@ pub enum RenameSubAccountResponse {
 pub struct RegisterHardwareWalletRequest {
     name: String,
     principal: PrincipalId,
+    device: Option<HardwareWalletDevice>,
 }
 
 #[cfg(test)]
@@ -286,6 +719,7 @@ impl RegisterHardwareWalletRequest {
         RegisterHardwareWalletRequest {
             name: "test".to_string(),
             principal: PrincipalId::new_user_test_id(0),
+            device: None,
         }
     }
 }
@@ -297,6 +731,44 @@ pub enum RegisterHardwareWalletResponse {
     HardwareWalletAlreadyRegistered,
     HardwareWalletLimitExceeded,
     NameTooLong,
+    DeviceFieldTooLong { max_length: i32 },
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct RenameHardwareWalletRequest {
+    pub principal: PrincipalId,
+    pub new_name: String,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct UnregisterHardwareWalletRequest {
+    pub principal: PrincipalId,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct UpdateHardwareWalletDeviceRequest {
+    pub principal: PrincipalId,
+    pub device: Option<HardwareWalletDevice>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct RemoveHardwareWalletLinkRequest {
+    pub account_identifier: AccountIdentifier,
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum RemoveHardwareWalletLinkResponse {
+    Ok,
+    LinkNotFound,
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum UpdateHardwareWalletResponse {
+    Ok,
+    AccountNotFound,
+    HardwareWalletNotFound,
+    NameTooLong,
+    DeviceFieldTooLong { max_length: i32 },
 }
 
 #[derive(CandidType)]
@@ -312,6 +784,51 @@ pub struct SubAccountDetails {
     name: String,
     sub_account: Subaccount,
     account_identifier: AccountIdentifier,
+    archived: bool,
+    display_order: Option<u32>,
+    style_key: Option<String>,
+}
+
+impl SubAccountDetails {
+    fn new(sub_account_id: u8, sub_account: &NamedSubAccount) -> Self {
+        SubAccountDetails {
+            name: sub_account.name.clone(),
+            sub_account: convert_byte_to_sub_account(sub_account_id),
+            account_identifier: sub_account.account_identifier,
+            archived: sub_account.archived.unwrap_or_default(),
+            display_order: sub_account.display_order,
+            style_key: sub_account.style_key.clone(),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct SetSubAccountArchivedRequest {
+    pub account_identifier: AccountIdentifier,
+    pub archived: bool,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct SetSubAccountStyleKeyRequest {
+    pub account_identifier: AccountIdentifier,
+    pub style_key: Option<String>,
+}
+
+/// The sub-accounts in the order in which they should be displayed.
+///
+/// Sub-accounts that are not listed lose any explicit position.
+#[derive(CandidType, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct ReorderSubAccountsRequest {
+    pub account_identifiers: Vec<AccountIdentifier>,
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum UpdateSubAccountResponse {
+    Ok,
+    AccountNotFound,
+    SubAccountNotFound,
+    DuplicateSubAccount,
+    StyleKeyTooLong { max_length: i32 },
 }
 
 #[derive(CandidType)]
@@ -319,6 +836,7 @@ pub struct HardwareWalletAccountDetails {
     pub name: String,
     pub principal: PrincipalId,
     pub account_identifier: AccountIdentifier,
+    pub device: Option<HardwareWalletDevice>,
 }
 
 #[derive(CandidType, Deserialize)]
@@ -328,6 +846,17 @@ pub struct AttachCanisterRequest {
     block_index: Option<BlockIndex>,
 }
 
+#[cfg(test)]
+impl AttachCanisterRequest {
+    pub fn test_data() -> Self {
+        AttachCanisterRequest {
+            name: "test".to_string(),
+            canister_id: CanisterId::from_u64(0),
+            block_index: None,
+        }
+    }
+}
+
 #[derive(CandidType)]
 pub enum AttachCanisterResponse {
     Ok,
@@ -365,37 +894,167 @@ pub enum DetachCanisterResponse {
     AccountNotFound,
 }
 
+/// Replaces the metadata of an attached canister.  Fields that are not provided are cleared.
+#[derive(CandidType, Deserialize)]
+pub struct UpdateCanisterMetadataRequest {
+    canister_id: CanisterId,
+    tags: Option<Vec<String>>,
+    note: Option<String>,
+    low_cycles_threshold: Option<u64>,
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum UpdateCanisterMetadataResponse {
+    Ok,
+    AccountNotFound,
+    CanisterNotFound,
+    TooManyTags { limit: i32 },
+    TagTooLong { max_length: i32 },
+    NoteTooLong { max_length: i32 },
+}
+
+#[derive(CandidType, Clone, Copy, Default, Deserialize, Debug, Eq, PartialEq)]
+pub enum CanisterSortOrder {
+    /// Named canisters first, by name, then unnamed canisters by canister ID.  This is the order of `get_canisters`.
+    #[default]
+    Name,
+    CanisterId,
+    /// Canisters with the lowest block index first.  Canisters without a block index come last.
+    BlockIndex,
+}
+
+#[derive(CandidType, Deserialize, Debug, Default)]
+pub struct ListCanistersRequest {
+    pub offset: u32,
+    pub limit: u32,
+    /// Only canisters whose name contains this string, ignoring case, are listed.
+    pub name_filter: Option<String>,
+    pub sort_by: Option<CanisterSortOrder>,
+}
+
+#[derive(CandidType, Debug, Eq, PartialEq)]
+pub struct ListCanistersResult {
+    /// The requested page of canisters.
+    pub canisters: Vec<NamedCanister>,
+    /// The number of canisters matching the filter, across all pages.
+    pub matching_count: u32,
+    /// The hashes of all canisters of the account, in canister ID order.
+    ///
+    /// The hash of their concatenation is certified at `canisters/<account identifier>`.
+    pub canister_hashes: Vec<Hash>,
+    /// The data certificate.  Only available in query calls, and only once the account's canisters are certified after
+    /// an upgrade.
+    pub certificate: Option<Vec<u8>>,
+    /// The CBOR encoded witness for the certified hash of the account's canisters.
+    pub tree: Vec<u8>,
+}
+
+#[derive(CandidType, Debug, Eq, PartialEq)]
+pub enum ListCanistersResponse {
+    Ok(ListCanistersResult),
+    AccountNotFound,
+}
+
 impl AccountsStore {
     /// Creates a new `AccountsStore`. Should be called during canister `init`.
     #[must_use]
     pub fn new() -> Self {
-        let accounts_partition = with_partitions(|partitions| partitions.get(PartitionType::Accounts.memory_id()));
-        let accounts_db = StableBTreeMap::new(accounts_partition);
-        let accounts_db_stats = AccountsDbStats::default();
+        with_partitions(Self::new_in)
+    }
 
+    /// Creates a new `AccountsStore` in the given stable memory partitions.
+    #[must_use]
+    pub fn new_in(partitions: &Partitions) -> Self {
         Self {
-            accounts_db,
-            accounts_db_stats,
+            accounts_db: AccountsDb::new(
+                partitions.get(PartitionType::AccountsByIdentifier.memory_id()),
+                partitions.get(PartitionType::MalformedAccounts.memory_id()),
+            ),
+            accounts_db_stats: StableValue::new(
+                partitions.get(PartitionType::AccountsDbStats.memory_id()),
+                AccountsDbStats::default(),
+            ),
+            pending_account_transfers: PrincipalMap::new(
+                partitions.get(PartitionType::PendingAccountTransfers.memory_id()),
+            ),
+            canisters_hashes: CanistersHashes::new(partitions.get(PartitionType::CanistersHashes.memory_id())),
+            limits: StableValue::new(partitions.get(PartitionType::Limits.memory_id()), Limits::default()),
+            account_limit_allowlist: PrincipalMap::new(
+                partitions.get(PartitionType::AccountLimitAllowlist.memory_id()),
+            ),
+            hardware_wallet_index: HardwareWalletIndex::new(
+                partitions.get(PartitionType::HardwareWalletIndex.memory_id()),
+            ),
+            canister_index: CanisterIndex::new(partitions.get(PartitionType::CanisterIndex.memory_id())),
+            principal_less_accounts_scan: PrincipalLessAccountsScan::default(),
+            backfills: StableValue::new(
+                partitions.get(PartitionType::Backfills.memory_id()),
+                Backfills::complete(),
+            ),
         }
     }
 
+    /// Loads an `AccountsStore` from stable memory.  Should be called in `post_upgrade`.
     #[must_use]
-    pub fn get_account(&self, caller: PrincipalId) -> Option<AccountDetails> {
-        let account_identifier = AccountIdentifier::from(caller);
-        if let Some(account) = self.accounts_db.get(&account_identifier.to_vec()) {
-            // If the principal is empty, return None so that the browser will call add_account
-            // which will allow us to set the principal.
+    pub fn load() -> Self {
+        with_partitions(Self::load_from)
+    }
+
+    /// Loads an `AccountsStore` from the given stable memory partitions.
+    ///
+    /// Data derived from the accounts is not recomputed.  Derived data that the stable memory predates is computed
+    /// later, by `step_backfills`.
+    #[must_use]
+    pub fn load_from(partitions: &Partitions) -> Self {
+        AccountsStore {
+            accounts_db: AccountsDb::load(
+                partitions.get(PartitionType::AccountsByIdentifier.memory_id()),
+                partitions.get(PartitionType::Accounts.memory_id()),
+                partitions.get(PartitionType::MalformedAccounts.memory_id()),
+            ),
+            accounts_db_stats: StableValue::init(
+                partitions.get(PartitionType::AccountsDbStats.memory_id()),
+                AccountsDbStats::default(),
+            ),
+            pending_account_transfers: PrincipalMap::init(
+                partitions.get(PartitionType::PendingAccountTransfers.memory_id()),
+            ),
+            canisters_hashes: CanistersHashes::load(partitions.get(PartitionType::CanistersHashes.memory_id())),
+            limits: StableValue::init(partitions.get(PartitionType::Limits.memory_id()), Limits::default()),
+            account_limit_allowlist: PrincipalMap::init(
+                partitions.get(PartitionType::AccountLimitAllowlist.memory_id()),
+            ),
+            hardware_wallet_index: HardwareWalletIndex::load(
+                partitions.get(PartitionType::HardwareWalletIndex.memory_id()),
+            ),
+            canister_index: CanisterIndex::load(partitions.get(PartitionType::CanisterIndex.memory_id())),
+            principal_less_accounts_scan: PrincipalLessAccountsScan::default(),
+            backfills: StableValue::init(
+                partitions.get(PartitionType::Backfills.memory_id()),
+                Backfills::default(),
+            ),
+        }
+    }
+
+    #[must_use]
+    pub fn get_account(&self, caller: PrincipalId) -> Option<AccountDetails> {
+        let account_identifier = AccountIdentifier::from(caller);
+        if let Some(account) = self.accounts_db.get(&account_identifier.to_vec()) {
+            // If the principal is empty, return None so that the browser will call add_account
+            // which will allow us to set the principal.
             let principal = account.principal?;
 
             let sub_accounts = account
                 .sub_accounts
                 .iter()
-                .sorted_unstable_by_key(|(_, sub_account)| sub_account.name.clone())
-                .map(|(id, sa)| SubAccountDetails {
-                    name: sa.name.clone(),
-                    sub_account: convert_byte_to_sub_account(*id),
-                    account_identifier: sa.account_identifier,
+                .sorted_unstable_by_key(|(_, sub_account)| {
+                    (
+                        sub_account.display_order.is_none(),
+                        sub_account.display_order,
+                        sub_account.name.clone(),
+                    )
                 })
+                .map(|(id, sub_account)| SubAccountDetails::new(*id, sub_account))
                 .collect();
 
             let hardware_wallet_accounts = account
@@ -405,6 +1064,7 @@ impl AccountsStore {
                     name: a.name.clone(),
                     principal: a.principal,
                     account_identifier: AccountIdentifier::from(a.principal),
+                    device: a.device.clone(),
                 })
                 .collect();
 
@@ -422,8 +1082,10 @@ impl AccountsStore {
     // This will be called for new accounts and also for old accounts where the principal has not
     // yet been stored, allowing us to set the principal (since originally we created accounts
     // without storing each user's principal).
-    pub fn add_account(&mut self, caller: PrincipalId) -> bool {
-        self.assert_account_limit();
+    //
+    // Once the account limit has been reached, only principals on the allowlist can create new accounts.  Existing
+    // accounts are never affected.
+    pub fn add_account(&mut self, caller: PrincipalId) -> AddAccountResponse {
         let account_identifier = AccountIdentifier::from(caller);
         if let Some(account) = self.accounts_db.get(&account_identifier.to_vec()) {
             if account.principal.is_none() {
@@ -431,21 +1093,95 @@ impl AccountsStore {
                 let mut account = account.clone();
                 account.principal = Some(caller);
                 self.accounts_db.insert(account_identifier.to_vec(), account);
+                self.principal_less_accounts_scan.forget(&account_identifier.to_vec());
             }
-            false
         } else {
+            if self.accounts_db.len() >= self.limits.get().account_limit
+                && !self.account_limit_allowlist.contains(&caller)
+            {
+                return AddAccountResponse::AccountLimitReached {
+                    limit: self.limits.get().account_limit,
+                };
+            }
             let new_account = Account::new(caller, account_identifier);
             self.accounts_db.insert(account_identifier.to_vec(), new_account);
+        }
+        AddAccountResponse::Ok(account_identifier)
+    }
 
-            true
+    /// Adds principals to, and removes principals from, the account limit allowlist.
+    ///
+    /// Removals are applied after additions.
+    #[allow(clippy::needless_pass_by_value)] // The pattern here is to pass a request by value.
+    pub fn update_account_limit_allowlist(
+        &mut self,
+        request: UpdateAccountLimitAllowlistRequest,
+    ) -> UpdateAccountLimitAllowlistResponse {
+        let mut allowlist: BTreeSet<PrincipalId> = self.get_account_limit_allowlist().into_iter().collect();
+        allowlist.extend(request.add.iter().copied());
+        for principal in &request.remove {
+            allowlist.remove(principal);
+        }
+        if allowlist.len() > len_limit(self.limits.get().max_account_limit_allowlist_size) {
+            return UpdateAccountLimitAllowlistResponse::AllowlistTooLarge {
+                limit: self.limits.get().max_account_limit_allowlist_size,
+            };
         }
+        for principal in &request.add {
+            self.account_limit_allowlist.insert(*principal, ());
+        }
+        for principal in &request.remove {
+            self.account_limit_allowlist.remove(principal);
+        }
+        UpdateAccountLimitAllowlistResponse::Ok
+    }
+
+    /// Principals that may create an account even when the account limit has been reached.
+    #[must_use]
+    pub fn get_account_limit_allowlist(&self) -> Vec<PrincipalId> {
+        self.account_limit_allowlist
+            .iter()
+            .map(|(principal, ())| principal)
+            .sorted()
+            .collect()
+    }
+
+    /// Removes the caller's account and everything stored with it.
+    pub fn delete_account(&mut self, caller: PrincipalId) -> DeleteAccountResponse {
+        let account_identifier = AccountIdentifier::from(caller).to_vec();
+        let Some(account) = self.accounts_db.remove(&account_identifier) else {
+            return DeleteAccountResponse::AccountNotFound;
+        };
+        if account.principal.is_none() {
+            self.principal_less_accounts_scan.forget(&account_identifier);
+        }
+
+        self.accounts_db_stats.update(|stats| {
+            stats.sub_accounts_count = stats
+                .sub_accounts_count
+                .saturating_sub(account.sub_accounts.len() as u64);
+            stats.hardware_wallet_accounts_count = stats
+                .hardware_wallet_accounts_count
+                .saturating_sub(account.hardware_wallet_accounts.len() as u64);
+        });
+        for hardware_wallet in &account.hardware_wallet_accounts {
+            self.hardware_wallet_index
+                .remove(hardware_wallet.principal, &account_identifier);
+        }
+        for canister in &account.canisters {
+            self.canister_index.remove(canister.canister_id, &account_identifier);
+        }
+        self.pending_account_transfers.remove(&caller);
+        self.certify_canisters(&account_identifier, &[]);
+
+        DeleteAccountResponse::Ok
     }
 
     /// Creates a sub-account for the given user.
     pub fn create_sub_account(&mut self, caller: PrincipalId, sub_account_name: String) -> CreateSubAccountResponse {
         let account_identifier = AccountIdentifier::from(caller);
 
-        if !Self::validate_account_name(&sub_account_name) {
+        if !self.validate_account_name(&sub_account_name) {
             return CreateSubAccountResponse::NameTooLong;
         }
 
@@ -453,24 +1189,23 @@ impl AccountsStore {
             return CreateSubAccountResponse::AccountNotFound;
         };
 
-        let Some(sub_account_id) = (1..=MAX_SUB_ACCOUNT_ID).find(|i| !account.sub_accounts.contains_key(i)) else {
+        let Some(sub_account_id) =
+            (1..=self.limits.get().max_sub_account_id).find(|i| !account.sub_accounts.contains_key(i))
+        else {
             return CreateSubAccountResponse::SubAccountLimitExceeded;
         };
 
         let sub_account = convert_byte_to_sub_account(sub_account_id);
         let sub_account_identifier = AccountIdentifier::new(caller, Some(sub_account));
-        let named_sub_account = NamedSubAccount::new(sub_account_name.clone(), sub_account_identifier);
+        let named_sub_account = NamedSubAccount::new(sub_account_name, sub_account_identifier);
+        let details = SubAccountDetails::new(sub_account_id, &named_sub_account);
 
         account.sub_accounts.insert(sub_account_id, named_sub_account);
         self.accounts_db.insert(account_identifier.to_vec(), account);
 
-        self.accounts_db_stats.sub_accounts_count += 1;
+        self.accounts_db_stats.update(|stats| stats.sub_accounts_count += 1);
 
-        CreateSubAccountResponse::Ok(SubAccountDetails {
-            name: sub_account_name,
-            sub_account,
-            account_identifier: sub_account_identifier,
-        })
+        CreateSubAccountResponse::Ok(details)
     }
 
     pub fn rename_sub_account(
@@ -480,7 +1215,7 @@ impl AccountsStore {
     ) -> RenameSubAccountResponse {
         let account_identifier = AccountIdentifier::from(caller).to_vec();
 
-        if !Self::validate_account_name(&request.new_name) {
+        if !self.validate_account_name(&request.new_name) {
             RenameSubAccountResponse::NameTooLong
         } else if let Some(mut account) = self.accounts_db.get(&account_identifier) {
             if let Some(sub_account) = account
@@ -499,6 +1234,96 @@ impl AccountsStore {
         }
     }
 
+    /// Archives or restores one of the caller's sub-accounts.
+    ///
+    /// Archived sub-accounts keep their name and are still returned by `get_account`, flagged as archived.
+    #[allow(clippy::needless_pass_by_value)] // The pattern here is to pass a request by value.
+    pub fn set_sub_account_archived(
+        &mut self,
+        caller: PrincipalId,
+        request: SetSubAccountArchivedRequest,
+    ) -> UpdateSubAccountResponse {
+        self.update_sub_account(caller, request.account_identifier, |sub_account| {
+            sub_account.archived = Some(request.archived);
+        })
+    }
+
+    /// Sets or clears the colour/icon key of one of the caller's sub-accounts.
+    pub fn set_sub_account_style_key(
+        &mut self,
+        caller: PrincipalId,
+        request: SetSubAccountStyleKeyRequest,
+    ) -> UpdateSubAccountResponse {
+        if request
+            .style_key
+            .as_ref()
+            .is_some_and(|style_key| style_key.len() > len_limit(self.limits.get().max_sub_account_style_key_length))
+        {
+            return UpdateSubAccountResponse::StyleKeyTooLong {
+                max_length: self.limits.get().max_sub_account_style_key_length,
+            };
+        }
+        self.update_sub_account(caller, request.account_identifier, |sub_account| {
+            sub_account.style_key = request.style_key;
+        })
+    }
+
+    /// Sets the display order of the caller's sub-accounts.
+    #[allow(clippy::needless_pass_by_value)] // The pattern here is to pass a request by value.
+    pub fn reorder_sub_accounts(
+        &mut self,
+        caller: PrincipalId,
+        request: ReorderSubAccountsRequest,
+    ) -> UpdateSubAccountResponse {
+        let account_identifier = AccountIdentifier::from(caller).to_vec();
+        let Some(mut account) = self.accounts_db.get(&account_identifier) else {
+            return UpdateSubAccountResponse::AccountNotFound;
+        };
+        if !request.account_identifiers.iter().all_unique() {
+            return UpdateSubAccountResponse::DuplicateSubAccount;
+        }
+        if !request.account_identifiers.iter().all(|listed| {
+            account
+                .sub_accounts
+                .values()
+                .any(|sub_account| sub_account.account_identifier == *listed)
+        }) {
+            return UpdateSubAccountResponse::SubAccountNotFound;
+        }
+        for sub_account in account.sub_accounts.values_mut() {
+            sub_account.display_order = request
+                .account_identifiers
+                .iter()
+                .position(|listed| *listed == sub_account.account_identifier)
+                .map(|position| u32::try_from(position).unwrap_or(u32::MAX));
+        }
+        self.accounts_db.insert(account_identifier, account);
+        UpdateSubAccountResponse::Ok
+    }
+
+    /// Applies a change to one of the caller's sub-accounts.
+    fn update_sub_account(
+        &mut self,
+        caller: PrincipalId,
+        sub_account_identifier: AccountIdentifier,
+        update: impl FnOnce(&mut NamedSubAccount),
+    ) -> UpdateSubAccountResponse {
+        let account_identifier = AccountIdentifier::from(caller).to_vec();
+        let Some(mut account) = self.accounts_db.get(&account_identifier) else {
+            return UpdateSubAccountResponse::AccountNotFound;
+        };
+        let Some(sub_account) = account
+            .sub_accounts
+            .values_mut()
+            .find(|sub_account| sub_account.account_identifier == sub_account_identifier)
+        else {
+            return UpdateSubAccountResponse::SubAccountNotFound;
+        };
+        update(sub_account);
+        self.accounts_db.insert(account_identifier, account);
+        UpdateSubAccountResponse::Ok
+    }
+
     pub fn register_hardware_wallet(
         &mut self,
         caller: PrincipalId,
@@ -506,8 +1331,10 @@ impl AccountsStore {
     ) -> RegisterHardwareWalletResponse {
         let account_identifier = AccountIdentifier::from(caller);
 
-        if !Self::validate_account_name(&request.name) {
+        if !self.validate_account_name(&request.name) {
             RegisterHardwareWalletResponse::NameTooLong
+        } else if let Err(max_length) = self.validate_hardware_wallet_device(request.device.as_ref()) {
+            RegisterHardwareWalletResponse::DeviceFieldTooLong { max_length }
         } else if let Some(mut account) = self.accounts_db.get(&account_identifier.to_vec()).clone() {
             if account.hardware_wallet_accounts.len() == (u8::MAX as usize) {
                 RegisterHardwareWalletResponse::HardwareWalletLimitExceeded
@@ -521,13 +1348,17 @@ impl AccountsStore {
                 account.hardware_wallet_accounts.push(NamedHardwareWalletAccount {
                     name: request.name,
                     principal: request.principal,
+                    device: request.device,
                 });
                 account
                     .hardware_wallet_accounts
                     .sort_unstable_by_key(|hw| hw.name.clone());
                 self.accounts_db.insert(account_identifier.to_vec(), account);
+                self.hardware_wallet_index
+                    .insert(request.principal, &account_identifier.to_vec());
 
-                self.accounts_db_stats.hardware_wallet_accounts_count += 1;
+                self.accounts_db_stats
+                    .update(|stats| stats.hardware_wallet_accounts_count += 1);
                 RegisterHardwareWalletResponse::Ok
             }
         } else {
@@ -535,6 +1366,152 @@ impl AccountsStore {
         }
     }
 
+    /// Changes the name of one of the caller's hardware wallets.
+    pub fn rename_hardware_wallet(
+        &mut self,
+        caller: PrincipalId,
+        request: RenameHardwareWalletRequest,
+    ) -> UpdateHardwareWalletResponse {
+        if !self.validate_account_name(&request.new_name) {
+            return UpdateHardwareWalletResponse::NameTooLong;
+        }
+        self.update_hardware_wallet(caller, request.principal, |hardware_wallet| {
+            hardware_wallet.name = request.new_name;
+        })
+    }
+
+    /// Sets or clears the device information of one of the caller's hardware wallets.
+    pub fn update_hardware_wallet_device(
+        &mut self,
+        caller: PrincipalId,
+        request: UpdateHardwareWalletDeviceRequest,
+    ) -> UpdateHardwareWalletResponse {
+        if let Err(max_length) = self.validate_hardware_wallet_device(request.device.as_ref()) {
+            return UpdateHardwareWalletResponse::DeviceFieldTooLong { max_length };
+        }
+        self.update_hardware_wallet(caller, request.principal, |hardware_wallet| {
+            hardware_wallet.device = request.device;
+        })
+    }
+
+    /// Unlinks a hardware wallet from the caller's account.
+    ///
+    /// The hardware wallet may be registered again later.
+    #[allow(clippy::needless_pass_by_value)] // The pattern here is to pass a request by value.
+    pub fn unregister_hardware_wallet(
+        &mut self,
+        caller: PrincipalId,
+        request: UnregisterHardwareWalletRequest,
+    ) -> UpdateHardwareWalletResponse {
+        let account_identifier = AccountIdentifier::from(caller).to_vec();
+        self.remove_hardware_wallet(account_identifier, request.principal)
+    }
+
+    /// Lists the accounts that have registered the caller as a hardware wallet.
+    ///
+    /// As only the holder of the hardware wallet can make calls as the hardware wallet principal, this reveals the
+    /// accounts only to the holder of the device.
+    ///
+    /// Note: After upgrading from a release without the hardware wallet index, accounts are missing from the list
+    /// until the index has been backfilled.
+    #[must_use]
+    pub fn get_hardware_wallet_links(&self, caller: PrincipalId) -> Vec<AccountIdentifier> {
+        self.hardware_wallet_index.accounts(caller)
+    }
+
+    /// Removes the caller, as a hardware wallet, from an account that registered it.
+    ///
+    /// This lets the holder of a hardware wallet remove stale links, for example from accounts they no longer use.
+    #[allow(clippy::needless_pass_by_value)] // The pattern here is to pass a request by value.
+    pub fn remove_hardware_wallet_link(
+        &mut self,
+        caller: PrincipalId,
+        request: RemoveHardwareWalletLinkRequest,
+    ) -> RemoveHardwareWalletLinkResponse {
+        match self.remove_hardware_wallet(request.account_identifier.to_vec(), caller) {
+            UpdateHardwareWalletResponse::Ok => RemoveHardwareWalletLinkResponse::Ok,
+            _ => RemoveHardwareWalletLinkResponse::LinkNotFound,
+        }
+    }
+
+    /// Removes a hardware wallet from an account.
+    fn remove_hardware_wallet(
+        &mut self,
+        account_identifier: Vec<u8>,
+        hardware_wallet_principal: PrincipalId,
+    ) -> UpdateHardwareWalletResponse {
+        let Some(mut account) = self.accounts_db.get(&account_identifier) else {
+            return UpdateHardwareWalletResponse::AccountNotFound;
+        };
+        let Some(index) = account
+            .hardware_wallet_accounts
+            .iter()
+            .position(|hw| hw.principal == hardware_wallet_principal)
+        else {
+            return UpdateHardwareWalletResponse::HardwareWalletNotFound;
+        };
+        account.hardware_wallet_accounts.remove(index);
+        self.hardware_wallet_index
+            .remove(hardware_wallet_principal, &account_identifier);
+        self.accounts_db.insert(account_identifier, account);
+
+        self.accounts_db_stats.update(|stats| {
+            stats.hardware_wallet_accounts_count = stats.hardware_wallet_accounts_count.saturating_sub(1);
+        });
+        UpdateHardwareWalletResponse::Ok
+    }
+
+    /// Adds the hardware wallets of an account to the hardware wallet index.
+    ///
+    /// This is needed once for every account, after upgrading from a release without the index.
+    fn index_hardware_wallets(&mut self, account_identifier: &[u8], hardware_wallets: &[NamedHardwareWalletAccount]) {
+        for hardware_wallet in hardware_wallets {
+            self.hardware_wallet_index
+                .insert(hardware_wallet.principal, account_identifier);
+        }
+    }
+
+    /// Applies a change to one of the caller's hardware wallets, keeping hardware wallets sorted by name.
+    fn update_hardware_wallet(
+        &mut self,
+        caller: PrincipalId,
+        hardware_wallet_principal: PrincipalId,
+        update: impl FnOnce(&mut NamedHardwareWalletAccount),
+    ) -> UpdateHardwareWalletResponse {
+        let account_identifier = AccountIdentifier::from(caller).to_vec();
+        let Some(mut account) = self.accounts_db.get(&account_identifier) else {
+            return UpdateHardwareWalletResponse::AccountNotFound;
+        };
+        let Some(hardware_wallet) = account
+            .hardware_wallet_accounts
+            .iter_mut()
+            .find(|hw| hw.principal == hardware_wallet_principal)
+        else {
+            return UpdateHardwareWalletResponse::HardwareWalletNotFound;
+        };
+        update(hardware_wallet);
+        account
+            .hardware_wallet_accounts
+            .sort_unstable_by_key(|hw| hw.name.clone());
+        self.accounts_db.insert(account_identifier, account);
+        UpdateHardwareWalletResponse::Ok
+    }
+
+    /// Checks the lengths of the device fields, returning the maximum length if any is too long.
+    fn validate_hardware_wallet_device(&self, device: Option<&HardwareWalletDevice>) -> Result<(), i32> {
+        let Some(device) = device else {
+            return Ok(());
+        };
+        if [&device.model, &device.app_version]
+            .into_iter()
+            .flatten()
+            .any(|field| field.len() > len_limit(self.limits.get().max_hardware_wallet_device_field_length))
+        {
+            return Err(self.limits.get().max_hardware_wallet_device_field_length);
+        }
+        Ok(())
+    }
+
     fn find_canister_index(account: &Account, canister_id: CanisterId) -> Option<usize> {
         account
             .canisters
@@ -545,7 +1522,7 @@ impl AccountsStore {
     }
 
     pub fn attach_canister(&mut self, caller: PrincipalId, request: AttachCanisterRequest) -> AttachCanisterResponse {
-        if !Self::validate_canister_name(&request.name) {
+        if !self.validate_canister_name(&request.name) {
             return AttachCanisterResponse::NameTooLong;
         }
 
@@ -559,6 +1536,9 @@ impl AccountsStore {
             name: request.name,
             canister_id: request.canister_id,
             block_index: request.block_index,
+            tags: None,
+            note: None,
+            low_cycles_threshold: None,
         };
 
         let mut index_to_remove: Option<usize> = None;
@@ -595,6 +1575,11 @@ impl AccountsStore {
                     }
                 }
 
+                // Metadata can't be set when attaching, so keep any existing metadata.
+                new_canister.tags.clone_from(&existing_canister.tags);
+                new_canister.note.clone_from(&existing_canister.note);
+                new_canister.low_cycles_threshold = existing_canister.low_cycles_threshold;
+
                 if new_canister == *existing_canister {
                     // Nothing new to add.
                     return AttachCanisterResponse::CanisterAlreadyAttached;
@@ -612,16 +1597,19 @@ impl AccountsStore {
         if account.canisters.len() >= u8::MAX as usize {
             return AttachCanisterResponse::CanisterLimitExceeded;
         }
+        self.canister_index
+            .insert(new_canister.canister_id, &account_identifier);
         account.canisters.push(new_canister);
         account.canisters.sort();
 
+        self.certify_canisters(&account_identifier, &account.canisters);
         self.accounts_db.insert(account_identifier, account);
 
         AttachCanisterResponse::Ok
     }
 
     pub fn rename_canister(&mut self, caller: PrincipalId, request: RenameCanisterRequest) -> RenameCanisterResponse {
-        if Self::validate_canister_name(&request.name) {
+        if self.validate_canister_name(&request.name) {
             let account_identifier = AccountIdentifier::from(caller).to_vec();
 
             if let Some(mut account) = self.accounts_db.get(&account_identifier) {
@@ -636,6 +1624,7 @@ impl AccountsStore {
                         ..existing_canister
                     });
                     account.canisters.sort();
+                    self.certify_canisters(&account_identifier, &account.canisters);
                     self.accounts_db.insert(account_identifier, account);
                     RenameCanisterResponse::Ok
                 } else {
@@ -656,6 +1645,8 @@ impl AccountsStore {
         if let Some(mut account) = self.accounts_db.get(&account_identifier) {
             if let Some(index) = Self::find_canister_index(&account, request.canister_id) {
                 account.canisters.remove(index);
+                self.canister_index.remove(request.canister_id, &account_identifier);
+                self.certify_canisters(&account_identifier, &account.canisters);
                 self.accounts_db.insert(account_identifier, account);
                 DetachCanisterResponse::Ok
             } else {
@@ -666,6 +1657,36 @@ impl AccountsStore {
         }
     }
 
+    pub fn update_canister_metadata(
+        &mut self,
+        caller: PrincipalId,
+        request: UpdateCanisterMetadataRequest,
+    ) -> UpdateCanisterMetadataResponse {
+        if let Err(error) = self.validate_canister_metadata(request.tags.as_deref(), request.note.as_deref()) {
+            return error;
+        }
+
+        let account_identifier = AccountIdentifier::from(caller).to_vec();
+        let Some(mut account) = self.accounts_db.get(&account_identifier) else {
+            return UpdateCanisterMetadataResponse::AccountNotFound;
+        };
+        let Some(canister) = account
+            .canisters
+            .iter_mut()
+            .find(|canister| canister.canister_id == request.canister_id)
+        else {
+            return UpdateCanisterMetadataResponse::CanisterNotFound;
+        };
+
+        canister.tags = request.tags;
+        canister.note = request.note;
+        canister.low_cycles_threshold = request.low_cycles_threshold;
+
+        self.certify_canisters(&account_identifier, &account.canisters);
+        self.accounts_db.insert(account_identifier, account);
+        UpdateCanisterMetadataResponse::Ok
+    }
+
     #[must_use]
     pub fn get_canisters(&self, caller: PrincipalId) -> Vec<NamedCanister> {
         let account_identifier = AccountIdentifier::from(caller);
@@ -676,20 +1697,140 @@ impl AccountsStore {
         }
     }
 
+    /// Lists a page of the caller's canisters, optionally filtered by name.
+    ///
+    /// Note: The certificate and witness are left empty; they are added by the query endpoint.
+    #[must_use]
+    #[allow(clippy::needless_pass_by_value)] // The pattern here is to pass a request by value.
+    pub fn list_canisters(&self, caller: PrincipalId, request: ListCanistersRequest) -> ListCanistersResponse {
+        let account_identifier = AccountIdentifier::from(caller).to_vec();
+        let Some(account) = self.accounts_db.get(&account_identifier) else {
+            return ListCanistersResponse::AccountNotFound;
+        };
+
+        let canister_hashes = canister_hashes(&account.canisters);
+        let name_filter = request.name_filter.as_ref().map(|filter| filter.to_lowercase());
+        let mut canisters: Vec<NamedCanister> = account
+            .canisters
+            .into_iter()
+            .filter(|canister| {
+                name_filter
+                    .as_ref()
+                    .is_none_or(|filter| canister.name.to_lowercase().contains(filter.as_str()))
+            })
+            .collect();
+        match request.sort_by.unwrap_or_default() {
+            CanisterSortOrder::Name => canisters.sort(),
+            CanisterSortOrder::CanisterId => canisters.sort_unstable_by_key(|canister| canister.canister_id),
+            CanisterSortOrder::BlockIndex => canisters.sort_unstable_by_key(|canister| {
+                (
+                    canister.block_index.is_none(),
+                    canister.block_index,
+                    canister.canister_id,
+                )
+            }),
+        }
+        let matching_count = u32::try_from(canisters.len()).unwrap_or(u32::MAX);
+        let canisters = canisters
+            .into_iter()
+            .skip(request.offset as usize)
+            .take(request.limit as usize)
+            .collect();
+
+        ListCanistersResponse::Ok(ListCanistersResult {
+            canisters,
+            matching_count,
+            canister_hashes,
+            certificate: None,
+            tree: Vec::new(),
+        })
+    }
+
+    /// The root hash of the certified hashes of all users' canisters.
+    #[must_use]
+    pub fn canisters_root_hash(&self) -> Hash {
+        self.canisters_hashes.root_hash()
+    }
+
+    /// A witness for the certified hash of the canisters of the given account.
+    #[must_use]
+    pub fn canisters_witness(&self, account_identifier: &[u8]) -> HashTree<'_> {
+        self.canisters_hashes.witness(account_identifier)
+    }
+
+    /// Whether the given hashes of all the canisters of an account are certified.
+    ///
+    /// After an upgrade, the hashes of existing accounts are certified once they have been read back into the
+    /// certification tree, or once they have been backfilled if the stable memory predates them.
+    #[must_use]
+    pub fn canisters_are_certified(&self, account_identifier: &[u8], canister_hashes: &[Hash]) -> bool {
+        let hash = (!canister_hashes.is_empty()).then(|| hash_bytes(canister_hashes.concat()));
+        self.canisters_hashes.is_certified(account_identifier, hash)
+    }
+
+    /// Discards the certified canister hashes and computes them again, with a backfill.
+    ///
+    /// Needed when the stable memory was last written by a build that did not keep the hashes up to date.
+    pub fn restart_canisters_hashes_backfill(&mut self) {
+        self.canisters_hashes.clear();
+        self.backfills
+            .update(|backfills| backfills.set_progress(Backfill::CanistersHashes, BackfillProgress::NOT_STARTED));
+    }
+
+    /// Updates the certified hash of an account's canisters.
+    ///
+    /// Note: This does NOT update the certified data of the canister.  Call `certification::update_root_hash` for that.
+    fn certify_canisters(&mut self, account_identifier: &[u8], canisters: &[NamedCanister]) {
+        if canisters.is_empty() {
+            self.canisters_hashes.remove(account_identifier);
+        } else {
+            let hash = hash_bytes(canister_hashes(canisters).concat());
+            self.canisters_hashes.insert(account_identifier, hash);
+        }
+    }
+
+    /// The number of accounts that have attached the given canister.
+    ///
+    /// Only the number is revealed, not which accounts have attached the canister.
+    ///
+    /// Note: After upgrading from a release without the canister index, the count may be too low until the index has
+    /// been backfilled.
+    #[must_use]
+    pub fn get_canister_attachment_count(&self, canister_id: CanisterId) -> u64 {
+        self.canister_index.attachment_count(canister_id)
+    }
+
+    /// Adds the canisters of an account to the canister index.
+    ///
+    /// This is needed once for every account, after upgrading from a release without the index.
+    fn index_canisters(&mut self, account_identifier: &[u8], canisters: &[NamedCanister]) {
+        for canister in canisters {
+            self.canister_index.insert(canister.canister_id, account_identifier);
+        }
+    }
+
     pub fn set_imported_tokens(
         &mut self,
         caller: PrincipalId,
-        new_imported_tokens: ImportedTokens,
+        mut new_imported_tokens: ImportedTokens,
+        expected_version: Option<u64>,
     ) -> SetImportedTokensResponse {
-        if new_imported_tokens.imported_tokens.len() > (MAX_IMPORTED_TOKENS as usize) {
+        if new_imported_tokens.imported_tokens.len() > len_limit(self.limits.get().max_imported_tokens) {
             return SetImportedTokensResponse::TooManyImportedTokens {
-                limit: MAX_IMPORTED_TOKENS,
+                limit: self.limits.get().max_imported_tokens,
             };
         }
         let account_identifier = AccountIdentifier::from(caller).to_vec();
         let Some(mut account) = self.accounts_db.get(&account_identifier) else {
             return SetImportedTokensResponse::AccountNotFound;
         };
+        if let Err(current_version) = advance_version(
+            account.imported_tokens.as_ref(),
+            &mut new_imported_tokens,
+            expected_version,
+        ) {
+            return SetImportedTokensResponse::VersionConflict { current_version };
+        }
 
         account.imported_tokens = Some(new_imported_tokens);
 
@@ -706,16 +1847,142 @@ impl AccountsStore {
         GetImportedTokensResponse::Ok(account.imported_tokens.unwrap_or_default())
     }
 
-    pub fn set_fav_projects(&mut self, caller: PrincipalId, new_fav_projects: FavProjects) -> SetFavProjectsResponse {
-        if new_fav_projects.fav_projects.len() > (MAX_FAVORITE_PROJECTS as usize) {
+    /// Gives a name to one of the caller's ICRC-1 subaccounts.
+    pub fn create_named_icrc1_subaccount(
+        &mut self,
+        caller: PrincipalId,
+        named_subaccount: NamedIcrc1Subaccount,
+    ) -> SetNamedIcrc1SubaccountResponse {
+        self.update_named_icrc1_subaccounts(caller, |named_subaccounts| {
+            named_subaccounts.push(named_subaccount);
+            Ok(())
+        })
+    }
+
+    /// Changes the name of one of the caller's named ICRC-1 subaccounts.
+    pub fn rename_named_icrc1_subaccount(
+        &mut self,
+        caller: PrincipalId,
+        request: RenameNamedIcrc1SubaccountRequest,
+    ) -> SetNamedIcrc1SubaccountResponse {
+        self.update_named_icrc1_subaccounts(caller, |named_subaccounts| {
+            let named_subaccount = named_subaccounts
+                .iter_mut()
+                .find(|named_subaccount| {
+                    named_subaccount.ledger_canister_id == request.ledger_canister_id
+                        && named_subaccount.subaccount == request.subaccount
+                })
+                .ok_or(SetNamedIcrc1SubaccountResponse::SubaccountNotFound)?;
+            named_subaccount.name = request.new_name;
+            Ok(())
+        })
+    }
+
+    /// The caller's named ICRC-1 subaccounts, sorted by ledger and then by name.
+    #[must_use]
+    #[allow(clippy::needless_pass_by_value)] // The pattern here is to pass a request by value.
+    pub fn list_named_icrc1_subaccounts(
+        &self,
+        caller: PrincipalId,
+        request: ListNamedIcrc1SubaccountsRequest,
+    ) -> ListNamedIcrc1SubaccountsResponse {
+        let account_identifier = AccountIdentifier::from(caller).to_vec();
+        let Some(account) = self.accounts_db.get(&account_identifier) else {
+            return ListNamedIcrc1SubaccountsResponse::AccountNotFound;
+        };
+        let named_subaccounts = account
+            .named_icrc1_subaccounts
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|named_subaccount| {
+                request
+                    .ledger_canister_id
+                    .is_none_or(|ledger_canister_id| named_subaccount.ledger_canister_id == ledger_canister_id)
+            })
+            .sorted_by(|a, b| {
+                (a.ledger_canister_id, &a.name, &a.subaccount).cmp(&(b.ledger_canister_id, &b.name, &b.subaccount))
+            })
+            .collect();
+        ListNamedIcrc1SubaccountsResponse::Ok(named_subaccounts)
+    }
+
+    /// Applies a change to the caller's named ICRC-1 subaccounts and stores the result if it is valid.
+    fn update_named_icrc1_subaccounts(
+        &mut self,
+        caller: PrincipalId,
+        update: impl FnOnce(&mut Vec<NamedIcrc1Subaccount>) -> Result<(), SetNamedIcrc1SubaccountResponse>,
+    ) -> SetNamedIcrc1SubaccountResponse {
+        let account_identifier = AccountIdentifier::from(caller).to_vec();
+        let Some(mut account) = self.accounts_db.get(&account_identifier) else {
+            return SetNamedIcrc1SubaccountResponse::AccountNotFound;
+        };
+        let mut named_subaccounts = account.named_icrc1_subaccounts.take().unwrap_or_default();
+        if let Err(error_response) = update(&mut named_subaccounts) {
+            return error_response;
+        }
+        if let Err(error_response) = self.validate_named_icrc1_subaccounts(&named_subaccounts) {
+            return error_response;
+        }
+        account.named_icrc1_subaccounts = Some(named_subaccounts);
+        self.accounts_db.insert(account_identifier, account);
+        SetNamedIcrc1SubaccountResponse::Ok
+    }
+
+    fn validate_named_icrc1_subaccounts(
+        &self,
+        named_subaccounts: &[NamedIcrc1Subaccount],
+    ) -> Result<(), SetNamedIcrc1SubaccountResponse> {
+        if named_subaccounts.len() > len_limit(self.limits.get().max_named_icrc1_subaccounts) {
+            return Err(SetNamedIcrc1SubaccountResponse::TooManyNamedSubaccounts {
+                limit: self.limits.get().max_named_icrc1_subaccounts,
+            });
+        }
+        for named_subaccount in named_subaccounts {
+            // The default subaccount is the main account and is not named here.
+            if named_subaccount.subaccount.len() != 32 || named_subaccount.subaccount.iter().all(|byte| *byte == 0) {
+                return Err(SetNamedIcrc1SubaccountResponse::InvalidSubaccount);
+            }
+            if !self.validate_account_name(&named_subaccount.name) {
+                return Err(SetNamedIcrc1SubaccountResponse::NameTooLong);
+            }
+        }
+        if !named_subaccounts
+            .iter()
+            .map(|named_subaccount| (named_subaccount.ledger_canister_id, &named_subaccount.subaccount))
+            .all_unique()
+        {
+            return Err(SetNamedIcrc1SubaccountResponse::SubaccountAlreadyNamed);
+        }
+        if !named_subaccounts
+            .iter()
+            .map(|named_subaccount| (named_subaccount.ledger_canister_id, &named_subaccount.name))
+            .all_unique()
+        {
+            return Err(SetNamedIcrc1SubaccountResponse::NameAlreadyTaken);
+        }
+        Ok(())
+    }
+
+    pub fn set_fav_projects(
+        &mut self,
+        caller: PrincipalId,
+        mut new_fav_projects: FavProjects,
+        expected_version: Option<u64>,
+    ) -> SetFavProjectsResponse {
+        if new_fav_projects.fav_projects.len() > len_limit(self.limits.get().max_favorite_projects) {
             return SetFavProjectsResponse::TooManyFavProjects {
-                limit: MAX_FAVORITE_PROJECTS,
+                limit: self.limits.get().max_favorite_projects,
             };
         }
         let account_identifier = AccountIdentifier::from(caller).to_vec();
         let Some(mut account) = self.accounts_db.get(&account_identifier) else {
             return SetFavProjectsResponse::AccountNotFound;
         };
+        if let Err(current_version) =
+            advance_version(account.fav_projects.as_ref(), &mut new_fav_projects, expected_version)
+        {
+            return SetFavProjectsResponse::VersionConflict { current_version };
+        }
 
         account.fav_projects = Some(new_fav_projects);
 
@@ -732,10 +1999,10 @@ impl AccountsStore {
         GetFavProjectsResponse::Ok(account.fav_projects.unwrap_or_default())
     }
 
-    fn validate_address_book_count(address_book: &AddressBook) -> Result<(), SetAddressBookResponse> {
-        if address_book.named_addresses.len() > (MAX_NAMED_ADDRESSES as usize) {
+    fn validate_address_book_count(&self, address_book: &AddressBook) -> Result<(), SetAddressBookResponse> {
+        if address_book.named_addresses.len() > len_limit(self.limits.get().max_named_addresses) {
             return Err(SetAddressBookResponse::TooManyNamedAddresses {
-                limit: MAX_NAMED_ADDRESSES,
+                limit: self.limits.get().max_named_addresses,
             });
         }
         Ok(())
@@ -804,11 +2071,62 @@ impl AccountsStore {
         Ok(())
     }
 
-    fn validate_address_book(address_book: &AddressBook) -> Result<(), SetAddressBookResponse> {
-        Self::validate_address_book_count(address_book)?;
+    fn validate_address_book_details(address_book: &AddressBook) -> Result<(), SetAddressBookResponse> {
+        for named_address in &address_book.named_addresses {
+            let is_icrc1 = matches!(named_address.address, AddressType::Icrc1(_));
+
+            if let Some(memo) = &named_address.default_memo {
+                let max_length = if is_icrc1 {
+                    MAX_ICRC1_MEMO_LENGTH
+                } else {
+                    MAX_ICP_MEMO_LENGTH
+                };
+                if memo.len() > (max_length as usize) {
+                    return Err(SetAddressBookResponse::MemoTooLong { max_length });
+                }
+            }
+
+            if let Some(subaccount) = &named_address.subaccount_hint {
+                if !is_icrc1 {
+                    return Err(SetAddressBookResponse::InvalidSubaccountHint {
+                        error: "Subaccount hints are only supported for ICRC1 addresses".to_string(),
+                    });
+                }
+                if subaccount.len() != 32 {
+                    return Err(SetAddressBookResponse::InvalidSubaccountHint {
+                        error: format!("Subaccounts have 32 bytes, got {}", subaccount.len()),
+                    });
+                }
+            }
+
+            let tags = named_address.tags.as_deref().unwrap_or_default();
+            if tags.len() > len_limit(self.limits.get().max_named_address_tags) {
+                return Err(SetAddressBookResponse::TooManyTags {
+                    limit: self.limits.get().max_named_address_tags,
+                });
+            }
+            if tags
+                .iter()
+                .any(|tag| tag.len() > len_limit(self.limits.get().max_named_address_tag_length))
+            {
+                return Err(SetAddressBookResponse::TagTooLong {
+                    max_length: self.limits.get().max_named_address_tag_length,
+                });
+            }
+
+            if named_address.ledger_canister_id.is_some() && !is_icrc1 {
+                return Err(SetAddressBookResponse::LedgerCanisterIdNotSupported);
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_address_book(&self, address_book: &AddressBook) -> Result<(), SetAddressBookResponse> {
+        self.validate_address_book_count(address_book)?;
         Self::validate_address_book_unique_names(address_book)?;
         Self::validate_address_book_names_length(address_book)?;
         Self::validate_address_book_addresses(address_book)?;
+        Self::validate_address_book_details(address_book)?;
         Ok(())
     }
 
@@ -817,18 +2135,28 @@ impl AccountsStore {
             named_addresses: address_book
                 .named_addresses
                 .into_iter()
-                .map(|entry| NamedAddress {
-                    name: Self::normalize_name(&entry.name),
-                    address: entry.address,
-                })
+                .map(Self::normalize_named_address)
                 .collect(),
+            version: address_book.version,
         }
     }
 
-    pub fn set_address_book(&mut self, caller: PrincipalId, new_address_book: AddressBook) -> SetAddressBookResponse {
-        let normalized = Self::normalize_address_book(new_address_book);
+    fn normalize_named_address(named_address: NamedAddress) -> NamedAddress {
+        NamedAddress {
+            name: Self::normalize_name(&named_address.name),
+            ..named_address
+        }
+    }
 
-        if let Err(error_response) = Self::validate_address_book(&normalized) {
+    pub fn set_address_book(
+        &mut self,
+        caller: PrincipalId,
+        new_address_book: AddressBook,
+        expected_version: Option<u64>,
+    ) -> SetAddressBookResponse {
+        let mut normalized = Self::normalize_address_book(new_address_book);
+
+        if let Err(error_response) = self.validate_address_book(&normalized) {
             return error_response;
         }
 
@@ -837,12 +2165,97 @@ impl AccountsStore {
             return SetAddressBookResponse::AccountNotFound;
         };
 
+        if let Err(current_version) = advance_version(account.address_book.as_ref(), &mut normalized, expected_version)
+        {
+            return SetAddressBookResponse::VersionConflict { current_version };
+        }
+
         account.address_book = Some(normalized);
 
         self.accounts_db.insert(account_identifier, account);
         SetAddressBookResponse::Ok
     }
 
+    /// Applies a change to the caller's address book, if nobody else changed it since `expected_version`.
+    ///
+    /// The resulting address book is validated in the same way as in `set_address_book`.
+    fn update_address_book(
+        &mut self,
+        caller: PrincipalId,
+        expected_version: u64,
+        update: impl FnOnce(&mut Vec<NamedAddress>) -> Result<(), SetAddressBookResponse>,
+    ) -> SetAddressBookResponse {
+        let account_identifier = AccountIdentifier::from(caller).to_vec();
+        let Some(mut account) = self.accounts_db.get(&account_identifier) else {
+            return SetAddressBookResponse::AccountNotFound;
+        };
+        let mut address_book = account.address_book.clone().unwrap_or_default();
+        if let Err(current_version) =
+            advance_version(account.address_book.as_ref(), &mut address_book, Some(expected_version))
+        {
+            return SetAddressBookResponse::VersionConflict { current_version };
+        }
+
+        if let Err(error_response) = update(&mut address_book.named_addresses) {
+            return error_response;
+        }
+        if let Err(error_response) = self.validate_address_book(&address_book) {
+            return error_response;
+        }
+
+        account.address_book = Some(address_book);
+
+        self.accounts_db.insert(account_identifier, account);
+        SetAddressBookResponse::Ok
+    }
+
+    /// Adds an entry to the caller's address book.
+    pub fn add_named_address(
+        &mut self,
+        caller: PrincipalId,
+        request: AddNamedAddressRequest,
+    ) -> SetAddressBookResponse {
+        let named_address = Self::normalize_named_address(request.named_address);
+        self.update_address_book(caller, request.expected_version, |named_addresses| {
+            named_addresses.push(named_address);
+            Ok(())
+        })
+    }
+
+    /// Replaces the entry with the given name in the caller's address book.
+    pub fn update_named_address(
+        &mut self,
+        caller: PrincipalId,
+        request: UpdateNamedAddressRequest,
+    ) -> SetAddressBookResponse {
+        let name = Self::normalize_name(&request.name);
+        let named_address = Self::normalize_named_address(request.named_address);
+        self.update_address_book(caller, request.expected_version, |named_addresses| {
+            let Some(entry) = named_addresses.iter_mut().find(|entry| entry.name == name) else {
+                return Err(SetAddressBookResponse::NamedAddressNotFound { name });
+            };
+            *entry = named_address;
+            Ok(())
+        })
+    }
+
+    /// Removes the entry with the given name from the caller's address book.
+    #[allow(clippy::needless_pass_by_value)] // The pattern here is to pass a request by value.
+    pub fn remove_named_address(
+        &mut self,
+        caller: PrincipalId,
+        request: RemoveNamedAddressRequest,
+    ) -> SetAddressBookResponse {
+        let name = Self::normalize_name(&request.name);
+        self.update_address_book(caller, request.expected_version, |named_addresses| {
+            let Some(index) = named_addresses.iter().position(|entry| entry.name == name) else {
+                return Err(SetAddressBookResponse::NamedAddressNotFound { name });
+            };
+            named_addresses.remove(index);
+            Ok(())
+        })
+    }
+
     pub fn get_address_book(&self, caller: PrincipalId) -> GetAddressBookResponse {
         let account_identifier = AccountIdentifier::from(caller).to_vec();
         let Some(account) = self.accounts_db.get(&account_identifier) else {
@@ -852,11 +2265,451 @@ impl AccountsStore {
         GetAddressBookResponse::Ok(account.address_book.unwrap_or_default())
     }
 
+    /// Returns all the data stored for the caller as a single bundle.
+    #[must_use]
+    pub fn export_account_data(&self, caller: PrincipalId) -> ExportAccountDataResponse {
+        let account_identifier = AccountIdentifier::from(caller).to_vec();
+        let Some(account) = self.accounts_db.get(&account_identifier) else {
+            return ExportAccountDataResponse::AccountNotFound;
+        };
+
+        let sub_accounts = account
+            .sub_accounts
+            .iter()
+            .sorted_unstable_by_key(|(id, _)| **id)
+            .map(|(id, sub_account)| ExportedSubAccount {
+                name: sub_account.name.clone(),
+                sub_account: convert_byte_to_sub_account(*id),
+                archived: sub_account.archived,
+                display_order: sub_account.display_order,
+                style_key: sub_account.style_key.clone(),
+            })
+            .collect();
+        let hardware_wallet_accounts = account
+            .hardware_wallet_accounts
+            .iter()
+            .map(|hw| ExportedHardwareWalletAccount {
+                name: hw.name.clone(),
+                principal: hw.principal,
+                device: hw.device.clone(),
+            })
+            .collect();
+
+        ExportAccountDataResponse::Ok(AccountDataBundle {
+            version: ACCOUNT_DATA_BUNDLE_VERSION,
+            sub_accounts,
+            hardware_wallet_accounts,
+            canisters: account.canisters,
+            imported_tokens: account.imported_tokens.unwrap_or_default(),
+            fav_projects: account.fav_projects.unwrap_or_default(),
+            address_book: account.address_book.unwrap_or_default(),
+            named_icrc1_subaccounts: account.named_icrc1_subaccounts,
+        })
+    }
+
+    /// Replaces the data stored for the caller with the contents of a bundle
+    /// created by `export_account_data`, possibly for a different principal.
+    ///
+    /// The bundle is validated as a whole; if any part is invalid nothing is changed.
+    pub fn import_account_data(&mut self, caller: PrincipalId, bundle: AccountDataBundle) -> ImportAccountDataResponse {
+        if bundle.version != ACCOUNT_DATA_BUNDLE_VERSION {
+            return ImportAccountDataResponse::UnsupportedVersion {
+                version: bundle.version,
+            };
+        }
+
+        let mut sub_accounts = HashMap::new();
+        for exported in bundle.sub_accounts {
+            if !self.validate_account_name(&exported.name) {
+                return ImportAccountDataResponse::NameTooLong;
+            }
+            let Some(sub_account_id) =
+                convert_sub_account_to_byte(&exported.sub_account, self.limits.get().max_sub_account_id)
+            else {
+                return ImportAccountDataResponse::InvalidSubAccount;
+            };
+            let sub_account_identifier = AccountIdentifier::new(caller, Some(exported.sub_account));
+            if exported.style_key.as_ref().is_some_and(|style_key| {
+                style_key.len() > len_limit(self.limits.get().max_sub_account_style_key_length)
+            }) {
+                return ImportAccountDataResponse::InvalidSubAccount;
+            }
+            let named_sub_account = NamedSubAccount {
+                archived: exported.archived,
+                display_order: exported.display_order,
+                style_key: exported.style_key,
+                ..NamedSubAccount::new(exported.name, sub_account_identifier)
+            };
+            if sub_accounts.insert(sub_account_id, named_sub_account).is_some() {
+                return ImportAccountDataResponse::DuplicateSubAccount;
+            }
+        }
+
+        if bundle.hardware_wallet_accounts.len() > (u8::MAX as usize) {
+            return ImportAccountDataResponse::HardwareWalletLimitExceeded;
+        }
+        let mut hardware_wallet_accounts: Vec<NamedHardwareWalletAccount> = Vec::new();
+        for exported in bundle.hardware_wallet_accounts {
+            if !self.validate_account_name(&exported.name) {
+                return ImportAccountDataResponse::NameTooLong;
+            }
+            if hardware_wallet_accounts
+                .iter()
+                .any(|hw| hw.principal == exported.principal)
+            {
+                return ImportAccountDataResponse::DuplicateHardwareWallet;
+            }
+            if let Err(max_length) = self.validate_hardware_wallet_device(exported.device.as_ref()) {
+                return ImportAccountDataResponse::InvalidHardwareWalletDevice {
+                    error: format!("model and app version may be at most {max_length} bytes long"),
+                };
+            }
+            hardware_wallet_accounts.push(NamedHardwareWalletAccount {
+                name: exported.name,
+                principal: exported.principal,
+                device: exported.device,
+            });
+        }
+        hardware_wallet_accounts.sort_unstable_by_key(|hw| hw.name.clone());
+
+        if bundle.canisters.len() > (u8::MAX as usize) {
+            return ImportAccountDataResponse::CanisterLimitExceeded;
+        }
+        let mut canisters = bundle.canisters;
+        if canisters
+            .iter()
+            .any(|canister| !self.validate_canister_name(&canister.name))
+        {
+            return ImportAccountDataResponse::NameTooLong;
+        }
+        for canister in &canisters {
+            if let Err(error) = self.validate_canister_metadata(canister.tags.as_deref(), canister.note.as_deref()) {
+                return ImportAccountDataResponse::InvalidCanisterMetadata {
+                    error: format!("{error:?}"),
+                };
+            }
+        }
+        if !canisters.iter().map(|canister| canister.canister_id).all_unique()
+            || !canisters
+                .iter()
+                .filter(|canister| !canister.name.is_empty())
+                .map(|canister| &canister.name)
+                .all_unique()
+        {
+            return ImportAccountDataResponse::DuplicateCanister;
+        }
+        canisters.sort();
+
+        if bundle.imported_tokens.imported_tokens.len() > len_limit(self.limits.get().max_imported_tokens) {
+            return ImportAccountDataResponse::TooManyImportedTokens {
+                limit: self.limits.get().max_imported_tokens,
+            };
+        }
+        if bundle.fav_projects.fav_projects.len() > len_limit(self.limits.get().max_favorite_projects) {
+            return ImportAccountDataResponse::TooManyFavProjects {
+                limit: self.limits.get().max_favorite_projects,
+            };
+        }
+        let mut address_book = Self::normalize_address_book(bundle.address_book);
+        if let Err(error) = self.validate_address_book(&address_book) {
+            return ImportAccountDataResponse::InvalidAddressBook {
+                error: format!("{error:?}"),
+            };
+        }
+
+        let named_icrc1_subaccounts = bundle.named_icrc1_subaccounts.unwrap_or_default();
+        if let Err(error) = self.validate_named_icrc1_subaccounts(&named_icrc1_subaccounts) {
+            return ImportAccountDataResponse::InvalidNamedIcrc1Subaccounts {
+                error: format!("{error:?}"),
+            };
+        }
+
+        let account_identifier = AccountIdentifier::from(caller).to_vec();
+        let Some(mut account) = self.accounts_db.get(&account_identifier) else {
+            return ImportAccountDataResponse::AccountNotFound;
+        };
+
+        self.accounts_db_stats.update(|stats| {
+            stats.sub_accounts_count = stats
+                .sub_accounts_count
+                .saturating_sub(account.sub_accounts.len() as u64)
+                + sub_accounts.len() as u64;
+            stats.hardware_wallet_accounts_count = stats
+                .hardware_wallet_accounts_count
+                .saturating_sub(account.hardware_wallet_accounts.len() as u64)
+                + hardware_wallet_accounts.len() as u64;
+        });
+
+        for hardware_wallet in &account.hardware_wallet_accounts {
+            self.hardware_wallet_index
+                .remove(hardware_wallet.principal, &account_identifier);
+        }
+        for hardware_wallet in &hardware_wallet_accounts {
+            self.hardware_wallet_index
+                .insert(hardware_wallet.principal, &account_identifier);
+        }
+        for canister in &account.canisters {
+            self.canister_index.remove(canister.canister_id, &account_identifier);
+        }
+        for canister in &canisters {
+            self.canister_index.insert(canister.canister_id, &account_identifier);
+        }
+
+        account.sub_accounts = sub_accounts;
+        account.hardware_wallet_accounts = hardware_wallet_accounts;
+        account.canisters = canisters;
+        // Continue the versions of the replaced settings, so that concurrent setters notice the change.
+        let mut imported_tokens = bundle.imported_tokens;
+        let mut fav_projects = bundle.fav_projects;
+        // Without an expected version, advancing the version can't fail.
+        let _ = advance_version(account.imported_tokens.as_ref(), &mut imported_tokens, None);
+        let _ = advance_version(account.fav_projects.as_ref(), &mut fav_projects, None);
+        let _ = advance_version(account.address_book.as_ref(), &mut address_book, None);
+        account.imported_tokens = Some(imported_tokens);
+        account.fav_projects = Some(fav_projects);
+        account.address_book = Some(address_book);
+        account.named_icrc1_subaccounts = Some(named_icrc1_subaccounts);
+
+        self.certify_canisters(&account_identifier, &account.canisters);
+        self.accounts_db.insert(account_identifier, account);
+        ImportAccountDataResponse::Ok
+    }
+
+    /// Offers the caller's account to another principal.
+    ///
+    /// The transfer takes effect only when the target principal claims it with
+    /// `claim_account_transfer`, before the offer expires.  A new offer replaces any previous offer.
+    #[allow(clippy::needless_pass_by_value)] // The pattern here is to pass a request by value.
+    pub fn create_account_transfer(
+        &mut self,
+        caller: PrincipalId,
+        request: CreateAccountTransferRequest,
+        now_nanos: u64,
+    ) -> CreateAccountTransferResponse {
+        if caller == request.to {
+            return CreateAccountTransferResponse::CannotTransferToSelf;
+        }
+        if !self.accounts_db.contains_key(&AccountIdentifier::from(caller).to_vec()) {
+            return CreateAccountTransferResponse::AccountNotFound;
+        }
+        self.remove_expired_account_transfers(now_nanos);
+
+        let expires_at_timestamp_nanos = now_nanos.saturating_add(ACCOUNT_TRANSFER_EXPIRY_NANOS);
+        self.pending_account_transfers.insert(
+            caller,
+            PendingAccountTransfer {
+                to: request.to,
+                expires_at_timestamp_nanos,
+            },
+        );
+        CreateAccountTransferResponse::Ok {
+            expires_at_timestamp_nanos,
+        }
+    }
+
+    /// Withdraws the caller's pending account transfer offer, if any.
+    pub fn cancel_account_transfer(&mut self, caller: PrincipalId) -> CancelAccountTransferResponse {
+        match self.pending_account_transfers.remove(&caller) {
+            Some(_) => CancelAccountTransferResponse::Ok,
+            None => CancelAccountTransferResponse::TransferNotFound,
+        }
+    }
+
+    /// Moves an account offered to the caller to the caller's principal.
+    ///
+    /// The caller may already have an account, as long as it is empty, as the browser creates an
+    /// account for every principal that logs in.
+    #[allow(clippy::needless_pass_by_value)] // The pattern here is to pass a request by value.
+    pub fn claim_account_transfer(
+        &mut self,
+        caller: PrincipalId,
+        request: ClaimAccountTransferRequest,
+        now_nanos: u64,
+    ) -> ClaimAccountTransferResponse {
+        let Some(expires_at_timestamp_nanos) = self
+            .pending_account_transfers
+            .get(&request.from)
+            .filter(|transfer| transfer.to == caller)
+            .map(|transfer| transfer.expires_at_timestamp_nanos)
+        else {
+            return ClaimAccountTransferResponse::TransferNotFound;
+        };
+        if expires_at_timestamp_nanos <= now_nanos {
+            self.pending_account_transfers.remove(&request.from);
+            return ClaimAccountTransferResponse::TransferExpired;
+        }
+
+        let source_account_identifier = AccountIdentifier::from(request.from).to_vec();
+        let Some(mut account) = self.accounts_db.get(&source_account_identifier) else {
+            self.pending_account_transfers.remove(&request.from);
+            return ClaimAccountTransferResponse::AccountNotFound;
+        };
+
+        let target_account_identifier = AccountIdentifier::from(caller);
+        let target_account = self.accounts_db.get(&target_account_identifier.to_vec());
+        if target_account
+            .as_ref()
+            .is_some_and(|target_account| !target_account.is_empty())
+        {
+            return ClaimAccountTransferResponse::TargetAccountNotEmpty;
+        }
+
+        // Both the transferred account and the empty account it replaces, if any, may predate principals.
+        if account.principal.is_none() {
+            self.principal_less_accounts_scan.forget(&source_account_identifier);
+        }
+        if target_account.is_some_and(|target_account| target_account.principal.is_none()) {
+            self.principal_less_accounts_scan
+                .forget(&target_account_identifier.to_vec());
+        }
+        account.principal = Some(caller);
+        account.account_identifier = target_account_identifier;
+        for (id, sub_account) in &mut account.sub_accounts {
+            sub_account.account_identifier = AccountIdentifier::new(caller, Some(convert_byte_to_sub_account(*id)));
+        }
+
+        self.certify_canisters(&source_account_identifier, &[]);
+        self.certify_canisters(&target_account_identifier.to_vec(), &account.canisters);
+        for hardware_wallet in &account.hardware_wallet_accounts {
+            self.hardware_wallet_index
+                .remove(hardware_wallet.principal, &source_account_identifier);
+            self.hardware_wallet_index
+                .insert(hardware_wallet.principal, &target_account_identifier.to_vec());
+        }
+        for canister in &account.canisters {
+            self.canister_index
+                .remove(canister.canister_id, &source_account_identifier);
+            self.canister_index
+                .insert(canister.canister_id, &target_account_identifier.to_vec());
+        }
+        self.accounts_db.remove(&source_account_identifier);
+        self.accounts_db.insert(target_account_identifier.to_vec(), account);
+        self.pending_account_transfers.remove(&request.from);
+        ClaimAccountTransferResponse::Ok
+    }
+
+    fn remove_expired_account_transfers(&mut self, now_nanos: u64) {
+        self.pending_account_transfers
+            .retain(|_, transfer| transfer.expires_at_timestamp_nanos > now_nanos);
+    }
+
+    /// The number of accounts that have yet to be moved to the map keyed by fixed-size account identifiers.
+    #[must_use]
+    pub fn migration_countdown(&self) -> u64 {
+        self.accounts_db.migration_countdown()
+    }
+
+    /// Moves up to `batch_size` accounts to the map keyed by fixed-size account identifiers.
+    ///
+    /// Returns whether the migration is complete.
+    pub fn step_migration(&mut self, batch_size: u64) -> bool {
+        self.accounts_db.step_migration(batch_size)
+    }
+
+    /// Whether all data derived from the accounts has been computed for every account, and the certified canister
+    /// hashes have been read back into the certification tree after an upgrade.
+    #[must_use]
+    pub fn backfills_complete(&self) -> bool {
+        self.canisters_hashes.is_loaded() && self.backfills.get().is_complete()
+    }
+
+    /// Computes the derived data of the first incomplete backfill for up to `batch_size` accounts, continuing from
+    /// where the previous step stopped.
+    ///
+    /// After an upgrade, the certified canister hashes are first read back into the certification tree, up to
+    /// `batch_size` per step.
+    ///
+    /// Note: This may change certified data.  Call `certification::update_root_hash` afterwards.
+    ///
+    /// Returns whether all backfills are complete.
+    pub fn step_backfills(&mut self, batch_size: u64) -> bool {
+        if !self.canisters_hashes.is_loaded() {
+            self.canisters_hashes.load_step(batch_size);
+            return self.backfills_complete();
+        }
+        let Some((backfill, cursor)) = self.backfills.get().next_pending() else {
+            return true;
+        };
+        let batch: Vec<(Vec<u8>, Account)> = self
+            .accounts_db
+            .iter_after(cursor.as_deref())
+            .take(usize::try_from(batch_size).unwrap_or(usize::MAX))
+            .collect();
+        for (account_identifier, account) in &batch {
+            match backfill {
+                Backfill::CanistersHashes => self.certify_canisters(account_identifier, &account.canisters),
+                Backfill::HardwareWalletIndex => {
+                    self.index_hardware_wallets(account_identifier, &account.hardware_wallet_accounts);
+                }
+                Backfill::CanisterIndex => self.index_canisters(account_identifier, &account.canisters),
+            }
+        }
+        let progress = if (batch.len() as u64) < batch_size {
+            BackfillProgress::Complete
+        } else {
+            BackfillProgress::InProgress {
+                cursor: batch.last().map(|(account_identifier, _)| account_identifier.clone()),
+            }
+        };
+        self.backfills
+            .update(|backfills| backfills.set_progress(backfill, progress));
+        self.backfills.get().is_complete()
+    }
+
+    /// Counts the accounts without a principal in up to `batch_size` accounts, continuing from where the previous step
+    /// stopped.
+    ///
+    /// Returns whether the scan is complete.
+    pub fn step_principal_less_accounts_scan(&mut self, batch_size: u64) -> bool {
+        let scan = &mut self.principal_less_accounts_scan;
+        if scan.count.is_some() {
+            return true;
+        }
+        let mut batch: Vec<(Vec<u8>, bool)> = self
+            .accounts_db
+            .iter_after(scan.cursor.as_deref())
+            .take(usize::try_from(batch_size).unwrap_or(usize::MAX))
+            .map(|(account_identifier, account)| (account_identifier, account.principal.is_none()))
+            .collect();
+        scan.count_so_far += batch.iter().filter(|(_, is_principal_less)| *is_principal_less).count() as u64;
+        if (batch.len() as u64) < batch_size {
+            scan.count = Some(scan.count_so_far);
+            scan.cursor = None;
+        } else {
+            scan.cursor = batch.pop().map(|(account_identifier, _)| account_identifier);
+        }
+        scan.count.is_some()
+    }
+
     pub fn get_stats(&self, stats: &mut Stats) {
         stats.accounts_count = self.accounts_db.len();
-        stats.sub_accounts_count = self.accounts_db_stats.sub_accounts_count;
-        stats.hardware_wallet_accounts_count = self.accounts_db_stats.hardware_wallet_accounts_count;
-        stats.migration_countdown = Some(0);
+        stats.sub_accounts_count = self.accounts_db_stats.get().sub_accounts_count;
+        stats.hardware_wallet_accounts_count = self.accounts_db_stats.get().hardware_wallet_accounts_count;
+        stats.migration_countdown = Some(u32::try_from(self.accounts_db.migration_countdown()).unwrap_or(u32::MAX));
+        stats.malformed_accounts_count = Some(self.accounts_db.malformed_accounts_count());
+        stats.principal_less_accounts_count = self.principal_less_accounts_scan.count;
+        let accounts_remaining = self.limits.get().account_limit.saturating_sub(stats.accounts_count);
+        stats.account_limit = Some(self.limits.get().account_limit);
+        stats.accounts_remaining = Some(accounts_remaining);
+        stats.account_limit_near = Some(
+            accounts_remaining.saturating_mul(100)
+                < self
+                    .limits
+                    .get()
+                    .account_limit
+                    .saturating_mul(ACCOUNT_LIMIT_NEAR_PERCENT),
+        );
+    }
+
+    /// The limits in force.
+    #[must_use]
+    pub fn limits(&self) -> &Limits {
+        self.limits.get()
+    }
+
+    /// Sets the limits, e.g. from the canister arguments.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits.set(limits);
     }
 
     #[must_use]
@@ -868,29 +2721,47 @@ impl AccountsStore {
             })
     }
 
-    fn validate_account_name(name: &str) -> bool {
-        const ACCOUNT_NAME_MAX_LENGTH: usize = 24;
-
-        name.len() <= ACCOUNT_NAME_MAX_LENGTH
+    fn validate_account_name(&self, name: &str) -> bool {
+        name.len() <= len_limit(self.limits.get().max_account_name_length)
     }
 
-    fn validate_canister_name(name: &str) -> bool {
-        const CANISTER_NAME_MAX_LENGTH: usize = 24;
-
-        name.len() <= CANISTER_NAME_MAX_LENGTH
+    fn validate_canister_name(&self, name: &str) -> bool {
+        name.len() <= len_limit(self.limits.get().max_canister_name_length)
     }
 
-    fn assert_account_limit(&self) {
-        let db_accounts_len = self.accounts_db.len();
-        assert!(
-            db_accounts_len < ACCOUNT_LIMIT,
-            "Pre migration account limit exceeded {db_accounts_len}"
-        );
+    fn validate_canister_metadata(
+        &self,
+        tags: Option<&[String]>,
+        note: Option<&str>,
+    ) -> Result<(), UpdateCanisterMetadataResponse> {
+        let tags = tags.unwrap_or_default();
+        if tags.len() > len_limit(self.limits.get().max_canister_tags) {
+            return Err(UpdateCanisterMetadataResponse::TooManyTags {
+                limit: self.limits.get().max_canister_tags,
+            });
+        }
+        if tags
+            .iter()
+            .any(|tag| tag.len() > len_limit(self.limits.get().max_canister_tag_length))
+        {
+            return Err(UpdateCanisterMetadataResponse::TagTooLong {
+                max_length: self.limits.get().max_canister_tag_length,
+            });
+        }
+        if note.is_some_and(|note| note.len() > len_limit(self.limits.get().max_canister_note_length)) {
+            return Err(UpdateCanisterMetadataResponse::NoteTooLong {
+                max_length: self.limits.get().max_canister_note_length,
+            });
+        }
+        Ok(())
     }
 }
 
 impl StableState for AccountsStore {
+    #[cfg(test)]
     fn encode(&self) -> Vec<u8> {
+        use on_wire::IntoWire;
+        use std::collections::{BTreeMap, VecDeque};
         // Accounts are now in stable structures and no longer in a simple map
         // on the heap. So we don't need to encode them here.
         let empty_accounts = BTreeMap::<Vec<u8>, candid::Empty>::new();
@@ -920,7 +2791,7 @@ impl StableState for AccountsStore {
             // neurons_topped_up_count is unused but we need to encode
             // it for backwards compatibility.
             0u64,
-            Some(&self.accounts_db_stats),
+            Some(self.accounts_db_stats.get()),
         ))
         .into_bytes()
         .unwrap()
@@ -959,13 +2830,11 @@ impl StableState for AccountsStore {
         let Some(accounts_db_stats) = accounts_db_stats_maybe else {
             return Err("Accounts DB stats should be present since the stable structures migration.".to_string());
         };
-        let accounts_partition = with_partitions(|partitions| partitions.get(PartitionType::Accounts.memory_id()));
-        let accounts_db = StableBTreeMap::load(accounts_partition);
 
-        Ok(AccountsStore {
-            accounts_db,
-            accounts_db_stats,
-        })
+        // The heap data was written by an older build; move it into its stable structures.
+        let mut accounts_store = AccountsStore::load();
+        accounts_store.accounts_db_stats.set(accounts_db_stats);
+        Ok(accounts_store)
     }
 }
 
@@ -981,8 +2850,23 @@ impl Account {
             imported_tokens: None,
             fav_projects: None,
             address_book: None,
+            named_icrc1_subaccounts: None,
         }
     }
+
+    /// Whether the account holds no data other than its owner.
+    fn is_empty(&self) -> bool {
+        self.sub_accounts.is_empty()
+            && self.hardware_wallet_accounts.is_empty()
+            && self.canisters.is_empty()
+            && self
+                .imported_tokens
+                .as_ref()
+                .is_none_or(|x| x.imported_tokens.is_empty())
+            && self.fav_projects.as_ref().is_none_or(|x| x.fav_projects.is_empty())
+            && self.address_book.as_ref().is_none_or(|x| x.named_addresses.is_empty())
+            && self.named_icrc1_subaccounts.as_ref().is_none_or(Vec::is_empty)
+    }
 }
 
 impl NamedSubAccount {
@@ -990,6 +2874,9 @@ impl NamedSubAccount {
         NamedSubAccount {
             name,
             account_identifier,
+            archived: None,
+            display_order: None,
+            style_key: None,
         }
     }
 }
@@ -1000,6 +2887,12 @@ fn convert_byte_to_sub_account(byte: u8) -> Subaccount {
     Subaccount(bytes)
 }
 
+/// The inverse of `convert_byte_to_sub_account`, for sub-account ids that may be assigned by `create_sub_account`.
+fn convert_sub_account_to_byte(sub_account: &Subaccount, max_sub_account_id: u8) -> Option<u8> {
+    let (byte, prefix) = sub_account.0.split_last()?;
+    (prefix.iter().all(|b| *b == 0) && (1..=max_sub_account_id).contains(byte)).then_some(*byte)
+}
+
 #[cfg(test)]
 pub(crate) mod tests;
 #[cfg(any(test, feature = "toy_data_gen"))]
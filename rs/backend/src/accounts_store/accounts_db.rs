@@ -0,0 +1,242 @@
+//! The accounts in stable memory, keyed by account identifier.
+//!
+//! Accounts used to be keyed by the account identifier as a variable-length byte vector.  They are now keyed by a
+//! fixed-size [`AccountKey`], in a separate stable memory partition.  Accounts are moved from the legacy map to the new
+//! map in bounded batches, driven by a timer, so that no single message has to move every account.  While the
+//! migration is in progress, every account is in exactly one of the two maps and reads consult both.
+//!
+//! Legacy accounts whose key is not a valid account identifier cannot be moved to the new map, and could never be read
+//! or written.  They are moved to a quarantine map of their own instead of being dropped, so that they can be
+//! inspected and, if need be, recovered.
+use super::Account;
+use ic_stable_structures::{
+    memory_manager::VirtualMemory, storable::Bound, DefaultMemoryImpl, StableBTreeMap, Storable,
+};
+use itertools::Itertools;
+use std::borrow::Cow;
+use std::ops::Bound::{self, Excluded, Included, Unbounded};
+
+#[cfg(test)]
+mod tests;
+
+/// The number of accounts moved to the new map in one migration step.
+pub const MIGRATION_BATCH_SIZE: u64 = 1_000;
+
+/// The key of an account: the 32 bytes of its account identifier, including the checksum.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct AccountKey([u8; AccountKey::LEN]);
+
+impl AccountKey {
+    /// The length of an account identifier, in bytes.
+    pub const LEN: usize = 32;
+
+    /// Creates a key from the bytes of an account identifier, if they have the right length.
+    #[must_use]
+    pub fn from_slice(account_identifier: &[u8]) -> Option<Self> {
+        account_identifier.try_into().ok().map(Self)
+    }
+
+    /// The lower bound of the keys after the given bytes, which need not have the length of an account identifier.
+    ///
+    /// - Shorter bytes are a prefix of the smallest key after them: the bytes padded with zeros.
+    /// - Longer bytes are after their own prefix of the length of a key, but before any larger key.
+    fn bound_after(bytes: &[u8]) -> Bound<Self> {
+        let mut key = [0u8; AccountKey::LEN];
+        let len = bytes.len().min(AccountKey::LEN);
+        key[..len].copy_from_slice(&bytes[..len]);
+        if bytes.len() < AccountKey::LEN {
+            Included(Self(key))
+        } else {
+            Excluded(Self(key))
+        }
+    }
+}
+
+impl Storable for AccountKey {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: AccountKey::LEN as u32,
+        is_fixed_size: true,
+    };
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+    fn into_bytes(self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self::from_slice(&bytes).expect("Account keys have a fixed size")
+    }
+}
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+pub struct AccountsDb {
+    /// The accounts, keyed by account identifier.
+    accounts: StableBTreeMap<AccountKey, Account, Memory>,
+    /// Accounts that have yet to be moved to `accounts`, keyed by the bytes of the account identifier.
+    ///
+    /// This is `None` once the migration is complete.
+    legacy_accounts: Option<StableBTreeMap<Vec<u8>, Account, Memory>>,
+    /// Legacy accounts whose key does not have the length of an account identifier, keyed by that key.
+    malformed_accounts: StableBTreeMap<Vec<u8>, Account, Memory>,
+}
+
+impl AccountsDb {
+    /// Creates an empty accounts database.
+    #[must_use]
+    pub fn new(memory: Memory, malformed_memory: Memory) -> Self {
+        Self {
+            accounts: StableBTreeMap::new(memory),
+            legacy_accounts: None,
+            malformed_accounts: StableBTreeMap::new(malformed_memory),
+        }
+    }
+
+    /// Loads the accounts database, resuming the migration of any accounts still in the legacy map.
+    #[must_use]
+    pub fn load(memory: Memory, legacy_memory: Memory, malformed_memory: Memory) -> Self {
+        let legacy_accounts: StableBTreeMap<Vec<u8>, Account, Memory> = StableBTreeMap::init(legacy_memory);
+        Self {
+            accounts: StableBTreeMap::init(memory),
+            legacy_accounts: (!legacy_accounts.is_empty()).then_some(legacy_accounts),
+            malformed_accounts: StableBTreeMap::init(malformed_memory),
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self, account_identifier: &[u8]) -> Option<Account> {
+        let key = AccountKey::from_slice(account_identifier)?;
+        self.accounts.get(&key).or_else(|| {
+            self.legacy_accounts
+                .as_ref()
+                .and_then(|legacy_accounts| legacy_accounts.get(&account_identifier.to_vec()))
+        })
+    }
+
+    #[must_use]
+    pub fn contains_key(&self, account_identifier: &[u8]) -> bool {
+        self.get(account_identifier).is_some()
+    }
+
+    /// Inserts or replaces an account.
+    ///
+    /// # Panics
+    /// - If the account identifier does not have the length of an account identifier.
+    pub fn insert(&mut self, account_identifier: Vec<u8>, account: Account) -> Option<Account> {
+        let key = AccountKey::from_slice(&account_identifier)
+            .unwrap_or_else(|| panic!("Invalid account identifier length: {}", account_identifier.len()));
+        let legacy_account = self
+            .legacy_accounts
+            .as_mut()
+            .and_then(|legacy_accounts| legacy_accounts.remove(&account_identifier));
+        self.accounts.insert(key, account).or(legacy_account)
+    }
+
+    pub fn remove(&mut self, account_identifier: &[u8]) -> Option<Account> {
+        let key = AccountKey::from_slice(account_identifier)?;
+        let legacy_account = self
+            .legacy_accounts
+            .as_mut()
+            .and_then(|legacy_accounts| legacy_accounts.remove(&account_identifier.to_vec()));
+        self.accounts.remove(&key).or(legacy_account)
+    }
+
+    /// The number of accounts.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.accounts.len() + self.legacy_accounts.as_ref().map_or(0, StableBTreeMap::len)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// All accounts with their account identifiers, in order of account identifier.
+    pub fn iter(&self) -> impl Iterator<Item = (Vec<u8>, Account)> + '_ {
+        self.iter_after(None)
+    }
+
+    /// The accounts with account identifiers after `after`, or all accounts if `after` is `None`, in order of account
+    /// identifier.
+    ///
+    /// This allows the accounts to be visited in bounded chunks, across several messages.  `after` may be the key of an
+    /// account in the legacy map that does not have the length of an account identifier.
+    pub fn iter_after(&self, after: Option<&[u8]>) -> impl Iterator<Item = (Vec<u8>, Account)> + '_ {
+        let start = after.map_or(Unbounded, AccountKey::bound_after);
+        let accounts = self
+            .accounts
+            .range((start, Unbounded))
+            .map(|entry| entry.into_pair())
+            .map(|(key, account)| (key.0.to_vec(), account));
+        let legacy_start = after.map_or(Unbounded, |after| Excluded(after.to_vec()));
+        let legacy_accounts = self.legacy_accounts.iter().flat_map(move |legacy_accounts| {
+            legacy_accounts
+                .range((legacy_start.clone(), Unbounded))
+                .map(|entry| entry.into_pair())
+        });
+        accounts.merge_by(legacy_accounts, |(left, _), (right, _)| left <= right)
+    }
+
+    /// All accounts, in order of account identifier.
+    pub fn values(&self) -> impl Iterator<Item = Account> + '_ {
+        self.iter().map(|(_, account)| account)
+    }
+
+    /// The number of accounts that have yet to be migrated.
+    #[must_use]
+    pub fn migration_countdown(&self) -> u64 {
+        self.legacy_accounts.as_ref().map_or(0, StableBTreeMap::len)
+    }
+
+    /// The number of legacy accounts that could not be migrated because their key is not a valid account identifier.
+    #[must_use]
+    pub fn malformed_accounts_count(&self) -> u64 {
+        self.malformed_accounts.len()
+    }
+
+    /// Moves up to `batch_size` accounts from the legacy map to the new map, or to the quarantine map if their key is
+    /// not a valid account identifier.
+    ///
+    /// Returns whether the migration is complete.
+    pub fn step_migration(&mut self, batch_size: u64) -> bool {
+        let Some(legacy_accounts) = &mut self.legacy_accounts else {
+            return true;
+        };
+        let batch: Vec<(Vec<u8>, Account)> = legacy_accounts
+            .range(..)
+            .take(usize::try_from(batch_size).unwrap_or(usize::MAX))
+            .map(|entry| entry.into_pair())
+            .collect();
+        for (account_identifier, account) in batch {
+            legacy_accounts.remove(&account_identifier);
+            match AccountKey::from_slice(&account_identifier) {
+                Some(key) => {
+                    self.accounts.insert(key, account);
+                }
+                None => {
+                    ic_cdk::println!(
+                        "WARNING: Quarantining account with invalid identifier of length {}",
+                        account_identifier.len()
+                    );
+                    self.malformed_accounts.insert(account_identifier, account);
+                }
+            }
+        }
+        if legacy_accounts.is_empty() {
+            self.legacy_accounts = None;
+        }
+        self.legacy_accounts.is_none()
+    }
+
+    /// Moves all accounts into a legacy map in the given memory, as they were before the migration.
+    #[cfg(test)]
+    pub fn move_to_legacy_map(&mut self, legacy_memory: Memory) {
+        let mut legacy_accounts = StableBTreeMap::new(legacy_memory);
+        for (account_identifier, account) in self.iter().collect::<Vec<_>>() {
+            legacy_accounts.insert(account_identifier, account);
+        }
+        self.accounts.clear_new();
+        self.legacy_accounts = Some(legacy_accounts);
+    }
+}
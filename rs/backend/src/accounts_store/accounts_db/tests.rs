@@ -0,0 +1,187 @@
+use super::*;
+use crate::accounts_store::toy_data::{toy_account, ToyAccountSize};
+use crate::state::partitions::{PartitionType, Partitions};
+use icp_ledger::AccountIdentifier;
+use pretty_assertions::assert_eq;
+
+fn account_identifier(toy_account_index: u64) -> Vec<u8> {
+    toy_account(toy_account_index, ToyAccountSize::default())
+        .account_identifier
+        .to_vec()
+}
+
+/// An accounts database with the given toy accounts in the legacy map.
+fn accounts_db_with_legacy_accounts(partitions: &Partitions, toy_account_indices: &[u64]) -> AccountsDb {
+    let mut accounts_db = AccountsDb::new(
+        partitions.get(PartitionType::AccountsByIdentifier.memory_id()),
+        partitions.get(PartitionType::MalformedAccounts.memory_id()),
+    );
+    for &index in toy_account_indices {
+        accounts_db.insert(account_identifier(index), toy_account(index, ToyAccountSize::default()));
+    }
+    accounts_db.move_to_legacy_map(partitions.get(PartitionType::Accounts.memory_id()));
+    accounts_db
+}
+
+#[test]
+fn account_key_should_implement_storable() {
+    let key =
+        AccountKey::from_slice(&AccountIdentifier::from(ic_base_types::PrincipalId::new_user_test_id(1)).to_vec())
+            .unwrap();
+    let bytes = key.to_bytes();
+    assert_eq!(bytes.len(), AccountKey::LEN);
+    assert_eq!(AccountKey::from_bytes(bytes), key);
+}
+
+#[test]
+fn account_key_should_have_the_length_of_an_account_identifier() {
+    assert_eq!(AccountKey::from_slice(&[0u8; 31]), None);
+    assert_eq!(AccountKey::from_slice(&[0u8; 33]), None);
+    assert!(AccountKey::from_slice(&[0u8; 32]).is_some());
+}
+
+#[test]
+fn accounts_are_visible_throughout_the_migration() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    let mut accounts_db = accounts_db_with_legacy_accounts(&partitions, &[1, 2, 3, 4, 5]);
+    let before: Vec<_> = accounts_db.iter().collect();
+    assert_eq!(before.len(), 5);
+    assert_eq!(accounts_db.migration_countdown(), 5);
+
+    assert!(!accounts_db.step_migration(2));
+    assert_eq!(accounts_db.migration_countdown(), 3);
+    assert_eq!(accounts_db.len(), 5);
+    assert_eq!(accounts_db.iter().collect::<Vec<_>>(), before);
+    for (account_identifier, account) in &before {
+        assert_eq!(accounts_db.get(account_identifier).as_ref(), Some(account));
+    }
+
+    assert!(!accounts_db.step_migration(2));
+    assert!(accounts_db.step_migration(2));
+    assert_eq!(accounts_db.migration_countdown(), 0);
+    assert_eq!(accounts_db.iter().collect::<Vec<_>>(), before);
+}
+
+#[test]
+fn writes_during_the_migration_move_the_account() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    let mut accounts_db = accounts_db_with_legacy_accounts(&partitions, &[1, 2, 3]);
+
+    let updated_account = toy_account(
+        1,
+        ToyAccountSize {
+            sub_accounts: 1,
+            ..ToyAccountSize::default()
+        },
+    );
+    accounts_db.insert(account_identifier(1), updated_account.clone());
+    assert_eq!(accounts_db.migration_countdown(), 2);
+    assert_eq!(accounts_db.len(), 3);
+    assert_eq!(accounts_db.get(&account_identifier(1)), Some(updated_account));
+
+    assert!(accounts_db.remove(&account_identifier(2)).is_some());
+    assert!(!accounts_db.contains_key(&account_identifier(2)));
+    assert_eq!(accounts_db.migration_countdown(), 1);
+    assert_eq!(accounts_db.len(), 2);
+}
+
+#[test]
+fn loading_resumes_the_migration() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    let mut accounts_db = accounts_db_with_legacy_accounts(&partitions, &[1, 2, 3]);
+    accounts_db.step_migration(1);
+    let before: Vec<_> = accounts_db.iter().collect();
+
+    let loaded = AccountsDb::load(
+        partitions.get(PartitionType::AccountsByIdentifier.memory_id()),
+        partitions.get(PartitionType::Accounts.memory_id()),
+        partitions.get(PartitionType::MalformedAccounts.memory_id()),
+    );
+    assert_eq!(loaded.migration_countdown(), 2);
+    assert_eq!(loaded.iter().collect::<Vec<_>>(), before);
+}
+
+#[test]
+fn iter_after_continues_from_the_given_account_during_the_migration() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    let mut accounts_db = accounts_db_with_legacy_accounts(&partitions, &[1, 2, 3, 4, 5]);
+    accounts_db.step_migration(2);
+    let all: Vec<_> = accounts_db.iter().collect();
+
+    assert_eq!(accounts_db.iter_after(None).collect::<Vec<_>>(), all);
+    for (index, (account_identifier, _)) in all.iter().enumerate() {
+        assert_eq!(
+            accounts_db.iter_after(Some(account_identifier)).collect::<Vec<_>>(),
+            all[index + 1..].to_vec()
+        );
+    }
+}
+
+#[test]
+fn iter_after_a_malformed_account_identifier_continues_after_it() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    let mut accounts_db = accounts_db_with_legacy_accounts(&partitions, &[1, 2, 3, 4, 5]);
+    // Keys that are shorter or longer than an account identifier, next to the identifiers of accounts in either map.
+    let malformed_keys: Vec<Vec<u8>> = [1, 2, 3, 4, 5]
+        .into_iter()
+        .flat_map(|index| {
+            let account_identifier = account_identifier(index);
+            [
+                account_identifier[..AccountKey::LEN - 1].to_vec(),
+                [account_identifier.as_slice(), &[0]].concat(),
+            ]
+        })
+        .collect();
+    for malformed_key in &malformed_keys {
+        accounts_db
+            .legacy_accounts
+            .as_mut()
+            .unwrap()
+            .insert(malformed_key.clone(), toy_account(6, ToyAccountSize::default()));
+    }
+    accounts_db.step_migration(4);
+    let all: Vec<_> = accounts_db.iter().collect();
+    assert!(all.iter().any(|(key, _)| key.len() != AccountKey::LEN));
+
+    let cursors = malformed_keys
+        .iter()
+        .cloned()
+        .chain([vec![], vec![0xff; AccountKey::LEN + 1]])
+        .chain(all.iter().map(|(key, _)| key.clone()));
+    for cursor in cursors {
+        let expected: Vec<_> = all.iter().filter(|(key, _)| *key > cursor).cloned().collect();
+        assert_eq!(
+            accounts_db.iter_after(Some(&cursor)).collect::<Vec<_>>(),
+            expected,
+            "Accounts after {cursor:?}"
+        );
+    }
+}
+
+#[test]
+fn accounts_with_malformed_keys_are_quarantined_by_the_migration() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    let mut accounts_db = accounts_db_with_legacy_accounts(&partitions, &[1, 2]);
+    let malformed_key = vec![7u8; AccountKey::LEN - 1];
+    let malformed_account = toy_account(3, ToyAccountSize::default());
+    accounts_db
+        .legacy_accounts
+        .as_mut()
+        .unwrap()
+        .insert(malformed_key.clone(), malformed_account.clone());
+
+    while !accounts_db.step_migration(1) {}
+
+    assert_eq!(accounts_db.migration_countdown(), 0);
+    assert_eq!(accounts_db.len(), 2);
+    assert_eq!(accounts_db.malformed_accounts_count(), 1);
+    assert_eq!(accounts_db.get(&malformed_key), None);
+
+    let loaded = AccountsDb::load(
+        partitions.get(PartitionType::AccountsByIdentifier.memory_id()),
+        partitions.get(PartitionType::Accounts.memory_id()),
+        partitions.get(PartitionType::MalformedAccounts.memory_id()),
+    );
+    assert_eq!(loaded.malformed_accounts_count(), 1);
+    assert_eq!(loaded.malformed_accounts.get(&malformed_key), Some(malformed_account));
+}
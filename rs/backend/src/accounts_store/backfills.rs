@@ -0,0 +1,126 @@
+//! Backfills of data derived from the accounts.
+//!
+//! Data derived from the accounts, such as the certified canister hashes, is updated whenever an account changes.
+//! When a new kind of derived data is introduced, it must also be computed for the accounts that already exist.  That
+//! is done in bounded batches, driven by a timer, so that no single message, and in particular not `post_upgrade`,
+//! has to visit every account.  The progress is kept in stable memory, so a backfill resumes after an upgrade and is
+//! never repeated once complete.
+//!
+//! Derived data is updated for every account that changes, including accounts that the backfill has not reached yet,
+//! so backfilling an account must be idempotent.
+use candid::CandidType;
+use ic_stable_structures::{storable::Bound, Storable};
+use serde::Deserialize;
+use std::borrow::Cow;
+
+#[cfg(test)]
+mod tests;
+
+/// The number of accounts visited in one backfill step.
+pub const BACKFILL_BATCH_SIZE: u64 = 1_000;
+
+/// Data derived from the accounts that may need to be backfilled.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Backfill {
+    /// The certified hashes of the canisters of every account.
+    CanistersHashes,
+    /// The index from hardware wallet principals to the accounts that have registered them.
+    HardwareWalletIndex,
+    /// The index from canister ids to the accounts that have attached them.
+    CanisterIndex,
+}
+
+impl Backfill {
+    /// All backfills, in the order in which they are run.
+    pub const ALL: [Backfill; 3] = [
+        Backfill::CanistersHashes,
+        Backfill::HardwareWalletIndex,
+        Backfill::CanisterIndex,
+    ];
+}
+
+/// The progress of a backfill.
+#[derive(CandidType, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub enum BackfillProgress {
+    /// The accounts up to and including the cursor have been visited.  No account has been visited if there is no
+    /// cursor.
+    InProgress {
+        cursor: Option<Vec<u8>>,
+    },
+    Complete,
+}
+
+impl BackfillProgress {
+    /// A backfill that has not started.
+    pub const NOT_STARTED: BackfillProgress = BackfillProgress::InProgress { cursor: None };
+}
+
+/// The progress of every backfill, as kept in stable memory.
+///
+/// A backfill that is missing, e.g. because it was added after the progress was written, has not started.
+#[derive(CandidType, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Backfills {
+    canisters_hashes: Option<BackfillProgress>,
+    hardware_wallet_index: Option<BackfillProgress>,
+    canister_index: Option<BackfillProgress>,
+}
+
+impl Backfills {
+    /// The progress for an accounts store without accounts, where there is nothing to backfill.
+    #[must_use]
+    pub fn complete() -> Self {
+        let mut backfills = Self::default();
+        for backfill in Backfill::ALL {
+            backfills.set_progress(backfill, BackfillProgress::Complete);
+        }
+        backfills
+    }
+
+    #[must_use]
+    pub fn progress(&self, backfill: Backfill) -> BackfillProgress {
+        let progress = match backfill {
+            Backfill::CanistersHashes => self.canisters_hashes.as_ref(),
+            Backfill::HardwareWalletIndex => self.hardware_wallet_index.as_ref(),
+            Backfill::CanisterIndex => self.canister_index.as_ref(),
+        };
+        progress.cloned().unwrap_or(BackfillProgress::NOT_STARTED)
+    }
+
+    pub fn set_progress(&mut self, backfill: Backfill, progress: BackfillProgress) {
+        match backfill {
+            Backfill::CanistersHashes => self.canisters_hashes = Some(progress),
+            Backfill::HardwareWalletIndex => self.hardware_wallet_index = Some(progress),
+            Backfill::CanisterIndex => self.canister_index = Some(progress),
+        }
+    }
+
+    /// The first backfill that is not complete, if any, with its cursor.
+    #[must_use]
+    pub fn next_pending(&self) -> Option<(Backfill, Option<Vec<u8>>)> {
+        Backfill::ALL
+            .into_iter()
+            .find_map(|backfill| match self.progress(backfill) {
+                BackfillProgress::InProgress { cursor } => Some((backfill, cursor)),
+                BackfillProgress::Complete => None,
+            })
+    }
+
+    /// Whether every backfill is complete.
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.next_pending().is_none()
+    }
+}
+
+impl Storable for Backfills {
+    const BOUND: Bound = Bound::Unbounded;
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        candid::encode_one(self).expect("Failed to serialize backfills").into()
+    }
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(&self).expect("Failed to serialize backfills")
+    }
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to parse backfills from store.")
+    }
+}
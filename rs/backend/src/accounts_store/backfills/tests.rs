@@ -0,0 +1,34 @@
+use super::*;
+use pretty_assertions::assert_eq;
+
+#[test]
+fn missing_backfills_should_not_have_started() {
+    let backfills = Backfills::default();
+    for backfill in Backfill::ALL {
+        assert_eq!(backfills.progress(backfill), BackfillProgress::NOT_STARTED);
+    }
+    assert_eq!(backfills.next_pending(), Some((Backfill::ALL[0], None)));
+}
+
+#[test]
+fn complete_backfills_should_not_be_pending() {
+    let backfills = Backfills::complete();
+    assert!(backfills.is_complete());
+    assert_eq!(backfills.next_pending(), None);
+}
+
+#[test]
+fn progress_should_survive_serialization() {
+    let mut backfills = Backfills::default();
+    backfills.set_progress(
+        Backfill::CanistersHashes,
+        BackfillProgress::InProgress {
+            cursor: Some(vec![1, 2, 3]),
+        },
+    );
+    assert_eq!(Backfills::from_bytes(backfills.to_bytes()), backfills);
+    assert_eq!(
+        backfills.next_pending(),
+        Some((Backfill::CanistersHashes, Some(vec![1, 2, 3])))
+    );
+}
@@ -0,0 +1,78 @@
+//! An index from canister ids to the accounts that have attached them.
+//!
+//! Accounts store the canisters they have attached, so without this index the only way to find the accounts that
+//! track a canister would be to scan every account.
+//!
+//! The index is kept in its own stable memory partition.  Entries have no value; the key is the canister id followed
+//! by the account identifier, so the accounts that have attached a canister are a key range.  Attaching or detaching
+//! a canister therefore costs the same however many accounts have attached it.
+use super::hardware_wallet_index::principal_prefix;
+use ic_base_types::CanisterId;
+use ic_stable_structures::{memory_manager::VirtualMemory, DefaultMemoryImpl, StableBTreeMap};
+
+#[cfg(test)]
+mod tests;
+
+pub struct CanisterIndex {
+    links: StableBTreeMap<Vec<u8>, (), VirtualMemory<DefaultMemoryImpl>>,
+}
+
+impl CanisterIndex {
+    /// Creates an empty index in the given memory.
+    #[must_use]
+    pub fn new(memory: VirtualMemory<DefaultMemoryImpl>) -> Self {
+        Self {
+            links: StableBTreeMap::new(memory),
+        }
+    }
+
+    /// Loads an existing index from the given memory.
+    #[must_use]
+    pub fn load(memory: VirtualMemory<DefaultMemoryImpl>) -> Self {
+        Self {
+            links: StableBTreeMap::load(memory),
+        }
+    }
+
+    /// Whether no account has any canister attached.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Records that the account has attached the canister.
+    pub fn insert(&mut self, canister_id: CanisterId, account_identifier: &[u8]) {
+        self.links.insert(link_key(canister_id, account_identifier), ());
+    }
+
+    /// Records that the account has detached the canister.
+    pub fn remove(&mut self, canister_id: CanisterId, account_identifier: &[u8]) {
+        self.links.remove(&link_key(canister_id, account_identifier));
+    }
+
+    /// The number of accounts that have attached the canister.
+    #[must_use]
+    pub fn attachment_count(&self, canister_id: CanisterId) -> u64 {
+        let prefix = principal_prefix(canister_id.get());
+        self.links
+            .range(prefix.clone()..)
+            .map(|entry| entry.into_pair().0)
+            .take_while(|key| key.starts_with(&prefix))
+            .count() as u64
+    }
+
+    /// All links, as (canister id prefix + account identifier) keys.
+    #[cfg(test)]
+    #[must_use]
+    pub fn keys(&self) -> Vec<Vec<u8>> {
+        self.links.range(..).map(|entry| entry.into_pair().0).collect()
+    }
+}
+
+/// The key of a link between a canister and an account that has attached it.
+#[must_use]
+pub fn link_key(canister_id: CanisterId, account_identifier: &[u8]) -> Vec<u8> {
+    let mut key = principal_prefix(canister_id.get());
+    key.extend_from_slice(account_identifier);
+    key
+}
@@ -0,0 +1,70 @@
+use super::*;
+use crate::state::partitions::{PartitionType, Partitions};
+use ic_base_types::PrincipalId;
+use pretty_assertions::assert_eq;
+
+fn empty_index() -> CanisterIndex {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    CanisterIndex::new(partitions.get(PartitionType::CanisterIndex.memory_id()))
+}
+
+#[test]
+fn attachments_are_counted_per_canister() {
+    let mut index = empty_index();
+    let canister1 = CanisterId::from(1);
+    let canister2 = CanisterId::from(2);
+    index.insert(canister1, b"account_1");
+    index.insert(canister1, b"account_2");
+    index.insert(canister2, b"account_2");
+
+    assert_eq!(index.attachment_count(canister1), 2);
+    assert_eq!(index.attachment_count(canister2), 1);
+    assert_eq!(index.attachment_count(CanisterId::from(3)), 0);
+}
+
+#[test]
+fn inserting_twice_and_removing_are_idempotent() {
+    let mut index = empty_index();
+    let canister = CanisterId::from(1);
+    index.insert(canister, b"account_1");
+    index.insert(canister, b"account_1");
+    assert_eq!(index.attachment_count(canister), 1);
+
+    index.remove(canister, b"account_2");
+    assert_eq!(index.attachment_count(canister), 1);
+    index.remove(canister, b"account_1");
+    index.remove(canister, b"account_1");
+    assert_eq!(index.attachment_count(canister), 0);
+}
+
+#[test]
+fn canisters_without_attachments_are_removed() {
+    let mut index = empty_index();
+    let canister = CanisterId::from(1);
+    index.insert(canister, b"account_1");
+    index.remove(canister, b"account_1");
+    assert!(index.is_empty());
+    assert_eq!(index.keys(), Vec::<Vec<u8>>::new());
+}
+
+#[test]
+fn links_of_other_canisters_are_not_counted() {
+    let mut index = empty_index();
+    // Canister ids of different lengths, one of which is a prefix of the other.
+    let canister = CanisterId::unchecked_from_principal(PrincipalId::try_from(&[1u8, 2][..]).unwrap());
+    let longer_canister = CanisterId::unchecked_from_principal(PrincipalId::try_from(&[1u8, 2, 3][..]).unwrap());
+    index.insert(canister, b"account_1");
+    index.insert(longer_canister, b"account_1");
+    index.insert(longer_canister, b"account_2");
+
+    assert_eq!(index.attachment_count(canister), 1);
+    assert_eq!(index.attachment_count(longer_canister), 2);
+    assert_eq!(
+        index.keys(),
+        vec![
+            link_key(canister, b"account_1"),
+            link_key(longer_canister, b"account_1"),
+            link_key(longer_canister, b"account_2"),
+        ]
+    );
+}
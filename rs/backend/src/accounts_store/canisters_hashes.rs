@@ -0,0 +1,154 @@
+//! The certified hashes of the canisters of every account.
+//!
+//! The hashes are kept in their own stable memory partition and updated whenever the canisters of an account change,
+//! so they never have to be recomputed from the accounts.  The certification tree needs them on the heap as well, so
+//! after an upgrade they are read back into an `RbTree` in batches, by `load_step`.  Until an account's hash has been
+//! read back, its canisters are not certified.
+use super::backfills::BackfillProgress;
+use ic_certified_map::{AsHashTree, Hash, HashTree, RbTree};
+use ic_stable_structures::{memory_manager::VirtualMemory, DefaultMemoryImpl, StableBTreeMap};
+use std::ops::Bound;
+
+#[cfg(test)]
+mod tests;
+
+pub struct CanistersHashes {
+    /// The hash of the canisters of every account with canisters, keyed by account identifier.
+    hashes: StableBTreeMap<Vec<u8>, Hash, VirtualMemory<DefaultMemoryImpl>>,
+    /// A copy of `hashes`, for certification.
+    tree: RbTree<Vec<u8>, Hash>,
+    /// How far `hashes` has been read into `tree` after an upgrade.
+    tree_progress: BackfillProgress,
+}
+
+#[cfg(test)]
+impl PartialEq for CanistersHashes {
+    fn eq(&self, other: &Self) -> bool {
+        self.hashes
+            .iter()
+            .map(|entry| entry.into_pair())
+            .eq(other.hashes.iter().map(|entry| entry.into_pair()))
+    }
+}
+
+impl CanistersHashes {
+    /// Creates an empty set of hashes in the given memory.
+    #[must_use]
+    pub fn new(memory: VirtualMemory<DefaultMemoryImpl>) -> Self {
+        Self {
+            hashes: StableBTreeMap::new(memory),
+            tree: RbTree::new(),
+            tree_progress: BackfillProgress::Complete,
+        }
+    }
+
+    /// Loads the hashes from the given memory.
+    ///
+    /// The certification tree starts out empty.  Call `load_step` until it returns `true` to read the hashes into it.
+    #[must_use]
+    pub fn load(memory: VirtualMemory<DefaultMemoryImpl>) -> Self {
+        let hashes: StableBTreeMap<Vec<u8>, Hash, VirtualMemory<DefaultMemoryImpl>> = StableBTreeMap::init(memory);
+        let tree_progress = if hashes.is_empty() {
+            BackfillProgress::Complete
+        } else {
+            BackfillProgress::NOT_STARTED
+        };
+        Self {
+            hashes,
+            tree: RbTree::new(),
+            tree_progress,
+        }
+    }
+
+    /// Reads up to `batch_size` hashes into the certification tree, continuing from where the previous step stopped.
+    ///
+    /// Note: This changes the root hash.  Call `certification::update_root_hash` afterwards.
+    ///
+    /// Returns whether all hashes are in the certification tree.
+    pub fn load_step(&mut self, batch_size: u64) -> bool {
+        let BackfillProgress::InProgress { cursor } = &self.tree_progress else {
+            return true;
+        };
+        let start = cursor
+            .as_ref()
+            .map_or(Bound::Unbounded, |cursor| Bound::Excluded(cursor.clone()));
+        let batch: Vec<(Vec<u8>, Hash)> = self
+            .hashes
+            .range((start, Bound::Unbounded))
+            .take(usize::try_from(batch_size).unwrap_or(usize::MAX))
+            .map(|entry| entry.into_pair())
+            .collect();
+        self.tree_progress = if (batch.len() as u64) < batch_size {
+            BackfillProgress::Complete
+        } else {
+            BackfillProgress::InProgress {
+                cursor: batch.last().map(|(account_identifier, _)| account_identifier.clone()),
+            }
+        };
+        for (account_identifier, hash) in batch {
+            self.tree.insert(account_identifier, hash);
+        }
+        self.is_loaded()
+    }
+
+    /// Whether all hashes are in the certification tree.
+    #[must_use]
+    pub fn is_loaded(&self) -> bool {
+        self.tree_progress == BackfillProgress::Complete
+    }
+
+    /// Removes all hashes, e.g. when they may be stale because an older build has changed the accounts.
+    pub fn clear(&mut self) {
+        self.hashes.clear_new();
+        self.tree = RbTree::new();
+        self.tree_progress = BackfillProgress::Complete;
+    }
+
+    /// Sets the hash of the canisters of an account.
+    pub fn insert(&mut self, account_identifier: &[u8], hash: Hash) {
+        self.hashes.insert(account_identifier.to_vec(), hash);
+        self.tree.insert(account_identifier.to_vec(), hash);
+    }
+
+    /// Removes the hash of the canisters of an account, e.g. when it has no canisters left.
+    pub fn remove(&mut self, account_identifier: &[u8]) {
+        if self.hashes.remove(&account_identifier.to_vec()).is_some() {
+            self.tree.delete(account_identifier);
+        }
+    }
+
+    /// The hash of the canisters of an account, if it has any canisters.
+    #[must_use]
+    pub fn get(&self, account_identifier: &[u8]) -> Option<Hash> {
+        self.hashes.get(&account_identifier.to_vec())
+    }
+
+    /// Whether the certification tree has the given hash for the canisters of an account, or no hash if `None`.
+    #[must_use]
+    pub fn is_certified(&self, account_identifier: &[u8], hash: Option<Hash>) -> bool {
+        self.tree.get(account_identifier).copied() == hash
+    }
+
+    /// The number of accounts with a hash.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.hashes.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// The root hash of the certification tree.
+    #[must_use]
+    pub fn root_hash(&self) -> Hash {
+        self.tree.root_hash()
+    }
+
+    /// A witness for the hash of the canisters of the given account, or for its absence.
+    #[must_use]
+    pub fn witness(&self, account_identifier: &[u8]) -> HashTree<'_> {
+        self.tree.witness(account_identifier)
+    }
+}
@@ -0,0 +1,75 @@
+use super::*;
+use crate::state::partitions::{PartitionType, Partitions};
+use pretty_assertions::assert_eq;
+
+#[test]
+fn hashes_should_survive_reloading() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    let mut hashes = CanistersHashes::new(partitions.get(PartitionType::CanistersHashes.memory_id()));
+    hashes.insert(b"account_1", [1; 32]);
+    hashes.insert(b"account_2", [2; 32]);
+    hashes.insert(b"account_3", [3; 32]);
+    hashes.remove(b"account_2");
+
+    let mut reloaded = CanistersHashes::load(partitions.get(PartitionType::CanistersHashes.memory_id()));
+    while !reloaded.load_step(1) {}
+
+    assert_eq!(reloaded.len(), 2);
+    assert_eq!(reloaded.root_hash(), hashes.root_hash());
+    assert_eq!(reloaded.witness(b"account_3").reconstruct(), hashes.root_hash());
+}
+
+#[test]
+fn removing_a_missing_hash_should_change_nothing() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    let mut hashes = CanistersHashes::new(partitions.get(PartitionType::CanistersHashes.memory_id()));
+    hashes.insert(b"account_1", [1; 32]);
+    let root_hash = hashes.root_hash();
+
+    hashes.remove(b"account_2");
+
+    assert_eq!(hashes.len(), 1);
+    assert_eq!(hashes.root_hash(), root_hash);
+}
+
+#[test]
+fn hashes_should_be_certified_once_loaded() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    let mut hashes = CanistersHashes::new(partitions.get(PartitionType::CanistersHashes.memory_id()));
+    hashes.insert(b"account_1", [1; 32]);
+    hashes.insert(b"account_2", [2; 32]);
+    hashes.insert(b"account_3", [3; 32]);
+    let root_hash = hashes.root_hash();
+
+    let mut reloaded = CanistersHashes::load(partitions.get(PartitionType::CanistersHashes.memory_id()));
+    assert!(!reloaded.is_loaded());
+    assert!(!reloaded.is_certified(b"account_1", Some([1; 32])));
+    assert!(reloaded.is_certified(b"account_4", None));
+
+    assert!(!reloaded.load_step(2));
+    assert!(reloaded.is_certified(b"account_1", Some([1; 32])));
+    assert!(!reloaded.is_certified(b"account_3", Some([3; 32])));
+    // Hashes that change while loading are certified right away, and not overwritten by the loading.
+    reloaded.insert(b"account_3", [4; 32]);
+    assert!(reloaded.is_certified(b"account_3", Some([4; 32])));
+
+    assert!(reloaded.load_step(2));
+    assert!(reloaded.is_loaded());
+    assert!(reloaded.is_certified(b"account_3", Some([4; 32])));
+    assert_ne!(reloaded.root_hash(), root_hash);
+}
+
+#[test]
+fn clearing_should_remove_all_hashes() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    let mut hashes = CanistersHashes::new(partitions.get(PartitionType::CanistersHashes.memory_id()));
+    let empty_root_hash = hashes.root_hash();
+    hashes.insert(b"account_1", [1; 32]);
+
+    hashes.clear();
+
+    assert!(hashes.is_empty());
+    assert!(hashes.is_loaded());
+    assert_eq!(hashes.root_hash(), empty_root_hash);
+    assert!(CanistersHashes::load(partitions.get(PartitionType::CanistersHashes.memory_id())).is_empty());
+}
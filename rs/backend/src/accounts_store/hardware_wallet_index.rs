@@ -0,0 +1,100 @@
+//! An index from hardware wallet principals to the accounts that have registered them.
+//!
+//! A hardware wallet may be registered with several accounts.  The accounts store the hardware wallets, so without
+//! this index the only way to find the accounts that reference a hardware wallet would be to scan every account.
+//!
+//! The index is kept in its own stable memory partition.  Entries have no value; the key is the hardware wallet
+//! principal followed by the account identifier, so the accounts referencing a hardware wallet are a key range.
+use ic_base_types::PrincipalId;
+use ic_stable_structures::{memory_manager::VirtualMemory, DefaultMemoryImpl, StableBTreeMap};
+use icp_ledger::AccountIdentifier;
+
+#[cfg(test)]
+mod tests;
+
+pub struct HardwareWalletIndex {
+    links: StableBTreeMap<Vec<u8>, (), VirtualMemory<DefaultMemoryImpl>>,
+}
+
+impl HardwareWalletIndex {
+    /// Creates an empty index in the given memory.
+    #[must_use]
+    pub fn new(memory: VirtualMemory<DefaultMemoryImpl>) -> Self {
+        Self {
+            links: StableBTreeMap::new(memory),
+        }
+    }
+
+    /// Loads an existing index from the given memory.
+    #[must_use]
+    pub fn load(memory: VirtualMemory<DefaultMemoryImpl>) -> Self {
+        Self {
+            links: StableBTreeMap::load(memory),
+        }
+    }
+
+    /// The number of links between hardware wallets and accounts.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.links.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Records that the account references the hardware wallet.
+    pub fn insert(&mut self, hardware_wallet: PrincipalId, account_identifier: &[u8]) {
+        self.links.insert(link_key(hardware_wallet, account_identifier), ());
+    }
+
+    /// Records that the account no longer references the hardware wallet.
+    pub fn remove(&mut self, hardware_wallet: PrincipalId, account_identifier: &[u8]) {
+        self.links.remove(&link_key(hardware_wallet, account_identifier));
+    }
+
+    /// Whether the account references the hardware wallet.
+    #[must_use]
+    pub fn contains(&self, hardware_wallet: PrincipalId, account_identifier: &[u8]) -> bool {
+        self.links.contains_key(&link_key(hardware_wallet, account_identifier))
+    }
+
+    /// The accounts that reference the hardware wallet, ordered by account identifier.
+    #[must_use]
+    pub fn accounts(&self, hardware_wallet: PrincipalId) -> Vec<AccountIdentifier> {
+        let prefix = principal_prefix(hardware_wallet);
+        self.links
+            .range(prefix.clone()..)
+            .map(|entry| entry.into_pair().0)
+            .take_while(|key| key.starts_with(&prefix))
+            .filter_map(|key| AccountIdentifier::from_slice(&key[prefix.len()..]).ok())
+            .collect()
+    }
+
+    /// All links, as (hardware wallet principal prefix + account identifier) keys.
+    #[cfg(test)]
+    #[must_use]
+    pub fn keys(&self) -> Vec<Vec<u8>> {
+        self.links.range(..).map(|entry| entry.into_pair().0).collect()
+    }
+}
+
+/// The key prefix shared by all links of a hardware wallet.
+///
+/// The principal is length-prefixed, so that the prefix of one principal is never the prefix of another.
+pub(super) fn principal_prefix(hardware_wallet: PrincipalId) -> Vec<u8> {
+    let principal = hardware_wallet.as_slice();
+    let mut prefix = Vec::with_capacity(1 + principal.len());
+    prefix.push(u8::try_from(principal.len()).unwrap_or(u8::MAX));
+    prefix.extend_from_slice(principal);
+    prefix
+}
+
+/// The key of a link between a hardware wallet and an account.
+#[must_use]
+pub fn link_key(hardware_wallet: PrincipalId, account_identifier: &[u8]) -> Vec<u8> {
+    let mut key = principal_prefix(hardware_wallet);
+    key.extend_from_slice(account_identifier);
+    key
+}
@@ -0,0 +1,56 @@
+use super::*;
+use crate::state::partitions::{PartitionType, Partitions};
+use pretty_assertions::assert_eq;
+
+fn empty_index() -> HardwareWalletIndex {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    HardwareWalletIndex::new(partitions.get(PartitionType::HardwareWalletIndex.memory_id()))
+}
+
+fn account(id: u64) -> AccountIdentifier {
+    AccountIdentifier::from(PrincipalId::new_user_test_id(id))
+}
+
+#[test]
+fn accounts_are_listed_per_hardware_wallet() {
+    let mut index = empty_index();
+    let hw1 = PrincipalId::new_user_test_id(1001);
+    let hw2 = PrincipalId::new_user_test_id(1002);
+    index.insert(hw1, &account(1).to_vec());
+    index.insert(hw1, &account(2).to_vec());
+    index.insert(hw2, &account(2).to_vec());
+
+    let mut expected = vec![account(1), account(2)];
+    expected.sort_unstable_by_key(AccountIdentifier::to_vec);
+    assert_eq!(index.accounts(hw1), expected);
+    assert_eq!(index.accounts(hw2), vec![account(2)]);
+    assert_eq!(index.accounts(PrincipalId::new_user_test_id(1003)), vec![]);
+    assert_eq!(index.len(), 3);
+}
+
+#[test]
+fn inserting_twice_and_removing_are_idempotent() {
+    let mut index = empty_index();
+    let hw = PrincipalId::new_user_test_id(1001);
+    index.insert(hw, &account(1).to_vec());
+    index.insert(hw, &account(1).to_vec());
+    assert_eq!(index.len(), 1);
+    assert!(index.contains(hw, &account(1).to_vec()));
+
+    index.remove(hw, &account(1).to_vec());
+    index.remove(hw, &account(1).to_vec());
+    assert!(index.is_empty());
+    assert!(!index.contains(hw, &account(1).to_vec()));
+}
+
+#[test]
+fn principals_that_are_prefixes_of_one_another_are_kept_apart() {
+    let mut index = empty_index();
+    let short = PrincipalId::try_from(&[1u8, 2][..]).unwrap();
+    let long = PrincipalId::try_from(&[1u8, 2, 3][..]).unwrap();
+    index.insert(short, &account(1).to_vec());
+    index.insert(long, &account(2).to_vec());
+
+    assert_eq!(index.accounts(short), vec![account(1)]);
+    assert_eq!(index.accounts(long), vec![account(2)]);
+}
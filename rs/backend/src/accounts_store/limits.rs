@@ -0,0 +1,296 @@
+//! Limits on the number of accounts and on the data stored per account.
+//!
+//! The defaults can be overridden with the `limits` canister argument at installation or upgrade.  Overrides are
+//! persisted across upgrades, so an upgrade without `limits` keeps the limits in force.
+use super::{CandidType, Deserialize};
+use crate::arguments::LimitsArguments;
+use ic_stable_structures::{storable::Bound, Storable};
+use std::borrow::Cow;
+
+#[cfg(test)]
+mod tests;
+
+/// The limits in force.
+#[derive(CandidType, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Limits {
+    /// The maximum number of accounts.
+    ///
+    /// This limit is for DoS protection but should be increased if we get close to the limit.
+    /// When you increase this limit, make sure to also increase the limit on the alerts.
+    /// See for example <https://github.com/dfinity-ops/k8s/pull/731>
+    pub account_limit: u64,
+    /// The largest id that may be given to a sub-account.
+    pub max_sub_account_id: u8,
+    /// The maximum number of imported tokens per account.
+    pub max_imported_tokens: i32,
+    /// The maximum number of favorite projects per account.
+    pub max_favorite_projects: i32,
+    /// The maximum number of named addresses in an address book.
+    pub max_named_addresses: i32,
+    /// The maximum length of the name of a sub-account or hardware wallet.
+    pub max_account_name_length: u32,
+    /// The maximum length of the name of an attached canister.
+    pub max_canister_name_length: u32,
+    /// The maximum number of named ICRC-1 sub-accounts per account.
+    pub max_named_icrc1_subaccounts: i32,
+    /// The maximum length of the model and app version of a hardware wallet device.
+    pub max_hardware_wallet_device_field_length: i32,
+    /// The maximum length of the colour/icon key of a sub-account.
+    pub max_sub_account_style_key_length: i32,
+    /// The maximum number of tags of a named address.
+    pub max_named_address_tags: i32,
+    /// The maximum length of a tag of a named address.
+    pub max_named_address_tag_length: i32,
+    /// The maximum number of principals that may be allowed to create accounts beyond the account limit.
+    pub max_account_limit_allowlist_size: u32,
+    /// The maximum number of tags of an attached canister.
+    pub max_canister_tags: i32,
+    /// The maximum length of a tag of an attached canister.
+    pub max_canister_tag_length: i32,
+    /// The maximum length of the note on an attached canister.
+    pub max_canister_note_length: i32,
+}
+
+/// Limits are stored as `LimitsArguments` with every limit set.  A limit that is missing, e.g. because it was added
+/// after the limits were written, takes its default value.
+impl Storable for Limits {
+    const BOUND: Bound = Bound::Unbounded;
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        candid::encode_one(LimitsArguments::from(self))
+            .expect("Failed to serialize limits")
+            .into()
+    }
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(LimitsArguments::from(&self)).expect("Failed to serialize limits")
+    }
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let stored: LimitsArguments = candid::decode_one(&bytes).expect("Failed to parse limits from store.");
+        Limits::DEFAULT.overridden_by(&stored)
+    }
+}
+
+impl From<&Limits> for LimitsArguments {
+    fn from(limits: &Limits) -> Self {
+        LimitsArguments {
+            account_limit: Some(limits.account_limit),
+            max_sub_account_id: Some(limits.max_sub_account_id),
+            max_imported_tokens: Some(limits.max_imported_tokens),
+            max_favorite_projects: Some(limits.max_favorite_projects),
+            max_named_addresses: Some(limits.max_named_addresses),
+            max_account_name_length: Some(limits.max_account_name_length),
+            max_canister_name_length: Some(limits.max_canister_name_length),
+            max_named_icrc1_subaccounts: Some(limits.max_named_icrc1_subaccounts),
+            max_hardware_wallet_device_field_length: Some(limits.max_hardware_wallet_device_field_length),
+            max_sub_account_style_key_length: Some(limits.max_sub_account_style_key_length),
+            max_named_address_tags: Some(limits.max_named_address_tags),
+            max_named_address_tag_length: Some(limits.max_named_address_tag_length),
+            max_account_limit_allowlist_size: Some(limits.max_account_limit_allowlist_size),
+            max_canister_tags: Some(limits.max_canister_tags),
+            max_canister_tag_length: Some(limits.max_canister_tag_length),
+            max_canister_note_length: Some(limits.max_canister_note_length),
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Limits {
+    /// The limits used unless overridden.
+    ///
+    /// The per-account limits are conservative to prevent using too much memory.  They can be revisited if users
+    /// find them too restrictive.
+    pub const DEFAULT: Limits = Limits {
+        account_limit: 330_000,
+        max_sub_account_id: u8::MAX - 1,
+        max_imported_tokens: 20,
+        max_favorite_projects: 20,
+        max_named_addresses: 20,
+        max_account_name_length: 24,
+        max_canister_name_length: 24,
+        max_named_icrc1_subaccounts: 100,
+        max_hardware_wallet_device_field_length: 32,
+        max_sub_account_style_key_length: 32,
+        max_named_address_tags: 10,
+        max_named_address_tag_length: 24,
+        max_account_limit_allowlist_size: 1_000,
+        max_canister_tags: 10,
+        max_canister_tag_length: 24,
+        max_canister_note_length: 500,
+    };
+
+    /// The largest values that may be configured.
+    ///
+    /// - Sub-account ids are stored as a single byte and `u8::MAX` is reserved.
+    /// - The other bounds keep the memory used by a single account, and by the canister as a whole, well within
+    ///   what the canister can serialize and upgrade.
+    pub const UPPER_BOUND: Limits = Limits {
+        account_limit: 1_000_000,
+        max_sub_account_id: u8::MAX - 1,
+        max_imported_tokens: 100,
+        max_favorite_projects: 100,
+        max_named_addresses: 100,
+        max_account_name_length: 64,
+        max_canister_name_length: 64,
+        max_named_icrc1_subaccounts: 500,
+        max_hardware_wallet_device_field_length: 64,
+        max_sub_account_style_key_length: 64,
+        max_named_address_tags: 50,
+        max_named_address_tag_length: 64,
+        max_account_limit_allowlist_size: 10_000,
+        max_canister_tags: 50,
+        max_canister_tag_length: 64,
+        max_canister_note_length: 2000,
+    };
+
+    /// Applies the given overrides.
+    ///
+    /// # Errors
+    /// - If any override is negative or exceeds the corresponding upper bound.  In that case no override is applied.
+    pub fn with_overrides(&self, overrides: &LimitsArguments) -> Result<Limits, String> {
+        let bound = Self::UPPER_BOUND;
+        check("account_limit", overrides.account_limit, bound.account_limit)?;
+        check(
+            "max_sub_account_id",
+            overrides.max_sub_account_id,
+            bound.max_sub_account_id,
+        )?;
+        check(
+            "max_imported_tokens",
+            overrides.max_imported_tokens,
+            bound.max_imported_tokens,
+        )?;
+        check(
+            "max_favorite_projects",
+            overrides.max_favorite_projects,
+            bound.max_favorite_projects,
+        )?;
+        check(
+            "max_named_addresses",
+            overrides.max_named_addresses,
+            bound.max_named_addresses,
+        )?;
+        check(
+            "max_account_name_length",
+            overrides.max_account_name_length,
+            bound.max_account_name_length,
+        )?;
+        check(
+            "max_canister_name_length",
+            overrides.max_canister_name_length,
+            bound.max_canister_name_length,
+        )?;
+        check(
+            "max_named_icrc1_subaccounts",
+            overrides.max_named_icrc1_subaccounts,
+            bound.max_named_icrc1_subaccounts,
+        )?;
+        check(
+            "max_hardware_wallet_device_field_length",
+            overrides.max_hardware_wallet_device_field_length,
+            bound.max_hardware_wallet_device_field_length,
+        )?;
+        check(
+            "max_sub_account_style_key_length",
+            overrides.max_sub_account_style_key_length,
+            bound.max_sub_account_style_key_length,
+        )?;
+        check(
+            "max_named_address_tags",
+            overrides.max_named_address_tags,
+            bound.max_named_address_tags,
+        )?;
+        check(
+            "max_named_address_tag_length",
+            overrides.max_named_address_tag_length,
+            bound.max_named_address_tag_length,
+        )?;
+        check(
+            "max_account_limit_allowlist_size",
+            overrides.max_account_limit_allowlist_size,
+            bound.max_account_limit_allowlist_size,
+        )?;
+        check(
+            "max_canister_tags",
+            overrides.max_canister_tags,
+            bound.max_canister_tags,
+        )?;
+        check(
+            "max_canister_tag_length",
+            overrides.max_canister_tag_length,
+            bound.max_canister_tag_length,
+        )?;
+        check(
+            "max_canister_note_length",
+            overrides.max_canister_note_length,
+            bound.max_canister_note_length,
+        )?;
+        let limits = self.overridden_by(overrides);
+        if limits.max_sub_account_id == 0 {
+            return Err("max_sub_account_id must be at least 1".to_string());
+        }
+        Ok(limits)
+    }
+
+    /// Applies the given overrides without checking them.
+    fn overridden_by(&self, overrides: &LimitsArguments) -> Limits {
+        Limits {
+            account_limit: overrides.account_limit.unwrap_or(self.account_limit),
+            max_sub_account_id: overrides.max_sub_account_id.unwrap_or(self.max_sub_account_id),
+            max_imported_tokens: overrides.max_imported_tokens.unwrap_or(self.max_imported_tokens),
+            max_favorite_projects: overrides.max_favorite_projects.unwrap_or(self.max_favorite_projects),
+            max_named_addresses: overrides.max_named_addresses.unwrap_or(self.max_named_addresses),
+            max_account_name_length: overrides
+                .max_account_name_length
+                .unwrap_or(self.max_account_name_length),
+            max_canister_name_length: overrides
+                .max_canister_name_length
+                .unwrap_or(self.max_canister_name_length),
+            max_named_icrc1_subaccounts: overrides
+                .max_named_icrc1_subaccounts
+                .unwrap_or(self.max_named_icrc1_subaccounts),
+            max_hardware_wallet_device_field_length: overrides
+                .max_hardware_wallet_device_field_length
+                .unwrap_or(self.max_hardware_wallet_device_field_length),
+            max_sub_account_style_key_length: overrides
+                .max_sub_account_style_key_length
+                .unwrap_or(self.max_sub_account_style_key_length),
+            max_named_address_tags: overrides.max_named_address_tags.unwrap_or(self.max_named_address_tags),
+            max_named_address_tag_length: overrides
+                .max_named_address_tag_length
+                .unwrap_or(self.max_named_address_tag_length),
+            max_account_limit_allowlist_size: overrides
+                .max_account_limit_allowlist_size
+                .unwrap_or(self.max_account_limit_allowlist_size),
+            max_canister_tags: overrides.max_canister_tags.unwrap_or(self.max_canister_tags),
+            max_canister_tag_length: overrides
+                .max_canister_tag_length
+                .unwrap_or(self.max_canister_tag_length),
+            max_canister_note_length: overrides
+                .max_canister_note_length
+                .unwrap_or(self.max_canister_note_length),
+        }
+    }
+}
+
+/// Converts a limit to a `usize`, for comparison with the length of a collection or string.
+#[must_use]
+pub fn len_limit<T: TryInto<usize>>(limit: T) -> usize {
+    limit.try_into().unwrap_or_default()
+}
+
+/// Checks that an override, if any, is between zero and the upper bound.
+fn check<T>(name: &str, value: Option<T>, upper_bound: T) -> Result<(), String>
+where
+    T: PartialOrd + Default + std::fmt::Display,
+{
+    match value {
+        Some(value) if value < T::default() || value > upper_bound => {
+            Err(format!("{name} must be between 0 and {upper_bound} but is {value}"))
+        }
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,129 @@
+use super::*;
+use pretty_assertions::assert_eq;
+
+#[test]
+fn no_overrides_keep_the_current_limits() {
+    let current = Limits {
+        max_named_addresses: 7,
+        ..Limits::default()
+    };
+    assert_eq!(current.with_overrides(&LimitsArguments::default()), Ok(current.clone()));
+}
+
+#[test]
+fn overrides_replace_only_the_given_limits() {
+    let overrides = LimitsArguments {
+        account_limit: Some(500_000),
+        max_account_name_length: Some(32),
+        ..LimitsArguments::default()
+    };
+    assert_eq!(
+        Limits::default().with_overrides(&overrides),
+        Ok(Limits {
+            account_limit: 500_000,
+            max_account_name_length: 32,
+            ..Limits::default()
+        })
+    );
+}
+
+#[test]
+fn overrides_may_equal_the_upper_bound() {
+    let bound = Limits::UPPER_BOUND;
+    let overrides = LimitsArguments {
+        account_limit: Some(bound.account_limit),
+        max_sub_account_id: Some(bound.max_sub_account_id),
+        max_imported_tokens: Some(bound.max_imported_tokens),
+        max_favorite_projects: Some(bound.max_favorite_projects),
+        max_named_addresses: Some(bound.max_named_addresses),
+        max_account_name_length: Some(bound.max_account_name_length),
+        max_canister_name_length: Some(bound.max_canister_name_length),
+        max_named_icrc1_subaccounts: Some(bound.max_named_icrc1_subaccounts),
+        max_hardware_wallet_device_field_length: Some(bound.max_hardware_wallet_device_field_length),
+        max_sub_account_style_key_length: Some(bound.max_sub_account_style_key_length),
+        max_named_address_tags: Some(bound.max_named_address_tags),
+        max_named_address_tag_length: Some(bound.max_named_address_tag_length),
+        max_account_limit_allowlist_size: Some(bound.max_account_limit_allowlist_size),
+        max_canister_tags: Some(bound.max_canister_tags),
+        max_canister_tag_length: Some(bound.max_canister_tag_length),
+        max_canister_note_length: Some(bound.max_canister_note_length),
+    };
+    assert_eq!(Limits::default().with_overrides(&overrides), Ok(bound));
+}
+
+#[test]
+fn overrides_above_the_upper_bound_are_rejected() {
+    let overrides = LimitsArguments {
+        max_imported_tokens: Some(Limits::UPPER_BOUND.max_imported_tokens + 1),
+        ..LimitsArguments::default()
+    };
+    assert_eq!(
+        Limits::default().with_overrides(&overrides),
+        Err("max_imported_tokens must be between 0 and 100 but is 101".to_string())
+    );
+}
+
+#[test]
+fn negative_overrides_are_rejected() {
+    let overrides = LimitsArguments {
+        max_named_addresses: Some(-1),
+        ..LimitsArguments::default()
+    };
+    assert_eq!(
+        Limits::default().with_overrides(&overrides),
+        Err("max_named_addresses must be between 0 and 100 but is -1".to_string())
+    );
+}
+
+#[test]
+fn sub_accounts_must_be_allowed() {
+    let overrides = LimitsArguments {
+        max_sub_account_id: Some(0),
+        ..LimitsArguments::default()
+    };
+    assert!(Limits::default().with_overrides(&overrides).is_err());
+}
+
+#[test]
+fn limits_should_survive_storing() {
+    let limits = Limits {
+        max_named_addresses: 7,
+        max_canister_note_length: 100,
+        ..Limits::default()
+    };
+    assert_eq!(Limits::from_bytes(limits.to_bytes()), limits);
+}
+
+#[test]
+fn limits_written_before_later_limits_were_added_should_get_their_defaults() {
+    /// The limits as written by a build that stored every limit as required and had fewer limits.
+    #[derive(CandidType)]
+    struct OlderLimits {
+        account_limit: u64,
+        max_sub_account_id: u8,
+        max_imported_tokens: i32,
+        max_favorite_projects: i32,
+        max_named_addresses: i32,
+        max_account_name_length: u32,
+        max_canister_name_length: u32,
+    }
+    let bytes = candid::encode_one(OlderLimits {
+        account_limit: 500_000,
+        max_sub_account_id: 10,
+        max_imported_tokens: 20,
+        max_favorite_projects: 20,
+        max_named_addresses: 7,
+        max_account_name_length: 24,
+        max_canister_name_length: 24,
+    })
+    .unwrap();
+    assert_eq!(
+        Limits::from_bytes(Cow::Owned(bytes)),
+        Limits {
+            account_limit: 500_000,
+            max_sub_account_id: 10,
+            max_named_addresses: 7,
+            ..Limits::DEFAULT
+        }
+    );
+}
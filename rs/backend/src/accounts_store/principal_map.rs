@@ -0,0 +1,94 @@
+//! A map keyed by principal, kept in a stable memory partition of its own.
+//!
+//! Entries are written to stable memory as they change, so the map does not need to be saved in `pre_upgrade`.
+use ic_base_types::PrincipalId;
+use ic_stable_structures::{memory_manager::VirtualMemory, DefaultMemoryImpl, StableBTreeMap, Storable};
+
+#[cfg(test)]
+mod tests;
+
+pub struct PrincipalMap<V: Storable> {
+    /// The entries, keyed by the bytes of the principal.
+    entries: StableBTreeMap<Vec<u8>, V, VirtualMemory<DefaultMemoryImpl>>,
+}
+
+impl<V: Storable> PrincipalMap<V> {
+    /// Creates an empty map in the given memory, replacing anything that was there.
+    #[must_use]
+    pub fn new(memory: VirtualMemory<DefaultMemoryImpl>) -> Self {
+        Self {
+            entries: StableBTreeMap::new(memory),
+        }
+    }
+
+    /// Loads the map from the given memory, creating an empty map if the memory is empty.
+    #[must_use]
+    pub fn init(memory: VirtualMemory<DefaultMemoryImpl>) -> Self {
+        Self {
+            entries: StableBTreeMap::init(memory),
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self, principal: &PrincipalId) -> Option<V> {
+        self.entries.get(&principal_key(principal))
+    }
+
+    #[must_use]
+    pub fn contains(&self, principal: &PrincipalId) -> bool {
+        self.entries.contains_key(&principal_key(principal))
+    }
+
+    pub fn insert(&mut self, principal: PrincipalId, value: V) -> Option<V> {
+        self.entries.insert(principal_key(&principal), value)
+    }
+
+    pub fn remove(&mut self, principal: &PrincipalId) -> Option<V> {
+        self.entries.remove(&principal_key(principal))
+    }
+
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// All entries, in order of the principal bytes.
+    pub fn iter(&self) -> impl Iterator<Item = (PrincipalId, V)> + '_ {
+        self.entries.range(..).map(|entry| {
+            let (key, value) = entry.into_pair();
+            (principal_from_key(&key), value)
+        })
+    }
+
+    /// Removes the entries for which `keep` returns false.
+    pub fn retain(&mut self, mut keep: impl FnMut(&PrincipalId, &V) -> bool) {
+        let removals: Vec<Vec<u8>> = self
+            .entries
+            .range(..)
+            .map(|entry| entry.into_pair())
+            .filter(|(key, value)| !keep(&principal_from_key(key), value))
+            .map(|(key, _)| key)
+            .collect();
+        for key in removals {
+            self.entries.remove(&key);
+        }
+    }
+
+    /// Removes all entries.
+    pub fn clear(&mut self) {
+        self.entries.clear_new();
+    }
+}
+
+fn principal_key(principal: &PrincipalId) -> Vec<u8> {
+    principal.as_slice().to_vec()
+}
+
+fn principal_from_key(key: &[u8]) -> PrincipalId {
+    PrincipalId::try_from(key).expect("Keys are the bytes of a principal")
+}
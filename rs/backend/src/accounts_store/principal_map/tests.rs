@@ -0,0 +1,58 @@
+use super::*;
+use crate::state::partitions::{PartitionType, Partitions};
+use pretty_assertions::assert_eq;
+use std::rc::Rc;
+
+fn principal(index: u64) -> PrincipalId {
+    PrincipalId::new_user_test_id(index)
+}
+
+fn map_in(partitions: &Partitions) -> PrincipalMap<u64> {
+    PrincipalMap::init(partitions.get(PartitionType::PendingAccountTransfers.memory_id()))
+}
+
+#[test]
+fn entries_should_be_inserted_and_removed() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    let mut map = map_in(&partitions);
+    assert!(map.is_empty());
+
+    assert_eq!(map.insert(principal(1), 10), None);
+    assert_eq!(map.insert(principal(2), 20), None);
+    assert_eq!(map.insert(principal(1), 11), Some(10));
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.get(&principal(1)), Some(11));
+    assert!(map.contains(&principal(2)));
+    assert!(!map.contains(&principal(3)));
+
+    assert_eq!(map.remove(&principal(2)), Some(20));
+    assert_eq!(map.remove(&principal(2)), None);
+    assert_eq!(map.iter().collect::<Vec<_>>(), vec![(principal(1), 11)]);
+
+    map.clear();
+    assert!(map.is_empty());
+}
+
+#[test]
+fn retain_should_remove_rejected_entries() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    let mut map = map_in(&partitions);
+    for index in 0..10 {
+        map.insert(principal(index), index);
+    }
+    map.retain(|_, value| value % 3 == 0);
+    let mut values: Vec<u64> = map.iter().map(|(_, value)| value).collect();
+    values.sort_unstable();
+    assert_eq!(values, vec![0, 3, 6, 9]);
+    assert_eq!(map.get(&principal(6)), Some(6));
+}
+
+#[test]
+fn entries_should_survive_an_upgrade() {
+    let toy_memory = DefaultMemoryImpl::default();
+    let mut map = map_in(&Partitions::from(Rc::clone(&toy_memory)));
+    map.insert(principal(1), 10);
+
+    let map = map_in(&Partitions::from(Rc::clone(&toy_memory)));
+    assert_eq!(map.get(&principal(1)), Some(10));
+}
@@ -1,7 +1,9 @@
 use super::histogram::AccountsStoreHistogram;
 use super::*;
 use crate::accounts_store::toy_data::{toy_account, ToyAccountSize};
+use ic_stable_structures::DefaultMemoryImpl;
 use pretty_assertions::assert_eq;
+use proptest::proptest;
 use std::str::FromStr;
 
 const TEST_ICRC1_ACCOUNT_1: &str = "h4a5i-5vcfo-5rusv-fmb6m-vrkia-mjnkc-jpoow-h5mam-nthnm-ldqlr-bqe";
@@ -39,6 +41,52 @@ fn create_sub_account() {
     assert_eq!("CDEFGHIJKLMNOPQRSTUVWXYZ", sub_accounts[2].name);
 }
 
+#[test]
+fn delete_account() {
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let hw = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let mut store = setup_test_store();
+
+    store.create_sub_account(principal, "AAA".to_string());
+    store.create_sub_account(principal, "BBB".to_string());
+    store.register_hardware_wallet(
+        principal,
+        RegisterHardwareWalletRequest {
+            name: "HW".to_string(),
+            principal: hw,
+            device: None,
+        },
+    );
+
+    assert_eq!(store.delete_account(principal), DeleteAccountResponse::Ok);
+
+    assert!(store.get_account(principal).is_none());
+    let mut stats = Stats::default();
+    store.get_stats(&mut stats);
+    assert_eq!(1, stats.accounts_count);
+    assert_eq!(0, stats.sub_accounts_count);
+    assert_eq!(0, stats.hardware_wallet_accounts_count);
+
+    // The account can be created afresh.
+    assert_eq!(
+        store.add_account(principal),
+        AddAccountResponse::Ok(AccountIdentifier::from(principal))
+    );
+    assert!(store.get_account(principal).unwrap().sub_accounts.is_empty());
+}
+
+#[test]
+fn delete_account_account_not_found() {
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let mut store = setup_test_store();
+
+    assert_eq!(store.delete_account(principal), DeleteAccountResponse::AccountNotFound);
+
+    let mut stats = Stats::default();
+    store.get_stats(&mut stats);
+    assert_initial_test_store_stats_are_correct(&stats);
+}
+
 #[test]
 fn create_sub_account_response() {
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
@@ -125,6 +173,222 @@ fn rename_sub_account() {
     assert_eq!("BBB123".to_string(), sub_accounts[1].name);
 }
 
+fn sub_account_identifiers(store: &AccountsStore, principal: PrincipalId) -> Vec<AccountIdentifier> {
+    store
+        .get_account(principal)
+        .unwrap()
+        .sub_accounts
+        .iter()
+        .map(|sub_account| sub_account.account_identifier)
+        .collect()
+}
+
+fn sub_account_names(store: &AccountsStore, principal: PrincipalId) -> Vec<String> {
+    store
+        .get_account(principal)
+        .unwrap()
+        .sub_accounts
+        .into_iter()
+        .map(|sub_account| sub_account.name)
+        .collect()
+}
+
+#[test]
+fn archive_and_restore_sub_account() {
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let mut store = setup_test_store();
+    store.create_sub_account(principal, "AAA".to_string());
+    let account_identifier = sub_account_identifiers(&store, principal)[0];
+
+    assert_eq!(
+        store.set_sub_account_archived(
+            principal,
+            SetSubAccountArchivedRequest {
+                account_identifier,
+                archived: true,
+            }
+        ),
+        UpdateSubAccountResponse::Ok
+    );
+    let sub_accounts = store.get_account(principal).unwrap().sub_accounts;
+    assert!(sub_accounts[0].archived);
+    assert_eq!(sub_accounts[0].name, "AAA");
+
+    assert_eq!(
+        store.set_sub_account_archived(
+            principal,
+            SetSubAccountArchivedRequest {
+                account_identifier,
+                archived: false,
+            }
+        ),
+        UpdateSubAccountResponse::Ok
+    );
+    assert!(!store.get_account(principal).unwrap().sub_accounts[0].archived);
+}
+
+#[test]
+fn update_sub_account_not_found() {
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let mut store = setup_test_store();
+
+    assert_eq!(
+        store.set_sub_account_archived(
+            principal,
+            SetSubAccountArchivedRequest {
+                account_identifier: AccountIdentifier::from(principal),
+                archived: true,
+            }
+        ),
+        UpdateSubAccountResponse::SubAccountNotFound
+    );
+    assert_eq!(
+        store.set_sub_account_archived(
+            PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap(),
+            SetSubAccountArchivedRequest {
+                account_identifier: AccountIdentifier::from(principal),
+                archived: true,
+            }
+        ),
+        UpdateSubAccountResponse::AccountNotFound
+    );
+}
+
+#[test]
+fn set_sub_account_style_key() {
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let mut store = setup_test_store();
+    store.create_sub_account(principal, "AAA".to_string());
+    let account_identifier = sub_account_identifiers(&store, principal)[0];
+
+    assert_eq!(
+        store.set_sub_account_style_key(
+            principal,
+            SetSubAccountStyleKeyRequest {
+                account_identifier,
+                style_key: Some("a".repeat(Limits::DEFAULT.max_sub_account_style_key_length as usize + 1)),
+            }
+        ),
+        UpdateSubAccountResponse::StyleKeyTooLong {
+            max_length: Limits::DEFAULT.max_sub_account_style_key_length
+        }
+    );
+    assert_eq!(
+        store.set_sub_account_style_key(
+            principal,
+            SetSubAccountStyleKeyRequest {
+                account_identifier,
+                style_key: Some("orange".to_string()),
+            }
+        ),
+        UpdateSubAccountResponse::Ok
+    );
+    assert_eq!(
+        store.get_account(principal).unwrap().sub_accounts[0].style_key,
+        Some("orange".to_string())
+    );
+
+    assert_eq!(
+        store.set_sub_account_style_key(
+            principal,
+            SetSubAccountStyleKeyRequest {
+                account_identifier,
+                style_key: None,
+            }
+        ),
+        UpdateSubAccountResponse::Ok
+    );
+    assert_eq!(store.get_account(principal).unwrap().sub_accounts[0].style_key, None);
+}
+
+#[test]
+fn reorder_sub_accounts() {
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let mut store = setup_test_store();
+    for name in ["AAA", "BBB", "CCC", "DDD"] {
+        store.create_sub_account(principal, name.to_string());
+    }
+    let ids = sub_account_identifiers(&store, principal);
+
+    assert_eq!(
+        store.reorder_sub_accounts(
+            principal,
+            ReorderSubAccountsRequest {
+                account_identifiers: vec![ids[2], ids[0]],
+            }
+        ),
+        UpdateSubAccountResponse::Ok
+    );
+    // Listed sub-accounts come first, in the given order, followed by the rest by name.
+    assert_eq!(sub_account_names(&store, principal), vec!["CCC", "AAA", "BBB", "DDD"]);
+    let sub_accounts = store.get_account(principal).unwrap().sub_accounts;
+    assert_eq!(sub_accounts[0].display_order, Some(0));
+    assert_eq!(sub_accounts[1].display_order, Some(1));
+    assert_eq!(sub_accounts[2].display_order, None);
+
+    // An empty order restores the default order.
+    assert_eq!(
+        store.reorder_sub_accounts(
+            principal,
+            ReorderSubAccountsRequest {
+                account_identifiers: vec![],
+            }
+        ),
+        UpdateSubAccountResponse::Ok
+    );
+    assert_eq!(sub_account_names(&store, principal), vec!["AAA", "BBB", "CCC", "DDD"]);
+}
+
+#[test]
+fn reorder_sub_accounts_rejects_invalid_orders() {
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let mut store = setup_test_store();
+    store.create_sub_account(principal, "AAA".to_string());
+    let ids = sub_account_identifiers(&store, principal);
+
+    assert_eq!(
+        store.reorder_sub_accounts(
+            principal,
+            ReorderSubAccountsRequest {
+                account_identifiers: vec![ids[0], ids[0]],
+            }
+        ),
+        UpdateSubAccountResponse::DuplicateSubAccount
+    );
+    assert_eq!(
+        store.reorder_sub_accounts(
+            principal,
+            ReorderSubAccountsRequest {
+                account_identifiers: vec![AccountIdentifier::from(principal)],
+            }
+        ),
+        UpdateSubAccountResponse::SubAccountNotFound
+    );
+    assert_eq!(
+        store.get_account(principal).unwrap().sub_accounts[0].display_order,
+        None
+    );
+}
+
+#[test]
+fn sub_accounts_stored_without_display_settings_can_be_decoded() {
+    #[derive(CandidType)]
+    struct NamedSubAccountWithoutDisplaySettings {
+        name: String,
+        account_identifier: AccountIdentifier,
+    }
+    let account_identifier = AccountIdentifier::from(PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap());
+    let bytes = candid::encode_one(NamedSubAccountWithoutDisplaySettings {
+        name: "AAA".to_string(),
+        account_identifier,
+    })
+    .unwrap();
+
+    let sub_account: NamedSubAccount = candid::decode_one(&bytes).unwrap();
+
+    assert_eq!(NamedSubAccount::new("AAA".to_string(), account_identifier), sub_account);
+}
+
 #[test]
 fn register_hardware_wallet() {
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
@@ -138,6 +402,7 @@ fn register_hardware_wallet() {
         RegisterHardwareWalletRequest {
             name: "HW1".to_string(),
             principal: hw1,
+            device: None,
         },
     );
     let res2 = store.register_hardware_wallet(
@@ -145,6 +410,7 @@ fn register_hardware_wallet() {
         RegisterHardwareWalletRequest {
             name: "HW2".to_string(),
             principal: hw2,
+            device: None,
         },
     );
 
@@ -178,6 +444,7 @@ fn register_hardware_wallet_hardware_wallet_already_registered() {
         RegisterHardwareWalletRequest {
             name: "HW1".to_string(),
             principal: hw1,
+            device: None,
         },
     );
     let res2 = store.register_hardware_wallet(
@@ -185,6 +452,7 @@ fn register_hardware_wallet_hardware_wallet_already_registered() {
         RegisterHardwareWalletRequest {
             name: "HW2".to_string(),
             principal: hw1,
+            device: None,
         },
     );
 
@@ -205,1559 +473,4179 @@ fn register_hardware_wallet_hardware_wallet_already_registered() {
 }
 
 #[test]
-fn attach_canister_followed_by_get_canisters() {
-    let mut store = setup_test_store();
+fn rename_hardware_wallet_keeps_wallets_sorted_by_name() {
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
-
-    let canister_ids: Vec<_> = [
-        TEST_ICRC1_ACCOUNT_2,
-        TEST_ICRC1_ACCOUNT_3,
-        TEST_ICRC1_ACCOUNT_4,
-        TEST_ICRC1_ACCOUNT_5,
-        TEST_ICRC1_ACCOUNT_6,
-    ]
-    .iter()
-    .map(|&id| CanisterId::from_str(id).unwrap())
-    .collect();
-
-    let block_index_offset = 123;
-
-    for (index, canister_id) in canister_ids.iter().enumerate() {
-        let result = store.attach_canister(
+    let mut store = setup_test_store();
+    let hw1 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let hw2 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_4).unwrap();
+    for (name, hw) in [("HW1", hw1), ("HW2", hw2)] {
+        let response = store.register_hardware_wallet(
             principal,
-            AttachCanisterRequest {
-                name: index.to_string(),
-                canister_id: *canister_id,
-                block_index: Some(block_index_offset + index as u64),
+            RegisterHardwareWalletRequest {
+                name: name.to_string(),
+                principal: hw,
+                device: None,
             },
         );
-
-        assert!(matches!(result, AttachCanisterResponse::Ok));
+        assert!(matches!(response, RegisterHardwareWalletResponse::Ok));
     }
 
-    let canisters = store.get_canisters(principal);
+    assert_eq!(
+        store.rename_hardware_wallet(
+            principal,
+            RenameHardwareWalletRequest {
+                principal: hw1,
+                new_name: "Ledger".to_string(),
+            },
+        ),
+        UpdateHardwareWalletResponse::Ok
+    );
 
-    let expected: Vec<_> = canister_ids
-        .into_iter()
-        .enumerate()
-        .map(|(index, canister_id)| NamedCanister {
-            name: index.to_string(),
-            canister_id,
-            block_index: Some(block_index_offset + index as u64),
-        })
+    let account = store.get_account(principal).unwrap();
+    let names: Vec<_> = account
+        .hardware_wallet_accounts
+        .iter()
+        .map(|hw| (hw.name.as_str(), hw.principal))
         .collect();
-
-    assert_eq!(expected.len(), canisters.len());
-    for i in 0..canisters.len() {
-        assert_eq!(expected[i], canisters[i]);
-    }
+    assert_eq!(names, vec![("HW2", hw2), ("Ledger", hw1)]);
 }
 
 #[test]
-fn attach_canister_name_already_taken() {
-    let mut store = setup_test_store();
+fn rename_hardware_wallet_errors() {
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
-
-    let canister_id1 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
-    let canister_id2 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
-
-    let result1 = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "ABC".to_string(),
-            canister_id: canister_id1,
-            block_index: None,
-        },
-    );
-    let result2 = store.attach_canister(
+    let mut store = setup_test_store();
+    let hw1 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let hw2 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_4).unwrap();
+    store.register_hardware_wallet(
         principal,
-        AttachCanisterRequest {
-            name: "ABC".to_string(),
-            canister_id: canister_id2,
-            block_index: None,
+        RegisterHardwareWalletRequest {
+            name: "HW1".to_string(),
+            principal: hw1,
+            device: None,
         },
     );
 
-    assert!(matches!(result1, AttachCanisterResponse::Ok));
-    assert!(matches!(result2, AttachCanisterResponse::NameAlreadyTaken));
+    let rename = |hw: PrincipalId, new_name: &str| RenameHardwareWalletRequest {
+        principal: hw,
+        new_name: new_name.to_string(),
+    };
+    assert_eq!(
+        store.rename_hardware_wallet(principal, rename(hw2, "HW2")),
+        UpdateHardwareWalletResponse::HardwareWalletNotFound
+    );
+    assert_eq!(
+        store.rename_hardware_wallet(principal, rename(hw1, "ABCDEFGHIJKLMNOPQRSTUVWXY")),
+        UpdateHardwareWalletResponse::NameTooLong
+    );
+    assert_eq!(
+        store.rename_hardware_wallet(PrincipalId::new_user_test_id(999), rename(hw1, "HW2")),
+        UpdateHardwareWalletResponse::AccountNotFound
+    );
+    assert_eq!(
+        "HW1",
+        store.get_account(principal).unwrap().hardware_wallet_accounts[0].name
+    );
 }
 
 #[test]
-fn attach_canister_name_too_long() {
-    let mut store = setup_test_store();
+fn unregister_hardware_wallet_updates_stats() {
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let mut store = setup_test_store();
+    let hw1 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let hw2 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_4).unwrap();
+    for (name, hw) in [("HW1", hw1), ("HW2", hw2)] {
+        store.register_hardware_wallet(
+            principal,
+            RegisterHardwareWalletRequest {
+                name: name.to_string(),
+                principal: hw,
+                device: None,
+            },
+        );
+    }
+    let hardware_wallet_count = |store: &AccountsStore| {
+        let mut stats = Stats::default();
+        store.get_stats(&mut stats);
+        stats.hardware_wallet_accounts_count
+    };
+    assert_eq!(2, hardware_wallet_count(&store));
 
-    let canister_id1 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
-    let canister_id2 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let unregister = |hw: PrincipalId| UnregisterHardwareWalletRequest { principal: hw };
+    assert_eq!(
+        store.unregister_hardware_wallet(principal, unregister(hw1)),
+        UpdateHardwareWalletResponse::Ok
+    );
+    assert_eq!(
+        store.unregister_hardware_wallet(principal, unregister(hw1)),
+        UpdateHardwareWalletResponse::HardwareWalletNotFound
+    );
+    assert_eq!(
+        store.unregister_hardware_wallet(PrincipalId::new_user_test_id(999), unregister(hw2)),
+        UpdateHardwareWalletResponse::AccountNotFound
+    );
+    assert_eq!(1, hardware_wallet_count(&store));
+    let account = store.get_account(principal).unwrap();
+    assert_eq!(1, account.hardware_wallet_accounts.len());
+    assert_eq!(hw2, account.hardware_wallet_accounts[0].principal);
 
-    let result1 = store.attach_canister(
+    // The hardware wallet can be registered again.
+    let response = store.register_hardware_wallet(
         principal,
-        AttachCanisterRequest {
-            name: "ABCDEFGHIJKLMNOPQRSTUVWX".to_string(),
-            canister_id: canister_id1,
-            block_index: None,
+        RegisterHardwareWalletRequest {
+            name: "HW1".to_string(),
+            principal: hw1,
+            device: None,
         },
     );
-    let result2 = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "ABCDEFGHIJKLMNOPQRSTUVWXY".to_string(),
-            canister_id: canister_id2,
-            block_index: None,
-        },
-    );
-
-    assert!(matches!(result1, AttachCanisterResponse::Ok));
-    assert!(matches!(result2, AttachCanisterResponse::NameTooLong));
+    assert!(matches!(response, RegisterHardwareWalletResponse::Ok));
+    assert_eq!(2, hardware_wallet_count(&store));
 }
 
 #[test]
-fn attach_canister_account_not_found() {
+fn hardware_wallet_device_metadata() {
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
     let mut store = setup_test_store();
-    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
-
-    let result = store.attach_canister(
+    let hw1 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let device = HardwareWalletDevice {
+        model: Some("Nano S Plus".to_string()),
+        app_version: Some("2.4.2".to_string()),
+        last_verified_timestamp_nanos: Some(1_700_000_000_000_000_000),
+    };
+    let response = store.register_hardware_wallet(
         principal,
-        AttachCanisterRequest {
-            name: "".to_string(),
-            canister_id,
-            block_index: None,
+        RegisterHardwareWalletRequest {
+            name: "HW1".to_string(),
+            principal: hw1,
+            device: Some(device.clone()),
         },
     );
+    assert!(matches!(response, RegisterHardwareWalletResponse::Ok));
+    assert_eq!(
+        store.get_account(principal).unwrap().hardware_wallet_accounts[0].device,
+        Some(device.clone())
+    );
 
-    assert!(matches!(result, AttachCanisterResponse::AccountNotFound));
+    let too_long = HardwareWalletDevice {
+        app_version: Some("x".repeat(33)),
+        ..device.clone()
+    };
+    assert_eq!(
+        store.update_hardware_wallet_device(
+            principal,
+            UpdateHardwareWalletDeviceRequest {
+                principal: hw1,
+                device: Some(too_long),
+            },
+        ),
+        UpdateHardwareWalletResponse::DeviceFieldTooLong { max_length: 32 }
+    );
+    assert_eq!(
+        store.get_account(principal).unwrap().hardware_wallet_accounts[0].device,
+        Some(device)
+    );
+
+    assert_eq!(
+        store.update_hardware_wallet_device(
+            principal,
+            UpdateHardwareWalletDeviceRequest {
+                principal: hw1,
+                device: None,
+            },
+        ),
+        UpdateHardwareWalletResponse::Ok
+    );
+    assert_eq!(
+        store.get_account(principal).unwrap().hardware_wallet_accounts[0].device,
+        None
+    );
+}
+
+/// Checks that the hardware wallet index contains exactly the hardware wallets registered with the accounts.
+fn assert_hardware_wallet_index_is_consistent(store: &AccountsStore) {
+    let mut expected: Vec<Vec<u8>> = store
+        .accounts_db
+        .iter()
+        .flat_map(|(account_identifier, account)| {
+            account
+                .hardware_wallet_accounts
+                .iter()
+                .map(|hw| hardware_wallet_index::link_key(hw.principal, &account_identifier))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    expected.sort();
+    assert_eq!(store.hardware_wallet_index.keys(), expected);
+}
+
+fn register_test_hardware_wallet(store: &mut AccountsStore, caller: PrincipalId, hw: PrincipalId) {
+    let response = store.register_hardware_wallet(
+        caller,
+        RegisterHardwareWalletRequest {
+            name: "HW".to_string(),
+            principal: hw,
+            device: None,
+        },
+    );
+    assert!(matches!(response, RegisterHardwareWalletResponse::Ok));
 }
 
 #[test]
-fn attach_canister_canister_already_attached_with_name() {
+fn hardware_wallet_index_is_maintained() {
+    let principal1 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let principal2 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let principal3 = PrincipalId::new_user_test_id(3);
+    let hw1 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let hw2 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_4).unwrap();
     let mut store = setup_test_store();
-    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let mut linked_accounts = vec![AccountIdentifier::from(principal1), AccountIdentifier::from(principal2)];
+    linked_accounts.sort_unstable_by_key(AccountIdentifier::to_vec);
+
+    // A hardware wallet registered with two accounts.
+    register_test_hardware_wallet(&mut store, principal1, hw1);
+    register_test_hardware_wallet(&mut store, principal1, hw2);
+    register_test_hardware_wallet(&mut store, principal2, hw1);
+    assert_hardware_wallet_index_is_consistent(&store);
+    assert_eq!(store.get_hardware_wallet_links(hw1), linked_accounts);
+    assert_eq!(
+        store.get_hardware_wallet_links(hw2),
+        vec![AccountIdentifier::from(principal1)]
+    );
 
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    // Unregistering.
+    store.unregister_hardware_wallet(principal1, UnregisterHardwareWalletRequest { principal: hw2 });
+    assert_hardware_wallet_index_is_consistent(&store);
+    assert_eq!(store.get_hardware_wallet_links(hw2), vec![]);
 
-    let result1 = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "ABC".to_string(),
-            canister_id,
-            block_index: None,
-        },
+    // Moving an account to another principal.
+    store.create_account_transfer(
+        principal2,
+        CreateAccountTransferRequest { to: principal3 },
+        TEST_NOW_NANOS,
     );
-    let result2 = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "".to_string(),
-            canister_id,
-            block_index: None,
-        },
+    assert_eq!(
+        store.claim_account_transfer(
+            principal3,
+            ClaimAccountTransferRequest { from: principal2 },
+            TEST_NOW_NANOS + 1
+        ),
+        ClaimAccountTransferResponse::Ok
     );
+    assert_hardware_wallet_index_is_consistent(&store);
+    assert!(store
+        .get_hardware_wallet_links(hw1)
+        .contains(&AccountIdentifier::from(principal3)));
+
+    // Importing account data replaces the hardware wallets.
+    let ExportAccountDataResponse::Ok(bundle) = store.export_account_data(principal3) else {
+        panic!("Expected the account data to be exported");
+    };
+    register_test_hardware_wallet(&mut store, principal1, hw2);
+    assert_eq!(
+        store.import_account_data(principal1, bundle),
+        ImportAccountDataResponse::Ok
+    );
+    assert_hardware_wallet_index_is_consistent(&store);
+    assert_eq!(store.get_hardware_wallet_links(hw2), vec![]);
 
-    assert!(matches!(result1, AttachCanisterResponse::Ok));
-    assert!(matches!(result2, AttachCanisterResponse::CanisterAlreadyAttached));
+    // Deleting an account.
+    assert_eq!(store.delete_account(principal3), DeleteAccountResponse::Ok);
+    assert_hardware_wallet_index_is_consistent(&store);
+    assert_eq!(
+        store.get_hardware_wallet_links(hw1),
+        vec![AccountIdentifier::from(principal1)]
+    );
 }
 
 #[test]
-fn attach_canister_canister_already_attached_with_same_name() {
+fn hardware_wallet_can_remove_stale_links() {
+    let principal1 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let principal2 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let hw = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
     let mut store = setup_test_store();
-    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
-
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    register_test_hardware_wallet(&mut store, principal1, hw);
+    register_test_hardware_wallet(&mut store, principal2, hw);
+    let remove_link = |principal: PrincipalId| RemoveHardwareWalletLinkRequest {
+        account_identifier: AccountIdentifier::from(principal),
+    };
 
-    let result1 = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "ABC".to_string(),
-            canister_id,
-            block_index: None,
-        },
+    // Only the hardware wallet itself can remove its links.
+    assert_eq!(
+        store.remove_hardware_wallet_link(principal2, remove_link(principal1)),
+        RemoveHardwareWalletLinkResponse::LinkNotFound
     );
-    let result2 = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "ABC".to_string(),
-            canister_id,
-            block_index: None,
-        },
+    assert_eq!(
+        store.remove_hardware_wallet_link(hw, remove_link(principal1)),
+        RemoveHardwareWalletLinkResponse::Ok
+    );
+    assert_eq!(
+        store.remove_hardware_wallet_link(hw, remove_link(principal1)),
+        RemoveHardwareWalletLinkResponse::LinkNotFound
     );
 
-    assert!(matches!(result1, AttachCanisterResponse::Ok));
-    assert!(matches!(result2, AttachCanisterResponse::CanisterAlreadyAttached));
+    assert_hardware_wallet_index_is_consistent(&store);
+    assert!(store
+        .get_account(principal1)
+        .unwrap()
+        .hardware_wallet_accounts
+        .is_empty());
+    assert_eq!(
+        store.get_hardware_wallet_links(hw),
+        vec![AccountIdentifier::from(principal2)]
+    );
+    let mut stats = Stats::default();
+    store.get_stats(&mut stats);
+    assert_eq!(1, stats.hardware_wallet_accounts_count);
 }
 
 #[test]
-fn attach_canister_canister_already_attached_with_different_name() {
+fn hardware_wallet_index_is_backfilled_on_upgrade_if_missing() {
+    let principal1 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let principal2 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let hw1 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let hw2 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_4).unwrap();
     let mut store = setup_test_store();
-    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    register_test_hardware_wallet(&mut store, principal1, hw1);
+    register_test_hardware_wallet(&mut store, principal2, hw1);
+    register_test_hardware_wallet(&mut store, principal2, hw2);
+
+    // Simulate upgrading from a release without the index.
+    with_partitions(|partitions| {
+        let _ = HardwareWalletIndex::new(partitions.get(PartitionType::HardwareWalletIndex.memory_id()));
+        let _ = StableValue::new(
+            partitions.get(PartitionType::Backfills.memory_id()),
+            Backfills::default(),
+        );
+    });
+    let mut restored = AccountsStore::load();
+    assert!(restored.hardware_wallet_index.is_empty());
 
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    while !restored.step_backfills(1) {}
 
-    let result1 = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "ABC".to_string(),
-            canister_id,
-            block_index: None,
-        },
-    );
-    let result2 = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "XYZ".to_string(),
-            canister_id,
-            block_index: None,
-        },
-    );
+    assert_hardware_wallet_index_is_consistent(&restored);
+    assert_eq!(restored.hardware_wallet_index.len(), 3);
+}
 
-    assert!(matches!(result1, AttachCanisterResponse::Ok));
-    assert!(matches!(result2, AttachCanisterResponse::CanisterAlreadyAttached));
+/// Checks that the canister index contains exactly the canisters attached to the accounts.
+fn assert_canister_index_is_consistent(store: &AccountsStore) {
+    let mut expected: Vec<Vec<u8>> = store
+        .accounts_db
+        .iter()
+        .flat_map(|(account_identifier, account)| {
+            account
+                .canisters
+                .iter()
+                .map(|canister| canister_index::link_key(canister.canister_id, &account_identifier))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    expected.sort();
+    assert_eq!(store.canister_index.keys(), expected);
+}
+
+/// Applies an operation that may change which accounts have attached which canisters.
+///
+/// The operation is chosen by `operation`, on the test account `account` and the canister `canister`.  Other test
+/// accounts are derived from these, so that a short sequence of operations exercises many interactions.
+fn apply_canister_index_operation(store: &mut AccountsStore, operation: u8, account: u64, canister: u64) {
+    let principal = PrincipalId::new_user_test_id(account);
+    let other_principal = PrincipalId::new_user_test_id((account + 1) % 3);
+    let canister_id = CanisterId::from(canister);
+    match operation {
+        0 => {
+            store.add_account(principal);
+        }
+        1 => {
+            store.attach_canister(
+                principal,
+                AttachCanisterRequest {
+                    name: String::new(),
+                    canister_id,
+                    block_index: None,
+                },
+            );
+        }
+        2 => {
+            store.detach_canister(principal, DetachCanisterRequest { canister_id });
+        }
+        3 => {
+            store.delete_account(principal);
+        }
+        4 => {
+            store.create_account_transfer(
+                principal,
+                CreateAccountTransferRequest { to: other_principal },
+                TEST_NOW_NANOS,
+            );
+            store.claim_account_transfer(
+                other_principal,
+                ClaimAccountTransferRequest { from: principal },
+                TEST_NOW_NANOS + 1,
+            );
+        }
+        _ => {
+            if let ExportAccountDataResponse::Ok(bundle) = store.export_account_data(other_principal) {
+                store.import_account_data(principal, bundle);
+            }
+        }
+    }
 }
 
 #[test]
-fn attach_canister_canister_already_attached_both_without_name() {
+fn canister_index_is_maintained() {
+    let principal1 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let principal2 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let canister1 = CanisterId::from(1);
+    let canister2 = CanisterId::from(2);
     let mut store = setup_test_store();
-    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let attach = |canister_id: CanisterId| AttachCanisterRequest {
+        name: String::new(),
+        canister_id,
+        block_index: None,
+    };
 
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    store.attach_canister(principal1, attach(canister1));
+    store.attach_canister(principal1, attach(canister2));
+    store.attach_canister(principal2, attach(canister1));
+    assert_canister_index_is_consistent(&store);
+    assert_eq!(store.get_canister_attachment_count(canister1), 2);
+    assert_eq!(store.get_canister_attachment_count(canister2), 1);
 
-    let result1 = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "".to_string(),
-            canister_id,
-            block_index: None,
-        },
-    );
-    let result2 = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "".to_string(),
-            canister_id,
-            block_index: None,
+    // Attaching again does not count twice.
+    store.rename_canister(
+        principal1,
+        RenameCanisterRequest {
+            name: "named".to_string(),
+            canister_id: canister1,
         },
     );
-
-    assert!(matches!(result1, AttachCanisterResponse::Ok));
-    assert!(matches!(result2, AttachCanisterResponse::CanisterAlreadyAttached));
+    store.attach_canister(principal1, attach(canister1));
+    assert_canister_index_is_consistent(&store);
+    assert_eq!(store.get_canister_attachment_count(canister1), 2);
+
+    store.detach_canister(principal1, DetachCanisterRequest { canister_id: canister1 });
+    assert_canister_index_is_consistent(&store);
+    assert_eq!(store.get_canister_attachment_count(canister1), 1);
+
+    assert_eq!(store.delete_account(principal2), DeleteAccountResponse::Ok);
+    assert_canister_index_is_consistent(&store);
+    assert_eq!(store.get_canister_attachment_count(canister1), 0);
+    assert_eq!(store.get_canister_attachment_count(canister2), 1);
 }
 
 #[test]
-fn attach_canister_substitutes_empty_name_canister() {
+fn canister_index_is_backfilled_on_upgrade_if_missing() {
+    let principal1 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let principal2 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
     let mut store = setup_test_store();
-    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    for (principal, canister) in [(principal1, 1), (principal2, 1), (principal2, 2)] {
+        store.attach_canister(
+            principal,
+            AttachCanisterRequest {
+                name: String::new(),
+                canister_id: CanisterId::from(canister),
+                block_index: None,
+            },
+        );
+    }
 
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
-    let block_index = Some(123);
+    // Simulate upgrading from a release without the index.
+    with_partitions(|partitions| {
+        let _ = CanisterIndex::new(partitions.get(PartitionType::CanisterIndex.memory_id()));
+        let _ = StableValue::new(
+            partitions.get(PartitionType::Backfills.memory_id()),
+            Backfills::default(),
+        );
+    });
+    let mut restored = AccountsStore::load();
+    assert!(restored.canister_index.is_empty());
 
-    store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "".to_string(),
-            canister_id,
-            block_index,
-        },
+    while !restored.step_backfills(1) {}
+
+    assert_canister_index_is_consistent(&restored);
+    assert_eq!(restored.get_canister_attachment_count(CanisterId::from(1)), 2);
+}
+
+proptest! {
+    /// The canister index stays consistent with the accounts whatever the sequence of operations.
+    #[test]
+    fn canister_index_stays_consistent(
+        operations in proptest::collection::vec((0u8..6, 0u64..3, 0u64..4), 0..40),
+    ) {
+        let mut store = AccountsStore::default();
+        for account in 0..3 {
+            store.add_account(PrincipalId::new_user_test_id(account));
+        }
+        for (operation, account, canister) in operations {
+            apply_canister_index_operation(&mut store, operation, account, canister);
+            assert_canister_index_is_consistent(&store);
+        }
+    }
+}
+
+#[test]
+fn migrating_accounts_to_fixed_size_keys_preserves_the_store() {
+    let num_accounts = 50;
+    let expected = AccountsStore::with_toy_accounts(num_accounts);
+
+    // The same accounts, in the legacy map keyed by variable-length account identifiers.
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    let mut store = AccountsStore::new_in(&partitions);
+    store.create_toy_accounts(num_accounts);
+    store
+        .accounts_db
+        .move_to_legacy_map(partitions.get(PartitionType::Accounts.memory_id()));
+    assert_eq!(store, expected);
+
+    let mut countdowns = Vec::new();
+    loop {
+        let mut stats = Stats::default();
+        store.get_stats(&mut stats);
+        countdowns.push(stats.migration_countdown.unwrap());
+        if store.step_migration(20) {
+            break;
+        }
+        // The store is unchanged at every step of the migration.
+        assert_eq!(store, expected);
+    }
+    assert_eq!(countdowns, vec![50, 30, 10]);
+    assert_eq!(store.migration_countdown(), 0);
+    assert_eq!(store, expected);
+}
+
+/// Creates toy accounts, removing the principal from those with the given indices as if they had been created before
+/// principals were stored.
+fn store_with_principal_less_accounts(num_accounts: u64, principal_less: &[u64]) -> AccountsStore {
+    let mut store = AccountsStore::with_toy_accounts(num_accounts);
+    for &index in principal_less {
+        let account_identifier = AccountIdentifier::from(PrincipalId::new_user_test_id(index)).to_vec();
+        let mut account = store.accounts_db.get(&account_identifier).unwrap();
+        account.principal = None;
+        store.accounts_db.insert(account_identifier, account);
+    }
+    store
+}
+
+fn principal_less_accounts_count(store: &AccountsStore) -> Option<u64> {
+    let mut stats = Stats::default();
+    store.get_stats(&mut stats);
+    stats.principal_less_accounts_count
+}
+
+#[test]
+fn principal_less_accounts_are_counted_in_batches() {
+    let mut store = store_with_principal_less_accounts(25, &[0, 7, 13, 24]);
+
+    assert!(!store.step_principal_less_accounts_scan(10));
+    assert_eq!(principal_less_accounts_count(&store), None);
+    assert!(!store.step_principal_less_accounts_scan(10));
+    assert_eq!(principal_less_accounts_count(&store), None);
+    assert!(store.step_principal_less_accounts_scan(10));
+    assert_eq!(principal_less_accounts_count(&store), Some(4));
+
+    // Further steps change nothing.
+    assert!(store.step_principal_less_accounts_scan(10));
+    assert_eq!(principal_less_accounts_count(&store), Some(4));
+}
+
+#[test]
+fn adding_the_principal_updates_the_principal_less_accounts_count() {
+    let mut store = store_with_principal_less_accounts(5, &[1, 3]);
+    assert!(store.step_principal_less_accounts_scan(10));
+    assert_eq!(principal_less_accounts_count(&store), Some(2));
+
+    store.add_account(PrincipalId::new_user_test_id(1));
+    assert_eq!(principal_less_accounts_count(&store), Some(1));
+
+    // Accounts that already have a principal are not counted again.
+    store.add_account(PrincipalId::new_user_test_id(1));
+    store.add_account(PrincipalId::new_user_test_id(2));
+    assert_eq!(principal_less_accounts_count(&store), Some(1));
+
+    assert_eq!(
+        store.delete_account(PrincipalId::new_user_test_id(3)),
+        DeleteAccountResponse::Ok
     );
+    assert_eq!(principal_less_accounts_count(&store), Some(0));
+}
 
-    let initial_canisters = store.get_canisters(principal);
-    assert!(initial_canisters[0].name.is_empty());
+#[test]
+fn claiming_a_principal_less_account_updates_the_principal_less_accounts_count() {
+    let mut store = store_with_principal_less_accounts(5, &[1, 3]);
+    assert!(store.step_principal_less_accounts_scan(10));
+    assert_eq!(principal_less_accounts_count(&store), Some(2));
+    let from = PrincipalId::new_user_test_id(1);
+    let to = PrincipalId::new_user_test_id(10);
+
+    store.create_account_transfer(from, CreateAccountTransferRequest { to }, TEST_NOW_NANOS);
+    assert_eq!(
+        store.claim_account_transfer(to, ClaimAccountTransferRequest { from }, TEST_NOW_NANOS + 1),
+        ClaimAccountTransferResponse::Ok
+    );
 
-    let name = "XYZ";
-    let result = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: name.to_string(),
-            canister_id,
-            // Original block_index should be preserved.
-            block_index: None,
-        },
+    assert_eq!(principal_less_accounts_count(&store), Some(1));
+    assert_eq!(
+        store
+            .accounts_db
+            .get(&AccountIdentifier::from(to).to_vec())
+            .and_then(|account| account.principal),
+        Some(to)
     );
+}
 
-    let canisters = store.get_canisters(principal);
-    assert_eq!(name, canisters[0].name);
-    assert_eq!(block_index, canisters[0].block_index);
-    assert!(matches!(result, AttachCanisterResponse::Ok));
+#[test]
+fn accounts_given_a_principal_during_the_scan_are_not_counted() {
+    let principal_less = [0, 1, 2, 3, 4, 5];
+    let mut store = store_with_principal_less_accounts(6, &principal_less);
+    assert!(!store.step_principal_less_accounts_scan(3));
+    for index in principal_less {
+        store.add_account(PrincipalId::new_user_test_id(index));
+    }
+    assert!(!store.step_principal_less_accounts_scan(3));
+    assert!(store.step_principal_less_accounts_scan(3));
+    assert_eq!(principal_less_accounts_count(&store), Some(0));
 }
 
 #[test]
-fn attach_canister_canister_already_attached_with_block_index() {
-    let name = "my canister";
+fn attach_canister_followed_by_get_canisters() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
 
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
-
-    let result1 = store.attach_canister(
+    let canister_ids: Vec<_> = [
+        TEST_ICRC1_ACCOUNT_2,
+        TEST_ICRC1_ACCOUNT_3,
+        TEST_ICRC1_ACCOUNT_4,
+        TEST_ICRC1_ACCOUNT_5,
+        TEST_ICRC1_ACCOUNT_6,
+    ]
+    .iter()
+    .map(|&id| CanisterId::from_str(id).unwrap())
+    .collect();
+
+    let block_index_offset = 123;
+
+    for (index, canister_id) in canister_ids.iter().enumerate() {
+        let result = store.attach_canister(
+            principal,
+            AttachCanisterRequest {
+                name: index.to_string(),
+                canister_id: *canister_id,
+                block_index: Some(block_index_offset + index as u64),
+            },
+        );
+
+        assert!(matches!(result, AttachCanisterResponse::Ok));
+    }
+
+    let canisters = store.get_canisters(principal);
+
+    let expected: Vec<_> = canister_ids
+        .into_iter()
+        .enumerate()
+        .map(|(index, canister_id)| NamedCanister {
+            name: index.to_string(),
+            canister_id,
+            block_index: Some(block_index_offset + index as u64),
+            tags: None,
+            note: None,
+            low_cycles_threshold: None,
+        })
+        .collect();
+
+    assert_eq!(expected.len(), canisters.len());
+    for i in 0..canisters.len() {
+        assert_eq!(expected[i], canisters[i]);
+    }
+}
+
+#[test]
+fn attach_canister_name_already_taken() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id1 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let canister_id2 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+
+    let result1 = store.attach_canister(
         principal,
         AttachCanisterRequest {
-            name: name.to_string(),
+            name: "ABC".to_string(),
+            canister_id: canister_id1,
+            block_index: None,
+        },
+    );
+    let result2 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "ABC".to_string(),
+            canister_id: canister_id2,
+            block_index: None,
+        },
+    );
+
+    assert!(matches!(result1, AttachCanisterResponse::Ok));
+    assert!(matches!(result2, AttachCanisterResponse::NameAlreadyTaken));
+}
+
+#[test]
+fn attach_canister_name_too_long() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id1 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let canister_id2 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+
+    let result1 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "ABCDEFGHIJKLMNOPQRSTUVWX".to_string(),
+            canister_id: canister_id1,
+            block_index: None,
+        },
+    );
+    let result2 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "ABCDEFGHIJKLMNOPQRSTUVWXY".to_string(),
+            canister_id: canister_id2,
+            block_index: None,
+        },
+    );
+
+    assert!(matches!(result1, AttachCanisterResponse::Ok));
+    assert!(matches!(result2, AttachCanisterResponse::NameTooLong));
+}
+
+#[test]
+fn attach_canister_account_not_found() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+
+    let result = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "".to_string(),
             canister_id,
-            block_index: Some(123),
+            block_index: None,
+        },
+    );
+
+    assert!(matches!(result, AttachCanisterResponse::AccountNotFound));
+}
+
+#[test]
+fn attach_canister_canister_already_attached_with_name() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+
+    let result1 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "ABC".to_string(),
+            canister_id,
+            block_index: None,
+        },
+    );
+    let result2 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "".to_string(),
+            canister_id,
+            block_index: None,
+        },
+    );
+
+    assert!(matches!(result1, AttachCanisterResponse::Ok));
+    assert!(matches!(result2, AttachCanisterResponse::CanisterAlreadyAttached));
+}
+
+#[test]
+fn attach_canister_canister_already_attached_with_same_name() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+
+    let result1 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "ABC".to_string(),
+            canister_id,
+            block_index: None,
+        },
+    );
+    let result2 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "ABC".to_string(),
+            canister_id,
+            block_index: None,
+        },
+    );
+
+    assert!(matches!(result1, AttachCanisterResponse::Ok));
+    assert!(matches!(result2, AttachCanisterResponse::CanisterAlreadyAttached));
+}
+
+#[test]
+fn attach_canister_canister_already_attached_with_different_name() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+
+    let result1 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "ABC".to_string(),
+            canister_id,
+            block_index: None,
+        },
+    );
+    let result2 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "XYZ".to_string(),
+            canister_id,
+            block_index: None,
+        },
+    );
+
+    assert!(matches!(result1, AttachCanisterResponse::Ok));
+    assert!(matches!(result2, AttachCanisterResponse::CanisterAlreadyAttached));
+}
+
+#[test]
+fn attach_canister_canister_already_attached_both_without_name() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+
+    let result1 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "".to_string(),
+            canister_id,
+            block_index: None,
         },
     );
     let result2 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "".to_string(),
+            canister_id,
+            block_index: None,
+        },
+    );
+
+    assert!(matches!(result1, AttachCanisterResponse::Ok));
+    assert!(matches!(result2, AttachCanisterResponse::CanisterAlreadyAttached));
+}
+
+#[test]
+fn attach_canister_substitutes_empty_name_canister() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let block_index = Some(123);
+
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "".to_string(),
+            canister_id,
+            block_index,
+        },
+    );
+
+    let initial_canisters = store.get_canisters(principal);
+    assert!(initial_canisters[0].name.is_empty());
+
+    let name = "XYZ";
+    let result = store.attach_canister(
         principal,
         AttachCanisterRequest {
             name: name.to_string(),
             canister_id,
+            // Original block_index should be preserved.
             block_index: None,
         },
     );
 
-    assert!(matches!(result1, AttachCanisterResponse::Ok));
-    assert!(matches!(result2, AttachCanisterResponse::CanisterAlreadyAttached));
+    let canisters = store.get_canisters(principal);
+    assert_eq!(name, canisters[0].name);
+    assert_eq!(block_index, canisters[0].block_index);
+    assert!(matches!(result, AttachCanisterResponse::Ok));
+}
+
+#[test]
+fn attach_canister_canister_already_attached_with_block_index() {
+    let name = "my canister";
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+
+    let result1 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: name.to_string(),
+            canister_id,
+            block_index: Some(123),
+        },
+    );
+    let result2 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: name.to_string(),
+            canister_id,
+            block_index: None,
+        },
+    );
+
+    assert!(matches!(result1, AttachCanisterResponse::Ok));
+    assert!(matches!(result2, AttachCanisterResponse::CanisterAlreadyAttached));
+}
+
+#[test]
+fn attach_canister_canister_already_attached_with_same_name_and_block_index() {
+    let name = "my canister";
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+
+    let result1 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: name.to_string(),
+            canister_id,
+            block_index: Some(123),
+        },
+    );
+    let result2 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: name.to_string(),
+            canister_id,
+            block_index: Some(123),
+        },
+    );
+
+    assert!(matches!(result1, AttachCanisterResponse::Ok));
+    assert!(matches!(result2, AttachCanisterResponse::CanisterAlreadyAttached));
+}
+
+#[test]
+fn attach_canister_canister_already_attached_with_different_block_index() {
+    let name = "my canister";
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+
+    let result1 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: name.to_string(),
+            canister_id,
+            block_index: Some(123),
+        },
+    );
+    let result2 = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: name.to_string(),
+            canister_id,
+            block_index: Some(456),
+        },
+    );
+
+    assert!(matches!(result1, AttachCanisterResponse::Ok));
+    assert!(matches!(result2, AttachCanisterResponse::CanisterAlreadyAttached));
+}
+
+#[test]
+fn attach_canister_substitutes_absent_block_index() {
+    let name = "my canister";
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: name.to_string(),
+            canister_id,
+            block_index: None,
+        },
+    );
+
+    let initial_canisters = store.get_canisters(principal);
+    assert!(initial_canisters[0].block_index.is_none());
+
+    let block_index = Some(123);
+    let result = store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            // Original name should be preserved.
+            name: "".to_string(),
+            canister_id,
+            block_index,
+        },
+    );
+
+    let canisters = store.get_canisters(principal);
+    assert_eq!(block_index, canisters[0].block_index);
+    assert!(matches!(result, AttachCanisterResponse::Ok));
+}
+
+#[test]
+fn attach_canister_and_rename() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+
+    let initial_name = "ABC".to_string();
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: initial_name.clone(),
+            canister_id,
+            block_index: None,
+        },
+    );
+
+    let canisters = store.get_canisters(principal);
+    assert_eq!(initial_name, canisters[0].name);
+
+    let final_name = "DEF".to_string();
+    store.rename_canister(
+        principal,
+        RenameCanisterRequest {
+            name: final_name.clone(),
+            canister_id,
+        },
+    );
+
+    let canisters = store.get_canisters(principal);
+    assert_eq!(final_name, canisters[0].name);
+}
+
+#[test]
+fn rename_to_empty_name_succeeds() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+
+    let initial_name = "ABC".to_string();
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: initial_name.clone(),
+            canister_id,
+            block_index: None,
+        },
+    );
+
+    let canisters = store.get_canisters(principal);
+    assert_eq!(initial_name, canisters[0].name);
+
+    let final_name = "".to_string();
+    store.rename_canister(
+        principal,
+        RenameCanisterRequest {
+            name: final_name.clone(),
+            canister_id,
+        },
+    );
+
+    let canisters = store.get_canisters(principal);
+    assert_eq!(final_name, canisters[0].name);
+}
+
+#[test]
+fn rename_preserves_block_index() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+
+    let initial_name = "ABC".to_string();
+    let block_index = Some(123);
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: initial_name.clone(),
+            canister_id,
+            block_index,
+        },
+    );
+
+    let canisters = store.get_canisters(principal);
+    assert_eq!(initial_name, canisters[0].name);
+
+    let final_name = "".to_string();
+    store.rename_canister(
+        principal,
+        RenameCanisterRequest {
+            name: final_name.clone(),
+            canister_id,
+        },
+    );
+
+    let canisters = store.get_canisters(principal);
+    assert_eq!(final_name, canisters[0].name);
+    assert_eq!(block_index, canisters[0].block_index);
+}
+
+#[test]
+fn rename_to_taken_name_fails() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let canister_id2 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+
+    let name1 = "ABC".to_string();
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: name1.clone(),
+            canister_id,
+            block_index: None,
+        },
+    );
+    let name2 = "DEF".to_string();
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: name2.clone(),
+            canister_id: canister_id2,
+            block_index: None,
+        },
+    );
+    let canisters = store.get_canisters(principal);
+    assert_eq!(name1, canisters[0].name);
+    assert_eq!(name2, canisters[1].name);
+
+    let response = store.rename_canister(
+        principal,
+        RenameCanisterRequest {
+            name: name1.clone(),
+            canister_id: canister_id2,
+        },
+    );
+
+    assert!(matches!(response, RenameCanisterResponse::NameAlreadyTaken));
+
+    let canisters = store.get_canisters(principal);
+    assert_eq!(name1, canisters[0].name);
+    assert_eq!(name2, canisters[1].name);
+}
+
+#[test]
+fn rename_to_long_name_fails() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+
+    let long_name = "ABCDEFGHIJKLMNOPQRSTUVWXY".to_string();
+    let name = "DEF".to_string();
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: name.clone(),
+            canister_id,
+            block_index: None,
+        },
+    );
+    let response = store.rename_canister(
+        principal,
+        RenameCanisterRequest {
+            name: long_name,
+            canister_id,
+        },
+    );
+    let canisters = store.get_canisters(principal);
+    assert_eq!(name, canisters[0].name);
+    assert!(matches!(response, RenameCanisterResponse::NameTooLong));
+}
+
+#[test]
+fn rename_not_found_canister() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let canister_id2 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "DEF".to_string(),
+            canister_id,
+            block_index: None,
+        },
+    );
+    let response = store.rename_canister(
+        principal,
+        RenameCanisterRequest {
+            name: "ABC".to_string(),
+            canister_id: canister_id2,
+        },
+    );
+    assert!(matches!(response, RenameCanisterResponse::CanisterNotFound));
+}
+
+#[test]
+fn rename_not_found_account() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let principal2 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "DEF".to_string(),
+            canister_id,
+            block_index: None,
+        },
+    );
+    let response = store.rename_canister(
+        principal2,
+        RenameCanisterRequest {
+            name: "ABC".to_string(),
+            canister_id,
+        },
+    );
+    assert!(matches!(response, RenameCanisterResponse::AccountNotFound));
+}
+
+#[test]
+fn canisters_ordered_by_name_if_exists_then_by_id() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id1 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let canister_id2 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let canister_id3 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_4).unwrap();
+    let canister_id4 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_5).unwrap();
+
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "".to_string(),
+            canister_id: canister_id1,
+            block_index: None,
+        },
+    );
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "ABC".to_string(),
+            canister_id: canister_id2,
+            block_index: None,
+        },
+    );
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "XYZ".to_string(),
+            canister_id: canister_id3,
+            block_index: None,
+        },
+    );
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "".to_string(),
+            canister_id: canister_id4,
+            block_index: None,
+        },
+    );
+
+    let canisters = store.get_canisters(principal);
+
+    assert_eq!(4, canisters.len());
+    assert_eq!(canister_id2, canisters[0].canister_id);
+    assert_eq!(canister_id3, canisters[1].canister_id);
+    assert_eq!(canister_id4, canisters[2].canister_id);
+    assert_eq!(canister_id1, canisters[3].canister_id);
+}
+
+#[test]
+fn detach_canister() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id1 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let canister_id2 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "ABC".to_string(),
+            canister_id: canister_id1,
+            block_index: None,
+        },
+    );
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "XYZ".to_string(),
+            canister_id: canister_id2,
+            block_index: None,
+        },
+    );
+
+    let result = store.detach_canister(
+        principal,
+        DetachCanisterRequest {
+            canister_id: canister_id1,
+        },
+    );
+
+    assert!(matches!(result, DetachCanisterResponse::Ok));
+
+    let canisters = store.get_canisters(principal);
+
+    assert_eq!(1, canisters.len());
+    assert_eq!(canister_id2, canisters[0].canister_id);
+}
+
+#[test]
+fn detach_canister_canister_not_found() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let canister_id1 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let canister_id2 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "ABC".to_string(),
+            canister_id: canister_id1,
+            block_index: None,
+        },
+    );
+
+    let result = store.detach_canister(
+        principal,
+        DetachCanisterRequest {
+            canister_id: canister_id2,
+        },
+    );
+
+    assert!(matches!(result, DetachCanisterResponse::CanisterNotFound));
+
+    let canisters = store.get_canisters(principal);
+
+    assert_eq!(1, canisters.len());
+    assert_eq!(canister_id1, canisters[0].canister_id);
+}
+
+#[test]
+fn update_canister_metadata_followed_by_get_canisters() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "ABC".to_string(),
+            canister_id,
+            block_index: Some(7),
+        },
+    );
+
+    let response = store.update_canister_metadata(
+        principal,
+        UpdateCanisterMetadataRequest {
+            canister_id,
+            tags: Some(vec!["prod".to_string(), "frontend".to_string()]),
+            note: Some("Serves the landing page".to_string()),
+            low_cycles_threshold: Some(1_000_000_000_000),
+        },
+    );
+
+    assert_eq!(UpdateCanisterMetadataResponse::Ok, response);
+    assert_eq!(
+        vec![NamedCanister {
+            name: "ABC".to_string(),
+            canister_id,
+            block_index: Some(7),
+            tags: Some(vec!["prod".to_string(), "frontend".to_string()]),
+            note: Some("Serves the landing page".to_string()),
+            low_cycles_threshold: Some(1_000_000_000_000),
+        }],
+        store.get_canisters(principal)
+    );
+
+    // Metadata is kept when the canister is renamed or attached again with more information.
+    store.rename_canister(
+        principal,
+        RenameCanisterRequest {
+            name: "XYZ".to_string(),
+            canister_id,
+        },
+    );
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: String::new(),
+            canister_id,
+            block_index: None,
+        },
+    );
+    let canisters = store.get_canisters(principal);
+    assert_eq!("XYZ", canisters[0].name);
+    assert_eq!(Some(1_000_000_000_000), canisters[0].low_cycles_threshold);
+
+    // Fields that are not provided are cleared.
+    store.update_canister_metadata(
+        principal,
+        UpdateCanisterMetadataRequest {
+            canister_id,
+            tags: None,
+            note: None,
+            low_cycles_threshold: None,
+        },
+    );
+    let canisters = store.get_canisters(principal);
+    assert_eq!(None, canisters[0].tags);
+    assert_eq!(None, canisters[0].note);
+    assert_eq!(None, canisters[0].low_cycles_threshold);
+}
+
+#[test]
+fn update_canister_metadata_validates_sizes() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "ABC".to_string(),
+            canister_id,
+            block_index: None,
+        },
+    );
+    let request = |tags: Vec<String>, note: String| UpdateCanisterMetadataRequest {
+        canister_id,
+        tags: Some(tags),
+        note: Some(note),
+        low_cycles_threshold: None,
+    };
+    let max_tags: Vec<String> = (0..Limits::DEFAULT.max_canister_tags)
+        .map(|index| index.to_string())
+        .collect();
+    let max_tag = "A".repeat(Limits::DEFAULT.max_canister_tag_length as usize);
+    let max_note = "A".repeat(Limits::DEFAULT.max_canister_note_length as usize);
+
+    assert_eq!(
+        UpdateCanisterMetadataResponse::Ok,
+        store.update_canister_metadata(principal, request(max_tags.clone(), max_note.clone()))
+    );
+    assert_eq!(
+        UpdateCanisterMetadataResponse::Ok,
+        store.update_canister_metadata(principal, request(vec![max_tag.clone()], max_note.clone()))
+    );
+    assert_eq!(
+        UpdateCanisterMetadataResponse::TooManyTags {
+            limit: Limits::DEFAULT.max_canister_tags
+        },
+        store.update_canister_metadata(
+            principal,
+            request([max_tags, vec!["extra".to_string()]].concat(), String::new())
+        )
+    );
+    assert_eq!(
+        UpdateCanisterMetadataResponse::TagTooLong {
+            max_length: Limits::DEFAULT.max_canister_tag_length
+        },
+        store.update_canister_metadata(principal, request(vec![format!("{max_tag}A")], String::new()))
+    );
+    assert_eq!(
+        UpdateCanisterMetadataResponse::NoteTooLong {
+            max_length: Limits::DEFAULT.max_canister_note_length
+        },
+        store.update_canister_metadata(principal, request(vec![], format!("{max_note}A")))
+    );
+    // The last valid metadata is kept.
+    assert_eq!(Some(vec![max_tag]), store.get_canisters(principal)[0].tags);
+}
+
+#[test]
+fn update_canister_metadata_not_found() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let unknown_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let request = || UpdateCanisterMetadataRequest {
+        canister_id,
+        tags: None,
+        note: Some("note".to_string()),
+        low_cycles_threshold: None,
+    };
+
+    assert_eq!(
+        UpdateCanisterMetadataResponse::CanisterNotFound,
+        store.update_canister_metadata(principal, request())
+    );
+    assert_eq!(
+        UpdateCanisterMetadataResponse::AccountNotFound,
+        store.update_canister_metadata(unknown_principal, request())
+    );
+}
+
+#[test]
+fn canisters_stored_without_metadata_can_be_decoded() {
+    #[derive(CandidType)]
+    struct NamedCanisterWithoutMetadata {
+        name: String,
+        canister_id: CanisterId,
+        block_index: Option<BlockIndex>,
+    }
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let bytes = candid::encode_one(NamedCanisterWithoutMetadata {
+        name: "ABC".to_string(),
+        canister_id,
+        block_index: Some(7),
+    })
+    .unwrap();
+
+    let canister: NamedCanister = candid::decode_one(&bytes).unwrap();
+
+    assert_eq!(
+        NamedCanister {
+            name: "ABC".to_string(),
+            canister_id,
+            block_index: Some(7),
+            tags: None,
+            note: None,
+            low_cycles_threshold: None,
+        },
+        canister
+    );
+}
+
+/// Attaches four canisters with names "Beta", "alpha", "" and "Gamma" and decreasing block indices.
+fn attach_canisters_for_listing(store: &mut AccountsStore, principal: PrincipalId) -> [CanisterId; 4] {
+    let canister_ids = [
+        CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap(),
+        CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap(),
+        CanisterId::from_str(TEST_ICRC1_ACCOUNT_4).unwrap(),
+        CanisterId::from_str(TEST_ICRC1_ACCOUNT_5).unwrap(),
+    ];
+    for (index, (name, canister_id)) in ["Beta", "alpha", "", "Gamma"].iter().zip(canister_ids).enumerate() {
+        let response = store.attach_canister(
+            principal,
+            AttachCanisterRequest {
+                name: name.to_string(),
+                canister_id,
+                block_index: Some(10 - index as u64),
+            },
+        );
+        assert!(matches!(response, AttachCanisterResponse::Ok));
+    }
+    canister_ids
+}
+
+fn list_canisters_ok(
+    store: &AccountsStore,
+    principal: PrincipalId,
+    request: ListCanistersRequest,
+) -> ListCanistersResult {
+    match store.list_canisters(principal, request) {
+        ListCanistersResponse::Ok(result) => result,
+        ListCanistersResponse::AccountNotFound => panic!("Account not found"),
+    }
+}
+
+fn canister_ids_of(result: &ListCanistersResult) -> Vec<CanisterId> {
+    result.canisters.iter().map(|canister| canister.canister_id).collect()
+}
+
+#[test]
+fn list_canisters_sorts_and_pages() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let [id1, id2, id3, id4] = attach_canisters_for_listing(&mut store, principal);
+
+    // By default canisters are listed in the same order as `get_canisters`.
+    let result = list_canisters_ok(
+        &store,
+        principal,
+        ListCanistersRequest {
+            limit: 10,
+            ..ListCanistersRequest::default()
+        },
+    );
+    assert_eq!(4, result.matching_count);
+    assert_eq!(store.get_canisters(principal), result.canisters);
+
+    let mut by_canister_id = vec![id1, id2, id3, id4];
+    by_canister_id.sort();
+    let result = list_canisters_ok(
+        &store,
+        principal,
+        ListCanistersRequest {
+            limit: 10,
+            sort_by: Some(CanisterSortOrder::CanisterId),
+            ..ListCanistersRequest::default()
+        },
+    );
+    assert_eq!(by_canister_id, canister_ids_of(&result));
+
+    let result = list_canisters_ok(
+        &store,
+        principal,
+        ListCanistersRequest {
+            offset: 1,
+            limit: 2,
+            name_filter: None,
+            sort_by: Some(CanisterSortOrder::BlockIndex),
+        },
+    );
+    assert_eq!(4, result.matching_count);
+    assert_eq!(vec![id3, id2], canister_ids_of(&result));
+
+    let result = list_canisters_ok(
+        &store,
+        principal,
+        ListCanistersRequest {
+            offset: 4,
+            limit: 2,
+            ..ListCanistersRequest::default()
+        },
+    );
+    assert_eq!(4, result.matching_count);
+    assert!(result.canisters.is_empty());
+}
+
+#[test]
+fn list_canisters_filters_by_name_ignoring_case() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let [id1, id2, _, id4] = attach_canisters_for_listing(&mut store, principal);
+
+    let result = list_canisters_ok(
+        &store,
+        principal,
+        ListCanistersRequest {
+            limit: 10,
+            name_filter: Some("A".to_string()),
+            ..ListCanistersRequest::default()
+        },
+    );
+    assert_eq!(3, result.matching_count);
+    assert_eq!(vec![id1, id4, id2], canister_ids_of(&result));
+    // The hashes cover all canisters, not just the listed ones.
+    assert_eq!(4, result.canister_hashes.len());
+}
+
+#[test]
+fn list_canisters_account_not_found() {
+    let store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+
+    let response = store.list_canisters(principal, ListCanistersRequest::default());
+
+    assert_eq!(ListCanistersResponse::AccountNotFound, response);
+}
+
+#[test]
+fn canisters_hashes_are_certified() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let account_identifier = AccountIdentifier::from(principal).to_vec();
+    let empty_root_hash = store.canisters_root_hash();
+
+    let [id1, id2, id3, id4] = attach_canisters_for_listing(&mut store, principal);
+    let result = list_canisters_ok(&store, principal, ListCanistersRequest::default());
+    assert_eq!(
+        Some(hash_bytes(result.canister_hashes.concat())),
+        store.canisters_hashes.get(&account_identifier)
+    );
+    assert_ne!(empty_root_hash, store.canisters_root_hash());
+
+    let root_hash_before_rename = store.canisters_root_hash();
+    store.rename_canister(
+        principal,
+        RenameCanisterRequest {
+            name: "Delta".to_string(),
+            canister_id: id3,
+        },
+    );
+    assert_ne!(root_hash_before_rename, store.canisters_root_hash());
+
+    for canister_id in [id1, id2, id3, id4] {
+        store.detach_canister(principal, DetachCanisterRequest { canister_id });
+    }
+    assert_eq!(None, store.canisters_hashes.get(&account_identifier));
+    assert_eq!(empty_root_hash, store.canisters_root_hash());
+}
+
+#[test]
+fn canisters_hashes_are_read_back_in_batches_but_not_recomputed_on_upgrade() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    attach_canisters_for_listing(&mut store, principal);
+    let backfills = store.backfills.get().clone();
+
+    let mut restored = AccountsStore::load();
+    assert!(!restored.backfills_complete());
+    while !restored.step_backfills(1) {}
+
+    assert_eq!(restored.canisters_root_hash(), store.canisters_root_hash());
+    // No backfill visited the accounts.
+    assert_eq!(*restored.backfills.get(), backfills);
+}
+
+#[test]
+fn canisters_are_not_certified_until_their_hashes_are_backfilled() {
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let account_identifier = AccountIdentifier::from(principal).to_vec();
+    let mut store = setup_test_store();
+    attach_canisters_for_listing(&mut store, principal);
+    let canister_hashes = list_canisters_ok(
+        &store,
+        principal,
+        ListCanistersRequest {
+            limit: 10,
+            ..ListCanistersRequest::default()
+        },
+    )
+    .canister_hashes;
+    assert!(store.canisters_are_certified(&account_identifier, &canister_hashes));
+
+    store.restart_canisters_hashes_backfill();
+    assert!(!store.canisters_are_certified(&account_identifier, &canister_hashes));
+
+    while !store.step_backfills(backfills::BACKFILL_BATCH_SIZE) {}
+    assert!(store.canisters_are_certified(&account_identifier, &canister_hashes));
+    // The hashes of an account without canisters are certified by their absence.
+    let other_account_identifier = AccountIdentifier::from(PrincipalId::new_user_test_id(999)).to_vec();
+    assert!(store.canisters_are_certified(&other_account_identifier, &[]));
+}
+
+#[test]
+fn canisters_hashes_are_backfilled_in_batches_if_missing() {
+    let principal1 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let principal2 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let mut store = setup_test_store();
+    attach_canisters_for_listing(&mut store, principal1);
+    attach_canisters_for_listing(&mut store, principal2);
+    let expected_root_hash = store.canisters_root_hash();
+
+    // Simulate upgrading from a release without persisted canister hashes.
+    with_partitions(|partitions| {
+        let _ = CanistersHashes::new(partitions.get(PartitionType::CanistersHashes.memory_id()));
+        let _ = StableValue::new(
+            partitions.get(PartitionType::Backfills.memory_id()),
+            Backfills::default(),
+        );
+    });
+    let mut restored = AccountsStore::load();
+    assert!(!restored.backfills_complete());
+    assert!(restored.canisters_hashes.is_empty());
+
+    let mut steps = 1;
+    while !restored.step_backfills(1) {
+        steps += 1;
+    }
+
+    // For every backfill, one step per account and one to find that there are no more accounts.
+    assert_eq!(steps, 3 * Backfill::ALL.len());
+    assert_eq!(restored.canisters_root_hash(), expected_root_hash);
+    assert!(AccountsStore::load().backfills_complete());
+}
+
+#[test]
+fn set_and_get_imported_tokens() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let ledger_canister_id = PrincipalId::new_user_test_id(101);
+    let index_canister_id = PrincipalId::new_user_test_id(102);
+
+    assert_eq!(
+        store.get_imported_tokens(principal),
+        GetImportedTokensResponse::Ok(ImportedTokens::default())
+    );
+
+    let imported_token = ImportedToken {
+        ledger_canister_id,
+        index_canister_id: Some(index_canister_id),
+    };
+
+    assert_eq!(
+        store.set_imported_tokens(
+            principal,
+            ImportedTokens {
+                imported_tokens: vec![imported_token.clone()],
+                version: None,
+            },
+            None,
+        ),
+        SetImportedTokensResponse::Ok
+    );
+
+    assert_eq!(
+        store.get_imported_tokens(principal),
+        GetImportedTokensResponse::Ok(ImportedTokens {
+            imported_tokens: vec![imported_token],
+            version: Some(1),
+        })
+    );
+}
+
+#[test]
+fn set_and_get_imported_tokens_without_index_canister() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let ledger_canister_id = PrincipalId::new_user_test_id(101);
+
+    assert_eq!(
+        store.get_imported_tokens(principal),
+        GetImportedTokensResponse::Ok(ImportedTokens::default())
+    );
+
+    let imported_token = ImportedToken {
+        ledger_canister_id,
+        index_canister_id: None,
+    };
+
+    assert_eq!(
+        store.set_imported_tokens(
+            principal,
+            ImportedTokens {
+                imported_tokens: vec![imported_token.clone()],
+                version: None,
+            },
+            None,
+        ),
+        SetImportedTokensResponse::Ok
+    );
+
+    assert_eq!(
+        store.get_imported_tokens(principal),
+        GetImportedTokensResponse::Ok(ImportedTokens {
+            imported_tokens: vec![imported_token],
+            version: Some(1),
+        })
+    );
+}
+
+fn get_unique_imported_tokens(count: u64) -> Vec<ImportedToken> {
+    (0..count)
+        .map(|i| ImportedToken {
+            ledger_canister_id: PrincipalId::new_user_test_id(i),
+            index_canister_id: Some(PrincipalId::new_user_test_id(i + 1000)),
+        })
+        .collect()
+}
+
+#[test]
+fn set_and_get_20_imported_tokens() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    assert_eq!(
+        store.get_imported_tokens(principal),
+        GetImportedTokensResponse::Ok(ImportedTokens::default())
+    );
+
+    let imported_tokens = get_unique_imported_tokens(20);
+
+    assert_eq!(
+        store.set_imported_tokens(
+            principal,
+            ImportedTokens {
+                imported_tokens: imported_tokens.clone(),
+                version: None,
+            },
+            None,
+        ),
+        SetImportedTokensResponse::Ok
+    );
+
+    assert_eq!(
+        store.get_imported_tokens(principal),
+        GetImportedTokensResponse::Ok(ImportedTokens {
+            imported_tokens,
+            version: Some(1)
+        })
+    );
+}
+
+#[test]
+fn set_imported_tokens_account_not_found() {
+    let mut store = setup_test_store();
+    let non_existing_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    assert_eq!(
+        store.set_imported_tokens(non_existing_principal, ImportedTokens::default(), None),
+        SetImportedTokensResponse::AccountNotFound
+    );
+}
+
+#[test]
+fn set_imported_tokens_too_many() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let imported_tokens = get_unique_imported_tokens(21);
+
+    assert_eq!(
+        store.set_imported_tokens(
+            principal,
+            ImportedTokens {
+                imported_tokens,
+                version: None
+            },
+            None
+        ),
+        SetImportedTokensResponse::TooManyImportedTokens { limit: 20 }
+    );
+}
+
+#[test]
+fn get_imported_tokens_account_not_found() {
+    let mut store = setup_test_store();
+    let non_existing_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    assert_eq!(
+        store.get_imported_tokens(non_existing_principal),
+        GetImportedTokensResponse::AccountNotFound
+    );
+}
+
+fn named_icrc1_subaccount(ledger: u64, subaccount_byte: u8, name: &str) -> NamedIcrc1Subaccount {
+    let mut subaccount = vec![0u8; 32];
+    subaccount[31] = subaccount_byte;
+    NamedIcrc1Subaccount {
+        ledger_canister_id: PrincipalId::new_user_test_id(ledger),
+        subaccount,
+        name: name.to_string(),
+    }
+}
+
+fn list_all_named_icrc1_subaccounts(store: &AccountsStore, principal: PrincipalId) -> Vec<NamedIcrc1Subaccount> {
+    let ListNamedIcrc1SubaccountsResponse::Ok(named_subaccounts) =
+        store.list_named_icrc1_subaccounts(principal, ListNamedIcrc1SubaccountsRequest::default())
+    else {
+        panic!("Expected the account to exist");
+    };
+    named_subaccounts
+}
+
+#[test]
+fn create_rename_and_list_named_icrc1_subaccounts() {
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let mut store = setup_test_store();
+
+    for named_subaccount in [
+        named_icrc1_subaccount(2, 1, "Savings"),
+        named_icrc1_subaccount(1, 2, "Trading"),
+        named_icrc1_subaccount(1, 1, "Savings"),
+    ] {
+        assert_eq!(
+            store.create_named_icrc1_subaccount(principal, named_subaccount),
+            SetNamedIcrc1SubaccountResponse::Ok
+        );
+    }
+    assert_eq!(
+        list_all_named_icrc1_subaccounts(&store, principal),
+        vec![
+            named_icrc1_subaccount(1, 1, "Savings"),
+            named_icrc1_subaccount(1, 2, "Trading"),
+            named_icrc1_subaccount(2, 1, "Savings"),
+        ]
+    );
+
+    assert_eq!(
+        store.rename_named_icrc1_subaccount(
+            principal,
+            RenameNamedIcrc1SubaccountRequest {
+                ledger_canister_id: PrincipalId::new_user_test_id(1),
+                subaccount: named_icrc1_subaccount(1, 2, "").subaccount,
+                new_name: "Allowance".to_string(),
+            }
+        ),
+        SetNamedIcrc1SubaccountResponse::Ok
+    );
+    assert_eq!(
+        store.list_named_icrc1_subaccounts(
+            principal,
+            ListNamedIcrc1SubaccountsRequest {
+                ledger_canister_id: Some(PrincipalId::new_user_test_id(1)),
+            }
+        ),
+        ListNamedIcrc1SubaccountsResponse::Ok(vec![
+            named_icrc1_subaccount(1, 2, "Allowance"),
+            named_icrc1_subaccount(1, 1, "Savings"),
+        ])
+    );
+}
+
+#[test]
+fn named_icrc1_subaccounts_are_validated() {
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let mut store = setup_test_store();
+    store.create_named_icrc1_subaccount(principal, named_icrc1_subaccount(1, 1, "Savings"));
+
+    assert_eq!(
+        store.create_named_icrc1_subaccount(principal, named_icrc1_subaccount(1, 1, "Other")),
+        SetNamedIcrc1SubaccountResponse::SubaccountAlreadyNamed
+    );
+    assert_eq!(
+        store.create_named_icrc1_subaccount(principal, named_icrc1_subaccount(1, 2, "Savings")),
+        SetNamedIcrc1SubaccountResponse::NameAlreadyTaken
+    );
+    assert_eq!(
+        store.create_named_icrc1_subaccount(principal, named_icrc1_subaccount(1, 0, "Main")),
+        SetNamedIcrc1SubaccountResponse::InvalidSubaccount
+    );
+    assert_eq!(
+        store.create_named_icrc1_subaccount(
+            principal,
+            NamedIcrc1Subaccount {
+                subaccount: vec![1; 31],
+                ..named_icrc1_subaccount(1, 3, "Short")
+            }
+        ),
+        SetNamedIcrc1SubaccountResponse::InvalidSubaccount
+    );
+    assert_eq!(
+        store.create_named_icrc1_subaccount(principal, named_icrc1_subaccount(1, 3, &"A".repeat(25))),
+        SetNamedIcrc1SubaccountResponse::NameTooLong
+    );
+    assert_eq!(
+        store.rename_named_icrc1_subaccount(
+            principal,
+            RenameNamedIcrc1SubaccountRequest {
+                ledger_canister_id: PrincipalId::new_user_test_id(2),
+                subaccount: named_icrc1_subaccount(1, 1, "").subaccount,
+                new_name: "Savings".to_string(),
+            }
+        ),
+        SetNamedIcrc1SubaccountResponse::SubaccountNotFound
+    );
+    assert_eq!(
+        list_all_named_icrc1_subaccounts(&store, principal),
+        vec![named_icrc1_subaccount(1, 1, "Savings")]
+    );
+}
+
+#[test]
+fn named_icrc1_subaccounts_are_limited() {
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let mut store = setup_test_store();
+    for ledger in 0..=u64::try_from(Limits::DEFAULT.max_named_icrc1_subaccounts).unwrap() {
+        let response = store.create_named_icrc1_subaccount(principal, named_icrc1_subaccount(ledger, 1, "Savings"));
+        if ledger < u64::try_from(Limits::DEFAULT.max_named_icrc1_subaccounts).unwrap() {
+            assert_eq!(response, SetNamedIcrc1SubaccountResponse::Ok);
+        } else {
+            assert_eq!(
+                response,
+                SetNamedIcrc1SubaccountResponse::TooManyNamedSubaccounts {
+                    limit: Limits::DEFAULT.max_named_icrc1_subaccounts
+                }
+            );
+        }
+    }
+}
+
+#[test]
+fn named_icrc1_subaccounts_account_not_found() {
+    let mut store = setup_test_store();
+    let unknown_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+
+    assert_eq!(
+        store.create_named_icrc1_subaccount(unknown_principal, named_icrc1_subaccount(1, 1, "Savings")),
+        SetNamedIcrc1SubaccountResponse::AccountNotFound
+    );
+    assert_eq!(
+        store.list_named_icrc1_subaccounts(unknown_principal, ListNamedIcrc1SubaccountsRequest::default()),
+        ListNamedIcrc1SubaccountsResponse::AccountNotFound
+    );
+}
+
+#[test]
+fn set_and_get_fav_projects() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let root_canister_id = PrincipalId::new_user_test_id(101);
+
+    assert_eq!(
+        store.get_fav_projects(principal),
+        GetFavProjectsResponse::Ok(FavProjects::default())
+    );
+
+    let fav_project = FavProject { root_canister_id };
+
+    assert_eq!(
+        store.set_fav_projects(
+            principal,
+            FavProjects {
+                fav_projects: vec![fav_project.clone()],
+                version: None,
+            },
+            None,
+        ),
+        SetFavProjectsResponse::Ok
+    );
+
+    assert_eq!(
+        store.get_fav_projects(principal),
+        GetFavProjectsResponse::Ok(FavProjects {
+            fav_projects: vec![fav_project],
+            version: Some(1),
+        })
+    );
+}
+
+fn get_unique_fav_projects(count: u64) -> Vec<FavProject> {
+    (0..count)
+        .map(|i| FavProject {
+            root_canister_id: PrincipalId::new_user_test_id(i),
+        })
+        .collect()
 }
 
 #[test]
-fn attach_canister_canister_already_attached_with_same_name_and_block_index() {
-    let name = "my canister";
+fn set_and_get_20_fav_projects() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
 
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    assert_eq!(
+        store.get_fav_projects(principal),
+        GetFavProjectsResponse::Ok(FavProjects::default())
+    );
 
-    let result1 = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: name.to_string(),
-            canister_id,
-            block_index: Some(123),
-        },
+    let fav_projects = get_unique_fav_projects(20);
+
+    assert_eq!(
+        store.set_fav_projects(
+            principal,
+            FavProjects {
+                fav_projects: fav_projects.clone(),
+                version: None,
+            },
+            None,
+        ),
+        SetFavProjectsResponse::Ok
     );
-    let result2 = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: name.to_string(),
-            canister_id,
-            block_index: Some(123),
-        },
+
+    assert_eq!(
+        store.get_fav_projects(principal),
+        GetFavProjectsResponse::Ok(FavProjects {
+            fav_projects,
+            version: Some(1)
+        })
     );
+}
 
-    assert!(matches!(result1, AttachCanisterResponse::Ok));
-    assert!(matches!(result2, AttachCanisterResponse::CanisterAlreadyAttached));
+#[test]
+fn set_fav_projects_account_not_found() {
+    let mut store = setup_test_store();
+    let non_existing_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    assert_eq!(
+        store.set_fav_projects(non_existing_principal, FavProjects::default(), None),
+        SetFavProjectsResponse::AccountNotFound
+    );
 }
 
 #[test]
-fn attach_canister_canister_already_attached_with_different_block_index() {
-    let name = "my canister";
+fn set_fav_projects_too_many() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
 
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let fav_projects = get_unique_fav_projects(21);
 
-    let result1 = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: name.to_string(),
-            canister_id,
-            block_index: Some(123),
-        },
-    );
-    let result2 = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: name.to_string(),
-            canister_id,
-            block_index: Some(456),
-        },
+    assert_eq!(
+        store.set_fav_projects(
+            principal,
+            FavProjects {
+                fav_projects,
+                version: None
+            },
+            None
+        ),
+        SetFavProjectsResponse::TooManyFavProjects { limit: 20 }
     );
+}
 
-    assert!(matches!(result1, AttachCanisterResponse::Ok));
-    assert!(matches!(result2, AttachCanisterResponse::CanisterAlreadyAttached));
+#[test]
+fn get_fav_projects_account_not_found() {
+    let store = setup_test_store();
+    let non_existing_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    assert_eq!(
+        store.get_fav_projects(non_existing_principal),
+        GetFavProjectsResponse::AccountNotFound
+    );
 }
 
 #[test]
-fn attach_canister_substitutes_absent_block_index() {
-    let name = "my canister";
+fn settings_setters_reject_stale_versions() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let imported_tokens = ImportedTokens {
+        imported_tokens: get_unique_imported_tokens(2),
+        version: None,
+    };
+    let fav_projects = FavProjects {
+        fav_projects: get_unique_fav_projects(2),
+        version: None,
+    };
 
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
-
-    store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: name.to_string(),
-            canister_id,
-            block_index: None,
-        },
+    // Settings that were never set have version 0.
+    assert_eq!(
+        store.set_imported_tokens(principal, imported_tokens.clone(), Some(0)),
+        SetImportedTokensResponse::Ok
+    );
+    assert_eq!(
+        store.set_fav_projects(principal, fav_projects.clone(), Some(0)),
+        SetFavProjectsResponse::Ok
+    );
+    assert_eq!(
+        store.set_address_book(principal, new_address_book(2), Some(0)),
+        SetAddressBookResponse::Ok
     );
 
-    let initial_canisters = store.get_canisters(principal);
-    assert!(initial_canisters[0].block_index.is_none());
-
-    let block_index = Some(123);
-    let result = store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            // Original name should be preserved.
-            name: "".to_string(),
-            canister_id,
-            block_index,
-        },
+    // A second device that still has version 0 must not overwrite the changes.
+    assert_eq!(
+        store.set_imported_tokens(principal, ImportedTokens::default(), Some(0)),
+        SetImportedTokensResponse::VersionConflict { current_version: 1 }
+    );
+    assert_eq!(
+        store.set_fav_projects(principal, FavProjects::default(), Some(0)),
+        SetFavProjectsResponse::VersionConflict { current_version: 1 }
+    );
+    assert_eq!(
+        store.set_address_book(principal, AddressBook::default(), Some(0)),
+        SetAddressBookResponse::VersionConflict { current_version: 1 }
+    );
+    assert_eq!(
+        store.get_imported_tokens(principal),
+        GetImportedTokensResponse::Ok(ImportedTokens {
+            version: Some(1),
+            ..imported_tokens
+        })
+    );
+    assert_eq!(
+        store.get_fav_projects(principal),
+        GetFavProjectsResponse::Ok(FavProjects {
+            version: Some(1),
+            ..fav_projects
+        })
     );
 
-    let canisters = store.get_canisters(principal);
-    assert_eq!(block_index, canisters[0].block_index);
-    assert!(matches!(result, AttachCanisterResponse::Ok));
+    // Changes based on the current version, or without a version, are accepted.
+    assert_eq!(
+        store.set_imported_tokens(principal, ImportedTokens::default(), Some(1)),
+        SetImportedTokensResponse::Ok
+    );
+    assert_eq!(
+        store.set_fav_projects(principal, FavProjects::default(), None),
+        SetFavProjectsResponse::Ok
+    );
+    assert_eq!(
+        store.get_imported_tokens(principal),
+        GetImportedTokensResponse::Ok(ImportedTokens {
+            imported_tokens: vec![],
+            version: Some(2),
+        })
+    );
+    assert_eq!(
+        store.get_fav_projects(principal),
+        GetFavProjectsResponse::Ok(FavProjects {
+            fav_projects: vec![],
+            version: Some(2),
+        })
+    );
 }
 
 #[test]
-fn attach_canister_and_rename() {
+fn settings_versions_set_by_the_caller_are_ignored() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
 
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
-
-    let initial_name = "ABC".to_string();
-    store.attach_canister(
+    store.set_fav_projects(
         principal,
-        AttachCanisterRequest {
-            name: initial_name.clone(),
-            canister_id,
-            block_index: None,
+        FavProjects {
+            fav_projects: vec![],
+            version: Some(42),
         },
+        None,
     );
 
-    let canisters = store.get_canisters(principal);
-    assert_eq!(initial_name, canisters[0].name);
-
-    let final_name = "DEF".to_string();
-    store.rename_canister(
-        principal,
-        RenameCanisterRequest {
-            name: final_name.clone(),
-            canister_id,
-        },
+    assert_eq!(
+        store.get_fav_projects(principal),
+        GetFavProjectsResponse::Ok(FavProjects {
+            fav_projects: vec![],
+            version: Some(1),
+        })
     );
-
-    let canisters = store.get_canisters(principal);
-    assert_eq!(final_name, canisters[0].name);
 }
 
 #[test]
-fn rename_to_empty_name_succeeds() {
+fn set_and_get_address_book() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let name = "Alice's Account".to_string();
 
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
-
-    let initial_name = "ABC".to_string();
-    store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: initial_name.clone(),
-            canister_id,
-            block_index: None,
-        },
+    assert_eq!(
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook::default())
     );
 
-    let canisters = store.get_canisters(principal);
-    assert_eq!(initial_name, canisters[0].name);
+    let named_address = NamedAddress {
+        address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+        name: name.clone(),
+        default_memo: None,
+        subaccount_hint: None,
+        tags: None,
+        ledger_canister_id: None,
+    };
+    let address_book = AddressBook {
+        named_addresses: vec![named_address],
+        version: None,
+    };
 
-    let final_name = "".to_string();
-    store.rename_canister(
-        principal,
-        RenameCanisterRequest {
-            name: final_name.clone(),
-            canister_id,
-        },
+    assert_eq!(
+        store.set_address_book(principal, address_book.clone(), None),
+        SetAddressBookResponse::Ok
     );
 
-    let canisters = store.get_canisters(principal);
-    assert_eq!(final_name, canisters[0].name);
+    assert_eq!(
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook {
+            version: Some(1),
+            ..address_book
+        })
+    );
+}
+
+fn new_address_book(count: i32) -> AddressBook {
+    let named_addresses = (0..count)
+        .map(|i| NamedAddress {
+            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+            name: format!("Name {}", i),
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        })
+        .collect();
+    AddressBook {
+        named_addresses,
+        version: None,
+    }
 }
 
 #[test]
-fn rename_preserves_block_index() {
+fn set_and_get_max_named_addresses() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
-
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
-
-    let initial_name = "ABC".to_string();
-    let block_index = Some(123);
-    store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: initial_name.clone(),
-            canister_id,
-            block_index,
-        },
+
+    assert_eq!(
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook::default())
     );
 
-    let canisters = store.get_canisters(principal);
-    assert_eq!(initial_name, canisters[0].name);
+    let address_book = new_address_book(Limits::DEFAULT.max_named_addresses);
 
-    let final_name = "".to_string();
-    store.rename_canister(
-        principal,
-        RenameCanisterRequest {
-            name: final_name.clone(),
-            canister_id,
-        },
+    assert_eq!(
+        store.set_address_book(principal, address_book.clone(), None),
+        SetAddressBookResponse::Ok
     );
 
-    let canisters = store.get_canisters(principal);
-    assert_eq!(final_name, canisters[0].name);
-    assert_eq!(block_index, canisters[0].block_index);
+    assert_eq!(
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook {
+            version: Some(1),
+            ..address_book
+        })
+    );
 }
 
 #[test]
-fn rename_to_taken_name_fails() {
+fn set_address_book_account_not_found() {
+    let mut store = setup_test_store();
+    let non_existing_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    assert_eq!(
+        store.set_address_book(non_existing_principal, AddressBook::default(), None),
+        SetAddressBookResponse::AccountNotFound
+    );
+}
+
+#[test]
+fn set_address_book_too_many() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
 
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
-    let canister_id2 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let address_book = new_address_book(Limits::DEFAULT.max_named_addresses + 1);
 
-    let name1 = "ABC".to_string();
-    store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: name1.clone(),
-            canister_id,
-            block_index: None,
-        },
-    );
-    let name2 = "DEF".to_string();
-    store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: name2.clone(),
-            canister_id: canister_id2,
-            block_index: None,
-        },
+    assert_eq!(
+        store.set_address_book(principal, address_book, None),
+        SetAddressBookResponse::TooManyNamedAddresses {
+            limit: Limits::DEFAULT.max_named_addresses
+        }
     );
-    let canisters = store.get_canisters(principal);
-    assert_eq!(name1, canisters[0].name);
-    assert_eq!(name2, canisters[1].name);
 
-    let response = store.rename_canister(
-        principal,
-        RenameCanisterRequest {
-            name: name1.clone(),
-            canister_id: canister_id2,
-        },
+    // Verify that the address book was not saved
+    assert_eq!(
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook::default())
     );
+}
 
-    assert!(matches!(response, RenameCanisterResponse::NameAlreadyTaken));
-
-    let canisters = store.get_canisters(principal);
-    assert_eq!(name1, canisters[0].name);
-    assert_eq!(name2, canisters[1].name);
+fn store_with_limits(limits: Limits) -> AccountsStore {
+    let mut store = setup_test_store();
+    store.set_limits(limits);
+    store
 }
 
 #[test]
-fn rename_to_long_name_fails() {
-    let mut store = setup_test_store();
+fn configured_name_lengths_are_honoured() {
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let mut store = store_with_limits(Limits {
+        max_account_name_length: 5,
+        max_canister_name_length: 30,
+        ..Limits::default()
+    });
 
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
-
-    let long_name = "ABCDEFGHIJKLMNOPQRSTUVWXY".to_string();
-    let name = "DEF".to_string();
-    store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: name.clone(),
-            canister_id,
-            block_index: None,
-        },
-    );
-    let response = store.rename_canister(
-        principal,
-        RenameCanisterRequest {
-            name: long_name,
-            canister_id,
-        },
+    assert_eq!(
+        store.create_sub_account(principal, "ABCDEF".to_string()),
+        CreateSubAccountResponse::NameTooLong
     );
-    let canisters = store.get_canisters(principal);
-    assert_eq!(name, canisters[0].name);
-    assert!(matches!(response, RenameCanisterResponse::NameTooLong));
+    assert!(matches!(
+        store.create_sub_account(principal, "ABCDE".to_string()),
+        CreateSubAccountResponse::Ok(_)
+    ));
+    assert!(matches!(
+        store.attach_canister(
+            principal,
+            AttachCanisterRequest {
+                name: "A".repeat(30),
+                ..AttachCanisterRequest::test_data()
+            }
+        ),
+        AttachCanisterResponse::Ok
+    ));
 }
 
 #[test]
-fn rename_not_found_canister() {
-    let mut store = setup_test_store();
+fn configured_max_sub_account_id_is_honoured() {
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let mut store = store_with_limits(Limits {
+        max_sub_account_id: 2,
+        ..Limits::default()
+    });
+
+    for _ in 0..2 {
+        assert!(matches!(
+            store.create_sub_account(principal, "AAA".to_string()),
+            CreateSubAccountResponse::Ok(_)
+        ));
+    }
+    assert_eq!(
+        store.create_sub_account(principal, "AAA".to_string()),
+        CreateSubAccountResponse::SubAccountLimitExceeded
+    );
+}
 
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
-    let canister_id2 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+#[test]
+fn configured_collection_limits_are_honoured() {
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let mut store = store_with_limits(Limits {
+        max_imported_tokens: 2,
+        max_favorite_projects: 2,
+        max_named_addresses: 2,
+        ..Limits::default()
+    });
 
-    store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "DEF".to_string(),
-            canister_id,
-            block_index: None,
-        },
+    assert_eq!(
+        store.set_imported_tokens(
+            principal,
+            ImportedTokens {
+                imported_tokens: get_unique_imported_tokens(3),
+                version: None,
+            },
+            None
+        ),
+        SetImportedTokensResponse::TooManyImportedTokens { limit: 2 }
     );
-    let response = store.rename_canister(
-        principal,
-        RenameCanisterRequest {
-            name: "ABC".to_string(),
-            canister_id: canister_id2,
-        },
+    assert_eq!(
+        store.set_address_book(principal, new_address_book(3), None),
+        SetAddressBookResponse::TooManyNamedAddresses { limit: 2 }
+    );
+    assert_eq!(
+        store.set_address_book(principal, new_address_book(2), None),
+        SetAddressBookResponse::Ok
     );
-    assert!(matches!(response, RenameCanisterResponse::CanisterNotFound));
 }
 
 #[test]
-fn rename_not_found_account() {
-    let mut store = setup_test_store();
+fn configured_lengths_and_allowlist_size_are_honoured() {
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
-    let principal2 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
-
-    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let mut store = store_with_limits(Limits {
+        max_sub_account_style_key_length: 2,
+        max_account_limit_allowlist_size: 1,
+        ..Limits::default()
+    });
+    store.create_sub_account(principal, "AAA".to_string());
+    let account_identifier = sub_account_identifiers(&store, principal)[0];
 
-    store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "DEF".to_string(),
-            canister_id,
-            block_index: None,
-        },
+    assert_eq!(
+        store.set_sub_account_style_key(
+            principal,
+            SetSubAccountStyleKeyRequest {
+                account_identifier,
+                style_key: Some("abc".to_string()),
+            }
+        ),
+        UpdateSubAccountResponse::StyleKeyTooLong { max_length: 2 }
     );
-    let response = store.rename_canister(
-        principal2,
-        RenameCanisterRequest {
-            name: "ABC".to_string(),
-            canister_id,
-        },
+    assert_eq!(
+        store.update_account_limit_allowlist(UpdateAccountLimitAllowlistRequest {
+            add: vec![principal, PrincipalId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap()],
+            remove: vec![],
+        }),
+        UpdateAccountLimitAllowlistResponse::AllowlistTooLarge { limit: 1 }
     );
-    assert!(matches!(response, RenameCanisterResponse::AccountNotFound));
 }
 
 #[test]
-fn canisters_ordered_by_name_if_exists_then_by_id() {
-    let mut store = setup_test_store();
+fn configured_canister_metadata_limits_are_honoured() {
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
-
-    let canister_id1 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
-    let canister_id2 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
-    let canister_id3 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_4).unwrap();
-    let canister_id4 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_5).unwrap();
-
-    store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "".to_string(),
-            canister_id: canister_id1,
-            block_index: None,
-        },
-    );
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let mut store = store_with_limits(Limits {
+        max_canister_tags: 1,
+        max_canister_note_length: 3,
+        ..Limits::default()
+    });
     store.attach_canister(
         principal,
         AttachCanisterRequest {
             name: "ABC".to_string(),
-            canister_id: canister_id2,
+            canister_id,
             block_index: None,
         },
     );
-    store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "XYZ".to_string(),
-            canister_id: canister_id3,
-            block_index: None,
-        },
+    let request = |tags: Vec<String>, note: &str| UpdateCanisterMetadataRequest {
+        canister_id,
+        tags: Some(tags),
+        note: Some(note.to_string()),
+        low_cycles_threshold: None,
+    };
+
+    assert_eq!(
+        store.update_canister_metadata(principal, request(vec!["a".to_string(), "b".to_string()], "")),
+        UpdateCanisterMetadataResponse::TooManyTags { limit: 1 }
     );
-    store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "".to_string(),
-            canister_id: canister_id4,
-            block_index: None,
-        },
+    assert_eq!(
+        store.update_canister_metadata(principal, request(vec![], "ABCD")),
+        UpdateCanisterMetadataResponse::NoteTooLong { max_length: 3 }
+    );
+    assert_eq!(
+        store.update_canister_metadata(principal, request(vec!["a".to_string()], "ABC")),
+        UpdateCanisterMetadataResponse::Ok
     );
+}
 
-    let canisters = store.get_canisters(principal);
+#[test]
+fn configured_account_limit_is_honoured() {
+    let mut store = store_with_limits(Limits {
+        account_limit: 2,
+        ..Limits::default()
+    });
 
-    assert_eq!(4, canisters.len());
-    assert_eq!(canister_id2, canisters[0].canister_id);
-    assert_eq!(canister_id3, canisters[1].canister_id);
-    assert_eq!(canister_id4, canisters[2].canister_id);
-    assert_eq!(canister_id1, canisters[3].canister_id);
+    assert_eq!(
+        store.add_account(PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap()),
+        AddAccountResponse::AccountLimitReached { limit: 2 }
+    );
 }
 
 #[test]
-fn detach_canister() {
-    let mut store = setup_test_store();
+fn existing_accounts_can_be_added_again_at_the_account_limit() {
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let mut store = store_with_limits(Limits {
+        account_limit: 2,
+        ..Limits::default()
+    });
 
-    let canister_id1 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
-    let canister_id2 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
-
-    store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "ABC".to_string(),
-            canister_id: canister_id1,
-            block_index: None,
-        },
+    assert_eq!(
+        store.add_account(principal),
+        AddAccountResponse::Ok(AccountIdentifier::from(principal))
     );
-    store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "XYZ".to_string(),
-            canister_id: canister_id2,
-            block_index: None,
-        },
+}
+
+#[test]
+fn allowlisted_principals_can_create_accounts_beyond_the_account_limit() {
+    let allowed = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let other = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_4).unwrap();
+    let mut store = store_with_limits(Limits {
+        account_limit: 2,
+        ..Limits::default()
+    });
+
+    assert_eq!(
+        store.update_account_limit_allowlist(UpdateAccountLimitAllowlistRequest {
+            add: vec![allowed, other],
+            remove: vec![other],
+        }),
+        UpdateAccountLimitAllowlistResponse::Ok
     );
+    assert_eq!(store.get_account_limit_allowlist(), vec![allowed]);
 
-    let result = store.detach_canister(
-        principal,
-        DetachCanisterRequest {
-            canister_id: canister_id1,
-        },
+    assert_eq!(
+        store.add_account(allowed),
+        AddAccountResponse::Ok(AccountIdentifier::from(allowed))
+    );
+    assert_eq!(
+        store.add_account(other),
+        AddAccountResponse::AccountLimitReached { limit: 2 }
     );
+}
 
-    assert!(matches!(result, DetachCanisterResponse::Ok));
+#[test]
+fn account_limit_allowlist_size_is_limited() {
+    let mut store = setup_test_store();
+    let too_many = (0..=u64::from(Limits::DEFAULT.max_account_limit_allowlist_size))
+        .map(PrincipalId::new_user_test_id)
+        .collect();
 
-    let canisters = store.get_canisters(principal);
+    assert_eq!(
+        store.update_account_limit_allowlist(UpdateAccountLimitAllowlistRequest {
+            add: too_many,
+            remove: vec![],
+        }),
+        UpdateAccountLimitAllowlistResponse::AllowlistTooLarge {
+            limit: Limits::DEFAULT.max_account_limit_allowlist_size
+        }
+    );
+    assert_eq!(store.get_account_limit_allowlist(), vec![]);
+}
 
-    assert_eq!(1, canisters.len());
-    assert_eq!(canister_id2, canisters[0].canister_id);
+#[test]
+fn stats_report_when_the_account_limit_is_near() {
+    let mut stats = Stats::default();
+    store_with_limits(Limits {
+        account_limit: 100,
+        ..Limits::default()
+    })
+    .get_stats(&mut stats);
+    assert_eq!(stats.account_limit, Some(100));
+    assert_eq!(stats.accounts_remaining, Some(98));
+    assert_eq!(stats.account_limit_near, Some(false));
+
+    store_with_limits(Limits {
+        account_limit: 2,
+        ..Limits::default()
+    })
+    .get_stats(&mut stats);
+    assert_eq!(stats.accounts_remaining, Some(0));
+    assert_eq!(stats.account_limit_near, Some(true));
 }
 
 #[test]
-fn detach_canister_canister_not_found() {
+fn set_address_book_account_id_invalid() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
 
-    let canister_id1 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
-    let canister_id2 = CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    // Test with invalid hex (too short)
+    let invalid_account_id = "invalid";
+    let address_book = AddressBook {
+        named_addresses: vec![NamedAddress {
+            address: AddressType::Icp(invalid_account_id.to_string()),
+            name: "Valid Name".to_string(),
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        }],
+        version: None,
+    };
 
-    store.attach_canister(
-        principal,
-        AttachCanisterRequest {
-            name: "ABC".to_string(),
-            canister_id: canister_id1,
-            block_index: None,
-        },
-    );
+    let response = store.set_address_book(principal, address_book, None);
+    assert!(matches!(response, SetAddressBookResponse::InvalidIcpAddress { .. }));
 
-    let result = store.detach_canister(
-        principal,
-        DetachCanisterRequest {
-            canister_id: canister_id2,
-        },
+    // Verify that the address book was not saved
+    assert_eq!(
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook::default())
     );
 
-    assert!(matches!(result, DetachCanisterResponse::CanisterNotFound));
+    // Test with invalid hex (not hex characters)
+    let invalid_account_id = "zzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
+    let address_book = AddressBook {
+        named_addresses: vec![NamedAddress {
+            address: AddressType::Icp(invalid_account_id.to_string()),
+            name: "Valid Name".to_string(),
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        }],
+        version: None,
+    };
 
-    let canisters = store.get_canisters(principal);
+    let response = store.set_address_book(principal, address_book, None);
+    assert!(matches!(response, SetAddressBookResponse::InvalidIcpAddress { .. }));
 
-    assert_eq!(1, canisters.len());
-    assert_eq!(canister_id1, canisters[0].canister_id);
+    // Verify that the address book was still not saved
+    assert_eq!(
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook::default())
+    );
 }
 
 #[test]
-fn set_and_get_imported_tokens() {
+fn set_address_book_name_too_long() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
-    let ledger_canister_id = PrincipalId::new_user_test_id(101);
-    let index_canister_id = PrincipalId::new_user_test_id(102);
 
-    assert_eq!(
-        store.get_imported_tokens(principal),
-        GetImportedTokensResponse::Ok(ImportedTokens::default())
-    );
+    let too_long_name = "a".repeat((MAX_NAMED_ADDRESS_NAME_LENGTH + 1) as usize);
 
-    let imported_token = ImportedToken {
-        ledger_canister_id,
-        index_canister_id: Some(index_canister_id),
+    let address_book = AddressBook {
+        named_addresses: vec![NamedAddress {
+            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+            name: too_long_name,
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        }],
+        version: None,
     };
 
     assert_eq!(
-        store.set_imported_tokens(
-            principal,
-            ImportedTokens {
-                imported_tokens: vec![imported_token.clone()],
-            },
-        ),
-        SetImportedTokensResponse::Ok
+        store.set_address_book(principal, address_book, None),
+        SetAddressBookResponse::AddressNameTooLong {
+            max_length: MAX_NAMED_ADDRESS_NAME_LENGTH
+        }
     );
 
+    // Verify that the address book was not saved
     assert_eq!(
-        store.get_imported_tokens(principal),
-        GetImportedTokensResponse::Ok(ImportedTokens {
-            imported_tokens: vec![imported_token],
-        })
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook::default())
     );
 }
 
 #[test]
-fn set_and_get_imported_tokens_without_index_canister() {
+fn set_address_book_name_at_max_length() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
-    let ledger_canister_id = PrincipalId::new_user_test_id(101);
 
-    assert_eq!(
-        store.get_imported_tokens(principal),
-        GetImportedTokensResponse::Ok(ImportedTokens::default())
-    );
+    let max_length_name = "a".repeat(MAX_NAMED_ADDRESS_NAME_LENGTH as usize);
 
-    let imported_token = ImportedToken {
-        ledger_canister_id,
-        index_canister_id: None,
+    let address_book = AddressBook {
+        named_addresses: vec![NamedAddress {
+            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+            name: max_length_name,
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        }],
+        version: None,
     };
 
     assert_eq!(
-        store.set_imported_tokens(
-            principal,
-            ImportedTokens {
-                imported_tokens: vec![imported_token.clone()],
-            },
-        ),
-        SetImportedTokensResponse::Ok
+        store.set_address_book(principal, address_book.clone(), None),
+        SetAddressBookResponse::Ok
     );
 
+    // Verify it was stored correctly
     assert_eq!(
-        store.get_imported_tokens(principal),
-        GetImportedTokensResponse::Ok(ImportedTokens {
-            imported_tokens: vec![imported_token],
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook {
+            version: Some(1),
+            ..address_book
         })
     );
 }
 
-fn get_unique_imported_tokens(count: u64) -> Vec<ImportedToken> {
-    (0..count)
-        .map(|i| ImportedToken {
-            ledger_canister_id: PrincipalId::new_user_test_id(i),
-            index_canister_id: Some(PrincipalId::new_user_test_id(i + 1000)),
-        })
-        .collect()
-}
-
 #[test]
-fn set_and_get_20_imported_tokens() {
+fn set_address_book_name_too_short() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
 
-    assert_eq!(
-        store.get_imported_tokens(principal),
-        GetImportedTokensResponse::Ok(ImportedTokens::default())
-    );
-
-    let imported_tokens = get_unique_imported_tokens(20);
+    // Test with name that's too short after normalization
+    let too_short_name = "ab".to_string();
 
-    assert_eq!(
-        store.set_imported_tokens(
-            principal,
-            ImportedTokens {
-                imported_tokens: imported_tokens.clone()
-            },
-        ),
-        SetImportedTokensResponse::Ok
-    );
+    let address_book = AddressBook {
+        named_addresses: vec![NamedAddress {
+            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+            name: too_short_name,
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        }],
+        version: None,
+    };
 
     assert_eq!(
-        store.get_imported_tokens(principal),
-        GetImportedTokensResponse::Ok(ImportedTokens { imported_tokens })
+        store.set_address_book(principal, address_book, None),
+        SetAddressBookResponse::AddressNameTooShort {
+            min_length: MIN_NAMED_ADDRESS_NAME_LENGTH
+        }
     );
-}
 
-#[test]
-fn set_imported_tokens_account_not_found() {
-    let mut store = setup_test_store();
-    let non_existing_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    // Verify that the address book was not saved
     assert_eq!(
-        store.set_imported_tokens(non_existing_principal, ImportedTokens::default()),
-        SetImportedTokensResponse::AccountNotFound
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook::default())
     );
 }
 
 #[test]
-fn set_imported_tokens_too_many() {
+fn set_address_book_name_too_short_after_trimming() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
 
-    let imported_tokens = get_unique_imported_tokens(21);
+    // Test with name that has spaces and becomes too short after normalization
+    let name_with_spaces = "  a    ".to_string();
+    let address_book_1 = AddressBook {
+        named_addresses: vec![NamedAddress {
+            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+            name: name_with_spaces,
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        }],
+        version: None,
+    };
 
     assert_eq!(
-        store.set_imported_tokens(principal, ImportedTokens { imported_tokens },),
-        SetImportedTokensResponse::TooManyImportedTokens { limit: 20 }
+        store.set_address_book(principal, address_book_1, None),
+        SetAddressBookResponse::AddressNameTooShort {
+            min_length: MIN_NAMED_ADDRESS_NAME_LENGTH
+        }
     );
-}
 
-#[test]
-fn get_imported_tokens_account_not_found() {
-    let mut store = setup_test_store();
-    let non_existing_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    // Verify that the address book was not saved
     assert_eq!(
-        store.get_imported_tokens(non_existing_principal),
-        GetImportedTokensResponse::AccountNotFound
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook::default())
     );
-}
 
-#[test]
-fn set_and_get_fav_projects() {
-    let mut store = setup_test_store();
-    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
-    let root_canister_id = PrincipalId::new_user_test_id(101);
+    // Test with name that's too short after trimming
+    let only_spaces = "   ".to_string(); // After trim: "" (0 chars)
+    let address_book_2 = AddressBook {
+        named_addresses: vec![NamedAddress {
+            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+            name: only_spaces,
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        }],
+        version: None,
+    };
 
     assert_eq!(
-        store.get_fav_projects(principal),
-        GetFavProjectsResponse::Ok(FavProjects::default())
+        store.set_address_book(principal, address_book_2, None),
+        SetAddressBookResponse::AddressNameTooShort {
+            min_length: MIN_NAMED_ADDRESS_NAME_LENGTH
+        }
     );
 
-    let fav_project = FavProject { root_canister_id };
+    // Verify that the address book was not saved
+    assert_eq!(
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook::default())
+    );
+
+    // Test with name that's OK after trimming
+    let raw_name = "  a     b ".to_string(); // After trim and normalize: "a b" (3 chars)
+    let address_book_3 = AddressBook {
+        named_addresses: vec![NamedAddress {
+            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+            name: raw_name,
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        }],
+        version: None,
+    };
 
     assert_eq!(
-        store.set_fav_projects(
-            principal,
-            FavProjects {
-                fav_projects: vec![fav_project.clone()],
-            },
-        ),
-        SetFavProjectsResponse::Ok
+        store.set_address_book(principal, address_book_3, None),
+        SetAddressBookResponse::Ok
     );
 
+    // Verify that the address book was saved with normalized name
+    let expected = AddressBook {
+        named_addresses: vec![NamedAddress {
+            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+            name: "a b".to_string(),
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        }],
+        version: Some(1),
+    };
+    assert_eq!(store.get_address_book(principal), GetAddressBookResponse::Ok(expected));
+}
+
+#[test]
+fn set_address_book_with_valid_icrc1_address() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    // Test with a valid ICRC1 address (principal format)
+    let address_book = AddressBook {
+        named_addresses: vec![NamedAddress {
+            address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_2.to_string()),
+            name: "ICRC1 Account".to_string(),
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        }],
+        version: None,
+    };
+
     assert_eq!(
-        store.get_fav_projects(principal),
-        GetFavProjectsResponse::Ok(FavProjects {
-            fav_projects: vec![fav_project],
-        })
+        store.set_address_book(principal, address_book.clone(), None),
+        SetAddressBookResponse::Ok
     );
-}
 
-fn get_unique_fav_projects(count: u64) -> Vec<FavProject> {
-    (0..count)
-        .map(|i| FavProject {
-            root_canister_id: PrincipalId::new_user_test_id(i),
+    // Verify it was stored correctly
+    assert_eq!(
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook {
+            version: Some(1),
+            ..address_book
         })
-        .collect()
+    );
 }
 
 #[test]
-fn set_and_get_20_fav_projects() {
+fn set_address_book_with_valid_icrc1_with_subaccount_address() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
 
-    assert_eq!(
-        store.get_fav_projects(principal),
-        GetFavProjectsResponse::Ok(FavProjects::default())
-    );
-
-    let fav_projects = get_unique_fav_projects(20);
+    // Test with a valid ICRC1 with subaccount address
+    let address_book = AddressBook {
+        named_addresses: vec![NamedAddress {
+            address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_SUBACCOUNT_VALID_1.to_string()),
+            name: "ICRC1 Account with Subaccount".to_string(),
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        }],
+        version: None,
+    };
 
     assert_eq!(
-        store.set_fav_projects(
-            principal,
-            FavProjects {
-                fav_projects: fav_projects.clone()
-            },
-        ),
-        SetFavProjectsResponse::Ok
+        store.set_address_book(principal, address_book.clone(), None),
+        SetAddressBookResponse::Ok
     );
 
+    // Verify it was stored correctly
     assert_eq!(
-        store.get_fav_projects(principal),
-        GetFavProjectsResponse::Ok(FavProjects { fav_projects })
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook {
+            version: Some(1),
+            ..address_book
+        })
     );
 }
 
 #[test]
-fn set_fav_projects_account_not_found() {
+fn set_address_book_with_invalid_icrc1_address() {
     let mut store = setup_test_store();
-    let non_existing_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    // Test with an invalid ICRC1 address
+    let address_book = AddressBook {
+        named_addresses: vec![NamedAddress {
+            address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_SUBACCOUNT_INVALID_1.to_string()),
+            name: "Invalid ICRC1".to_string(),
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        }],
+        version: None,
+    };
+
+    let response = store.set_address_book(principal, address_book, None);
+    assert!(matches!(response, SetAddressBookResponse::InvalidIcrc1Address { .. }));
+
+    // Verify that the address book was not saved
     assert_eq!(
-        store.set_fav_projects(non_existing_principal, FavProjects::default()),
-        SetFavProjectsResponse::AccountNotFound
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook::default())
     );
-}
 
-#[test]
-fn set_fav_projects_too_many() {
-    let mut store = setup_test_store();
-    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let address_book = AddressBook {
+        named_addresses: vec![NamedAddress {
+            address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_SUBACCOUNT_INVALID_2.to_string()),
+            name: "Invalid ICRC1".to_string(),
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        }],
+        version: None,
+    };
 
-    let fav_projects = get_unique_fav_projects(21);
+    let response = store.set_address_book(principal, address_book, None);
+    assert!(matches!(response, SetAddressBookResponse::InvalidIcrc1Address { .. }));
 
+    // Verify that the address book was not saved
     assert_eq!(
-        store.set_fav_projects(principal, FavProjects { fav_projects },),
-        SetFavProjectsResponse::TooManyFavProjects { limit: 20 }
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook::default())
     );
-}
 
-#[test]
-fn get_fav_projects_account_not_found() {
-    let store = setup_test_store();
-    let non_existing_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let address_book = AddressBook {
+        named_addresses: vec![NamedAddress {
+            address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_SUBACCOUNT_INVALID_3.to_string()),
+            name: "Invalid ICRC1".to_string(),
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        }],
+        version: None,
+    };
+
+    let response = store.set_address_book(principal, address_book, None);
+    assert!(matches!(response, SetAddressBookResponse::InvalidIcrc1Address { .. }));
+
+    // Verify that the address book was not saved
     assert_eq!(
-        store.get_fav_projects(non_existing_principal),
-        GetFavProjectsResponse::AccountNotFound
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook::default())
     );
 }
 
 #[test]
-fn set_and_get_address_book() {
+fn set_address_book_with_invalid_icrc1_with_subaccount_address() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
-    let name = "Alice's Account".to_string();
 
+    // Test with an invalid subaccount address
+    let address_book = AddressBook {
+        named_addresses: vec![NamedAddress {
+            address: AddressType::Icrc1(format!("{}.123", TEST_ICRC1_ACCOUNT_2)),
+            name: "Invalid ICRC1 with Subaccount".to_string(),
+            default_memo: None,
+            subaccount_hint: None,
+            tags: None,
+            ledger_canister_id: None,
+        }],
+        version: None,
+    };
+
+    let response = store.set_address_book(principal, address_book, None);
+    assert!(matches!(response, SetAddressBookResponse::InvalidIcrc1Address { .. }));
+
+    // Verify that the address book was not saved
     assert_eq!(
         store.get_address_book(principal),
         GetAddressBookResponse::Ok(AddressBook::default())
     );
+}
 
-    let named_address = NamedAddress {
-        address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
-        name: name.clone(),
-    };
+#[test]
+fn set_address_book_with_mixed_address_types() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    // Test with both ICP and ICRC1 addresses
     let address_book = AddressBook {
-        named_addresses: vec![named_address],
+        named_addresses: vec![
+            NamedAddress {
+                address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+                name: "ICP Account".to_string(),
+                default_memo: None,
+                subaccount_hint: None,
+                tags: None,
+                ledger_canister_id: None,
+            },
+            NamedAddress {
+                address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_3.to_string()),
+                name: "ICRC1 Account".to_string(),
+                default_memo: None,
+                subaccount_hint: None,
+                tags: None,
+                ledger_canister_id: None,
+            },
+        ],
+        version: None,
     };
 
     assert_eq!(
-        store.set_address_book(principal, address_book.clone()),
+        store.set_address_book(principal, address_book.clone(), None),
         SetAddressBookResponse::Ok
     );
 
+    // Verify both were stored correctly
     assert_eq!(
         store.get_address_book(principal),
-        GetAddressBookResponse::Ok(address_book)
-    );
-}
-
-fn new_address_book(count: i32) -> AddressBook {
-    let named_addresses = (0..count)
-        .map(|i| NamedAddress {
-            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
-            name: format!("Name {}", i),
+        GetAddressBookResponse::Ok(AddressBook {
+            version: Some(1),
+            ..address_book
         })
-        .collect();
-    AddressBook { named_addresses }
+    );
 }
 
 #[test]
-fn set_and_get_max_named_addresses() {
+fn set_address_book_duplicate_names() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
 
+    // Test with duplicate names
+    let duplicate_name = "Alice's Account".to_string();
+    let address_book = AddressBook {
+        named_addresses: vec![
+            NamedAddress {
+                address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+                name: duplicate_name.clone(),
+                default_memo: None,
+                subaccount_hint: None,
+                tags: None,
+                ledger_canister_id: None,
+            },
+            NamedAddress {
+                address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_2.to_string()),
+                name: duplicate_name.clone(),
+                default_memo: None,
+                subaccount_hint: None,
+                tags: None,
+                ledger_canister_id: None,
+            },
+        ],
+        version: None,
+    };
+
+    assert_eq!(
+        store.set_address_book(principal, address_book, None),
+        SetAddressBookResponse::DuplicateAddressName { name: duplicate_name }
+    );
+
+    // Verify that the address book was not saved
     assert_eq!(
         store.get_address_book(principal),
         GetAddressBookResponse::Ok(AddressBook::default())
     );
+}
+
+#[test]
+fn set_address_book_duplicate_names_multiple() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
 
-    let address_book = new_address_book(MAX_NAMED_ADDRESSES);
+    // Test with three addresses where two have the same name
+    let duplicate_name = "Duplicate".to_string();
+    let duplicate_name_2 = "Duplicate 2".to_string();
+    let address_book = AddressBook {
+        named_addresses: vec![
+            NamedAddress {
+                address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+                name: "Unique Name".to_string(),
+                default_memo: None,
+                subaccount_hint: None,
+                tags: None,
+                ledger_canister_id: None,
+            },
+            NamedAddress {
+                address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_2.to_string()),
+                name: duplicate_name.clone(),
+                default_memo: None,
+                subaccount_hint: None,
+                tags: None,
+                ledger_canister_id: None,
+            },
+            NamedAddress {
+                address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_3.to_string()),
+                name: duplicate_name.clone(),
+                default_memo: None,
+                subaccount_hint: None,
+                tags: None,
+                ledger_canister_id: None,
+            },
+            NamedAddress {
+                address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_4.to_string()),
+                name: duplicate_name_2.clone(),
+                default_memo: None,
+                subaccount_hint: None,
+                tags: None,
+                ledger_canister_id: None,
+            },
+            NamedAddress {
+                address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_5.to_string()),
+                name: duplicate_name_2.clone(),
+                default_memo: None,
+                subaccount_hint: None,
+                tags: None,
+                ledger_canister_id: None,
+            },
+        ],
+        version: None,
+    };
 
     assert_eq!(
-        store.set_address_book(principal, address_book.clone(),),
-        SetAddressBookResponse::Ok
+        store.set_address_book(principal, address_book, None),
+        SetAddressBookResponse::DuplicateAddressName { name: duplicate_name }
     );
 
+    // Verify that the address book was not saved
     assert_eq!(
         store.get_address_book(principal),
-        GetAddressBookResponse::Ok(address_book)
+        GetAddressBookResponse::Ok(AddressBook::default())
     );
 }
 
+fn icrc1_named_address_with_details() -> NamedAddress {
+    NamedAddress {
+        address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_2.to_string()),
+        name: "Exchange".to_string(),
+        default_memo: Some(vec![1; MAX_ICRC1_MEMO_LENGTH as usize]),
+        subaccount_hint: Some(vec![2; 32]),
+        tags: Some(vec!["ckBTC".to_string(), "exchange".to_string()]),
+        ledger_canister_id: Some(CanisterId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap()),
+    }
+}
+
+fn set_single_named_address(
+    store: &mut AccountsStore,
+    principal: PrincipalId,
+    named_address: NamedAddress,
+) -> SetAddressBookResponse {
+    store.set_address_book(
+        principal,
+        AddressBook {
+            named_addresses: vec![named_address],
+            version: None,
+        },
+        None,
+    )
+}
+
 #[test]
-fn set_address_book_account_not_found() {
+fn set_and_get_address_book_with_details() {
     let mut store = setup_test_store();
-    let non_existing_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let address_book = AddressBook {
+        named_addresses: vec![
+            icrc1_named_address_with_details(),
+            NamedAddress {
+                address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+                name: "Savings".to_string(),
+                default_memo: Some(vec![3; MAX_ICP_MEMO_LENGTH as usize]),
+                subaccount_hint: None,
+                tags: Some(vec![]),
+                ledger_canister_id: None,
+            },
+        ],
+        version: None,
+    };
+
     assert_eq!(
-        store.set_address_book(non_existing_principal, AddressBook::default()),
-        SetAddressBookResponse::AccountNotFound
+        store.set_address_book(principal, address_book.clone(), None),
+        SetAddressBookResponse::Ok
+    );
+    assert_eq!(
+        store.get_address_book(principal),
+        GetAddressBookResponse::Ok(AddressBook {
+            version: Some(1),
+            ..address_book
+        })
     );
 }
 
 #[test]
-fn set_address_book_too_many() {
+fn set_address_book_memo_too_long() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
 
-    let address_book = new_address_book(MAX_NAMED_ADDRESSES + 1);
-
+    let named_address = NamedAddress {
+        default_memo: Some(vec![1; MAX_ICRC1_MEMO_LENGTH as usize + 1]),
+        ..icrc1_named_address_with_details()
+    };
     assert_eq!(
-        store.set_address_book(principal, address_book),
-        SetAddressBookResponse::TooManyNamedAddresses {
-            limit: MAX_NAMED_ADDRESSES
+        set_single_named_address(&mut store, principal, named_address),
+        SetAddressBookResponse::MemoTooLong {
+            max_length: MAX_ICRC1_MEMO_LENGTH
         }
     );
 
-    // Verify that the address book was not saved
+    let named_address = NamedAddress {
+        address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+        default_memo: Some(vec![1; MAX_ICP_MEMO_LENGTH as usize + 1]),
+        subaccount_hint: None,
+        ledger_canister_id: None,
+        ..icrc1_named_address_with_details()
+    };
     assert_eq!(
-        store.get_address_book(principal),
-        GetAddressBookResponse::Ok(AddressBook::default())
+        set_single_named_address(&mut store, principal, named_address),
+        SetAddressBookResponse::MemoTooLong {
+            max_length: MAX_ICP_MEMO_LENGTH
+        }
     );
 }
 
 #[test]
-fn set_address_book_account_id_invalid() {
+fn set_address_book_invalid_subaccount_hint() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
 
-    // Test with invalid hex (too short)
-    let invalid_account_id = "invalid";
-    let address_book = AddressBook {
-        named_addresses: vec![NamedAddress {
-            address: AddressType::Icp(invalid_account_id.to_string()),
-            name: "Valid Name".to_string(),
-        }],
+    let named_address = NamedAddress {
+        subaccount_hint: Some(vec![2; 31]),
+        ..icrc1_named_address_with_details()
     };
+    assert!(matches!(
+        set_single_named_address(&mut store, principal, named_address),
+        SetAddressBookResponse::InvalidSubaccountHint { .. }
+    ));
 
-    let response = store.set_address_book(principal, address_book);
-    assert!(matches!(response, SetAddressBookResponse::InvalidIcpAddress { .. }));
+    let named_address = NamedAddress {
+        address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+        default_memo: None,
+        ledger_canister_id: None,
+        ..icrc1_named_address_with_details()
+    };
+    assert!(matches!(
+        set_single_named_address(&mut store, principal, named_address),
+        SetAddressBookResponse::InvalidSubaccountHint { .. }
+    ));
+}
 
-    // Verify that the address book was not saved
+#[test]
+fn set_address_book_invalid_tags() {
+    let mut store = setup_test_store();
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let named_address = NamedAddress {
+        tags: Some(
+            (0..=Limits::DEFAULT.max_named_address_tags)
+                .map(|i| i.to_string())
+                .collect(),
+        ),
+        ..icrc1_named_address_with_details()
+    };
     assert_eq!(
-        store.get_address_book(principal),
-        GetAddressBookResponse::Ok(AddressBook::default())
+        set_single_named_address(&mut store, principal, named_address),
+        SetAddressBookResponse::TooManyTags {
+            limit: Limits::DEFAULT.max_named_address_tags
+        }
     );
 
-    // Test with invalid hex (not hex characters)
-    let invalid_account_id = "zzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
-    let address_book = AddressBook {
-        named_addresses: vec![NamedAddress {
-            address: AddressType::Icp(invalid_account_id.to_string()),
-            name: "Valid Name".to_string(),
-        }],
+    let named_address = NamedAddress {
+        tags: Some(vec![
+            "A".repeat(Limits::DEFAULT.max_named_address_tag_length as usize + 1)
+        ]),
+        ..icrc1_named_address_with_details()
     };
-
-    let response = store.set_address_book(principal, address_book);
-    assert!(matches!(response, SetAddressBookResponse::InvalidIcpAddress { .. }));
-
-    // Verify that the address book was still not saved
     assert_eq!(
-        store.get_address_book(principal),
-        GetAddressBookResponse::Ok(AddressBook::default())
+        set_single_named_address(&mut store, principal, named_address),
+        SetAddressBookResponse::TagTooLong {
+            max_length: Limits::DEFAULT.max_named_address_tag_length
+        }
     );
 }
 
 #[test]
-fn set_address_book_name_too_long() {
+fn set_address_book_ledger_canister_id_requires_icrc1_address() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
 
-    let too_long_name = "a".repeat((MAX_NAMED_ADDRESS_NAME_LENGTH + 1) as usize);
-
-    let address_book = AddressBook {
-        named_addresses: vec![NamedAddress {
-            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
-            name: too_long_name,
-        }],
+    let named_address = NamedAddress {
+        address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+        default_memo: None,
+        subaccount_hint: None,
+        ..icrc1_named_address_with_details()
     };
 
     assert_eq!(
-        store.set_address_book(principal, address_book),
-        SetAddressBookResponse::AddressNameTooLong {
-            max_length: MAX_NAMED_ADDRESS_NAME_LENGTH
-        }
+        set_single_named_address(&mut store, principal, named_address),
+        SetAddressBookResponse::LedgerCanisterIdNotSupported
     );
-
-    // Verify that the address book was not saved
     assert_eq!(
         store.get_address_book(principal),
         GetAddressBookResponse::Ok(AddressBook::default())
     );
 }
 
+fn icp_named_address(name: &str) -> NamedAddress {
+    NamedAddress {
+        address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
+        name: name.to_string(),
+        default_memo: None,
+        subaccount_hint: None,
+        tags: None,
+        ledger_canister_id: None,
+    }
+}
+
+fn get_named_addresses(store: &AccountsStore, principal: PrincipalId) -> (Vec<NamedAddress>, Option<u64>) {
+    let GetAddressBookResponse::Ok(address_book) = store.get_address_book(principal) else {
+        panic!("Expected the address book to be found");
+    };
+    (address_book.named_addresses, address_book.version)
+}
+
 #[test]
-fn set_address_book_name_at_max_length() {
+fn add_update_and_remove_named_addresses() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
 
-    let max_length_name = "a".repeat(MAX_NAMED_ADDRESS_NAME_LENGTH as usize);
+    assert_eq!(
+        store.add_named_address(
+            principal,
+            AddNamedAddressRequest {
+                named_address: icp_named_address("  Alice  "),
+                expected_version: 0,
+            }
+        ),
+        SetAddressBookResponse::Ok
+    );
+    assert_eq!(
+        store.add_named_address(
+            principal,
+            AddNamedAddressRequest {
+                named_address: icp_named_address("Bob"),
+                expected_version: 1,
+            }
+        ),
+        SetAddressBookResponse::Ok
+    );
+    assert_eq!(
+        get_named_addresses(&store, principal),
+        (vec![icp_named_address("Alice"), icp_named_address("Bob")], Some(2))
+    );
 
-    let address_book = AddressBook {
-        named_addresses: vec![NamedAddress {
-            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
-            name: max_length_name,
-        }],
-    };
+    assert_eq!(
+        store.update_named_address(
+            principal,
+            UpdateNamedAddressRequest {
+                name: "Alice".to_string(),
+                named_address: icp_named_address("Carol"),
+                expected_version: 2,
+            }
+        ),
+        SetAddressBookResponse::Ok
+    );
+    assert_eq!(
+        get_named_addresses(&store, principal),
+        (vec![icp_named_address("Carol"), icp_named_address("Bob")], Some(3))
+    );
 
     assert_eq!(
-        store.set_address_book(principal, address_book.clone()),
+        store.remove_named_address(
+            principal,
+            RemoveNamedAddressRequest {
+                name: "Bob".to_string(),
+                expected_version: 3,
+            }
+        ),
         SetAddressBookResponse::Ok
     );
+    assert_eq!(
+        get_named_addresses(&store, principal),
+        (vec![icp_named_address("Carol")], Some(4))
+    );
 
-    // Verify it was stored correctly
+    // Replacing the whole address book also increments the version.
     assert_eq!(
-        store.get_address_book(principal),
-        GetAddressBookResponse::Ok(address_book)
+        store.set_address_book(principal, new_address_book(1), None),
+        SetAddressBookResponse::Ok
     );
+    assert_eq!(get_named_addresses(&store, principal).1, Some(5));
 }
 
 #[test]
-fn set_address_book_name_too_short() {
+fn named_address_mutations_reject_stale_versions() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
-
-    // Test with name that's too short after normalization
-    let too_short_name = "ab".to_string();
-
-    let address_book = AddressBook {
-        named_addresses: vec![NamedAddress {
-            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
-            name: too_short_name,
-        }],
-    };
+    store.set_address_book(principal, new_address_book(2), None);
 
     assert_eq!(
-        store.set_address_book(principal, address_book),
-        SetAddressBookResponse::AddressNameTooShort {
-            min_length: MIN_NAMED_ADDRESS_NAME_LENGTH
-        }
+        store.add_named_address(
+            principal,
+            AddNamedAddressRequest {
+                named_address: icp_named_address("Alice"),
+                expected_version: 0,
+            }
+        ),
+        SetAddressBookResponse::VersionConflict { current_version: 1 }
+    );
+    assert_eq!(
+        store.update_named_address(
+            principal,
+            UpdateNamedAddressRequest {
+                name: "Name 0".to_string(),
+                named_address: icp_named_address("Alice"),
+                expected_version: 2,
+            }
+        ),
+        SetAddressBookResponse::VersionConflict { current_version: 1 }
+    );
+    assert_eq!(
+        store.remove_named_address(
+            principal,
+            RemoveNamedAddressRequest {
+                name: "Name 0".to_string(),
+                expected_version: 0,
+            }
+        ),
+        SetAddressBookResponse::VersionConflict { current_version: 1 }
     );
-
-    // Verify that the address book was not saved
     assert_eq!(
         store.get_address_book(principal),
-        GetAddressBookResponse::Ok(AddressBook::default())
+        GetAddressBookResponse::Ok(AddressBook {
+            version: Some(1),
+            ..new_address_book(2)
+        })
     );
 }
 
 #[test]
-fn set_address_book_name_too_short_after_trimming() {
+fn named_address_mutations_are_validated() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
-
-    // Test with name that has spaces and becomes too short after normalization
-    let name_with_spaces = "  a    ".to_string();
-    let address_book_1 = AddressBook {
-        named_addresses: vec![NamedAddress {
-            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
-            name: name_with_spaces,
-        }],
-    };
+    let unknown_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    store.set_address_book(principal, new_address_book(Limits::DEFAULT.max_named_addresses), None);
 
     assert_eq!(
-        store.set_address_book(principal, address_book_1),
-        SetAddressBookResponse::AddressNameTooShort {
-            min_length: MIN_NAMED_ADDRESS_NAME_LENGTH
+        store.add_named_address(
+            principal,
+            AddNamedAddressRequest {
+                named_address: icp_named_address("Alice"),
+                expected_version: 1,
+            }
+        ),
+        SetAddressBookResponse::TooManyNamedAddresses {
+            limit: Limits::DEFAULT.max_named_addresses
         }
     );
-
-    // Verify that the address book was not saved
     assert_eq!(
-        store.get_address_book(principal),
-        GetAddressBookResponse::Ok(AddressBook::default())
+        store.update_named_address(
+            principal,
+            UpdateNamedAddressRequest {
+                name: "Name 0".to_string(),
+                named_address: icp_named_address("Name 1"),
+                expected_version: 1,
+            }
+        ),
+        SetAddressBookResponse::DuplicateAddressName {
+            name: "Name 1".to_string()
+        }
     );
-
-    // Test with name that's too short after trimming
-    let only_spaces = "   ".to_string(); // After trim: "" (0 chars)
-    let address_book_2 = AddressBook {
-        named_addresses: vec![NamedAddress {
-            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
-            name: only_spaces,
-        }],
-    };
-
     assert_eq!(
-        store.set_address_book(principal, address_book_2),
+        store.update_named_address(
+            principal,
+            UpdateNamedAddressRequest {
+                name: "Name 0".to_string(),
+                named_address: icp_named_address("AB"),
+                expected_version: 1,
+            }
+        ),
         SetAddressBookResponse::AddressNameTooShort {
             min_length: MIN_NAMED_ADDRESS_NAME_LENGTH
         }
     );
-
-    // Verify that the address book was not saved
     assert_eq!(
-        store.get_address_book(principal),
-        GetAddressBookResponse::Ok(AddressBook::default())
+        store.remove_named_address(
+            principal,
+            RemoveNamedAddressRequest {
+                name: "Alice".to_string(),
+                expected_version: 1,
+            }
+        ),
+        SetAddressBookResponse::NamedAddressNotFound {
+            name: "Alice".to_string()
+        }
+    );
+    assert_eq!(
+        store.remove_named_address(
+            unknown_principal,
+            RemoveNamedAddressRequest {
+                name: "Alice".to_string(),
+                expected_version: 0,
+            }
+        ),
+        SetAddressBookResponse::AccountNotFound
     );
+    assert_eq!(get_named_addresses(&store, principal).1, Some(1));
+}
 
-    // Test with name that's OK after trimming
-    let raw_name = "  a     b ".to_string(); // After trim and normalize: "a b" (3 chars)
-    let address_book_3 = AddressBook {
-        named_addresses: vec![NamedAddress {
-            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
-            name: raw_name,
-        }],
-    };
+#[test]
+fn sub_account_name_too_long() {
+    let mut store = setup_test_store();
 
-    assert_eq!(
-        store.set_address_book(principal, address_book_3),
-        SetAddressBookResponse::Ok
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+
+    let res1 = store.create_sub_account(principal, "ABCDEFGHIJKLMNOPQRSTUVWX".to_string());
+    let res2 = store.create_sub_account(principal, "ABCDEFGHIJKLMNOPQRSTUVWXY".to_string());
+
+    assert!(matches!(res1, CreateSubAccountResponse::Ok(_)));
+    assert!(matches!(res2, CreateSubAccountResponse::NameTooLong));
+}
+
+#[test]
+fn hardware_wallet_account_name_too_long() {
+    let mut store = setup_test_store();
+
+    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let hw1 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let hw2 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_4).unwrap();
+
+    let res1 = store.register_hardware_wallet(
+        principal,
+        RegisterHardwareWalletRequest {
+            name: "ABCDEFGHIJKLMNOPQRSTUVWX".to_string(),
+            principal: hw1,
+            device: None,
+        },
     );
 
-    // Verify that the address book was saved with normalized name
-    let expected = AddressBook {
-        named_addresses: vec![NamedAddress {
-            address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
-            name: "a b".to_string(),
-        }],
-    };
-    assert_eq!(store.get_address_book(principal), GetAddressBookResponse::Ok(expected));
+    let res2 = store.register_hardware_wallet(
+        principal,
+        RegisterHardwareWalletRequest {
+            name: "ABCDEFGHIJKLMNOPQRSTUVWXY".to_string(),
+            principal: hw2,
+            device: None,
+        },
+    );
+
+    assert!(matches!(res1, RegisterHardwareWalletResponse::Ok));
+    assert!(matches!(res2, RegisterHardwareWalletResponse::NameTooLong));
 }
 
 #[test]
-fn set_address_book_with_valid_icrc1_address() {
+fn export_and_import_account_data_to_another_principal() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let new_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    let hw = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_4).unwrap();
 
-    // Test with a valid ICRC1 address (principal format)
-    let address_book = AddressBook {
-        named_addresses: vec![NamedAddress {
-            address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_2.to_string()),
-            name: "ICRC1 Account".to_string(),
-        }],
+    store.create_sub_account(principal, "AAA".to_string());
+    store.create_sub_account(principal, "BBB".to_string());
+    let sub_account_ids = sub_account_identifiers(&store, principal);
+    store.set_sub_account_archived(
+        principal,
+        SetSubAccountArchivedRequest {
+            account_identifier: sub_account_ids[1],
+            archived: true,
+        },
+    );
+    store.set_sub_account_style_key(
+        principal,
+        SetSubAccountStyleKeyRequest {
+            account_identifier: sub_account_ids[0],
+            style_key: Some("blue".to_string()),
+        },
+    );
+    store.reorder_sub_accounts(
+        principal,
+        ReorderSubAccountsRequest {
+            account_identifiers: vec![sub_account_ids[1]],
+        },
+    );
+    store.register_hardware_wallet(
+        principal,
+        RegisterHardwareWalletRequest {
+            name: "HW".to_string(),
+            principal: hw,
+            device: None,
+        },
+    );
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "ABC".to_string(),
+            canister_id,
+            block_index: Some(123),
+        },
+    );
+    let imported_tokens = ImportedTokens {
+        imported_tokens: get_unique_imported_tokens(2),
+        version: None,
     };
+    store.set_imported_tokens(principal, imported_tokens.clone(), None);
+    let fav_projects = FavProjects {
+        fav_projects: get_unique_fav_projects(3),
+        version: None,
+    };
+    store.set_fav_projects(principal, fav_projects.clone(), None);
+    let address_book = new_address_book(2);
+    store.set_address_book(principal, address_book.clone(), None);
+    store.create_named_icrc1_subaccount(principal, named_icrc1_subaccount(1, 1, "Savings"));
 
+    let ExportAccountDataResponse::Ok(bundle) = store.export_account_data(principal) else {
+        panic!("Expected the account data to be exported");
+    };
+    assert_eq!(bundle.version, ACCOUNT_DATA_BUNDLE_VERSION);
     assert_eq!(
-        store.set_address_book(principal, address_book.clone()),
-        SetAddressBookResponse::Ok
+        store.import_account_data(new_principal, bundle),
+        ImportAccountDataResponse::Ok
     );
 
-    // Verify it was stored correctly
+    let old_account = store.get_account(principal).unwrap();
+    let new_account = store.get_account(new_principal).unwrap();
     assert_eq!(
-        store.get_address_book(principal),
-        GetAddressBookResponse::Ok(address_book)
+        old_account
+            .sub_accounts
+            .iter()
+            .map(|sub_account| {
+                (
+                    &sub_account.name,
+                    sub_account.sub_account,
+                    sub_account.archived,
+                    sub_account.display_order,
+                    &sub_account.style_key,
+                )
+            })
+            .collect::<Vec<_>>(),
+        new_account
+            .sub_accounts
+            .iter()
+            .map(|sub_account| {
+                (
+                    &sub_account.name,
+                    sub_account.sub_account,
+                    sub_account.archived,
+                    sub_account.display_order,
+                    &sub_account.style_key,
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+    // Sub-account identifiers are derived from the new principal.
+    assert_eq!(
+        new_account.sub_accounts[0].account_identifier,
+        AccountIdentifier::new(new_principal, Some(new_account.sub_accounts[0].sub_account))
+    );
+    assert_eq!(1, new_account.hardware_wallet_accounts.len());
+    assert_eq!(hw, new_account.hardware_wallet_accounts[0].principal);
+    assert_eq!(store.get_canisters(principal), store.get_canisters(new_principal));
+    assert_eq!(
+        store.get_imported_tokens(new_principal),
+        GetImportedTokensResponse::Ok(ImportedTokens {
+            version: Some(1),
+            ..imported_tokens
+        })
+    );
+    assert_eq!(
+        store.get_fav_projects(new_principal),
+        GetFavProjectsResponse::Ok(FavProjects {
+            version: Some(1),
+            ..fav_projects
+        })
+    );
+    assert_eq!(
+        store.get_address_book(new_principal),
+        GetAddressBookResponse::Ok(AddressBook {
+            version: Some(1),
+            ..address_book
+        })
+    );
+    assert_eq!(
+        list_all_named_icrc1_subaccounts(&store, new_principal),
+        vec![named_icrc1_subaccount(1, 1, "Savings")]
     );
+
+    let mut stats = Stats::default();
+    store.get_stats(&mut stats);
+    assert_eq!(4, stats.sub_accounts_count);
+    assert_eq!(2, stats.hardware_wallet_accounts_count);
 }
 
 #[test]
-fn set_address_book_with_valid_icrc1_with_subaccount_address() {
+fn import_account_data_replaces_existing_data() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let other_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
 
-    // Test with a valid ICRC1 with subaccount address
-    let address_book = AddressBook {
-        named_addresses: vec![NamedAddress {
-            address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_SUBACCOUNT_VALID_1.to_string()),
-            name: "ICRC1 Account with Subaccount".to_string(),
-        }],
+    store.create_sub_account(principal, "AAA".to_string());
+    store.create_sub_account(principal, "BBB".to_string());
+    let ExportAccountDataResponse::Ok(empty_bundle) = store.export_account_data(other_principal) else {
+        panic!("Expected the account data to be exported");
     };
 
     assert_eq!(
-        store.set_address_book(principal, address_book.clone()),
-        SetAddressBookResponse::Ok
+        store.import_account_data(principal, empty_bundle),
+        ImportAccountDataResponse::Ok
     );
 
-    // Verify it was stored correctly
-    assert_eq!(
-        store.get_address_book(principal),
-        GetAddressBookResponse::Ok(address_book)
-    );
+    assert!(store.get_account(principal).unwrap().sub_accounts.is_empty());
+    let mut stats = Stats::default();
+    store.get_stats(&mut stats);
+    assert_eq!(0, stats.sub_accounts_count);
 }
 
 #[test]
-fn set_address_book_with_invalid_icrc1_address() {
+fn import_account_data_advances_settings_versions() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
-
-    // Test with an invalid ICRC1 address
-    let address_book = AddressBook {
-        named_addresses: vec![NamedAddress {
-            address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_SUBACCOUNT_INVALID_1.to_string()),
-            name: "Invalid ICRC1".to_string(),
-        }],
+    let other_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
+    store.set_address_book(principal, new_address_book(1), None);
+    store.set_address_book(principal, new_address_book(2), None);
+    let ExportAccountDataResponse::Ok(empty_bundle) = store.export_account_data(other_principal) else {
+        panic!("Expected the account data to be exported");
     };
 
-    let response = store.set_address_book(principal, address_book);
-    assert!(matches!(response, SetAddressBookResponse::InvalidIcrc1Address { .. }));
-
-    // Verify that the address book was not saved
     assert_eq!(
-        store.get_address_book(principal),
-        GetAddressBookResponse::Ok(AddressBook::default())
+        store.import_account_data(principal, empty_bundle),
+        ImportAccountDataResponse::Ok
     );
 
-    let address_book = AddressBook {
-        named_addresses: vec![NamedAddress {
-            address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_SUBACCOUNT_INVALID_2.to_string()),
-            name: "Invalid ICRC1".to_string(),
-        }],
-    };
-
-    let response = store.set_address_book(principal, address_book);
-    assert!(matches!(response, SetAddressBookResponse::InvalidIcrc1Address { .. }));
-
-    // Verify that the address book was not saved
+    // A device that read the address book before the import must not overwrite it.
     assert_eq!(
-        store.get_address_book(principal),
-        GetAddressBookResponse::Ok(AddressBook::default())
+        store.set_address_book(principal, new_address_book(2), Some(2)),
+        SetAddressBookResponse::VersionConflict { current_version: 3 }
     );
+    assert_eq!(
+        store.get_imported_tokens(principal),
+        GetImportedTokensResponse::Ok(ImportedTokens {
+            imported_tokens: vec![],
+            version: Some(1),
+        })
+    );
+}
 
-    let address_book = AddressBook {
-        named_addresses: vec![NamedAddress {
-            address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_SUBACCOUNT_INVALID_3.to_string()),
-            name: "Invalid ICRC1".to_string(),
-        }],
-    };
-
-    let response = store.set_address_book(principal, address_book);
-    assert!(matches!(response, SetAddressBookResponse::InvalidIcrc1Address { .. }));
-
-    // Verify that the address book was not saved
+#[test]
+fn export_account_data_account_not_found() {
+    let store = setup_test_store();
+    let non_existing_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
     assert_eq!(
-        store.get_address_book(principal),
-        GetAddressBookResponse::Ok(AddressBook::default())
+        store.export_account_data(non_existing_principal),
+        ExportAccountDataResponse::AccountNotFound
     );
 }
 
 #[test]
-fn set_address_book_with_invalid_icrc1_with_subaccount_address() {
+fn import_account_data_rejects_invalid_bundles() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
-
-    // Test with an invalid subaccount address
-    let address_book = AddressBook {
-        named_addresses: vec![NamedAddress {
-            address: AddressType::Icrc1(format!("{}.123", TEST_ICRC1_ACCOUNT_2)),
-            name: "Invalid ICRC1 with Subaccount".to_string(),
-        }],
+    let ExportAccountDataResponse::Ok(bundle) = store.export_account_data(principal) else {
+        panic!("Expected the account data to be exported");
     };
 
-    let response = store.set_address_book(principal, address_book);
-    assert!(matches!(response, SetAddressBookResponse::InvalidIcrc1Address { .. }));
-
-    // Verify that the address book was not saved
+    let unsupported_version = AccountDataBundle {
+        version: ACCOUNT_DATA_BUNDLE_VERSION + 1,
+        ..bundle.clone()
+    };
     assert_eq!(
-        store.get_address_book(principal),
-        GetAddressBookResponse::Ok(AddressBook::default())
+        store.import_account_data(principal, unsupported_version),
+        ImportAccountDataResponse::UnsupportedVersion {
+            version: ACCOUNT_DATA_BUNDLE_VERSION + 1
+        }
     );
-}
 
-#[test]
-fn set_address_book_with_mixed_address_types() {
-    let mut store = setup_test_store();
-    let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let mut invalid_sub_account = [0u8; 32];
+    invalid_sub_account[0] = 1;
+    let invalid_sub_account = AccountDataBundle {
+        sub_accounts: vec![ExportedSubAccount {
+            name: "AAA".to_string(),
+            sub_account: Subaccount(invalid_sub_account),
+            archived: None,
+            display_order: None,
+            style_key: None,
+        }],
+        ..bundle.clone()
+    };
+    assert_eq!(
+        store.import_account_data(principal, invalid_sub_account),
+        ImportAccountDataResponse::InvalidSubAccount
+    );
 
-    // Test with both ICP and ICRC1 addresses
-    let address_book = AddressBook {
-        named_addresses: vec![
-            NamedAddress {
-                address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
-                name: "ICP Account".to_string(),
+    let duplicate_sub_account = AccountDataBundle {
+        sub_accounts: vec![
+            ExportedSubAccount {
+                name: "AAA".to_string(),
+                sub_account: convert_byte_to_sub_account(1),
+                archived: None,
+                display_order: None,
+                style_key: None,
             },
-            NamedAddress {
-                address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_3.to_string()),
-                name: "ICRC1 Account".to_string(),
+            ExportedSubAccount {
+                name: "BBB".to_string(),
+                sub_account: convert_byte_to_sub_account(1),
+                archived: None,
+                display_order: None,
+                style_key: None,
             },
         ],
+        ..bundle.clone()
     };
+    assert_eq!(
+        store.import_account_data(principal, duplicate_sub_account),
+        ImportAccountDataResponse::DuplicateSubAccount
+    );
 
+    let too_many_imported_tokens = AccountDataBundle {
+        imported_tokens: ImportedTokens {
+            imported_tokens: get_unique_imported_tokens(21),
+            version: None,
+        },
+        ..bundle.clone()
+    };
     assert_eq!(
-        store.set_address_book(principal, address_book.clone()),
-        SetAddressBookResponse::Ok
+        store.import_account_data(principal, too_many_imported_tokens),
+        ImportAccountDataResponse::TooManyImportedTokens { limit: 20 }
     );
 
-    // Verify both were stored correctly
+    let non_existing_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
     assert_eq!(
-        store.get_address_book(principal),
-        GetAddressBookResponse::Ok(address_book)
+        store.import_account_data(non_existing_principal, bundle),
+        ImportAccountDataResponse::AccountNotFound
     );
 }
 
+const TEST_NOW_NANOS: u64 = 1_724_314_428_000_000_000;
+
 #[test]
-fn set_address_book_duplicate_names() {
+fn account_transfer_moves_account_to_target_principal() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let new_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let canister_id = CanisterId::from_str(TEST_ICRC1_ACCOUNT_4).unwrap();
 
-    // Test with duplicate names
-    let duplicate_name = "Alice's Account".to_string();
-    let address_book = AddressBook {
-        named_addresses: vec![
-            NamedAddress {
-                address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
-                name: duplicate_name.clone(),
-            },
-            NamedAddress {
-                address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_2.to_string()),
-                name: duplicate_name.clone(),
-            },
-        ],
-    };
+    store.create_sub_account(principal, "AAA".to_string());
+    store.attach_canister(
+        principal,
+        AttachCanisterRequest {
+            name: "ABC".to_string(),
+            canister_id,
+            block_index: None,
+        },
+    );
+    let canisters = store.get_canisters(principal);
 
     assert_eq!(
-        store.set_address_book(principal, address_book),
-        SetAddressBookResponse::DuplicateAddressName { name: duplicate_name }
+        store.create_account_transfer(
+            principal,
+            CreateAccountTransferRequest { to: new_principal },
+            TEST_NOW_NANOS
+        ),
+        CreateAccountTransferResponse::Ok {
+            expires_at_timestamp_nanos: TEST_NOW_NANOS + ACCOUNT_TRANSFER_EXPIRY_NANOS
+        }
+    );
+    // The target principal logs in for the first time.
+    store.add_account(new_principal);
+    assert_eq!(
+        store.claim_account_transfer(
+            new_principal,
+            ClaimAccountTransferRequest { from: principal },
+            TEST_NOW_NANOS + 1
+        ),
+        ClaimAccountTransferResponse::Ok
     );
 
-    // Verify that the address book was not saved
+    assert!(store.get_account(principal).is_none());
+    let account = store.get_account(new_principal).unwrap();
+    assert_eq!(new_principal, account.principal);
+    assert_eq!(1, account.sub_accounts.len());
+    assert_eq!("AAA", account.sub_accounts[0].name);
     assert_eq!(
-        store.get_address_book(principal),
-        GetAddressBookResponse::Ok(AddressBook::default())
+        AccountIdentifier::new(new_principal, Some(account.sub_accounts[0].sub_account)),
+        account.sub_accounts[0].account_identifier
+    );
+    assert_eq!(canisters, store.get_canisters(new_principal));
+
+    // The offer cannot be claimed twice.
+    assert_eq!(
+        store.claim_account_transfer(
+            new_principal,
+            ClaimAccountTransferRequest { from: principal },
+            TEST_NOW_NANOS + 1
+        ),
+        ClaimAccountTransferResponse::TransferNotFound
     );
 }
 
 #[test]
-fn set_address_book_duplicate_names_multiple() {
+fn account_transfer_can_only_be_claimed_by_target() {
     let mut store = setup_test_store();
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let new_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+    let other_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_4).unwrap();
 
-    // Test with three addresses where two have the same name
-    let duplicate_name = "Duplicate".to_string();
-    let duplicate_name_2 = "Duplicate 2".to_string();
-    let address_book = AddressBook {
-        named_addresses: vec![
-            NamedAddress {
-                address: AddressType::Icp(TEST_ICP_ACCOUNT_1.to_string()),
-                name: "Unique Name".to_string(),
-            },
-            NamedAddress {
-                address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_2.to_string()),
-                name: duplicate_name.clone(),
-            },
-            NamedAddress {
-                address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_3.to_string()),
-                name: duplicate_name.clone(),
-            },
-            NamedAddress {
-                address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_4.to_string()),
-                name: duplicate_name_2.clone(),
-            },
-            NamedAddress {
-                address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_5.to_string()),
-                name: duplicate_name_2.clone(),
-            },
-        ],
-    };
-
-    assert_eq!(
-        store.set_address_book(principal, address_book),
-        SetAddressBookResponse::DuplicateAddressName { name: duplicate_name }
+    store.create_account_transfer(
+        principal,
+        CreateAccountTransferRequest { to: new_principal },
+        TEST_NOW_NANOS,
     );
 
-    // Verify that the address book was not saved
     assert_eq!(
-        store.get_address_book(principal),
-        GetAddressBookResponse::Ok(AddressBook::default())
+        store.claim_account_transfer(
+            other_principal,
+            ClaimAccountTransferRequest { from: principal },
+            TEST_NOW_NANOS
+        ),
+        ClaimAccountTransferResponse::TransferNotFound
     );
+    assert!(store.get_account(principal).is_some());
 }
 
 #[test]
-fn sub_account_name_too_long() {
+fn account_transfer_rejects_non_empty_target_account() {
     let mut store = setup_test_store();
-
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+    let new_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_2).unwrap();
 
-    let res1 = store.create_sub_account(principal, "ABCDEFGHIJKLMNOPQRSTUVWX".to_string());
-    let res2 = store.create_sub_account(principal, "ABCDEFGHIJKLMNOPQRSTUVWXY".to_string());
+    store.create_sub_account(new_principal, "AAA".to_string());
+    store.create_account_transfer(
+        principal,
+        CreateAccountTransferRequest { to: new_principal },
+        TEST_NOW_NANOS,
+    );
 
-    assert!(matches!(res1, CreateSubAccountResponse::Ok(_)));
-    assert!(matches!(res2, CreateSubAccountResponse::NameTooLong));
+    assert_eq!(
+        store.claim_account_transfer(
+            new_principal,
+            ClaimAccountTransferRequest { from: principal },
+            TEST_NOW_NANOS
+        ),
+        ClaimAccountTransferResponse::TargetAccountNotEmpty
+    );
+    assert!(store.get_account(principal).is_some());
 }
 
 #[test]
-fn hardware_wallet_account_name_too_long() {
+fn account_transfer_can_be_cancelled() {
     let mut store = setup_test_store();
-
     let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
-    let hw1 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
-    let hw2 = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_4).unwrap();
+    let new_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
 
-    let res1 = store.register_hardware_wallet(
-        principal,
-        RegisterHardwareWalletRequest {
-            name: "ABCDEFGHIJKLMNOPQRSTUVWX".to_string(),
-            principal: hw1,
-        },
+    assert_eq!(
+        store.create_account_transfer(
+            principal,
+            CreateAccountTransferRequest { to: principal },
+            TEST_NOW_NANOS
+        ),
+        CreateAccountTransferResponse::CannotTransferToSelf
+    );
+    assert_eq!(
+        store.create_account_transfer(
+            new_principal,
+            CreateAccountTransferRequest { to: principal },
+            TEST_NOW_NANOS
+        ),
+        CreateAccountTransferResponse::AccountNotFound
     );
 
-    let res2 = store.register_hardware_wallet(
+    store.create_account_transfer(
         principal,
-        RegisterHardwareWalletRequest {
-            name: "ABCDEFGHIJKLMNOPQRSTUVWXY".to_string(),
-            principal: hw2,
-        },
+        CreateAccountTransferRequest { to: new_principal },
+        TEST_NOW_NANOS,
+    );
+    assert_eq!(
+        store.cancel_account_transfer(principal),
+        CancelAccountTransferResponse::Ok
     );
+    assert_eq!(
+        store.cancel_account_transfer(principal),
+        CancelAccountTransferResponse::TransferNotFound
+    );
+    assert_eq!(
+        store.claim_account_transfer(
+            new_principal,
+            ClaimAccountTransferRequest { from: principal },
+            TEST_NOW_NANOS
+        ),
+        ClaimAccountTransferResponse::TransferNotFound
+    );
+}
 
-    assert!(matches!(res1, RegisterHardwareWalletResponse::Ok));
-    assert!(matches!(res2, RegisterHardwareWalletResponse::NameTooLong));
+proptest! {
+    /// Claiming a transfer moves all the account data and keeps the stats consistent, as long as
+    /// the offer has not expired.
+    #[test]
+    fn account_transfer_preserves_account_data(
+        num_sub_accounts in 0u64..5,
+        num_hardware_wallets in 0u64..3,
+        num_canisters in 0u64..4,
+        claim_delay_nanos in 0u64..(2 * ACCOUNT_TRANSFER_EXPIRY_NANOS),
+    ) {
+        let mut store = setup_test_store();
+        let principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_1).unwrap();
+        let new_principal = PrincipalId::from_str(TEST_ICRC1_ACCOUNT_3).unwrap();
+        for i in 0..num_sub_accounts {
+            store.create_sub_account(principal, format!("sub_account_{i}"));
+        }
+        for i in 0..num_hardware_wallets {
+            store.register_hardware_wallet(
+                principal,
+                RegisterHardwareWalletRequest {
+                    name: format!("hw_{i}"),
+                    principal: PrincipalId::new_user_test_id(i),
+                    device: None,
+                },
+            );
+        }
+        for i in 0..num_canisters {
+            store.attach_canister(
+                principal,
+                AttachCanisterRequest {
+                    name: format!("canister_{i}"),
+                    canister_id: CanisterId::from(i),
+                    block_index: Some(i),
+                },
+            );
+        }
+        let ExportAccountDataResponse::Ok(data_before) = store.export_account_data(principal) else {
+            panic!("Expected the account data to be exported");
+        };
+        let mut stats_before = Stats::default();
+        store.get_stats(&mut stats_before);
+
+        store.create_account_transfer(principal, CreateAccountTransferRequest { to: new_principal }, TEST_NOW_NANOS);
+        let response = store.claim_account_transfer(
+            new_principal,
+            ClaimAccountTransferRequest { from: principal },
+            TEST_NOW_NANOS + claim_delay_nanos,
+        );
+
+        let mut stats_after = Stats::default();
+        store.get_stats(&mut stats_after);
+        assert_eq!(stats_before, stats_after);
+        if claim_delay_nanos < ACCOUNT_TRANSFER_EXPIRY_NANOS {
+            assert_eq!(response, ClaimAccountTransferResponse::Ok);
+            assert_eq!(
+                store.export_account_data(principal),
+                ExportAccountDataResponse::AccountNotFound
+            );
+            assert_eq!(
+                store.export_account_data(new_principal),
+                ExportAccountDataResponse::Ok(data_before)
+            );
+        } else {
+            assert_eq!(response, ClaimAccountTransferResponse::TransferExpired);
+            assert_eq!(
+                store.export_account_data(principal),
+                ExportAccountDataResponse::Ok(data_before)
+            );
+        }
+    }
 }
 
 /// Test that the stats are as expected for a new test store.
@@ -1806,6 +4694,7 @@ fn get_stats() {
         RegisterHardwareWalletRequest {
             name: "HW1".to_string(),
             principal: hw1,
+            device: None,
         },
     );
     store.register_hardware_wallet(
@@ -1813,6 +4702,7 @@ fn get_stats() {
         RegisterHardwareWalletRequest {
             name: "HW2".to_string(),
             principal: hw2,
+            device: None,
         },
     );
 
@@ -1896,6 +4786,7 @@ fn get_histogram() {
             RegisterHardwareWalletRequest {
                 name: "HW1".to_string(),
                 principal: hw1,
+                device: None,
             },
         );
         store.register_hardware_wallet(
@@ -1903,6 +4794,7 @@ fn get_histogram() {
             RegisterHardwareWalletRequest {
                 name: "HW2".to_string(),
                 principal: hw2,
+                device: None,
             },
         );
         // The two accounts (principal3 and principal4) have 1 hardware wallet each, so the 1 bucket should be incremented in each histogram:
@@ -1959,8 +4851,6 @@ pub fn test_store_histogram() -> AccountsStoreHistogram {
 }
 
 /// Stored accounts should be recovered with the same value.
-///
-/// Note: Given that account implement `CandidType` this is little more than a formality.
 #[test]
 fn accounts_should_implement_storable() {
     let account = toy_account(
@@ -1975,3 +4865,88 @@ fn accounts_should_implement_storable() {
     let parsed = Account::from_bytes(bytes);
     assert_eq!(account, parsed);
 }
+
+/// An account with every optional field set, to check that no field is lost when encoding.
+fn account_with_all_fields() -> Account {
+    let mut account = toy_account(
+        1,
+        ToyAccountSize {
+            sub_accounts: 2,
+            canisters: 3,
+            hardware_wallets: 2,
+        },
+    );
+    for sub_account in account.sub_accounts.values_mut() {
+        sub_account.archived = Some(true);
+        sub_account.display_order = Some(7);
+        sub_account.style_key = Some("blue".to_string());
+    }
+    for hardware_wallet in &mut account.hardware_wallet_accounts {
+        hardware_wallet.device = Some(HardwareWalletDevice {
+            model: Some("Nano S Plus".to_string()),
+            app_version: Some("2.4.2".to_string()),
+            last_verified_timestamp_nanos: Some(TEST_NOW_NANOS),
+        });
+    }
+    for canister in &mut account.canisters {
+        canister.tags = Some(vec!["prod".to_string()]);
+        canister.note = Some("Backend".to_string());
+        canister.low_cycles_threshold = Some(1_000_000_000_000);
+    }
+    account.imported_tokens = Some(ImportedTokens {
+        imported_tokens: get_unique_imported_tokens(2),
+        version: Some(3),
+    });
+    account.fav_projects = Some(FavProjects {
+        fav_projects: get_unique_fav_projects(2),
+        version: Some(4),
+    });
+    let mut address_book = new_address_book(2);
+    address_book.named_addresses.push(NamedAddress {
+        address: AddressType::Icrc1(TEST_ICRC1_ACCOUNT_SUBACCOUNT_VALID_1.to_string()),
+        name: "ICRC-1".to_string(),
+        default_memo: Some(vec![1, 2, 3]),
+        subaccount_hint: Some(vec![4; 32]),
+        tags: Some(vec!["exchange".to_string()]),
+        ledger_canister_id: Some(CanisterId::from(5)),
+    });
+    address_book.version = Some(5);
+    account.address_book = Some(address_book);
+    account.named_icrc1_subaccounts = Some(vec![named_icrc1_subaccount(1, 1, "Savings")]);
+    account
+}
+
+#[test]
+fn accounts_are_stored_in_the_compact_encoding() {
+    for account in [toy_account(1, ToyAccountSize::default()), account_with_all_fields()] {
+        let bytes = account.to_bytes();
+        assert_eq!(bytes[0], ACCOUNT_ENCODING_PACKED_CBOR);
+        let candid_bytes = candid::encode_one(&account).unwrap();
+        assert!(
+            bytes.len() < candid_bytes.len(),
+            "The compact encoding should be smaller than Candid: {} vs {} bytes",
+            bytes.len(),
+            candid_bytes.len()
+        );
+        assert_eq!(Account::from_bytes(bytes), account);
+    }
+}
+
+/// Accounts written before the compact encoding are still Candid-encoded in stable memory and must stay readable.
+#[test]
+fn accounts_stored_as_candid_can_be_decoded() {
+    for account in [toy_account(1, ToyAccountSize::default()), account_with_all_fields()] {
+        let candid_bytes = candid::encode_one(&account).unwrap();
+        assert_eq!(Account::from_bytes(Cow::Owned(candid_bytes)), account);
+    }
+}
+
+/// An account stored as Candid is re-encoded compactly when it is next written.
+#[test]
+fn accounts_stored_as_candid_are_reencoded_when_written() {
+    let account = account_with_all_fields();
+    let candid_account = Account::from_bytes(Cow::Owned(candid::encode_one(&account).unwrap()));
+    let bytes = candid_account.into_bytes();
+    assert_eq!(bytes[0], ACCOUNT_ENCODING_PACKED_CBOR);
+    assert_eq!(Account::from_bytes(Cow::Owned(bytes)), account);
+}
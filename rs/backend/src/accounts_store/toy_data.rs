@@ -4,8 +4,10 @@ use crate::accounts_store::{
     Account, AccountsStore, AttachCanisterRequest, CanisterId, PrincipalId, RegisterHardwareWalletRequest,
 };
 
-#[cfg(test)]
 use crate::accounts_store::AccountIdentifier;
+use crate::perf::{count_instructions, PerformanceCount};
+use ic_stable_structures::Storable;
+use std::borrow::Cow;
 
 #[cfg(test)]
 use std::collections::HashMap;
@@ -81,6 +83,9 @@ pub fn toy_account(account_index: u64, size: ToyAccountSize) -> Account {
             name: format!("canister_{account_index}_{canister_index}"),
             canister_id,
             block_index: Some(123),
+            tags: None,
+            note: None,
+            low_cycles_threshold: None,
         };
         account.canisters.push(canister);
     }
@@ -95,6 +100,7 @@ pub fn toy_account(account_index: u64, size: ToyAccountSize) -> Account {
         let hardware_wallet = NamedHardwareWalletAccount {
             name: format!("hw_wallet_{account_index}_{hardware_wallet_index}"),
             principal,
+            device: None,
         };
         account.hardware_wallet_accounts.push(hardware_wallet);
     }
@@ -146,6 +152,7 @@ impl AccountsStore {
                     RegisterHardwareWalletRequest {
                         name: format!("hw_wallet_{toy_account_index}_{hardware_wallet_index}"),
                         principal,
+                        device: None,
                     },
                 );
             }
@@ -163,6 +170,29 @@ impl AccountsStore {
         index_range_start
     }
 
+    /// Measures the instructions used to read a toy account, stored either as Candid or in the compact encoding.
+    ///
+    /// Reading an account is dominated by decoding it, so this shows the savings of the compact encoding on
+    /// `get_account`.
+    #[must_use]
+    pub fn benchmark_get_account(&self, toy_account_index: u64) -> Vec<PerformanceCount> {
+        let principal = PrincipalId::new_user_test_id(toy_account_index);
+        let Some(account) = self.accounts_db.get(&AccountIdentifier::from(principal).to_vec()) else {
+            return Vec::new();
+        };
+        let candid_bytes = candid::encode_one(&account).expect("Failed to serialize account");
+        let compact_bytes = account.into_bytes();
+        vec![
+            count_instructions("get_account: decode Candid", || {
+                Account::from_bytes(Cow::Borrowed(&candid_bytes))
+            }),
+            count_instructions("get_account: decode compact", || {
+                Account::from_bytes(Cow::Borrowed(&compact_bytes))
+            }),
+            count_instructions("get_account", || self.get_account(principal)),
+        ]
+    }
+
     /// Creates an account store with the given number of test accounts.
     #[cfg(test)]
     pub fn with_toy_accounts(num_accounts: u64) -> AccountsStore {
@@ -17,6 +17,47 @@ use std::collections::HashMap;
 pub struct CanisterArguments {
     /// Values that are to be set in the web front end, by injecting them into JavaScript.
     pub args: Vec<(String, String)>,
+    /// Overrides for the limits on accounts and account data.  Limits that are not given are left unchanged.
+    pub limits: Option<LimitsArguments>,
+}
+
+/// Overrides for the limits on accounts and account data.
+///
+/// Every value is checked against a safe upper bound; if any is out of bounds, installation or upgrade fails.
+#[derive(Clone, Debug, Default, Eq, PartialEq, CandidType, Serialize, Deserialize)]
+pub struct LimitsArguments {
+    /// The maximum number of accounts.
+    pub account_limit: Option<u64>,
+    /// The largest id that may be given to a sub-account.
+    pub max_sub_account_id: Option<u8>,
+    /// The maximum number of imported tokens per account.
+    pub max_imported_tokens: Option<i32>,
+    /// The maximum number of favorite projects per account.
+    pub max_favorite_projects: Option<i32>,
+    /// The maximum number of named addresses in an address book.
+    pub max_named_addresses: Option<i32>,
+    /// The maximum length of the name of a sub-account or hardware wallet.
+    pub max_account_name_length: Option<u32>,
+    /// The maximum length of the name of an attached canister.
+    pub max_canister_name_length: Option<u32>,
+    /// The maximum number of named ICRC-1 sub-accounts per account.
+    pub max_named_icrc1_subaccounts: Option<i32>,
+    /// The maximum length of the model and app version of a hardware wallet device.
+    pub max_hardware_wallet_device_field_length: Option<i32>,
+    /// The maximum length of the colour/icon key of a sub-account.
+    pub max_sub_account_style_key_length: Option<i32>,
+    /// The maximum number of tags of a named address.
+    pub max_named_address_tags: Option<i32>,
+    /// The maximum length of a tag of a named address.
+    pub max_named_address_tag_length: Option<i32>,
+    /// The maximum number of principals that may be allowed to create accounts beyond the account limit.
+    pub max_account_limit_allowlist_size: Option<u32>,
+    /// The maximum number of tags of an attached canister.
+    pub max_canister_tags: Option<i32>,
+    /// The maximum length of a tag of an attached canister.
+    pub max_canister_tag_length: Option<i32>,
+    /// The maximum length of the note on an attached canister.
+    pub max_canister_note_length: Option<i32>,
 }
 
 thread_local! {
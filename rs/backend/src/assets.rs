@@ -1,22 +1,41 @@
 use crate::arguments::{TemplateEngine, CANISTER_ARGUMENTS};
+use crate::certification::{asset_witness, encode_witness, responses_witness, update_root_hash};
 use crate::metrics_encoder::MetricsEncoder;
 use crate::state::{with_state, with_state_mut, State};
 use crate::stats::encode_metrics;
 use crate::StableState;
 use base64::{engine::general_purpose::STANDARD as BASE64_ENGINE, Engine};
-use candid::{CandidType, Decode, Encode};
+use candid::{CandidType, Decode};
+use certification_v2::{
+    encode_expr_path, expr_path, path_segments, response_expression, response_hash, ExprTree,
+    CERTIFICATE_EXPRESSION_HEADER, EXACT_PATH_TERMINATOR, SKIP_CERTIFICATION_EXPRESSION, WILDCARD_PATH_TERMINATOR,
+};
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use ic_cdk::api::time;
 use ic_cdk::println;
-use ic_certified_map::{labeled, labeled_hash, AsHashTree, Hash, RbTree};
-use serde::{Deserialize, Serialize};
+use ic_certified_map::{AsHashTree, Hash, HashTree, RbTree};
+use ic_stable_structures::{
+    memory_manager::VirtualMemory, storable::Bound, DefaultMemoryImpl, StableBTreeMap, Storable,
+};
+use serde::Deserialize;
 use serde_bytes::ByteBuf;
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
 use std::io::prelude::*;
 use std::io::Read;
+use std::ops::Range;
+use streaming::{
+    chunk_range, content_range, requested_chunk, StreamingCallback, StreamingCallbackHttpResponse,
+    StreamingCallbackToken, StreamingStrategy, STREAMING_CHUNK_SIZE_BYTES,
+};
+use upload::{CommitAssetBatchRequest, CommitAssetBatchResponse};
+
+pub mod certification_v2;
+pub mod streaming;
+pub mod upload;
 
 #[cfg(test)]
 use pretty_assertions::assert_eq;
@@ -29,6 +48,18 @@ pub struct HttpRequest {
     url: String,
     headers: Vec<(String, String)>,
     body: ByteBuf,
+    /// The highest version of response certification that the HTTP gateway supports, if any.
+    certificate_version: Option<u16>,
+}
+
+impl HttpRequest {
+    /// The value of the request header with the given name, if any.  Header names are case insensitive.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -36,12 +67,15 @@ pub struct HttpResponse {
     status_code: u16,
     headers: Vec<HeaderField>,
     body: ByteBuf,
+    /// How to get the rest of the body, if it does not fit into this response.
+    streaming_strategy: Option<StreamingStrategy>,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum ContentEncoding {
     Identity,
     GZip,
+    Brotli,
 }
 impl ContentEncoding {
     /// Returns the file suffix for every encoding.
@@ -50,6 +84,7 @@ impl ContentEncoding {
         match self {
             ContentEncoding::Identity => "",
             ContentEncoding::GZip => ".gz",
+            ContentEncoding::Brotli => ".br",
         }
     }
     /// Returns the content encoding, as used in an HTTP header, if applicable.
@@ -58,22 +93,171 @@ impl ContentEncoding {
         match self {
             ContentEncoding::Identity => None,
             ContentEncoding::GZip => Some("gzip"),
+            ContentEncoding::Brotli => Some("br"),
+        }
+    }
+    /// Returns the name of the content encoding, as used in streaming callback tokens.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        self.header().unwrap_or("identity")
+    }
+    /// Returns the content encoding with the given name, if supported.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<ContentEncoding> {
+        Assets::CONTENT_ENCODINGS
+            .into_iter()
+            .find(|content_encoding| content_encoding.name() == name)
+    }
+    /// Returns the encodings that a client accepts, given the value of its `Accept-Encoding` header, if any.
+    ///
+    /// - Identity is always accepted, as is a client that sends no `Accept-Encoding` header.
+    /// - Weights are ignored, except that an encoding with `q=0` is not accepted.  The server prefers smaller
+    ///   encodings.
+    #[must_use]
+    pub fn accepted(accept_encoding: Option<&str>) -> Vec<ContentEncoding> {
+        let mut accepted = vec![ContentEncoding::Identity];
+        for coding in accept_encoding.unwrap_or_default().split(',') {
+            let mut parameters = coding.split(';').map(str::trim);
+            let name = parameters.next().unwrap_or_default().to_ascii_lowercase();
+            let is_rejected = parameters.any(|parameter| {
+                parameter
+                    .strip_prefix("q=")
+                    .and_then(|weight| weight.trim().parse::<f32>().ok())
+                    .is_some_and(|weight| weight <= 0.0)
+            });
+            if is_rejected {
+                continue;
+            }
+            match name.as_str() {
+                "gzip" => accepted.push(ContentEncoding::GZip),
+                "br" => accepted.push(ContentEncoding::Brotli),
+                "*" => accepted.extend([ContentEncoding::GZip, ContentEncoding::Brotli]),
+                _ => {}
+            }
+        }
+        accepted
+    }
+}
+
+/// The label of the subtree of certified data that contains the hashes of assets.
+pub const LABEL_ASSETS: &[u8] = b"http_assets";
+
+/// The certified data for assets.
+#[derive(Debug, Eq, PartialEq)]
+pub struct AssetHashes {
+    /// The hash of the body served at every path, for certification v1.
+    paths: RbTree<Vec<u8>, Hash>,
+    /// The responses served at every path, for certification v2.
+    responses: ExprTree,
+    /// The hash of the body served at every path in every encoding, from which the `ETag` is derived.
+    body_hashes: HashMap<(String, ContentEncoding), Hash>,
+}
+
+impl Default for AssetHashes {
+    /// Hashes with no assets.
+    ///
+    /// The response for assets that are not found is certified, and certification is skipped for `/metrics`, as the
+    /// metrics change all the time.
+    fn default() -> Self {
+        let mut responses = ExprTree::default();
+        let not_found = not_found_response();
+        responses.set_responses(
+            &expr_path(&[], WILDCARD_PATH_TERMINATOR),
+            &[not_found.certified_response()],
+        );
+        responses.set_skip_certification(&expr_path(&path_segments(METRICS_PATH), EXACT_PATH_TERMINATOR));
+        Self {
+            paths: RbTree::default(),
+            responses,
+            body_hashes: HashMap::new(),
         }
     }
 }
 
-const LABEL_ASSETS: &[u8] = b"http_assets";
+impl AssetHashes {
+    /// The root hash of the asset hashes, without the `http_assets` label.
+    #[must_use]
+    pub fn root_hash(&self) -> Hash {
+        self.paths.root_hash()
+    }
 
-#[derive(Default, Debug, Eq, PartialEq)]
-pub struct AssetHashes(RbTree<Vec<u8>, Hash>);
+    /// A witness for the asset at the given path.
+    #[must_use]
+    pub fn witness(&self, path: &[u8]) -> HashTree<'_> {
+        self.paths.witness(path)
+    }
+
+    /// The root hash of the certified responses, without the `http_expr` label.
+    #[must_use]
+    pub fn responses_root_hash(&self) -> Hash {
+        self.responses.root_hash()
+    }
+
+    /// The path of the certified response for the given URL path, and a witness for it.
+    #[must_use]
+    pub fn response_witness(&self, request_path: &str) -> Option<(Vec<String>, HashTree<'_>)> {
+        self.responses.response_witness(request_path)
+    }
+
+    /// Certifies the responses for all paths at which the asset at the given path may be served.
+    fn insert(&mut self, assets: &Assets, path: &str) {
+        for alternate_path in Assets::alternate_paths(path) {
+            self.certify_path(assets, &alternate_path);
+        }
+    }
+
+    /// Certifies the responses served at the given path.
+    ///
+    /// Note: The responses may be for other assets than the one just inserted, e.g. for the gzipped version of it.
+    fn certify_path(&mut self, assets: &Assets, request_path: &str) {
+        let variants = assets.variants(request_path);
+        for (content_encoding, asset) in &variants {
+            self.body_hashes
+                .insert((request_path.to_string(), *content_encoding), hash_bytes(&asset.bytes));
+        }
+        if let Some((content_encoding, _)) = assets.get(request_path, &CERTIFICATION_V1_ENCODINGS) {
+            if let Some(body_hash) = self.body_hash(request_path, content_encoding) {
+                self.paths.insert(request_path.as_bytes().to_vec(), body_hash);
+            }
+        }
+        // Certification v2 certifies every encoding, the response to a matching `If-None-Match` header and the partial
+        // response for every chunk.  Paths without a leading slash cannot be requested over HTTP.
+        if request_path.starts_with('/') {
+            let responses: Vec<(String, Hash)> = variants
+                .iter()
+                .flat_map(|(content_encoding, _)| {
+                    let response = asset_response(assets, self, request_path, &[*content_encoding]);
+                    let chunks = (0..).map_while(|index| chunk_range(index, response.body.len()));
+                    [
+                        response.certified_response(),
+                        response.not_modified().certified_response(),
+                    ]
+                    .into_iter()
+                    .chain(chunks.map(|range| response.partial(range).certified_response()))
+                    .collect::<Vec<_>>()
+                })
+                .collect();
+            self.responses.set_responses(
+                &expr_path(&path_segments(request_path), EXACT_PATH_TERMINATOR),
+                &responses,
+            );
+        }
+    }
+
+    /// The hash of the body served at the given path in the given encoding.
+    fn body_hash(&self, request_path: &str, content_encoding: ContentEncoding) -> Option<Hash> {
+        self.body_hashes
+            .get(&(request_path.to_string(), content_encoding))
+            .copied()
+    }
+}
 
 impl From<&Assets> for AssetHashes {
     fn from(assets: &Assets) -> Self {
         let mut asset_hashes = Self::default();
-        for (path, asset) in &assets.0 {
-            asset_hashes
-                .0
-                .insert(path.as_bytes().to_vec(), hash_bytes(&asset.bytes));
+        let request_paths: BTreeSet<String> = assets.0.keys().flat_map(|path| Assets::alternate_paths(path)).collect();
+        for request_path in request_paths {
+            asset_hashes.certify_path(assets, &request_path);
         }
         asset_hashes
     }
@@ -114,12 +298,69 @@ impl Asset {
     }
 }
 
+impl Storable for Asset {
+    const BOUND: Bound = Bound::Unbounded;
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        candid::encode_one(self).expect("Failed to serialize asset").into()
+    }
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(&self).expect("Failed to serialize asset")
+    }
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to parse asset from store.")
+    }
+}
+
+/// The assets that are persisted across upgrades, keyed by path, in their own stable memory partition.
+///
+/// Assets are served from `Assets`.  Stable assets are also written here, and copied back into `Assets` on upgrade.
+pub struct StableAssets {
+    assets: StableBTreeMap<String, Asset, VirtualMemory<DefaultMemoryImpl>>,
+}
+
+impl StableAssets {
+    /// Creates an empty collection in the given memory.
+    #[must_use]
+    pub fn new(memory: VirtualMemory<DefaultMemoryImpl>) -> Self {
+        Self {
+            assets: StableBTreeMap::new(memory),
+        }
+    }
+
+    /// Loads the assets in the given memory, if any.
+    #[must_use]
+    pub fn init(memory: VirtualMemory<DefaultMemoryImpl>) -> Self {
+        Self {
+            assets: StableBTreeMap::init(memory),
+        }
+    }
+
+    pub fn insert(&mut self, path: String, asset: Asset) {
+        self.assets.insert(path, asset);
+    }
+
+    /// All stable assets, in order of path.
+    pub fn iter(&self) -> impl Iterator<Item = (String, Asset)> + '_ {
+        self.assets.range(..).map(|entry| entry.into_pair())
+    }
+}
+
+impl From<&StableAssets> for Assets {
+    fn from(stable_assets: &StableAssets) -> Self {
+        Assets(stable_assets.iter().collect())
+    }
+}
+
 #[derive(Default, CandidType, Deserialize, PartialEq, Eq, Debug)]
 pub struct Assets(HashMap<String, Asset>);
 
 impl Assets {
-    /// List of content encodings supported by the assets database.
-    const CONTENT_ENCODINGS: [ContentEncoding; 2] = [ContentEncoding::GZip, ContentEncoding::Identity];
+    /// List of content encodings supported by the assets database, in order of preference.
+    const CONTENT_ENCODINGS: [ContentEncoding; 3] = [
+        ContentEncoding::Brotli,
+        ContentEncoding::GZip,
+        ContentEncoding::Identity,
+    ];
     /// List of suffix changes that may be made.
     ///
     /// - "" -> "" A path may be served unchanged.
@@ -136,32 +377,53 @@ impl Assets {
     pub fn insert<S: Into<String>>(&mut self, path: S, asset: Asset) {
         self.0.insert(path.into(), asset);
     }
-    /// Gets a given URL path from the assets, if available.
+    /// Gets a given URL path from the assets, if available, in the best encoding that the client accepts.
     ///
     /// - If the path looks like an index, the canonical suffix `/index.html` will be used.
     /// - The retrieval search will look for compressed versions of the data.  E.g. if
-    ///   `foo.json` is requested and `foo.json.gz` is available, that will be returned along with
-    ///   `gzip` as the encoding.  The encoding can be set in the browser response HTTP header
-    ///   so that the browser will decompress the data before giving it to the requester.  If
-    ///   the requester wishes to receive the compressed data, without transparent decoding,
-    ///   the requester should ask for `foo.json.gz` instead of `foo.json`.
-    /// - The current asset signature scheme supports only one signature per path, so we cannot
-    ///   take browser capabilities into account.
+    ///   `foo.json` is requested and `foo.json.br` is available, that will be returned along with
+    ///   `br` as the encoding, if the client accepts brotli.  The encoding can be set in the browser
+    ///   response HTTP header so that the browser will decompress the data before giving it to the
+    ///   requester.  If the requester wishes to receive the compressed data, without transparent
+    ///   decoding, the requester should ask for `foo.json.br` instead of `foo.json`.
+    /// - If the client accepts none of the available encodings, the preferred one other than brotli is returned
+    ///   anyway.  Every browser decodes gzip, even when it sends no `Accept-Encoding` header, but not every browser
+    ///   decodes brotli.
     #[must_use]
-    pub fn get(&self, path: &str) -> Option<(ContentEncoding, &Asset)> {
+    pub fn get(&self, path: &str, accepted: &[ContentEncoding]) -> Option<(ContentEncoding, &Asset)> {
+        let variants = self.variants(path);
+        variants
+            .iter()
+            .find(|(content_encoding, _)| accepted.contains(content_encoding))
+            .or_else(|| {
+                variants
+                    .iter()
+                    .find(|(content_encoding, _)| *content_encoding != ContentEncoding::Brotli)
+            })
+            .copied()
+    }
+    /// Gets all the encodings in which a given URL path is available, in order of preference.
+    ///
+    /// Note: Every one of these may be served, so all of them must be certified.
+    #[must_use]
+    pub fn variants(&self, path: &str) -> Vec<(ContentEncoding, &Asset)> {
         // Note: The logic for finding an asset is the reverse of listing all asset paths.
         for (old_suffix, new_suffix) in Self::SUFFIX_REWRITES {
             if let Some(root) = path.strip_suffix(old_suffix) {
                 let new_path = root.to_string() + new_suffix;
-                if let Some(asset_with_encoding) = Self::CONTENT_ENCODINGS.iter().find_map(|content_encoding| {
-                    self.get_with_encoding(*content_encoding, &new_path)
-                        .map(|asset| (*content_encoding, asset))
-                }) {
-                    return Some(asset_with_encoding);
+                let variants: Vec<(ContentEncoding, &Asset)> = Self::CONTENT_ENCODINGS
+                    .iter()
+                    .filter_map(|content_encoding| {
+                        self.get_with_encoding(*content_encoding, &new_path)
+                            .map(|asset| (*content_encoding, asset))
+                    })
+                    .collect();
+                if !variants.is_empty() {
+                    return variants;
                 }
             }
         }
-        None
+        Vec::new()
     }
     /// Gets the given URL path from the assets, with the given encoding.
     fn get_with_encoding(&self, content_encoding: ContentEncoding, path: &str) -> Option<&Asset> {
@@ -203,15 +465,22 @@ impl Assets {
     }
 }
 
+/// The encodings served to HTTP gateways that support only certification v1, which certifies one body per path.
+const CERTIFICATION_V1_ENCODINGS: [ContentEncoding; 2] = [ContentEncoding::GZip, ContentEncoding::Identity];
+
+/// The path at which metrics are served.
+const METRICS_PATH: &str = "/metrics";
+
 #[must_use]
 #[allow(clippy::needless_pass_by_value)] // This is the standard signature that must be provided by the canister.
 pub fn http_request(req: HttpRequest) -> HttpResponse {
     let parts: Vec<&str> = req.url.split('?').collect();
+    let certificate_version = req.certificate_version.unwrap_or(1);
     match *parts.first().unwrap_or(&"") {
-        "/metrics" => {
+        METRICS_PATH => {
             let now = time();
             let mut writer = MetricsEncoder::new(vec![], now / 1_000_000);
-            match encode_metrics(&mut writer) {
+            let mut response = match encode_metrics(&mut writer) {
                 Ok(()) => {
                     let body = writer.into_inner();
                     HttpResponse {
@@ -221,50 +490,285 @@ pub fn http_request(req: HttpRequest) -> HttpResponse {
                             ("Content-Length".to_string(), body.len().to_string()),
                         ],
                         body: ByteBuf::from(body),
+                        streaming_strategy: None,
                     }
                 }
                 Err(err) => HttpResponse {
                     status_code: 500,
                     headers: vec![],
                     body: ByteBuf::from(format!("Failed to encode metrics: {err}")),
+                    streaming_strategy: None,
                 },
+            };
+            // The metrics change all the time, so they are not certified.  Gateways that support certification v2 are
+            // told so explicitly.
+            if certificate_version >= 2 {
+                response.headers.push((
+                    CERTIFICATE_EXPRESSION_HEADER.to_string(),
+                    SKIP_CERTIFICATION_EXPRESSION.to_string(),
+                ));
+                with_state(|s| {
+                    response
+                        .headers
+                        .push(make_response_certificate_header(s, METRICS_PATH, &data_certificate()));
+                });
             }
+            response
         }
-        request_path => with_state(|s| {
-            let mut headers = security_headers();
-            let certificate_header = make_asset_certificate_header(&s.asset_hashes, request_path);
-            headers.push(certificate_header);
-
-            match s.assets.get(request_path) {
-                Some((content_encoding, asset)) => {
-                    headers.extend(asset.headers.clone());
-                    if let Some(content_type) = content_type_of(request_path) {
-                        headers.push(("Content-Type".to_string(), content_type.to_string()));
-                    }
-                    if let Some(content_encoding_header) = content_encoding.header() {
-                        headers.push(("Content-Encoding".to_string(), content_encoding_header.to_string()));
-                    }
-                    // Assets within .well-known are used by II and should be accessible
-                    if request_path.starts_with("/.well-known") {
-                        headers.push(("Access-Control-Allow-Origin".to_string(), "*".to_string()));
-                    }
+        request_path => with_state(|s| asset_http_response(s, &req, request_path, &data_certificate())),
+    }
+}
 
-                    HttpResponse {
-                        status_code: 200,
-                        headers,
-                        body: ByteBuf::from(asset.bytes.clone()),
-                    }
-                }
-                None => HttpResponse {
-                    status_code: 404,
-                    headers,
-                    body: ByteBuf::from(format!("Asset {request_path} not found.")),
-                },
-            }
-        }),
+/// The response to a request for an asset, with the given certificate of the certified data.
+fn asset_http_response(s: &State, req: &HttpRequest, request_path: &str, certificate: &[u8]) -> HttpResponse {
+    let certificate_version = req.certificate_version.unwrap_or(1);
+    let accepted = if certificate_version >= 2 {
+        ContentEncoding::accepted(req.header("Accept-Encoding"))
+    } else {
+        CERTIFICATION_V1_ENCODINGS.to_vec()
+    };
+    let mut response = asset_response(&s.assets, &s.asset_hashes, request_path, &accepted);
+    // 304 and 206 responses can only be verified with certification v2.
+    if certificate_version >= 2 && response.status_code == 200 {
+        if req
+            .header("If-None-Match")
+            .is_some_and(|if_none_match| response.matches_if_none_match(if_none_match))
+        {
+            response = response.not_modified();
+        } else if let Some(range) = req
+            .header("Range")
+            .and_then(|range| requested_chunk(range, response.body.len()))
+        {
+            response = response.partial(range);
+        }
+    }
+    if certificate_version >= 2 {
+        response.headers.push(response.certificate_expression_header());
+        response
+            .headers
+            .push(make_response_certificate_header(s, request_path, certificate));
+    } else {
+        response
+            .headers
+            .push(make_asset_certificate_header(s, request_path, certificate));
+    }
+    if response.body.len() > STREAMING_CHUNK_SIZE_BYTES {
+        let token = StreamingCallbackToken {
+            key: request_path.to_string(),
+            content_encoding: response.content_encoding.name().to_string(),
+            index: 1,
+            sha256: s
+                .asset_hashes
+                .body_hash(request_path, response.content_encoding)
+                .map(|body_hash| ByteBuf::from(body_hash.to_vec())),
+        };
+        HttpResponse {
+            status_code: response.status_code,
+            headers: response.headers,
+            body: ByteBuf::from(&response.body[..STREAMING_CHUNK_SIZE_BYTES]),
+            streaming_strategy: Some(StreamingStrategy::Callback {
+                callback: StreamingCallback::new(
+                    ic_cdk::api::canister_self(),
+                    "http_request_streaming_callback".to_string(),
+                ),
+                token,
+            }),
+        }
+    } else {
+        HttpResponse {
+            status_code: response.status_code,
+            headers: response.headers,
+            body: ByteBuf::from(response.body.to_vec()),
+            streaming_strategy: None,
+        }
+    }
+}
+
+/// Returns the next chunk of an asset that is served with the callback streaming strategy.
+///
+/// # Panics
+/// - If the asset no longer exists, or has changed since the first chunk was served.
+#[must_use]
+pub fn http_request_streaming_callback(token: StreamingCallbackToken) -> StreamingCallbackHttpResponse {
+    with_state(|s| streaming_chunk(&s.assets, &s.asset_hashes, &token))
+        .unwrap_or_else(|| ic_cdk::api::trap(format!("The asset at {} is no longer available.", token.key)))
+}
+
+/// The chunk requested by a streaming callback token, if the asset is still the same.
+fn streaming_chunk(
+    assets: &Assets,
+    asset_hashes: &AssetHashes,
+    token: &StreamingCallbackToken,
+) -> Option<StreamingCallbackHttpResponse> {
+    let content_encoding = ContentEncoding::from_name(&token.content_encoding)?;
+    let (_, asset) = assets
+        .variants(&token.key)
+        .into_iter()
+        .find(|(variant_encoding, _)| *variant_encoding == content_encoding)?;
+    let body_hash = asset_hashes.body_hash(&token.key, content_encoding)?;
+    if token.sha256.as_ref().is_some_and(|sha256| sha256[..] != body_hash[..]) {
+        return None;
+    }
+    let range = chunk_range(token.index, asset.bytes.len())?;
+    let next_token = (range.end < asset.bytes.len()).then(|| StreamingCallbackToken {
+        index: token.index + 1,
+        ..token.clone()
+    });
+    Some(StreamingCallbackHttpResponse {
+        body: ByteBuf::from(&asset.bytes[range]),
+        token: next_token,
+    })
+}
+
+/// A response to an asset request, without the certificate headers.
+struct AssetResponse<'a> {
+    status_code: u16,
+    headers: Vec<HeaderField>,
+    body: &'a [u8],
+    /// The SHA-256 of the body.
+    body_hash: Hash,
+    content_encoding: ContentEncoding,
+}
+
+impl<'a> AssetResponse<'a> {
+    /// The `IC-CertificateExpression` header that certifies all the other headers of the response.
+    fn certificate_expression_header(&self) -> HeaderField {
+        (
+            CERTIFICATE_EXPRESSION_HEADER.to_string(),
+            response_expression(&self.headers),
+        )
+    }
+
+    /// The certificate expression and hash of the response, as certified in the `http_expr` subtree.
+    fn certified_response(&self) -> (String, Hash) {
+        let expression_header = self.certificate_expression_header();
+        let mut headers = self.headers.clone();
+        headers.push(expression_header.clone());
+        let response_hash = response_hash(self.status_code, &headers, &self.body_hash);
+        (expression_header.1, response_hash)
+    }
+
+    /// The `304 Not Modified` response for a client that already has this response cached.
+    fn not_modified(&self) -> AssetResponse<'static> {
+        AssetResponse {
+            status_code: 304,
+            headers: self.headers.clone(),
+            body: &[],
+            body_hash: hash_bytes(b""),
+            content_encoding: self.content_encoding,
+        }
+    }
+
+    /// The `206 Partial Content` response with the given bytes of the body.
+    fn partial(&self, range: Range<usize>) -> AssetResponse<'a> {
+        let mut headers = self.headers.clone();
+        headers.push(("Content-Range".to_string(), content_range(&range, self.body.len())));
+        let body_hash = if range == (0..self.body.len()) {
+            self.body_hash
+        } else {
+            hash_bytes(&self.body[range.clone()])
+        };
+        AssetResponse {
+            status_code: 206,
+            headers,
+            body: &self.body[range],
+            body_hash,
+            content_encoding: self.content_encoding,
+        }
+    }
+
+    /// Whether the response matches the value of an `If-None-Match` header.
+    ///
+    /// Tags are compared with the weak comparison, as required for `If-None-Match`.
+    fn matches_if_none_match(&self, if_none_match: &str) -> bool {
+        let Some((_, etag)) = self.headers.iter().find(|(name, _)| name == "ETag") else {
+            return false;
+        };
+        if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|candidate| candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag.as_str())
+    }
+}
+
+/// The response to a request for the asset at the given path, from a client that accepts the given encodings.
+fn asset_response<'a>(
+    assets: &'a Assets,
+    asset_hashes: &AssetHashes,
+    request_path: &str,
+    accepted: &[ContentEncoding],
+) -> AssetResponse<'a> {
+    let variants = assets.variants(request_path);
+    let Some((content_encoding, asset)) = assets.get(request_path, accepted) else {
+        return not_found_response();
+    };
+    let mut headers = security_headers();
+    headers.extend(asset.headers.clone());
+    if let Some(content_type) = content_type_of(request_path) {
+        headers.push(("Content-Type".to_string(), content_type.to_string()));
+    }
+    if let Some(content_encoding_header) = content_encoding.header() {
+        headers.push(("Content-Encoding".to_string(), content_encoding_header.to_string()));
+    }
+    if variants.len() > 1 {
+        headers.push(("Vary".to_string(), "Accept-Encoding".to_string()));
+    }
+    let body_hash = asset_hashes
+        .body_hash(request_path, content_encoding)
+        .unwrap_or_else(|| hash_bytes(&asset.bytes));
+    headers.push(("ETag".to_string(), etag(&body_hash)));
+    headers.push(("Cache-Control".to_string(), cache_control_of(request_path).to_string()));
+    headers.push(("Accept-Ranges".to_string(), "bytes".to_string()));
+    // Assets within .well-known are used by II and should be accessible
+    if request_path.starts_with("/.well-known") {
+        headers.push(("Access-Control-Allow-Origin".to_string(), "*".to_string()));
+    }
+    AssetResponse {
+        status_code: 200,
+        headers,
+        body: &asset.bytes,
+        body_hash,
+        content_encoding,
     }
 }
 
+/// The response to a request for an asset that does not exist.
+///
+/// The response is the same for all paths, so that it can be certified once for all of them.
+fn not_found_response() -> AssetResponse<'static> {
+    const BODY: &[u8] = b"Asset not found.";
+    AssetResponse {
+        status_code: 404,
+        headers: security_headers(),
+        body: BODY,
+        body_hash: hash_bytes(BODY),
+        content_encoding: ContentEncoding::Identity,
+    }
+}
+
+/// A strong entity tag for a body with the given hash.
+fn etag(body_hash: &Hash) -> String {
+    let hex: String = body_hash.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("\"{hex}\"")
+}
+
+/// `Cache-Control` policies by path prefix.  The first matching policy applies.
+///
+/// The bundles in `/_app/immutable/` have the hash of their content in their name, so they never change.
+const CACHE_CONTROL_POLICIES: [(&str, &str); 1] = [("/_app/immutable/", "public, max-age=31536000, immutable")];
+/// The `Cache-Control` policy for all other assets.
+///
+/// Other assets, such as `index.html`, may change with every release, so clients must revalidate them with the
+/// `ETag`.
+const DEFAULT_CACHE_CONTROL: &str = "no-cache";
+
+fn cache_control_of(request_path: &str) -> &'static str {
+    CACHE_CONTROL_POLICIES
+        .iter()
+        .find(|(prefix, _)| request_path.starts_with(prefix))
+        .map_or(DEFAULT_CACHE_CONTROL, |(_, policy)| policy)
+}
+
 fn content_type_of(request_path: &str) -> Option<&'static str> {
     if request_path.ends_with('/') {
         return Some("text/html");
@@ -364,22 +868,42 @@ fn security_headers() -> Vec<HeaderField> {
     ]
 }
 
-fn make_asset_certificate_header(asset_hashes: &AssetHashes, asset_name: &str) -> (String, String) {
-    let certificate = ic_cdk::api::data_certificate().unwrap_or_else(|| {
+/// The certificate of the certified data.
+///
+/// # Panics
+/// - If called outside of a query call.
+fn data_certificate() -> Vec<u8> {
+    ic_cdk::api::data_certificate().unwrap_or_else(|| {
         ic_cdk::api::trap("data certificate is only available in query calls");
-    });
-    let witness = asset_hashes.0.witness(asset_name.as_bytes());
-    let tree = labeled(LABEL_ASSETS, witness);
-    let mut serializer = serde_cbor::ser::Serializer::new(vec![]);
-    serializer.self_describe().unwrap();
-    tree.serialize(&mut serializer)
-        .unwrap_or_else(|e| ic_cdk::api::trap(format!("failed to serialize a hash tree: {e}")));
+    })
+}
+
+fn make_asset_certificate_header(state: &State, asset_name: &str, certificate: &[u8]) -> (String, String) {
+    let tree = encode_witness(&asset_witness(state, asset_name));
     (
         "IC-Certificate".to_string(),
         format!(
             "certificate=:{}:, tree=:{}:",
             BASE64_ENGINE.encode(certificate),
-            BASE64_ENGINE.encode(serializer.into_inner())
+            BASE64_ENGINE.encode(tree)
+        ),
+    )
+}
+
+/// The `IC-Certificate` header for certification v2 of the response to a request for the given path.
+fn make_response_certificate_header(state: &State, request_path: &str, certificate: &[u8]) -> HeaderField {
+    let (path, witness) = state
+        .asset_hashes
+        .response_witness(request_path)
+        .unwrap_or_else(|| ic_cdk::api::trap(format!("no certified response for {request_path}")));
+    let tree = encode_witness(&responses_witness(state, witness));
+    (
+        "IC-Certificate".to_string(),
+        format!(
+            "certificate=:{}:, tree=:{}:, expr_path=:{}:, version=2",
+            BASE64_ENGINE.encode(certificate),
+            BASE64_ENGINE.encode(tree),
+            BASE64_ENGINE.encode(encode_expr_path(&path))
         ),
     )
 }
@@ -394,22 +918,32 @@ pub fn hash_bytes(value: impl AsRef<[u8]>) -> Hash {
 pub fn insert_asset<S: Into<String> + Clone>(path: S, asset: Asset) {
     with_state_mut(|state| {
         insert_asset_into_state(state, path, asset);
-        update_root_hash(&state.asset_hashes);
+        update_root_hash(state);
     });
 }
+/// Commits an uploaded asset batch, inserting the asset into the state and updating the certificates.
+pub fn commit_asset_batch(request: CommitAssetBatchRequest) -> CommitAssetBatchResponse {
+    with_state_mut(|state| match state.asset_uploads.commit_batch(request) {
+        Ok((path, asset)) => {
+            insert_asset_into_state(state, path, asset);
+            update_root_hash(state);
+            CommitAssetBatchResponse::Ok
+        }
+        Err(response) => response,
+    })
+}
+
 /// Insert an asset into the given state.
 ///
 /// Note:  This does NOT update the certificates.  To insert multiple assets, call
 ///        this repeatedly and then update the root hash.
 pub fn insert_asset_into_state<S: Into<String> + Clone>(state: &mut State, path: S, asset: Asset) {
-    let asset_hashes = &mut state.asset_hashes;
-    let assets = &mut state.assets;
     let path: String = path.into();
-    let hash = hash_bytes(&asset.bytes);
-    for alternate_path in Assets::alternate_paths(&path) {
-        asset_hashes.0.insert(alternate_path.as_bytes().to_vec(), hash);
+    if asset.stable {
+        state.stable_assets.insert(path.clone(), asset.clone());
     }
-    assets.insert(path, asset);
+    state.assets.insert(path.clone(), asset);
+    state.asset_hashes.insert(&state.assets, &path);
 }
 
 /// Adds the files bundled in the WASM to the state.
@@ -426,7 +960,7 @@ pub fn init_assets() {
 /// Adds an xz compressed tarball of assets to the state.
 ///
 /// - Adds the files to `state.assets`.
-/// - Signs the given path and all alternate paths for the given asset.
+/// - Signs all paths at which the assets may be served.
 ///
 /// Note: The `Vec` is mutated during decompression, so pass by reference is inefficient
 ///       as it would force the data to be copied into a new vector, even when the
@@ -444,6 +978,13 @@ pub fn insert_tar_xz(compressed: Vec<u8>) {
     let mut tar: tar::Archive<&[u8]> = tar::Archive::new(decompressed.as_ref());
     let arguments_html = CANISTER_ARGUMENTS.with(|args| args.borrow().to_html());
     let template_engine = CANISTER_ARGUMENTS.with(|args| TemplateEngine::new(&args.borrow().args));
+    let populate_html = |html: &str| {
+        let mut html = html.to_string();
+        if let Some(insertion_point) = html.find("</head>") {
+            html.insert_str(insertion_point, &arguments_html);
+        }
+        template_engine.populate(&html)
+    };
     with_state_mut(|state| {
         for entry in tar.entries().expect("Failed to get entry from tarball.") {
             let mut entry = entry.expect("Invalid entry in tarball.");
@@ -474,25 +1015,39 @@ pub fn insert_tar_xz(compressed: Vec<u8>) {
                 .read_to_end(&mut bytes)
                 .expect("Failed to read an entry from the tarball.");
 
+            // The canister arguments are inserted into every `index.html`.  There is no brotli encoder in the
+            // canister, so `index.html` is served with one of the other encodings.
+            if name.ends_with("index.html.br") {
+                continue;
+            }
             if name.ends_with("index.html.gz") {
-                let mut html = gunzip_string(&bytes);
-                if let Some(insertion_point) = html.find("</head>") {
-                    html.insert_str(insertion_point, &arguments_html);
-                }
-                let html = template_engine.populate(&html);
-                bytes = gzip(html.as_bytes());
+                bytes = gzip(populate_html(&gunzip_string(&bytes)).as_bytes());
+            } else if name.ends_with("index.html") {
+                bytes = populate_html(&String::from_utf8_lossy(&bytes)).into_bytes();
             }
 
-            insert_asset_into_state(state, name, Asset::new(bytes));
+            state.assets.insert(name, Asset::new(bytes));
             num_assets += 1;
         }
-        update_root_hash(&state.asset_hashes);
+        // Every path is certified once, after all encodings of it have been inserted.
+        state.asset_hashes = AssetHashes::from(&state.assets);
+        update_root_hash(state);
     });
     println!("Inserted {num_assets} assets.");
 }
 
+impl IntoIterator for Assets {
+    type Item = (String, Asset);
+    type IntoIter = std::collections::hash_map::IntoIter<String, Asset>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
 impl StableState for Assets {
+    #[cfg(test)]
     fn encode(&self) -> Vec<u8> {
+        use candid::Encode;
         // Encode all stable assets.
         let stable_assets: Assets = Assets(self.0.clone().into_iter().filter(|(_, asset)| asset.stable).collect());
 
@@ -504,11 +1059,6 @@ impl StableState for Assets {
     }
 }
 
-fn update_root_hash(a: &AssetHashes) {
-    let prefixed_root_hash = &labeled_hash(LABEL_ASSETS, &a.0.root_hash());
-    ic_cdk::api::certified_data_set(&prefixed_root_hash[..]);
-}
-
 #[test]
 fn encode_decode() {
     // Test that encoding/decoding preserves stable assets.
@@ -528,6 +1078,317 @@ fn encode_decode() {
     );
 }
 
+#[test]
+fn responses_should_be_certified_at_every_path_they_are_served_at() {
+    use maplit::hashmap;
+    let assets = Assets(hashmap! {
+        "/index.html".to_string() => Asset::new(b"<html></html>".to_vec()),
+        "/main.js".to_string() => Asset::new(b"main()".to_vec()),
+        "/main.js.gz".to_string() => Asset::new(gzip(b"main()")),
+    });
+
+    let asset_hashes = AssetHashes::from(&assets);
+
+    for (request_path, expected_expr_path) in [
+        ("/", vec!["", "<$>"]),
+        ("/index.html", vec!["index.html", "<$>"]),
+        ("/main.js", vec!["main.js", "<$>"]),
+        ("/main.js.gz", vec!["main.js.gz", "<$>"]),
+        ("/metrics", vec!["metrics", "<$>"]),
+        ("/missing.js", vec!["<*>"]),
+    ] {
+        let (expr_path, _) = asset_hashes
+            .response_witness(request_path)
+            .expect("No certified response");
+        assert_eq!(
+            expr_path, expected_expr_path,
+            "Wrong expression path for {request_path}"
+        );
+    }
+    // Assets may be inserted in any order.  The certified response is the one that is served.
+    let mut inserted_asset_hashes = AssetHashes::default();
+    for path in ["/main.js.gz", "/index.html", "/main.js"] {
+        inserted_asset_hashes.insert(&assets, path);
+    }
+    assert_eq!(inserted_asset_hashes, asset_hashes);
+}
+
+#[test]
+fn the_best_encoding_that_the_client_accepts_should_be_served() {
+    use maplit::hashmap;
+    let assets = Assets(hashmap! {
+        "/main.js".to_string() => Asset::new(b"main()".to_vec()),
+        "/main.js.gz".to_string() => Asset::new(gzip(b"main()")),
+        "/main.js.br".to_string() => Asset::new(b"brotli".to_vec()),
+        "/other.js.gz".to_string() => Asset::new(gzip(b"other()")),
+        "/other.js.br".to_string() => Asset::new(b"brotli".to_vec()),
+        "/brotli-only.js.br".to_string() => Asset::new(b"brotli".to_vec()),
+    });
+
+    for (accept_encoding, expected_content_encoding) in [
+        (None, ContentEncoding::Identity),
+        (Some("identity"), ContentEncoding::Identity),
+        (Some("gzip, deflate"), ContentEncoding::GZip),
+        (Some("gzip, deflate, br, zstd"), ContentEncoding::Brotli),
+        (Some("GZIP;q=0.5, br;q=0"), ContentEncoding::GZip),
+        (Some("*"), ContentEncoding::Brotli),
+    ] {
+        let accepted = ContentEncoding::accepted(accept_encoding);
+        let (content_encoding, _) = assets.get("/main.js", &accepted).expect("Asset not found");
+        assert_eq!(
+            content_encoding, expected_content_encoding,
+            "Wrong encoding for Accept-Encoding: {accept_encoding:?}"
+        );
+        let response = asset_response(&assets, &AssetHashes::from(&assets), "/main.js", &accepted);
+        assert_eq!(
+            response
+                .headers
+                .iter()
+                .find(|(name, _)| name == "Content-Encoding")
+                .map(|(_, value)| value.as_str()),
+            expected_content_encoding.header()
+        );
+        assert!(response
+            .headers
+            .contains(&("Vary".to_string(), "Accept-Encoding".to_string())));
+    }
+    // If the client accepts no available encoding, the preferred one other than brotli is served anyway.
+    assert_eq!(
+        assets
+            .get("/other.js", &ContentEncoding::accepted(None))
+            .map(|(content_encoding, _)| content_encoding),
+        Some(ContentEncoding::GZip)
+    );
+    assert_eq!(assets.get("/brotli-only.js", &ContentEncoding::accepted(None)), None);
+}
+
+#[test]
+fn assets_should_have_an_etag_and_a_cache_control_policy() {
+    use maplit::hashmap;
+    let assets = Assets(hashmap! {
+        "/index.html".to_string() => Asset::new(b"<html></html>".to_vec()),
+        "/_app/immutable/entry/app.12345678.js".to_string() => Asset::new(b"app()".to_vec()),
+    });
+    let asset_hashes = AssetHashes::from(&assets);
+    let header = |response: &AssetResponse, name: &str| {
+        response
+            .headers
+            .iter()
+            .find(|(header_name, _)| header_name == name)
+            .map(|(_, value)| value.clone())
+    };
+
+    let response = asset_response(&assets, &asset_hashes, "/", &CERTIFICATION_V1_ENCODINGS);
+    assert_eq!(header(&response, "ETag"), Some(etag(&hash_bytes(b"<html></html>"))));
+    assert_eq!(header(&response, "Cache-Control").as_deref(), Some("no-cache"));
+
+    let response = asset_response(
+        &assets,
+        &asset_hashes,
+        "/_app/immutable/entry/app.12345678.js",
+        &CERTIFICATION_V1_ENCODINGS,
+    );
+    assert_eq!(header(&response, "ETag"), Some(etag(&hash_bytes(b"app()"))));
+    assert_eq!(
+        header(&response, "Cache-Control").as_deref(),
+        Some("public, max-age=31536000, immutable")
+    );
+}
+
+#[test]
+fn a_matching_if_none_match_header_should_get_a_certified_304_response() {
+    use maplit::hashmap;
+    let assets = Assets(hashmap! {
+        "/index.html".to_string() => Asset::new(b"<html></html>".to_vec()),
+    });
+    let asset_hashes = AssetHashes::from(&assets);
+    let response = asset_response(&assets, &asset_hashes, "/index.html", &CERTIFICATION_V1_ENCODINGS);
+    let etag = etag(&hash_bytes(b"<html></html>"));
+
+    for if_none_match in [
+        etag.clone(),
+        format!("W/{etag}"),
+        format!("\"other\", {etag}"),
+        "*".to_string(),
+    ] {
+        assert!(
+            response.matches_if_none_match(&if_none_match),
+            "Should match If-None-Match: {if_none_match}"
+        );
+    }
+    for if_none_match in ["\"other\"", "", "W/\"other\""] {
+        assert!(
+            !response.matches_if_none_match(if_none_match),
+            "Should not match If-None-Match: {if_none_match}"
+        );
+    }
+
+    let not_modified = response.not_modified();
+    assert_eq!(not_modified.status_code, 304);
+    assert_eq!(not_modified.headers, response.headers);
+    assert!(not_modified.body.is_empty());
+    // The full response, the 304 response and the partial response for the one chunk are certified, at every path at
+    // which the asset is served.
+    let mut expected_responses = AssetHashes::default().responses;
+    for request_path in ["/", "/index.html"] {
+        let response = asset_response(&assets, &asset_hashes, request_path, &CERTIFICATION_V1_ENCODINGS);
+        expected_responses.set_responses(
+            &expr_path(&path_segments(request_path), EXACT_PATH_TERMINATOR),
+            &[
+                response.certified_response(),
+                response.not_modified().certified_response(),
+                response.partial(0..response.body.len()).certified_response(),
+            ],
+        );
+    }
+    assert_eq!(asset_hashes.responses, expected_responses);
+}
+
+#[test]
+fn oversized_assets_should_be_streamed_in_chunks() {
+    use maplit::hashmap;
+    let bytes: Vec<u8> = (0..2 * STREAMING_CHUNK_SIZE_BYTES + 100)
+        .map(|i| u8::try_from(i % 251).unwrap())
+        .collect();
+    let assets = Assets(hashmap! {
+        "/assets/canvaskit/canvaskit.wasm".to_string() => Asset::new_stable(bytes.clone()),
+    });
+    let asset_hashes = AssetHashes::from(&assets);
+    let mut streamed = bytes[..STREAMING_CHUNK_SIZE_BYTES].to_vec();
+    let mut token = Some(StreamingCallbackToken {
+        key: "/assets/canvaskit/canvaskit.wasm".to_string(),
+        content_encoding: "identity".to_string(),
+        index: 1,
+        sha256: Some(ByteBuf::from(hash_bytes(&bytes).to_vec())),
+    });
+    let mut num_chunks = 1;
+    while let Some(next_token) = token {
+        let chunk = streaming_chunk(&assets, &asset_hashes, &next_token).expect("Failed to get a chunk");
+        streamed.extend_from_slice(&chunk.body);
+        token = chunk.token;
+        num_chunks += 1;
+    }
+    assert_eq!(num_chunks, 3);
+    assert!(streamed == bytes, "The streamed body differs from the asset");
+
+    // A token for a different version of the asset is rejected.
+    let stale_token = StreamingCallbackToken {
+        key: "/assets/canvaskit/canvaskit.wasm".to_string(),
+        content_encoding: "identity".to_string(),
+        index: 1,
+        sha256: Some(ByteBuf::from(vec![0; 32])),
+    };
+    assert_eq!(streaming_chunk(&assets, &asset_hashes, &stale_token), None);
+}
+
+#[test]
+fn a_range_request_should_get_a_certified_206_response_only_with_certification_v2() {
+    let body = b"<html></html>".to_vec();
+    let mut state = State::new();
+    insert_asset_into_state(&mut state, "/index.html", Asset::new(body.clone()));
+    let request = |certificate_version| HttpRequest {
+        method: "GET".to_string(),
+        url: "/index.html".to_string(),
+        headers: vec![("Range".to_string(), "bytes=0-".to_string())],
+        body: ByteBuf::new(),
+        certificate_version,
+    };
+    let header = |response: &HttpResponse, name: &str| {
+        response
+            .headers
+            .iter()
+            .find(|(header_name, _)| header_name == name)
+            .map(|(_, value)| value.clone())
+    };
+
+    let response = asset_http_response(&state, &request(Some(2)), "/index.html", b"certificate");
+    assert_eq!(response.status_code, 206);
+    assert_eq!(response.body.to_vec(), body);
+    assert_eq!(header(&response, "Content-Range"), Some("bytes 0-12/13".to_string()));
+    // The certificate expression covers all the other headers, including the `Content-Range`.
+    let (certificate_header, headers) = response.headers.split_last().unwrap();
+    let (expression_header, certified_headers) = headers.split_last().unwrap();
+    assert_eq!(
+        *expression_header,
+        (
+            CERTIFICATE_EXPRESSION_HEADER.to_string(),
+            response_expression(certified_headers)
+        )
+    );
+    assert!(expression_header.1.contains("\"content-range\""));
+    let exact_path = expr_path(&path_segments("/index.html"), EXACT_PATH_TERMINATOR);
+    let (_, witness) = state.asset_hashes.response_witness("/index.html").unwrap();
+    assert_eq!(
+        *certificate_header,
+        (
+            "IC-Certificate".to_string(),
+            format!(
+                "certificate=:{}:, tree=:{}:, expr_path=:{}:, version=2",
+                BASE64_ENGINE.encode(b"certificate"),
+                BASE64_ENGINE.encode(encode_witness(&responses_witness(&state, witness))),
+                BASE64_ENGINE.encode(encode_expr_path(&exact_path))
+            )
+        )
+    );
+    // The 206 response, including its status code, is one of the certified responses.
+    let served_response = (
+        expression_header.1.clone(),
+        response_hash(206, headers, &hash_bytes(&response.body)),
+    );
+    let mut expected_responses = AssetHashes::default().responses;
+    for request_path in ["/", "/index.html"] {
+        let full_response = asset_response(
+            &state.assets,
+            &state.asset_hashes,
+            request_path,
+            &[ContentEncoding::Identity],
+        );
+        let partial_response = if request_path == "/index.html" {
+            served_response.clone()
+        } else {
+            full_response.partial(0..body.len()).certified_response()
+        };
+        expected_responses.set_responses(
+            &expr_path(&path_segments(request_path), EXACT_PATH_TERMINATOR),
+            &[
+                full_response.certified_response(),
+                full_response.not_modified().certified_response(),
+                partial_response,
+            ],
+        );
+    }
+    assert_eq!(state.asset_hashes.responses, expected_responses);
+
+    // Certification v1 cannot certify a partial response, so the range is ignored.
+    let response = asset_http_response(&state, &request(None), "/index.html", b"certificate");
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.body.to_vec(), body);
+    assert_eq!(header(&response, "Content-Range"), None);
+    assert!(header(&response, "IC-Certificate").is_some());
+}
+
+#[test]
+fn a_request_without_accept_encoding_should_not_get_brotli() {
+    let mut state = State::new();
+    insert_asset_into_state(&mut state, "/main.js.gz", Asset::new(gzip(b"main()")));
+    insert_asset_into_state(&mut state, "/main.js.br", Asset::new(b"brotli".to_vec()));
+    let request = HttpRequest {
+        method: "GET".to_string(),
+        url: "/main.js".to_string(),
+        headers: Vec::new(),
+        body: ByteBuf::new(),
+        certificate_version: Some(2),
+    };
+
+    let response = asset_http_response(&state, &request, "/main.js", b"certificate");
+    assert_eq!(response.status_code, 200);
+    assert_eq!(gunzip_string(&response.body), "main()");
+    assert!(response
+        .headers
+        .contains(&("Content-Encoding".to_string(), "gzip".to_string())));
+    assert!(response.headers.iter().any(|(name, _)| name == "IC-Certificate"));
+}
+
 /// Compress data
 #[must_use]
 pub fn gzip(uncompressed: &[u8]) -> Vec<u8> {
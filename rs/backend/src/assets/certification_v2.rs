@@ -0,0 +1,293 @@
+//! Certification v2 of HTTP responses, as specified by the HTTP gateway protocol.
+//!
+//! Certification v1 certifies only the hash of the body served at a path.  In v2, the canister
+//! certifies the hash of the whole response, i.e. the status code, the headers listed in a
+//! certificate expression and the body.  The certified responses are kept in the `http_expr`
+//! subtree, at the path:
+//!
+//! `http_expr / <path segments> / <$> or <*> / <expression hash> / "" / <response hash>`
+//!
+//! - `<$>` marks a response for exactly the given path, `<*>` a response for any path below it.
+//! - The empty label means that the request is not certified.
+//! - A response may skip certification, in which case the path ends with the expression hash.
+use super::{hash_bytes, HeaderField};
+use ic_certified_map::{AsHashTree, Hash, HashTree, RbTree};
+use serde::Serialize;
+
+#[cfg(test)]
+mod tests;
+
+/// The label of the subtree of certified data that contains the certified responses.
+pub const LABEL_EXPR: &[u8] = b"http_expr";
+/// The last path segment for a response to exactly the given path.
+pub const EXACT_PATH_TERMINATOR: &str = "<$>";
+/// The last path segment for a response to any path that starts with the given path.
+pub const WILDCARD_PATH_TERMINATOR: &str = "<*>";
+/// The response header that holds the certificate expression.
+pub const CERTIFICATE_EXPRESSION_HEADER: &str = "IC-CertificateExpression";
+/// The certificate expression for responses that are not certified.
+pub const SKIP_CERTIFICATION_EXPRESSION: &str = "default_certification(ValidationArgs{no_certification:Empty{}})";
+/// The name under which the status code is included in the hash of the response headers.
+const STATUS_CODE_PSEUDO_HEADER: &str = ":ic-cert-status";
+
+/// The certificate expression for a response with the given headers, all of which are certified.
+///
+/// The request is not certified.
+#[must_use]
+pub fn response_expression(headers: &[HeaderField]) -> String {
+    let header_names = headers
+        .iter()
+        .map(|(name, _)| format!("\"{}\"", name.to_ascii_lowercase()))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        "default_certification(ValidationArgs{{certification:Certification{{no_request_certification:Empty{{}},response_certification:ResponseCertification{{certified_response_headers:ResponseHeaderList{{headers:[{header_names}]}}}}}}}})"
+    )
+}
+
+/// The hash of a response, as certified in the `http_expr` subtree.
+///
+/// The headers must include the `IC-CertificateExpression` header, and the certificate expression must list all the
+/// other headers.  The body is given by its SHA-256, so that bodies are hashed once, not once per response.
+#[must_use]
+pub fn response_hash(status_code: u16, headers: &[HeaderField], body_hash: &Hash) -> Hash {
+    let mut fields: Vec<(String, Value)> = headers
+        .iter()
+        .map(|(name, value)| (name.to_ascii_lowercase(), Value::String(value)))
+        .collect();
+    fields.push((
+        STATUS_CODE_PSEUDO_HEADER.to_string(),
+        Value::Number(u64::from(status_code)),
+    ));
+    let mut hashes = representation_independent_hash(&fields).to_vec();
+    hashes.extend_from_slice(body_hash);
+    hash_bytes(hashes)
+}
+
+/// A value in a map hashed by `representation_independent_hash`.
+enum Value<'a> {
+    String(&'a str),
+    Number(u64),
+}
+
+/// The representation-independent hash of a map, as used to hash response headers.
+fn representation_independent_hash(fields: &[(String, Value)]) -> Hash {
+    let mut field_hashes: Vec<Vec<u8>> = fields
+        .iter()
+        .map(|(key, value)| {
+            let value_hash = match value {
+                Value::String(value) => hash_bytes(value.as_bytes()),
+                Value::Number(value) => hash_bytes(leb128(*value)),
+            };
+            [hash_bytes(key.as_bytes()), value_hash].concat()
+        })
+        .collect();
+    field_hashes.sort();
+    hash_bytes(field_hashes.concat())
+}
+
+/// Unsigned LEB128 encoding of a number.
+fn leb128(mut value: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// The segments of a URL path, e.g. `["foo", "bar.js"]` for `/foo/bar.js` and `[""]` for `/`.
+#[must_use]
+pub fn path_segments(request_path: &str) -> Vec<String> {
+    request_path
+        .strip_prefix('/')
+        .unwrap_or(request_path)
+        .split('/')
+        .map(str::to_string)
+        .collect()
+}
+
+/// The path of a response in the `http_expr` subtree, up to and including the terminator.
+#[must_use]
+pub fn expr_path(segments: &[String], terminator: &str) -> Vec<String> {
+    segments
+        .iter()
+        .cloned()
+        .chain(std::iter::once(terminator.to_string()))
+        .collect()
+}
+
+/// Encodes an expression path for the `expr_path` field of the `IC-Certificate` header.
+///
+/// The encoding is self-describing CBOR of the path, including the `http_expr` label.
+#[must_use]
+pub fn encode_expr_path(path: &[String]) -> Vec<u8> {
+    let full_path: Vec<&str> = std::iter::once("http_expr")
+        .chain(path.iter().map(String::as_str))
+        .collect();
+    let mut serializer = serde_cbor::ser::Serializer::new(vec![]);
+    serializer
+        .self_describe()
+        .unwrap_or_else(|e| ic_cdk::api::trap(format!("failed to serialize an expression path: {e}")));
+    full_path
+        .serialize(&mut serializer)
+        .unwrap_or_else(|e| ic_cdk::api::trap(format!("failed to serialize an expression path: {e}")));
+    serializer.into_inner()
+}
+
+/// A tree of labels, as stored in the `http_expr` subtree.
+///
+/// Every path from the root to a leaf is a certified response.  Leaves are empty.
+#[derive(Debug, Eq, PartialEq)]
+pub enum ExprTree {
+    Node(RbTree<Vec<u8>, ExprTree>),
+    Leaf(Vec<u8>),
+}
+
+impl Default for ExprTree {
+    fn default() -> Self {
+        ExprTree::Node(RbTree::new())
+    }
+}
+
+impl AsHashTree for ExprTree {
+    fn root_hash(&self) -> Hash {
+        match self {
+            ExprTree::Node(children) => children.root_hash(),
+            ExprTree::Leaf(leaf) => leaf.root_hash(),
+        }
+    }
+
+    fn as_hash_tree(&self) -> HashTree<'_> {
+        match self {
+            ExprTree::Node(children) => children.as_hash_tree(),
+            ExprTree::Leaf(leaf) => leaf.as_hash_tree(),
+        }
+    }
+}
+
+impl ExprTree {
+    /// Replaces the responses at the given path with a response that skips certification.
+    pub fn set_skip_certification(&mut self, path: &[String]) {
+        let mut responses = ExprTree::default();
+        responses.insert(&[hash_bytes(SKIP_CERTIFICATION_EXPRESSION).to_vec()]);
+        self.replace(path, responses);
+    }
+
+    /// Replaces the responses at the given path with the given `(certificate expression, response hash)` pairs.
+    pub fn set_responses(&mut self, path: &[String], responses: &[(String, Hash)]) {
+        let mut subtree = ExprTree::default();
+        for (expression, response_hash) in responses {
+            subtree.insert(&[hash_bytes(expression).to_vec(), Vec::new(), response_hash.to_vec()]);
+        }
+        self.replace(path, subtree);
+    }
+
+    /// Adds a leaf at the given path of labels.
+    fn insert(&mut self, labels: &[Vec<u8>]) {
+        let ExprTree::Node(children) = self else {
+            return;
+        };
+        let Some((label, rest)) = labels.split_first() else {
+            return;
+        };
+        if rest.is_empty() {
+            children.insert(label.clone(), ExprTree::Leaf(Vec::new()));
+            return;
+        }
+        if children.get(label).is_none() {
+            children.insert(label.clone(), ExprTree::default());
+        }
+        children.modify(label, |child| child.insert(rest));
+    }
+
+    /// Replaces the subtree at the given path.
+    fn replace(&mut self, path: &[String], subtree: ExprTree) {
+        let Some((segment, rest)) = path.split_first() else {
+            *self = subtree;
+            return;
+        };
+        let ExprTree::Node(children) = self else {
+            return;
+        };
+        match rest {
+            [] => children.insert(segment.as_bytes().to_vec(), subtree),
+            _ => {
+                if children.get(segment.as_bytes()).is_none() {
+                    children.insert(segment.as_bytes().to_vec(), ExprTree::default());
+                }
+                children.modify(segment.as_bytes(), |child| child.replace(rest, subtree));
+            }
+        }
+    }
+
+    /// Whether there is a subtree at the given path.
+    #[must_use]
+    pub fn contains(&self, path: &[String]) -> bool {
+        match (self, path.split_first()) {
+            (_, None) => true,
+            (ExprTree::Node(children), Some((segment, rest))) => children
+                .get(segment.as_bytes())
+                .is_some_and(|child| child.contains(rest)),
+            (ExprTree::Leaf(_), Some(_)) => false,
+        }
+    }
+
+    /// A witness for the subtree at the given path, or for its absence.
+    #[must_use]
+    pub fn witness(&self, path: &[String]) -> HashTree<'_> {
+        match (self, path.split_first()) {
+            (ExprTree::Node(children), Some((segment, rest))) => {
+                children.nested_witness(segment.as_bytes(), |child| child.witness(rest))
+            }
+            _ => self.as_hash_tree(),
+        }
+    }
+
+    /// The path of the response for the given URL path, and a witness for it.
+    ///
+    /// If there is no response for exactly the given path, the most specific wildcard response is used.  The witness
+    /// then also proves that there are no more specific responses, as the HTTP gateway checks that.
+    ///
+    /// Returns `None` if there is no response for the path.
+    #[must_use]
+    pub fn response_witness(&self, request_path: &str) -> Option<(Vec<String>, HashTree<'_>)> {
+        let segments = path_segments(request_path);
+        let exact_path = expr_path(&segments, EXACT_PATH_TERMINATOR);
+        let mut witness = self.witness(&exact_path);
+        if self.contains(&exact_path) {
+            return Some((exact_path, witness));
+        }
+        for prefix_len in (0..=segments.len()).rev() {
+            let wildcard_path = expr_path(&segments[..prefix_len], WILDCARD_PATH_TERMINATOR);
+            witness = merge_witnesses(witness, self.witness(&wildcard_path));
+            if self.contains(&wildcard_path) {
+                return Some((wildcard_path, witness));
+            }
+        }
+        None
+    }
+}
+
+/// Merges two witnesses of the same tree into one that reveals everything that either reveals.
+fn merge_witnesses<'a>(a: HashTree<'a>, b: HashTree<'a>) -> HashTree<'a> {
+    match (a, b) {
+        (HashTree::Pruned(_), b) => b,
+        (HashTree::Fork(a), HashTree::Fork(b)) => {
+            let (a_left, a_right) = *a;
+            let (b_left, b_right) = *b;
+            HashTree::Fork(Box::new((
+                merge_witnesses(a_left, b_left),
+                merge_witnesses(a_right, b_right),
+            )))
+        }
+        (HashTree::Labeled(label, a), HashTree::Labeled(_, b)) => {
+            HashTree::Labeled(label, Box::new(merge_witnesses(*a, *b)))
+        }
+        (a, _) => a,
+    }
+}
@@ -0,0 +1,134 @@
+use super::*;
+use pretty_assertions::assert_eq;
+
+fn strings(segments: &[&str]) -> Vec<String> {
+    segments.iter().map(|segment| segment.to_string()).collect()
+}
+
+fn headers(headers: &[(&str, &str)]) -> Vec<HeaderField> {
+    headers
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn numbers_should_be_leb128_encoded() {
+    assert_eq!(leb128(0), vec![0]);
+    assert_eq!(leb128(127), vec![0x7f]);
+    assert_eq!(leb128(128), vec![0x80, 0x01]);
+    assert_eq!(leb128(624_485), vec![0xe5, 0x8e, 0x26]);
+}
+
+#[test]
+fn url_paths_should_be_split_into_segments() {
+    assert_eq!(path_segments("/"), strings(&[""]));
+    assert_eq!(path_segments("/index.html"), strings(&["index.html"]));
+    assert_eq!(path_segments("/launchpad/"), strings(&["launchpad", ""]));
+    assert_eq!(
+        path_segments("/assets/canvaskit/canvaskit.wasm"),
+        strings(&["assets", "canvaskit", "canvaskit.wasm"])
+    );
+}
+
+#[test]
+fn expression_should_list_the_headers_in_lower_case() {
+    assert_eq!(
+        response_expression(&headers(&[("Content-Type", "text/html"), ("X-Frame-Options", "DENY")])),
+        "default_certification(ValidationArgs{certification:Certification{no_request_certification:Empty{},response_certification:ResponseCertification{certified_response_headers:ResponseHeaderList{headers:[\"content-type\",\"x-frame-options\"]}}}})"
+    );
+}
+
+#[test]
+fn response_hash_should_not_depend_on_header_order_or_case() {
+    let body = hash_bytes(b"<html></html>");
+    let hash = response_hash(
+        200,
+        &headers(&[("Content-Type", "text/html"), ("X-Frame-Options", "DENY")]),
+        &body,
+    );
+    assert_eq!(
+        hash,
+        response_hash(
+            200,
+            &headers(&[("x-frame-options", "DENY"), ("content-type", "text/html")]),
+            &body
+        )
+    );
+    assert_ne!(
+        hash,
+        response_hash(
+            404,
+            &headers(&[("Content-Type", "text/html"), ("X-Frame-Options", "DENY")]),
+            &body
+        )
+    );
+    assert_ne!(
+        hash,
+        response_hash(
+            200,
+            &headers(&[("Content-Type", "text/plain"), ("X-Frame-Options", "DENY")]),
+            &body
+        )
+    );
+    assert_ne!(
+        hash,
+        response_hash(
+            200,
+            &headers(&[("Content-Type", "text/html"), ("X-Frame-Options", "DENY")]),
+            &hash_bytes(b"")
+        )
+    );
+}
+
+/// A tree with responses at `/`, `/foo/bar.js`, a wildcard for everything and a wildcard for `/foo/`.
+fn test_tree() -> ExprTree {
+    let mut tree = ExprTree::default();
+    tree.set_responses(&strings(&["<*>"]), &[("root wildcard".to_string(), [1; 32])]);
+    tree.set_responses(&strings(&["", "<$>"]), &[("index".to_string(), [2; 32])]);
+    tree.set_responses(&strings(&["foo", "bar.js", "<$>"]), &[("bar".to_string(), [3; 32])]);
+    tree.set_responses(&strings(&["foo", "<*>"]), &[("foo wildcard".to_string(), [4; 32])]);
+    tree.set_skip_certification(&strings(&["metrics", "<$>"]));
+    tree
+}
+
+#[test]
+fn exact_responses_should_be_preferred() {
+    let tree = test_tree();
+    let (path, witness) = tree.response_witness("/foo/bar.js").expect("No response");
+    assert_eq!(path, strings(&["foo", "bar.js", "<$>"]));
+    assert_eq!(witness.reconstruct(), tree.root_hash());
+    let (path, _) = tree.response_witness("/").expect("No response");
+    assert_eq!(path, strings(&["", "<$>"]));
+    let (path, _) = tree.response_witness("/metrics").expect("No response");
+    assert_eq!(path, strings(&["metrics", "<$>"]));
+}
+
+#[test]
+fn the_most_specific_wildcard_should_be_used() {
+    let tree = test_tree();
+    let (path, witness) = tree.response_witness("/foo/baz/qux.js").expect("No response");
+    assert_eq!(path, strings(&["foo", "<*>"]));
+    assert_eq!(witness.reconstruct(), tree.root_hash());
+    let (path, witness) = tree.response_witness("/unknown").expect("No response");
+    assert_eq!(path, strings(&["<*>"]));
+    assert_eq!(witness.reconstruct(), tree.root_hash());
+}
+
+#[test]
+fn there_should_be_no_response_without_a_matching_path() {
+    let mut tree = ExprTree::default();
+    tree.set_responses(&strings(&["foo", "<$>"]), &[("foo".to_string(), [1; 32])]);
+    assert!(tree.response_witness("/bar").is_none());
+    assert!(tree.response_witness("/foo").is_some());
+}
+
+#[test]
+fn responses_should_be_replaced() {
+    let mut tree = test_tree();
+    let original_root_hash = tree.root_hash();
+    tree.set_responses(&strings(&["foo", "bar.js", "<$>"]), &[("bar".to_string(), [5; 32])]);
+    assert_ne!(tree.root_hash(), original_root_hash);
+    tree.set_responses(&strings(&["foo", "bar.js", "<$>"]), &[("bar".to_string(), [3; 32])]);
+    assert_eq!(tree.root_hash(), original_root_hash);
+}
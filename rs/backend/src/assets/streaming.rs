@@ -0,0 +1,110 @@
+//! Serving assets in parts, for assets that do not fit into one query response.
+//!
+//! - A request from an HTTP gateway that supports certification v2, with a `Range` header for one of the chunks of an
+//!   asset, gets a `206 Partial Content` response with that chunk.  The partial responses are certified per chunk, so
+//!   other ranges, and all ranges in certification v1, are ignored and the whole asset is served.
+//! - A request without a `Range` header for an asset larger than `STREAMING_CHUNK_SIZE_BYTES` gets the first chunk
+//!   in the response body, and a callback streaming strategy.  The HTTP gateway then calls
+//!   `http_request_streaming_callback` with the token until it has received the whole body.
+//!
+//! A streamed response carries the certificate of the whole asset: the HTTP gateway verifies the body once it has
+//! received all of it.
+use candid::{define_function, CandidType};
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+use std::ops::Range;
+
+#[cfg(test)]
+mod tests;
+
+/// The maximum number of bytes of an asset in one response.
+///
+/// Query responses are limited to 3 MiB, which must also fit the headers and the Candid encoding.
+pub const STREAMING_CHUNK_SIZE_BYTES: usize = 2 * 1024 * 1024;
+
+#[derive(Clone, Debug, CandidType, Deserialize, Eq, PartialEq)]
+pub struct StreamingCallbackToken {
+    /// The path of the asset, as requested.
+    pub key: String,
+    /// The encoding of the asset, as in the `Content-Encoding` header, or `identity`.
+    pub content_encoding: String,
+    /// The index of the chunk to return.
+    pub index: u64,
+    /// The SHA-256 of the whole body, to detect assets that change while they are being streamed.
+    pub sha256: Option<ByteBuf>,
+}
+
+define_function!(pub StreamingCallback : (StreamingCallbackToken) -> (StreamingCallbackHttpResponse) query);
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum StreamingStrategy {
+    Callback {
+        callback: StreamingCallback,
+        token: StreamingCallbackToken,
+    },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Eq, PartialEq)]
+pub struct StreamingCallbackHttpResponse {
+    pub body: ByteBuf,
+    /// The token for the next chunk, if any.
+    pub token: Option<StreamingCallbackToken>,
+}
+
+/// The bytes of the chunk with the given index of a body of the given length, if there is such a chunk.
+#[must_use]
+pub fn chunk_range(index: u64, body_len: usize) -> Option<Range<usize>> {
+    let start = usize::try_from(index).ok()?.checked_mul(STREAMING_CHUNK_SIZE_BYTES)?;
+    if start >= body_len {
+        return None;
+    }
+    Some(start..body_len.min(start + STREAMING_CHUNK_SIZE_BYTES))
+}
+
+/// The bytes to serve for the value of a `Range` header, for a body of the given length.
+///
+/// Only a single range of bytes is supported.  Returns `None` if the header should be ignored, i.e. if it cannot be
+/// parsed, asks for several ranges or cannot be satisfied; the whole body is then served instead.
+///
+/// Note: At most `STREAMING_CHUNK_SIZE_BYTES` are served.  The `Content-Range` header tells the client which bytes
+/// it got, so that it can request the rest.
+#[must_use]
+pub fn requested_range(range_header: &str, body_len: usize) -> Option<Range<usize>> {
+    let (unit, range) = range_header.trim().split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") || range.contains(',') {
+        return None;
+    }
+    let (first, last) = range.trim().split_once('-')?;
+    let (start, end) = match (first.trim(), last.trim()) {
+        ("", suffix_len) => {
+            let suffix_len: usize = suffix_len.parse().ok()?;
+            (body_len.checked_sub(suffix_len.min(body_len))?, body_len)
+        }
+        (first, "") => (first.parse().ok()?, body_len),
+        (first, last) => {
+            let last: usize = last.parse().ok()?;
+            (first.parse().ok()?, body_len.min(last.checked_add(1)?))
+        }
+    };
+    if start >= end {
+        return None;
+    }
+    Some(start..end.min(start + STREAMING_CHUNK_SIZE_BYTES))
+}
+
+/// The chunk to serve for the value of a `Range` header, for a body of the given length.
+///
+/// Only whole chunks have a certified partial response, so the header is ignored, and `None` returned, unless it asks
+/// for exactly one chunk, e.g. `bytes=0-` for the first one.
+#[must_use]
+pub fn requested_chunk(range_header: &str, body_len: usize) -> Option<Range<usize>> {
+    let range = requested_range(range_header, body_len)?;
+    let index = u64::try_from(range.start / STREAMING_CHUNK_SIZE_BYTES).ok()?;
+    chunk_range(index, body_len).filter(|chunk| *chunk == range)
+}
+
+/// The value of the `Content-Range` header for the given bytes of a body of the given length.
+#[must_use]
+pub fn content_range(range: &Range<usize>, body_len: usize) -> String {
+    format!("bytes {}-{}/{body_len}", range.start, range.end - 1)
+}
@@ -0,0 +1,76 @@
+use super::*;
+use pretty_assertions::assert_eq;
+
+#[test]
+fn single_byte_ranges_should_be_served() {
+    assert_eq!(requested_range("bytes=0-99", 1000), Some(0..100));
+    assert_eq!(requested_range("bytes=500-", 1000), Some(500..1000));
+    assert_eq!(requested_range("bytes=-100", 1000), Some(900..1000));
+    assert_eq!(requested_range(" Bytes = 10 - 19 ", 1000), Some(10..20));
+    // Ranges that extend beyond the body are cut short.
+    assert_eq!(requested_range("bytes=900-2000", 1000), Some(900..1000));
+    assert_eq!(requested_range("bytes=-2000", 1000), Some(0..1000));
+}
+
+#[test]
+fn unsupported_or_unsatisfiable_ranges_should_be_ignored() {
+    for range in [
+        "bytes=1000-",
+        "bytes=20-10",
+        "bytes=-0",
+        "bytes=0-9, 20-29",
+        "items=0-9",
+        "bytes=a-b",
+        "bytes=10",
+        "",
+    ] {
+        assert_eq!(requested_range(range, 1000), None, "Range: {range}");
+    }
+}
+
+#[test]
+fn ranges_should_not_exceed_the_chunk_size() {
+    let body_len = 3 * STREAMING_CHUNK_SIZE_BYTES;
+    assert_eq!(
+        requested_range("bytes=10-", body_len),
+        Some(10..10 + STREAMING_CHUNK_SIZE_BYTES)
+    );
+}
+
+#[test]
+fn content_range_should_list_the_last_byte_inclusively() {
+    assert_eq!(content_range(&(0..100), 1000), "bytes 0-99/1000");
+    assert_eq!(content_range(&(999..1000), 1000), "bytes 999-999/1000");
+}
+
+#[test]
+fn chunks_should_cover_the_body() {
+    let body_len = 2 * STREAMING_CHUNK_SIZE_BYTES + 1;
+    assert_eq!(chunk_range(0, body_len), Some(0..STREAMING_CHUNK_SIZE_BYTES));
+    assert_eq!(
+        chunk_range(1, body_len),
+        Some(STREAMING_CHUNK_SIZE_BYTES..2 * STREAMING_CHUNK_SIZE_BYTES)
+    );
+    assert_eq!(chunk_range(2, body_len), Some(2 * STREAMING_CHUNK_SIZE_BYTES..body_len));
+    assert_eq!(chunk_range(3, body_len), None);
+    assert_eq!(chunk_range(u64::MAX, body_len), None);
+}
+
+#[test]
+fn only_ranges_of_whole_chunks_should_be_served_as_chunks() {
+    let body_len = 2 * STREAMING_CHUNK_SIZE_BYTES + 100;
+    let second_chunk = format!(
+        "bytes={}-{}",
+        STREAMING_CHUNK_SIZE_BYTES,
+        2 * STREAMING_CHUNK_SIZE_BYTES - 1
+    );
+    let last_chunk = format!("bytes={}-", 2 * STREAMING_CHUNK_SIZE_BYTES);
+    assert_eq!(requested_chunk("bytes=0-", body_len), chunk_range(0, body_len));
+    assert_eq!(requested_chunk(&second_chunk, body_len), chunk_range(1, body_len));
+    assert_eq!(requested_chunk(&last_chunk, body_len), chunk_range(2, body_len));
+    assert_eq!(requested_chunk("bytes=-100", body_len), chunk_range(2, body_len));
+    assert_eq!(requested_chunk("bytes=0-", 1000), Some(0..1000));
+    for range in ["bytes=0-99", "bytes=10-", "bytes=-50", "bytes=1000-"] {
+        assert_eq!(requested_chunk(range, body_len), None, "Range: {range}");
+    }
+}
@@ -0,0 +1,163 @@
+//! Chunked upload of stable assets by controllers.
+//!
+//! An asset may be larger than the ingress message limit, so it is uploaded in parts:
+//!
+//! - `create_asset_batch` opens a batch.
+//! - `upload_asset_chunk` appends a chunk to the batch.  Chunks are concatenated in the order in which they are uploaded.
+//! - `commit_asset_batch` checks the SHA-256 of the concatenated chunks and stores the result as a stable asset at the
+//!   given path, with the given headers.
+//!
+//! Open batches are kept on the heap only, so an upgrade discards them.
+use super::{hash_bytes, Asset};
+use candid::CandidType;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[cfg(test)]
+mod tests;
+
+/// The maximum number of batches that may be open at the same time.
+pub const MAX_OPEN_ASSET_BATCHES: u32 = 10;
+/// The maximum size of an asset uploaded in a batch.
+pub const MAX_ASSET_BATCH_SIZE_BYTES: u64 = 32 * 1024 * 1024;
+/// Batches that have not been committed this long after they were created are discarded.
+pub const ASSET_BATCH_EXPIRY_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum CreateAssetBatchResponse {
+    Ok { batch_id: u64 },
+    TooManyBatches { limit: u32 },
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct UploadAssetChunkRequest {
+    pub batch_id: u64,
+    pub content: Vec<u8>,
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum UploadAssetChunkResponse {
+    Ok { chunk_index: u64 },
+    BatchNotFound,
+    BatchTooLarge { limit: u64 },
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct CommitAssetBatchRequest {
+    pub batch_id: u64,
+    /// The path at which the asset is served, e.g. `/assets/canvaskit/canvaskit.wasm`.
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    /// The expected SHA-256 of the concatenated chunks.
+    pub sha256: Vec<u8>,
+}
+
+#[derive(CandidType, Debug, PartialEq)]
+pub enum CommitAssetBatchResponse {
+    Ok,
+    BatchNotFound,
+    InvalidPath,
+    /// The uploaded content has a different hash.  The batch is discarded.
+    HashMismatch {
+        sha256: Vec<u8>,
+    },
+}
+
+/// A batch of chunks that have been uploaded but not yet committed.
+#[derive(Debug, Default, Eq, PartialEq)]
+struct AssetBatch {
+    chunks: Vec<Vec<u8>>,
+    size_bytes: u64,
+    created_timestamp_nanos: u64,
+}
+
+/// The open asset batches.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct AssetUploads {
+    batches: BTreeMap<u64, AssetBatch>,
+    next_batch_id: u64,
+}
+
+impl AssetUploads {
+    /// Opens a new batch, after discarding any batches that have expired.
+    pub fn create_batch(&mut self, now_nanos: u64) -> CreateAssetBatchResponse {
+        self.batches
+            .retain(|_, batch| now_nanos.saturating_sub(batch.created_timestamp_nanos) < ASSET_BATCH_EXPIRY_NANOS);
+        if self.batches.len() >= MAX_OPEN_ASSET_BATCHES as usize {
+            return CreateAssetBatchResponse::TooManyBatches {
+                limit: MAX_OPEN_ASSET_BATCHES,
+            };
+        }
+        let batch_id = self.next_batch_id;
+        self.next_batch_id += 1;
+        self.batches.insert(
+            batch_id,
+            AssetBatch {
+                created_timestamp_nanos: now_nanos,
+                ..AssetBatch::default()
+            },
+        );
+        CreateAssetBatchResponse::Ok { batch_id }
+    }
+
+    /// Appends a chunk to a batch.
+    pub fn upload_chunk(&mut self, request: UploadAssetChunkRequest) -> UploadAssetChunkResponse {
+        let Some(batch) = self.batches.get_mut(&request.batch_id) else {
+            return UploadAssetChunkResponse::BatchNotFound;
+        };
+        let size_bytes = batch.size_bytes + request.content.len() as u64;
+        if size_bytes > MAX_ASSET_BATCH_SIZE_BYTES {
+            return UploadAssetChunkResponse::BatchTooLarge {
+                limit: MAX_ASSET_BATCH_SIZE_BYTES,
+            };
+        }
+        batch.size_bytes = size_bytes;
+        batch.chunks.push(request.content);
+        UploadAssetChunkResponse::Ok {
+            chunk_index: batch.chunks.len() as u64 - 1,
+        }
+    }
+
+    /// Closes a batch and returns the path and stable asset assembled from its chunks.
+    ///
+    /// Note: The asset is not inserted into the state.  See `super::commit_asset_batch` for that.
+    ///
+    /// # Errors
+    /// - If the path is invalid, the batch does not exist or the content does not have the expected hash.
+    pub fn commit_batch(
+        &mut self,
+        request: CommitAssetBatchRequest,
+    ) -> Result<(String, Asset), CommitAssetBatchResponse> {
+        if !is_valid_asset_path(&request.path) {
+            return Err(CommitAssetBatchResponse::InvalidPath);
+        }
+        let Some(batch) = self.batches.remove(&request.batch_id) else {
+            return Err(CommitAssetBatchResponse::BatchNotFound);
+        };
+        let bytes = batch.chunks.concat();
+        let sha256 = hash_bytes(&bytes);
+        if sha256[..] != request.sha256[..] {
+            return Err(CommitAssetBatchResponse::HashMismatch {
+                sha256: sha256.to_vec(),
+            });
+        }
+        let asset = request
+            .headers
+            .into_iter()
+            .fold(Asset::new_stable(bytes), |asset, (key, value)| {
+                asset.with_header(key, value)
+            });
+        Ok((request.path, asset))
+    }
+
+    /// The number of open batches.
+    #[must_use]
+    pub fn num_batches(&self) -> usize {
+        self.batches.len()
+    }
+}
+
+/// Uploaded assets must have an absolute path and may not shadow the metrics endpoint.
+fn is_valid_asset_path(path: &str) -> bool {
+    path.starts_with('/') && path != "/metrics" && !path.contains('?')
+}
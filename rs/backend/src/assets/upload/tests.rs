@@ -0,0 +1,151 @@
+use super::*;
+use pretty_assertions::assert_eq;
+
+const NOW: u64 = 1_724_314_428_000_000_000;
+
+fn open_batch(uploads: &mut AssetUploads) -> u64 {
+    match uploads.create_batch(NOW) {
+        CreateAssetBatchResponse::Ok { batch_id } => batch_id,
+        response => panic!("Failed to create a batch: {response:?}"),
+    }
+}
+
+fn commit_request(batch_id: u64, content: &[u8]) -> CommitAssetBatchRequest {
+    CommitAssetBatchRequest {
+        batch_id,
+        path: "/assets/canvaskit/canvaskit.wasm".to_string(),
+        headers: vec![("content-type".to_string(), "application/wasm".to_string())],
+        sha256: hash_bytes(content).to_vec(),
+    }
+}
+
+#[test]
+fn chunks_should_be_committed_as_one_stable_asset() {
+    let mut uploads = AssetUploads::default();
+    let batch_id = open_batch(&mut uploads);
+    for (index, chunk) in [vec![1, 2, 3], vec![4, 5], vec![6]].into_iter().enumerate() {
+        assert_eq!(
+            uploads.upload_chunk(UploadAssetChunkRequest {
+                batch_id,
+                content: chunk
+            }),
+            UploadAssetChunkResponse::Ok {
+                chunk_index: index as u64
+            }
+        );
+    }
+
+    let committed = uploads.commit_batch(commit_request(batch_id, &[1, 2, 3, 4, 5, 6]));
+
+    assert_eq!(
+        committed,
+        Ok((
+            "/assets/canvaskit/canvaskit.wasm".to_string(),
+            Asset::new_stable(vec![1, 2, 3, 4, 5, 6]).with_header("content-type", "application/wasm")
+        ))
+    );
+    assert_eq!(uploads.num_batches(), 0);
+}
+
+#[test]
+fn commit_should_fail_if_the_hash_does_not_match() {
+    let mut uploads = AssetUploads::default();
+    let batch_id = open_batch(&mut uploads);
+    let _ = uploads.upload_chunk(UploadAssetChunkRequest {
+        batch_id,
+        content: vec![1, 2, 3],
+    });
+
+    let committed = uploads.commit_batch(commit_request(batch_id, &[1, 2, 4]));
+
+    assert_eq!(
+        committed,
+        Err(CommitAssetBatchResponse::HashMismatch {
+            sha256: hash_bytes([1, 2, 3]).to_vec()
+        })
+    );
+    // The batch is discarded.
+    assert_eq!(
+        uploads.commit_batch(commit_request(batch_id, &[1, 2, 3])),
+        Err(CommitAssetBatchResponse::BatchNotFound)
+    );
+}
+
+#[test]
+fn commit_should_fail_for_an_invalid_path() {
+    let mut uploads = AssetUploads::default();
+    let batch_id = open_batch(&mut uploads);
+    for path in ["relative/path", "/metrics", "/foo?bar"] {
+        let request = CommitAssetBatchRequest {
+            path: path.to_string(),
+            ..commit_request(batch_id, &[])
+        };
+        assert_eq!(
+            uploads.commit_batch(request),
+            Err(CommitAssetBatchResponse::InvalidPath)
+        );
+    }
+    // The batch is still open.
+    assert_eq!(uploads.num_batches(), 1);
+}
+
+#[test]
+fn unknown_batches_should_be_rejected() {
+    let mut uploads = AssetUploads::default();
+    assert_eq!(
+        uploads.upload_chunk(UploadAssetChunkRequest {
+            batch_id: 7,
+            content: vec![1]
+        }),
+        UploadAssetChunkResponse::BatchNotFound
+    );
+    assert_eq!(
+        uploads.commit_batch(commit_request(7, &[])),
+        Err(CommitAssetBatchResponse::BatchNotFound)
+    );
+}
+
+#[test]
+fn batches_should_not_exceed_the_maximum_size() {
+    let mut uploads = AssetUploads::default();
+    let batch_id = open_batch(&mut uploads);
+    let chunk_size = usize::try_from(MAX_ASSET_BATCH_SIZE_BYTES / 2).unwrap();
+    for _ in 0..2 {
+        let response = uploads.upload_chunk(UploadAssetChunkRequest {
+            batch_id,
+            content: vec![0; chunk_size],
+        });
+        assert!(matches!(response, UploadAssetChunkResponse::Ok { .. }));
+    }
+    assert_eq!(
+        uploads.upload_chunk(UploadAssetChunkRequest {
+            batch_id,
+            content: vec![0]
+        }),
+        UploadAssetChunkResponse::BatchTooLarge {
+            limit: MAX_ASSET_BATCH_SIZE_BYTES
+        }
+    );
+}
+
+#[test]
+fn the_number_of_open_batches_should_be_limited_until_they_expire() {
+    let mut uploads = AssetUploads::default();
+    for _ in 0..MAX_OPEN_ASSET_BATCHES {
+        open_batch(&mut uploads);
+    }
+    assert_eq!(
+        uploads.create_batch(NOW),
+        CreateAssetBatchResponse::TooManyBatches {
+            limit: MAX_OPEN_ASSET_BATCHES
+        }
+    );
+
+    assert_eq!(
+        uploads.create_batch(NOW + ASSET_BATCH_EXPIRY_NANOS),
+        CreateAssetBatchResponse::Ok {
+            batch_id: u64::from(MAX_OPEN_ASSET_BATCHES)
+        }
+    );
+    assert_eq!(uploads.num_batches(), 1);
+}
@@ -0,0 +1,98 @@
+//! Certification of data served by query calls.
+//!
+//! The certified data of the canister is the root hash of a tree with three labeled subtrees:
+//!
+//! - `canisters`: For every account with attached canisters, a hash of those canisters.
+//! - `http_assets`: The hashes of the assets served over HTTP, for certification v1.
+//! - `http_expr`: The hashes of the responses served over HTTP, for certification v2.
+//!
+//! The tree is `fork(fork(canisters, http_assets), http_expr)`.  A witness for data in one subtree contains the other
+//! subtrees as pruned hashes.
+use crate::accounts_store::LABEL_CANISTERS;
+use crate::assets::certification_v2::LABEL_EXPR;
+use crate::assets::LABEL_ASSETS;
+use crate::state::State;
+use ic_certified_map::{fork, fork_hash, labeled, labeled_hash, Hash, HashTree};
+use serde::Serialize;
+
+/// The root hash of all certified data.
+#[must_use]
+pub fn root_hash(state: &State) -> Hash {
+    fork_hash(&canisters_and_assets_hash(state), &responses_hash(state))
+}
+
+/// The hash of the fork of the `canisters` and `http_assets` subtrees.
+fn canisters_and_assets_hash(state: &State) -> Hash {
+    fork_hash(&canisters_hash(state), &assets_hash(state))
+}
+
+/// The hash of the labeled `canisters` subtree.
+fn canisters_hash(state: &State) -> Hash {
+    labeled_hash(LABEL_CANISTERS, &state.accounts_store.canisters_root_hash())
+}
+
+/// The hash of the labeled `http_assets` subtree.
+fn assets_hash(state: &State) -> Hash {
+    labeled_hash(LABEL_ASSETS, &state.asset_hashes.root_hash())
+}
+
+/// The hash of the labeled `http_expr` subtree.
+fn responses_hash(state: &State) -> Hash {
+    labeled_hash(LABEL_EXPR, &state.asset_hashes.responses_root_hash())
+}
+
+/// Sets the certified data of the canister.
+///
+/// Note: This must be called in update calls that change any certified data.
+pub fn update_root_hash(state: &State) {
+    let root_hash = root_hash(state);
+    ic_cdk::api::certified_data_set(&root_hash[..]);
+}
+
+/// A witness for the asset at the given path.
+#[must_use]
+pub fn asset_witness<'a>(state: &'a State, path: &str) -> HashTree<'a> {
+    fork(
+        fork(
+            HashTree::Pruned(canisters_hash(state)),
+            labeled(LABEL_ASSETS, state.asset_hashes.witness(path.as_bytes())),
+        ),
+        HashTree::Pruned(responses_hash(state)),
+    )
+}
+
+/// A witness for certified responses, given a witness within the `http_expr` subtree.
+#[must_use]
+pub fn responses_witness<'a>(state: &State, responses_witness: HashTree<'a>) -> HashTree<'a> {
+    fork(
+        HashTree::Pruned(canisters_and_assets_hash(state)),
+        labeled(LABEL_EXPR, responses_witness),
+    )
+}
+
+/// A witness for the canisters attached to the given account.
+#[must_use]
+pub fn canisters_witness<'a>(state: &'a State, account_identifier: &[u8]) -> HashTree<'a> {
+    fork(
+        fork(
+            labeled(
+                LABEL_CANISTERS,
+                state.accounts_store.canisters_witness(account_identifier),
+            ),
+            HashTree::Pruned(assets_hash(state)),
+        ),
+        HashTree::Pruned(responses_hash(state)),
+    )
+}
+
+/// Serializes a witness as self-describing CBOR, as expected by agents.
+#[must_use]
+pub fn encode_witness(tree: &HashTree) -> Vec<u8> {
+    let mut serializer = serde_cbor::ser::Serializer::new(vec![]);
+    serializer
+        .self_describe()
+        .unwrap_or_else(|e| ic_cdk::api::trap(format!("failed to serialize a hash tree: {e}")));
+    tree.serialize(&mut serializer)
+        .unwrap_or_else(|e| ic_cdk::api::trap(format!("failed to serialize a hash tree: {e}")));
+    serializer.into_inner()
+}
@@ -6,6 +6,7 @@
 pub mod accounts_store;
 pub mod arguments;
 pub mod assets;
+pub mod certification;
 pub mod constants;
 pub mod metrics_encoder;
 pub mod perf;
@@ -1,15 +1,32 @@
+use crate::accounts_store::accounts_db::MIGRATION_BATCH_SIZE;
+use crate::accounts_store::backfills::BACKFILL_BATCH_SIZE;
 use crate::accounts_store::histogram::AccountsStoreHistogram;
+use crate::accounts_store::limits::Limits;
 use crate::accounts_store::{
-    AccountDetails, AddressBook, AttachCanisterRequest, AttachCanisterResponse, CreateSubAccountResponse,
-    DetachCanisterRequest, DetachCanisterResponse, FavProjects, GetAddressBookResponse, GetFavProjectsResponse,
-    GetImportedTokensResponse, ImportedTokens, NamedCanister, RegisterHardwareWalletRequest,
-    RegisterHardwareWalletResponse, RenameCanisterRequest, RenameCanisterResponse, RenameSubAccountRequest,
-    RenameSubAccountResponse, SetAddressBookResponse, SetFavProjectsResponse, SetImportedTokensResponse,
+    AccountDataBundle, AccountDetails, AddAccountResponse, AddNamedAddressRequest, AddressBook, AttachCanisterRequest,
+    AttachCanisterResponse, CancelAccountTransferResponse, ClaimAccountTransferRequest, ClaimAccountTransferResponse,
+    CreateAccountTransferRequest, CreateAccountTransferResponse, CreateSubAccountResponse, DeleteAccountResponse,
+    DetachCanisterRequest, DetachCanisterResponse, ExportAccountDataResponse, FavProjects, GetAddressBookResponse,
+    GetFavProjectsResponse, GetImportedTokensResponse, ImportAccountDataResponse, ImportedTokens, ListCanistersRequest,
+    ListCanistersResponse, ListNamedIcrc1SubaccountsRequest, ListNamedIcrc1SubaccountsResponse, NamedCanister,
+    NamedIcrc1Subaccount, RegisterHardwareWalletRequest, RegisterHardwareWalletResponse,
+    RemoveHardwareWalletLinkRequest, RemoveHardwareWalletLinkResponse, RemoveNamedAddressRequest,
+    RenameCanisterRequest, RenameCanisterResponse, RenameHardwareWalletRequest, RenameNamedIcrc1SubaccountRequest,
+    RenameSubAccountRequest, RenameSubAccountResponse, ReorderSubAccountsRequest, SetAddressBookResponse,
+    SetFavProjectsResponse, SetImportedTokensResponse, SetNamedIcrc1SubaccountResponse, SetSubAccountArchivedRequest,
+    SetSubAccountStyleKeyRequest, UnregisterHardwareWalletRequest, UpdateAccountLimitAllowlistRequest,
+    UpdateAccountLimitAllowlistResponse, UpdateCanisterMetadataRequest, UpdateCanisterMetadataResponse,
+    UpdateHardwareWalletDeviceRequest, UpdateHardwareWalletResponse, UpdateNamedAddressRequest,
+    UpdateSubAccountResponse, PRINCIPAL_LESS_ACCOUNTS_SCAN_BATCH_SIZE,
 };
 use crate::arguments::{set_canister_arguments, CanisterArguments};
-use crate::assets::{hash_bytes, insert_asset, Asset};
+use crate::assets::upload::{
+    CommitAssetBatchRequest, CommitAssetBatchResponse, CreateAssetBatchResponse, UploadAssetChunkRequest,
+    UploadAssetChunkResponse,
+};
 use crate::perf::PerformanceCount;
-use crate::state::{init_state, restore_state, save_state, with_state, with_state_mut, StableState};
+use crate::state::partitions::{Partitions, SchemaInfo};
+use crate::state::{init_state, restore_state, save_state, with_partitions, with_state, with_state_mut, StableState};
 use crate::tvl::TvlResponse;
 
 pub use candid::{candid_method, CandidType, Deserialize};
@@ -17,13 +34,15 @@ use ic_cdk::println;
 use ic_cdk::{init, post_upgrade, pre_upgrade};
 use icp_ledger::AccountIdentifier;
 pub use serde::Serialize;
+use std::time::Duration;
 
-use ic_base_types::PrincipalId;
+use ic_base_types::{CanisterId, PrincipalId};
 
 mod accounts_store;
 mod arguments;
 mod assets;
 mod canisters;
+mod certification;
 mod constants;
 mod metrics_encoder;
 mod perf;
@@ -41,15 +60,71 @@ fn init(args: Option<CanisterArguments>) {
     let counter_before = PerformanceCount::new("init start");
     init_state();
     perf::save_instruction_count(counter_before);
+    set_limits(args.as_ref());
     set_canister_arguments(args);
     perf::record_instruction_count("init after set_canister_arguments");
     // Legacy:
     assets::init_assets();
     tvl::init_timers();
+    schedule_principal_less_accounts_scan_step();
     perf::record_instruction_count("init stop");
     println!("END   init with args");
 }
 
+/// Applies any limits given in the canister arguments on top of the limits in force.
+///
+/// Traps if a limit is out of bounds, so that an installation or upgrade with invalid limits fails.
+fn set_limits(args: Option<&CanisterArguments>) {
+    let Some(overrides) = args.and_then(|args| args.limits.as_ref()) else {
+        return;
+    };
+    with_state_mut(|s| {
+        let limits = s
+            .accounts_store
+            .limits()
+            .with_overrides(overrides)
+            .unwrap_or_else(|err| ic_cdk::api::trap(format!("Invalid limits: {err}")));
+        s.accounts_store.set_limits(limits);
+    });
+}
+
+/// Moves accounts still keyed by variable-length identifiers to the new accounts map, one bounded batch per timer,
+/// until none are left.
+fn schedule_accounts_db_migration_step() {
+    if with_state(|s| s.accounts_store.migration_countdown()) > 0 {
+        timer::set_timer(Duration::ZERO, async {
+            with_state_mut(|s| s.accounts_store.step_migration(MIGRATION_BATCH_SIZE));
+            schedule_accounts_db_migration_step();
+        });
+    }
+}
+
+/// Computes data derived from the accounts for the accounts that predate it, one bounded batch per timer, until every
+/// backfill is complete.
+fn schedule_backfill_step() {
+    if !with_state(|s| s.accounts_store.backfills_complete()) {
+        timer::set_timer(Duration::ZERO, async {
+            with_state_mut(|s| {
+                s.accounts_store.step_backfills(BACKFILL_BATCH_SIZE);
+                certification::update_root_hash(s);
+            });
+            schedule_backfill_step();
+        });
+    }
+}
+
+/// Counts the accounts that have no principal, one bounded batch per timer, until every account has been scanned.
+fn schedule_principal_less_accounts_scan_step() {
+    timer::set_timer(Duration::ZERO, async {
+        if !with_state_mut(|s| {
+            s.accounts_store
+                .step_principal_less_accounts_scan(PRINCIPAL_LESS_ACCOUNTS_SCAN_BATCH_SIZE)
+        }) {
+            schedule_principal_less_accounts_scan_step();
+        }
+    });
+}
+
 /// Redundant function, never called but required as this is `main.rs`.
 fn main() {}
 
@@ -79,6 +154,10 @@ fn post_upgrade(args_maybe: Option<CanisterArguments>) {
     restore_state();
     perf::save_instruction_count(counter_before);
     perf::record_instruction_count("post_upgrade after state_recovery");
+    schedule_accounts_db_migration_step();
+    schedule_backfill_step();
+    schedule_principal_less_accounts_scan_step();
+    set_limits(args_maybe.as_ref());
     set_canister_arguments(args_maybe);
     perf::record_instruction_count("post_upgrade after set_canister_arguments");
     assets::init_assets();
@@ -110,6 +189,16 @@ pub fn http_request(req: assets::HttpRequest) -> assets::HttpResponse {
     assets::http_request(req)
 }
 
+/// Returns the next chunk of an asset that is too large for a single `http_request` response.
+#[must_use]
+#[candid_method(query)]
+#[ic_cdk::query]
+pub fn http_request_streaming_callback(
+    token: assets::streaming::StreamingCallbackToken,
+) -> assets::streaming::StreamingCallbackHttpResponse {
+    assets::http_request_streaming_callback(token)
+}
+
 fn get_caller() -> PrincipalId {
     let caller = ic_cdk::api::msg_caller();
     if caller == candid::Principal::anonymous() {
@@ -133,16 +222,61 @@ pub fn get_account() -> GetAccountResponse {
     })
 }
 
-/// Creates a new account controlled by the caller's principal.
+/// Creates a new account controlled by the caller's principal, unless the caller already has one.
+///
+/// Returns the caller's account identifier, or `AccountLimitReached` if no new accounts may be
+/// created and the caller is not on the account limit allowlist.
+#[must_use]
+#[ic_cdk::update]
+pub fn add_account() -> AddAccountResponse {
+    let principal = get_caller();
+    with_state_mut(|s| s.accounts_store.add_account(principal))
+}
+
+/// Adds principals to, and removes principals from, the list of principals that may create an
+/// account even when the account limit has been reached.
+///
+/// # Panics
+/// - If the caller is not a controller.
+#[must_use]
+#[ic_cdk::update]
+pub fn update_account_limit_allowlist(
+    request: UpdateAccountLimitAllowlistRequest,
+) -> UpdateAccountLimitAllowlistResponse {
+    let caller = ic_cdk::api::msg_caller();
+    if !ic_cdk::api::is_controller(&caller) {
+        ic_cdk::api::trap("Only a controller may update the account limit allowlist");
+    }
+    with_state_mut(|s| s.accounts_store.update_account_limit_allowlist(request))
+}
+
+/// Returns the principals that may create an account even when the account limit has been reached.
 ///
-/// Returns true if the account was created, else false (which happens if the principal already has
-/// an account).
+/// # Panics
+/// - If the caller is not a controller.
+#[must_use]
+#[ic_cdk::query]
+pub fn get_account_limit_allowlist() -> Vec<PrincipalId> {
+    let caller = ic_cdk::api::msg_caller();
+    if !ic_cdk::api::is_controller(&caller) {
+        ic_cdk::api::trap("Only a controller may view the account limit allowlist");
+    }
+    with_state(|s| s.accounts_store.get_account_limit_allowlist())
+}
+
+/// Deletes the user's account together with all sub-account names, hardware wallets, canisters
+/// and settings stored for it.
+///
+/// Funds are not affected, as they are held by the ledger rather than by this canister.
 #[must_use]
 #[ic_cdk::update]
-pub fn add_account() -> AccountIdentifier {
+pub fn delete_account() -> DeleteAccountResponse {
     let principal = get_caller();
-    with_state_mut(|s| s.accounts_store.add_account(principal));
-    AccountIdentifier::from(principal)
+    with_state_mut(|s| {
+        let response = s.accounts_store.delete_account(principal);
+        certification::update_root_hash(s);
+        response
+    })
 }
 
 /// Creates a new ledger sub account and links it to the user's account.
@@ -167,6 +301,34 @@ pub fn rename_sub_account(request: RenameSubAccountRequest) -> RenameSubAccountR
     with_state_mut(|s| s.accounts_store.rename_sub_account(principal, request))
 }
 
+/// Archives or restores one of the caller's sub-accounts.
+///
+/// Archived sub-accounts keep their name and are flagged as archived in `get_account`.
+#[must_use]
+#[ic_cdk::update]
+pub fn set_sub_account_archived(request: SetSubAccountArchivedRequest) -> UpdateSubAccountResponse {
+    let principal = get_caller();
+    with_state_mut(|s| s.accounts_store.set_sub_account_archived(principal, request))
+}
+
+/// Sets or clears the key the front end uses to choose a colour or icon for a sub-account.
+#[must_use]
+#[ic_cdk::update]
+pub fn set_sub_account_style_key(request: SetSubAccountStyleKeyRequest) -> UpdateSubAccountResponse {
+    let principal = get_caller();
+    with_state_mut(|s| s.accounts_store.set_sub_account_style_key(principal, request))
+}
+
+/// Sets the order in which the caller's sub-accounts are returned by `get_account`.
+///
+/// The listed sub-accounts come first, in the given order, followed by the others sorted by name.
+#[must_use]
+#[ic_cdk::update]
+pub fn reorder_sub_accounts(request: ReorderSubAccountsRequest) -> UpdateSubAccountResponse {
+    let principal = get_caller();
+    with_state_mut(|s| s.accounts_store.reorder_sub_accounts(principal, request))
+}
+
 /// Links a hardware wallet to the user's account.
 ///
 /// A single hardware wallet can be linked to multiple user accounts, but in order to make calls to
@@ -179,6 +341,49 @@ pub fn register_hardware_wallet(request: RegisterHardwareWalletRequest) -> Regis
     with_state_mut(|s| s.accounts_store.register_hardware_wallet(principal, request))
 }
 
+/// Renames one of the caller's hardware wallets.
+#[must_use]
+#[ic_cdk::update]
+pub fn rename_hardware_wallet(request: RenameHardwareWalletRequest) -> UpdateHardwareWalletResponse {
+    let principal = get_caller();
+    with_state_mut(|s| s.accounts_store.rename_hardware_wallet(principal, request))
+}
+
+/// Removes one of the caller's hardware wallets from their account.
+#[must_use]
+#[ic_cdk::update]
+pub fn unregister_hardware_wallet(request: UnregisterHardwareWalletRequest) -> UpdateHardwareWalletResponse {
+    let principal = get_caller();
+    with_state_mut(|s| s.accounts_store.unregister_hardware_wallet(principal, request))
+}
+
+/// Sets or clears the device information (model, app version, when last verified) of one of the caller's hardware
+/// wallets.
+#[must_use]
+#[ic_cdk::update]
+pub fn update_hardware_wallet_device(request: UpdateHardwareWalletDeviceRequest) -> UpdateHardwareWalletResponse {
+    let principal = get_caller();
+    with_state_mut(|s| s.accounts_store.update_hardware_wallet_device(principal, request))
+}
+
+/// Lists the accounts that have registered the caller as a hardware wallet.
+///
+/// The caller is the hardware wallet, so only the holder of the device can see which accounts reference it.
+#[must_use]
+#[ic_cdk::query]
+pub fn get_hardware_wallet_links() -> Vec<AccountIdentifier> {
+    let principal = get_caller();
+    with_state(|s| s.accounts_store.get_hardware_wallet_links(principal))
+}
+
+/// Removes the caller, as a hardware wallet, from an account that registered it.
+#[must_use]
+#[ic_cdk::update]
+pub fn remove_hardware_wallet_link(request: RemoveHardwareWalletLinkRequest) -> RemoveHardwareWalletLinkResponse {
+    let principal = get_caller();
+    with_state_mut(|s| s.accounts_store.remove_hardware_wallet_link(principal, request))
+}
+
 /// Returns the list of canisters which the user has attached to their account.
 #[must_use]
 #[ic_cdk::query]
@@ -187,12 +392,42 @@ pub fn get_canisters() -> Vec<NamedCanister> {
     with_state_mut(|s| s.accounts_store.get_canisters(principal))
 }
 
+/// Returns a page of the canisters which the user has attached to their account.
+///
+/// The response is certified, so it can be trusted without making an update call: The
+/// `canister_hashes` of all the user's canisters are certified at `canisters/<account identifier>`.
+///
+/// After an upgrade, the hashes are certified in batches, on a timer.  Until the user's account has been reached, the
+/// response has no certificate and must be confirmed with an update call.
+#[must_use]
+#[ic_cdk::query]
+pub fn list_canisters(request: ListCanistersRequest) -> ListCanistersResponse {
+    let principal = get_caller();
+    with_state(|s| {
+        let mut response = s.accounts_store.list_canisters(principal, request);
+        if let ListCanistersResponse::Ok(result) = &mut response {
+            let account_identifier = AccountIdentifier::from(principal).to_vec();
+            if s.accounts_store
+                .canisters_are_certified(&account_identifier, &result.canister_hashes)
+            {
+                result.certificate = ic_cdk::api::data_certificate();
+                result.tree = certification::encode_witness(&certification::canisters_witness(s, &account_identifier));
+            }
+        }
+        response
+    })
+}
+
 /// Attaches a canister to the user's account.
 #[must_use]
 #[ic_cdk::update]
 pub fn attach_canister(request: AttachCanisterRequest) -> AttachCanisterResponse {
     let principal = get_caller();
-    with_state_mut(|s| s.accounts_store.attach_canister(principal, request))
+    with_state_mut(|s| {
+        let response = s.accounts_store.attach_canister(principal, request);
+        certification::update_root_hash(s);
+        response
+    })
 }
 
 /// Renames a canister of the user.
@@ -200,7 +435,11 @@ pub fn attach_canister(request: AttachCanisterRequest) -> AttachCanisterResponse
 #[ic_cdk::update]
 pub fn rename_canister(request: RenameCanisterRequest) -> RenameCanisterResponse {
     let principal = get_caller();
-    with_state_mut(|s| s.accounts_store.rename_canister(principal, request))
+    with_state_mut(|s| {
+        let response = s.accounts_store.rename_canister(principal, request);
+        certification::update_root_hash(s);
+        response
+    })
 }
 
 /// Detaches a canister from the user's account.
@@ -208,14 +447,46 @@ pub fn rename_canister(request: RenameCanisterRequest) -> RenameCanisterResponse
 #[ic_cdk::update]
 pub fn detach_canister(request: DetachCanisterRequest) -> DetachCanisterResponse {
     let principal = get_caller();
-    with_state_mut(|s| s.accounts_store.detach_canister(principal, request))
+    with_state_mut(|s| {
+        let response = s.accounts_store.detach_canister(principal, request);
+        certification::update_root_hash(s);
+        response
+    })
+}
+
+/// Returns the number of accounts that have attached the given canister.
+///
+/// Only the number is returned, not which accounts have attached the canister.
+#[must_use]
+#[ic_cdk::query]
+pub fn get_canister_attachment_count(canister_id: CanisterId) -> u64 {
+    with_state(|s| s.accounts_store.get_canister_attachment_count(canister_id))
+}
+
+/// Sets the tags, note and low-cycles threshold of a canister attached to the user's account.
+#[must_use]
+#[ic_cdk::update]
+pub fn update_canister_metadata(request: UpdateCanisterMetadataRequest) -> UpdateCanisterMetadataResponse {
+    let principal = get_caller();
+    with_state_mut(|s| {
+        let response = s.accounts_store.update_canister_metadata(principal, request);
+        certification::update_root_hash(s);
+        response
+    })
 }
 
+/// Replaces the user's imported tokens.
+///
+/// If `expected_version` is provided, the change is rejected with `VersionConflict` unless it is
+/// the current version, as returned by the getter.
 #[must_use]
 #[ic_cdk::update]
-pub fn set_imported_tokens(settings: ImportedTokens) -> SetImportedTokensResponse {
+pub fn set_imported_tokens(settings: ImportedTokens, expected_version: Option<u64>) -> SetImportedTokensResponse {
     let principal = get_caller();
-    with_state_mut(|s| s.accounts_store.set_imported_tokens(principal, settings))
+    with_state_mut(|s| {
+        s.accounts_store
+            .set_imported_tokens(principal, settings, expected_version)
+    })
 }
 
 #[must_use]
@@ -225,11 +496,42 @@ pub fn get_imported_tokens() -> GetImportedTokensResponse {
     with_state_mut(|s| s.accounts_store.get_imported_tokens(principal))
 }
 
+/// Gives a name to one of the user's ICRC-1 subaccounts on a given ledger.
 #[must_use]
 #[ic_cdk::update]
-pub fn set_fav_projects(settings: FavProjects) -> SetFavProjectsResponse {
+pub fn create_named_icrc1_subaccount(named_subaccount: NamedIcrc1Subaccount) -> SetNamedIcrc1SubaccountResponse {
     let principal = get_caller();
-    with_state_mut(|s| s.accounts_store.set_fav_projects(principal, settings))
+    with_state_mut(|s| {
+        s.accounts_store
+            .create_named_icrc1_subaccount(principal, named_subaccount)
+    })
+}
+
+/// Changes the name of one of the user's named ICRC-1 subaccounts.
+#[must_use]
+#[ic_cdk::update]
+pub fn rename_named_icrc1_subaccount(request: RenameNamedIcrc1SubaccountRequest) -> SetNamedIcrc1SubaccountResponse {
+    let principal = get_caller();
+    with_state_mut(|s| s.accounts_store.rename_named_icrc1_subaccount(principal, request))
+}
+
+/// Lists the user's named ICRC-1 subaccounts, optionally only those on a given ledger.
+#[must_use]
+#[ic_cdk::query]
+pub fn list_named_icrc1_subaccounts(request: ListNamedIcrc1SubaccountsRequest) -> ListNamedIcrc1SubaccountsResponse {
+    let principal = get_caller();
+    with_state(|s| s.accounts_store.list_named_icrc1_subaccounts(principal, request))
+}
+
+/// Replaces the user's favorite projects.
+///
+/// If `expected_version` is provided, the change is rejected with `VersionConflict` unless it is
+/// the current version, as returned by the getter.
+#[must_use]
+#[ic_cdk::update]
+pub fn set_fav_projects(settings: FavProjects, expected_version: Option<u64>) -> SetFavProjectsResponse {
+    let principal = get_caller();
+    with_state_mut(|s| s.accounts_store.set_fav_projects(principal, settings, expected_version))
 }
 
 #[must_use]
@@ -239,11 +541,18 @@ pub fn get_fav_projects() -> GetFavProjectsResponse {
     with_state(|s| s.accounts_store.get_fav_projects(principal))
 }
 
+/// Replaces the user's address book.
+///
+/// If `expected_version` is provided, the change is rejected with `VersionConflict` unless it is
+/// the current version, as returned by the getter.
 #[must_use]
 #[ic_cdk::update]
-pub fn set_address_book(addresses: AddressBook) -> SetAddressBookResponse {
+pub fn set_address_book(addresses: AddressBook, expected_version: Option<u64>) -> SetAddressBookResponse {
     let principal = get_caller();
-    with_state_mut(|s| s.accounts_store.set_address_book(principal, addresses))
+    with_state_mut(|s| {
+        s.accounts_store
+            .set_address_book(principal, addresses, expected_version)
+    })
 }
 
 #[must_use]
@@ -253,6 +562,97 @@ pub fn get_address_book() -> GetAddressBookResponse {
     with_state(|s| s.accounts_store.get_address_book(principal))
 }
 
+/// Adds an entry to the user's address book.
+///
+/// Fails with `VersionConflict` if the address book has changed since `expected_version`.
+/// On success, the version of the address book is `expected_version + 1`.
+#[must_use]
+#[ic_cdk::update]
+pub fn add_named_address(request: AddNamedAddressRequest) -> SetAddressBookResponse {
+    let principal = get_caller();
+    with_state_mut(|s| s.accounts_store.add_named_address(principal, request))
+}
+
+/// Replaces an entry of the user's address book.
+///
+/// Fails with `VersionConflict` if the address book has changed since `expected_version`.
+/// On success, the version of the address book is `expected_version + 1`.
+#[must_use]
+#[ic_cdk::update]
+pub fn update_named_address(request: UpdateNamedAddressRequest) -> SetAddressBookResponse {
+    let principal = get_caller();
+    with_state_mut(|s| s.accounts_store.update_named_address(principal, request))
+}
+
+/// Removes an entry from the user's address book.
+///
+/// Fails with `VersionConflict` if the address book has changed since `expected_version`.
+/// On success, the version of the address book is `expected_version + 1`.
+#[must_use]
+#[ic_cdk::update]
+pub fn remove_named_address(request: RemoveNamedAddressRequest) -> SetAddressBookResponse {
+    let principal = get_caller();
+    with_state_mut(|s| s.accounts_store.remove_named_address(principal, request))
+}
+
+/// Returns everything stored for the user's account as a single versioned bundle.
+///
+/// The bundle can be passed to `import_account_data`, for example to restore the user's settings
+/// after moving to a new Internet Identity anchor.
+#[must_use]
+#[ic_cdk::query]
+pub fn export_account_data() -> ExportAccountDataResponse {
+    let principal = get_caller();
+    with_state(|s| s.accounts_store.export_account_data(principal))
+}
+
+/// Replaces the data stored for the user's account with a bundle created by `export_account_data`.
+#[must_use]
+#[ic_cdk::update]
+pub fn import_account_data(bundle: AccountDataBundle) -> ImportAccountDataResponse {
+    let principal = get_caller();
+    with_state_mut(|s| {
+        let response = s.accounts_store.import_account_data(principal, bundle);
+        certification::update_root_hash(s);
+        response
+    })
+}
+
+/// Offers the user's account to another principal, e.g. the user's principal with a new identity provider.
+///
+/// The account is moved only once the target principal calls `claim_account_transfer`. The offer
+/// expires after a day.
+#[must_use]
+#[ic_cdk::update]
+pub fn create_account_transfer(request: CreateAccountTransferRequest) -> CreateAccountTransferResponse {
+    let principal = get_caller();
+    let now = time::time();
+    with_state_mut(|s| s.accounts_store.create_account_transfer(principal, request, now))
+}
+
+/// Withdraws the user's pending account transfer offer.
+#[must_use]
+#[ic_cdk::update]
+pub fn cancel_account_transfer() -> CancelAccountTransferResponse {
+    let principal = get_caller();
+    with_state_mut(|s| s.accounts_store.cancel_account_transfer(principal))
+}
+
+/// Moves an account that has been offered to the caller under the caller's principal.
+///
+/// Sub-account names, hardware wallets, canisters and settings all move with the account.
+#[must_use]
+#[ic_cdk::update]
+pub fn claim_account_transfer(request: ClaimAccountTransferRequest) -> ClaimAccountTransferResponse {
+    let principal = get_caller();
+    let now = time::time();
+    with_state_mut(|s| {
+        let response = s.accounts_store.claim_account_transfer(principal, request, now);
+        certification::update_root_hash(s);
+        response
+    })
+}
+
 /// Returns stats about the canister.
 ///
 /// These stats include things such as the number of accounts registered, the memory usage, the
@@ -263,6 +663,23 @@ pub fn get_stats() -> stats::Stats {
     with_state(stats::get_stats)
 }
 
+/// Returns the schema of the data in stable memory and the build that last wrote it.
+///
+/// Before upgrading or rolling back, check that the target build supports at least `min_compatible_schema_version`;
+/// otherwise the upgrade will be refused.
+#[must_use]
+#[ic_cdk::query]
+pub fn get_schema_info() -> Option<SchemaInfo> {
+    with_partitions(Partitions::schema_info).unwrap_or_else(|err| ic_cdk::api::trap(format!("Invalid metadata: {err}")))
+}
+
+/// Returns the limits on accounts and account data that are in force.
+#[must_use]
+#[ic_cdk::query]
+pub fn get_limits() -> Limits {
+    with_state(|s| s.accounts_store.limits().clone())
+}
+
 /// Makes a histogram of the number of sub-accounts etc per account.
 ///
 /// This is to be able to design an efficient account store.
@@ -283,29 +700,45 @@ pub fn get_histogram() -> AccountsStoreHistogram {
     with_state(|state| state.accounts_store.get_histogram())
 }
 
-/// Add an asset to be served by the canister.
+/// Opens a batch for uploading a stable asset in chunks.
+///
+/// # Panics
+/// - If the caller is not a controller.
+#[must_use]
+#[ic_cdk::update]
+pub fn create_asset_batch() -> CreateAssetBatchResponse {
+    assert_caller_may_upload_assets();
+    with_state_mut(|s| s.asset_uploads.create_batch(time::time()))
+}
+
+/// Appends a chunk to an asset batch.
 ///
-/// Only a whitelist of assets are accepted.
+/// Each chunk must fit into an ingress message, so large assets need several chunks.
+///
+/// # Panics
+/// - If the caller is not a controller.
+#[must_use]
 #[ic_cdk::update]
-pub fn add_stable_asset(asset_bytes: Vec<u8>) {
-    let hash_bytes = hash_bytes(&asset_bytes);
-    match hex::encode(hash_bytes).as_str() {
-        "933c135529499e2ed6b911feb8e8824068dc545298b61b93ae813358b306e7a6" => {
-            // Canvaskit wasm.
-            insert_asset(
-                "/assets/canvaskit/canvaskit.wasm",
-                Asset::new_stable(asset_bytes)
-                    .with_header("content-type", "application/wasm")
-                    .with_header("content-encoding", "gzip"),
-            );
-        }
-        "12729155ff56fce7be6bb93ab2666c99fd7ff844e6c4611d144808c942b50748" => {
-            // Canvaskit.js
-            insert_asset("/assets/canvaskit/canvaskit.js", Asset::new_stable(asset_bytes));
-        }
-        unknown_hash => {
-            ic_cdk::api::trap(format!("Unknown asset with hash {unknown_hash}"));
-        }
+pub fn upload_asset_chunk(request: UploadAssetChunkRequest) -> UploadAssetChunkResponse {
+    assert_caller_may_upload_assets();
+    with_state_mut(|s| s.asset_uploads.upload_chunk(request))
+}
+
+/// Stores the content of an asset batch as a stable asset at the given path, if it has the expected SHA-256.
+///
+/// # Panics
+/// - If the caller is not a controller.
+#[must_use]
+#[ic_cdk::update]
+pub fn commit_asset_batch(request: CommitAssetBatchRequest) -> CommitAssetBatchResponse {
+    assert_caller_may_upload_assets();
+    assets::commit_asset_batch(request)
+}
+
+fn assert_caller_may_upload_assets() {
+    let caller = ic_cdk::api::msg_caller();
+    if !ic_cdk::api::is_controller(&caller) {
+        ic_cdk::api::trap("Only a controller may upload assets");
     }
 }
 
@@ -328,10 +761,13 @@ pub fn create_toy_accounts(num_accounts: u128) -> u64 {
         ic_cdk::api::trap("Only the controller may generate toy accounts");
     }
     with_state_mut(|s| {
-        s.accounts_store
+        let num_created = s
+            .accounts_store
             .create_toy_accounts(u64::try_from(num_accounts).unwrap_or_else(|_| {
                 unreachable!("The number of accounts is well below the number of atoms in the universe")
-            }))
+            }));
+        certification::update_root_hash(s);
+        num_created
     })
 }
 
@@ -351,6 +787,18 @@ pub fn get_toy_account(toy_account_index: u64) -> GetAccountResponse {
     })
 }
 
+/// Measures the instructions used to read a toy account.
+#[cfg(any(test, feature = "toy_data_gen"))]
+#[must_use]
+#[ic_cdk::query]
+pub fn benchmark_get_account(toy_account_index: u64) -> Vec<PerformanceCount> {
+    let caller = ic_cdk::api::msg_caller();
+    if !ic_cdk::api::is_controller(&caller) {
+        ic_cdk::api::trap("Only the controller may benchmark toy accounts");
+    }
+    with_state(|s| s.accounts_store.benchmark_get_account(toy_account_index))
+}
+
 #[must_use]
 #[ic_cdk::query]
 pub fn get_tvl() -> TvlResponse {
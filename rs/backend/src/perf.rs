@@ -5,7 +5,7 @@ use crate::StableState;
 use candid::CandidType;
 use dfn_candid::Candid;
 use ic_cdk::api::instruction_counter;
-use on_wire::{FromWire, IntoWire};
+use on_wire::FromWire;
 use serde::Deserialize;
 use std::collections::VecDeque;
 #[cfg(test)]
@@ -71,7 +71,9 @@ impl PerformanceCounts {
 
 impl StableState for PerformanceCounts {
     /// Try to serialize state. On error, return an empty vector.
+    #[cfg(test)]
     fn encode(&self) -> Vec<u8> {
+        use on_wire::IntoWire;
         Candid((self,)).into_bytes().unwrap_or_default()
     }
 
@@ -82,6 +84,20 @@ impl StableState for PerformanceCounts {
     }
 }
 
+/// Counts the instructions used by a function.
+///
+/// Note: The `instruction_count` of the result is the number of instructions used, not the value of the counter.
+#[cfg(any(test, feature = "toy_data_gen"))]
+pub fn count_instructions<R>(name: &str, f: impl FnOnce() -> R) -> PerformanceCount {
+    let before = instruction_counter();
+    std::hint::black_box(f());
+    PerformanceCount {
+        timestamp_ns_since_epoch: crate::time::time(),
+        name: name.to_string(),
+        instruction_count: instruction_counter().saturating_sub(before),
+    }
+}
+
 /// Gets the value of the instruction count and saves it with the given label.
 pub fn record_instruction_count(name: &str) {
     save_instruction_count(PerformanceCount::new(name));
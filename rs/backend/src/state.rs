@@ -1,11 +1,15 @@
 pub mod partitions;
+pub mod stable_value;
 #[cfg(test)]
 pub mod tests;
 
-use self::partitions::Partitions;
+use self::partitions::{PartitionType, Partitions, SchemaInfo, CANISTERS_HASHES_SCHEMA_VERSION};
+use self::stable_value::StableValue;
 use crate::accounts_store::AccountsStore;
+use crate::assets::upload::AssetUploads;
 use crate::assets::AssetHashes;
 use crate::assets::Assets;
+use crate::assets::StableAssets;
 use crate::perf::PerformanceCounts;
 use crate::tvl::state::TvlState;
 
@@ -13,17 +17,21 @@ use dfn_candid::Candid;
 use ic_cdk::api::trap;
 use ic_cdk::println;
 use ic_stable_structures::DefaultMemoryImpl;
-use on_wire::{FromWire, IntoWire};
+use on_wire::FromWire;
 use std::cell::RefCell;
 
 pub struct State {
-    // NOTE: When adding new persistent fields here, ensure that these fields
-    // are being persisted in the `replace` method below.
+    // NOTE: Persistent fields are kept in stable structures, each in its own partition, so that nothing needs to be
+    // saved in `pre_upgrade`.  When adding a new persistent field, load it in `State::load` below.
     pub accounts_store: AccountsStore,
+    /// All assets, including those in `stable_assets`.
     pub assets: Assets,
+    pub stable_assets: StableAssets,
     pub asset_hashes: AssetHashes,
+    /// Asset uploads in progress.  These are not persisted, so an upgrade cancels them.
+    pub asset_uploads: AssetUploads,
     pub performance: PerformanceCounts,
-    pub tvl_state: TvlState,
+    pub tvl_state: StableValue<TvlState>,
 }
 
 #[cfg(test)]
@@ -34,6 +42,7 @@ impl PartialEq for State {
             && (self.assets == other.assets)
             && (self.asset_hashes == other.asset_hashes)
             && (self.performance == other.performance)
+            && (self.tvl_state.get() == other.tvl_state.get())
     }
 }
 #[cfg(test)]
@@ -45,7 +54,9 @@ impl core::fmt::Debug for State {
         let State {
             accounts_store,
             assets: _,
+            stable_assets: _,
             asset_hashes: _,
+            asset_uploads,
             performance: _,
             tvl_state,
         } = self;
@@ -53,6 +64,7 @@ impl core::fmt::Debug for State {
         writeln!(f, "  accounts: {accounts_store:?}")?;
         writeln!(f, "  assets: <html etc> (elided)")?;
         writeln!(f, "  asset_hashes: <hashes of the assets> (elided)")?;
+        writeln!(f, "  asset_uploads: {} batches", asset_uploads.num_batches())?;
         writeln!(f, "  performance: <stats for the metrics endpoint> (elided)")?;
         writeln!(f, "  tvl_state: {tvl_state:?}")?;
         writeln!(f, "}}")
@@ -60,6 +72,10 @@ impl core::fmt::Debug for State {
 }
 
 pub trait StableState: Sized {
+    /// Encodes the data as older builds serialized it to the `Heap` partition in `pre_upgrade`.
+    ///
+    /// Nothing is serialized to the heap any more; this is only used to test that such data can still be decoded.
+    #[cfg(test)]
     fn encode(&self) -> Vec<u8>;
     fn decode(bytes: Vec<u8>) -> Result<Self, String>;
 }
@@ -72,11 +88,17 @@ thread_local! {
 /// Initializes the state when the canister is initialized.
 pub fn init_state() {
     STATE.set(Some(State::new()));
+    with_partitions(|p| p.set_schema_info(&SchemaInfo::current()));
 }
 
 /// Initializes the state when the canister is upgraded.
+///
+/// Traps if stable memory was written with a newer schema that this build cannot read.
 pub fn restore_state() {
+    with_partitions(Partitions::check_schema_compatibility)
+        .unwrap_or_else(|err| trap(format!("Refusing to upgrade: {err}")));
     STATE.set(Some(State::new_restored()));
+    with_partitions(|p| p.set_schema_info(&SchemaInfo::current()));
 }
 
 /// Saves the state to stable memory.
@@ -120,59 +142,99 @@ impl State {
     /// Creates new state. Should be called in `init`.
     #[must_use]
     pub fn new() -> Self {
-        State {
-            accounts_store: AccountsStore::new(),
+        with_partitions(|partitions| State {
+            accounts_store: AccountsStore::new_in(partitions),
             assets: Assets::default(),
+            stable_assets: StableAssets::new(partitions.get(PartitionType::StableAssets.memory_id())),
             asset_hashes: AssetHashes::default(),
+            asset_uploads: AssetUploads::default(),
             performance: PerformanceCounts::default(),
-            tvl_state: TvlState::default(),
-        }
+            tvl_state: StableValue::new(partitions.get(PartitionType::TvlState.memory_id()), TvlState::default()),
+        })
+    }
+
+    /// Loads the rest of the state from the stable structures.
+    #[must_use]
+    fn load(accounts_store: AccountsStore) -> Self {
+        with_partitions(|partitions| {
+            let stable_assets = StableAssets::init(partitions.get(PartitionType::StableAssets.memory_id()));
+            let assets = Assets::from(&stable_assets);
+            let asset_hashes = AssetHashes::from(&assets);
+            State {
+                accounts_store,
+                assets,
+                stable_assets,
+                asset_hashes,
+                asset_uploads: AssetUploads::default(),
+                performance: PerformanceCounts::default(),
+                tvl_state: StableValue::init(partitions.get(PartitionType::TvlState.memory_id()), TvlState::default()),
+            }
+        })
     }
 
     /// Recovers the state from stable memory. Should be called in `post_upgrade`.
+    ///
+    /// If the upgrade is from a build that serialized heap data, that data is moved into the stable structures and
+    /// removed from the `Heap` partition.  If it is from a build that did not keep the certified canister hashes up to
+    /// date, they are computed again by a backfill.
     #[must_use]
     pub fn new_restored() -> Self {
         println!("START state::new_restored: ())");
+        let written_by = with_partitions(Partitions::schema_info).ok().flatten();
         let bytes = with_partitions(Partitions::read_bytes_from_managed_memory);
-        let state =
-            State::decode(bytes).unwrap_or_else(|e| trap(format!("Decoding stable memory failed. Error: {e:?}")));
+        let mut state = if bytes.is_empty() {
+            State::load(AccountsStore::load())
+        } else {
+            let state =
+                State::decode(bytes).unwrap_or_else(|e| trap(format!("Decoding stable memory failed. Error: {e:?}")));
+            with_partitions(|p| p.write_bytes_to_managed_memory(&[]));
+            state
+        };
+        // A build that predates the persisted canister hashes may have changed accounts since they were written.
+        if written_by.is_some_and(|schema_info| schema_info.schema_version < CANISTERS_HASHES_SCHEMA_VERSION) {
+            state.accounts_store.restart_canisters_hashes_backfill();
+        }
         println!("END   state::new_restored: ()");
         state
     }
 
-    /// Saves the state to stable memory. Should be called in `pre_upgrade`.
+    /// Prepares stable memory for an upgrade. Should be called in `pre_upgrade`.
+    ///
+    /// All persistent data is already in stable structures, so this has a small, constant cost.  It only ensures
+    /// that the `Heap` partition holds no heap data.
     pub fn save(&self) {
-        println!("START state::save_heap: ()");
-        let bytes = self.encode();
-        with_partitions(|p| p.write_bytes_to_managed_memory(&bytes));
+        println!("START state::save: ()");
+        with_partitions(|p| p.write_bytes_to_managed_memory(&[]));
+        println!("END   state::save: ()");
     }
 }
 
 impl StableState for State {
+    #[cfg(test)]
     fn encode(&self) -> Vec<u8> {
+        use on_wire::IntoWire;
         Candid((
             self.accounts_store.encode(),
             self.assets.encode(),
-            self.tvl_state.encode(),
+            self.tvl_state.get().encode(),
         ))
         .into_bytes()
         .unwrap()
     }
 
+    /// Decodes heap data written by an older build, moving it into the stable structures.
     fn decode(bytes: Vec<u8>) -> Result<Self, String> {
         let (account_store_bytes, assets_bytes, tvl_state_bytes) = Candid::from_bytes(bytes).map(|c| c.0)?;
 
-        let assets = Assets::decode(assets_bytes)?;
-        let asset_hashes = AssetHashes::from(&assets);
-        let performance = PerformanceCounts::default();
-        let tvl_state = TvlState::decode(tvl_state_bytes)?;
+        let accounts_store = AccountsStore::decode(account_store_bytes)?;
+        let legacy_assets = Assets::decode(assets_bytes)?;
+        let legacy_tvl_state = TvlState::decode(tvl_state_bytes)?;
 
-        Ok(State {
-            accounts_store: AccountsStore::decode(account_store_bytes)?,
-            assets,
-            asset_hashes,
-            performance,
-            tvl_state,
-        })
+        let mut state = State::load(accounts_store);
+        for (path, asset) in legacy_assets {
+            crate::assets::insert_asset_into_state(&mut state, path, asset);
+        }
+        state.tvl_state.set(legacy_tvl_state);
+        Ok(state)
     }
 }
@@ -4,14 +4,35 @@
 //! This code is here to protect the memory!
 //!
 //! This code also stores virtual memory IDs and other memory functions.
+use candid::CandidType;
 use core::borrow::Borrow;
 use ic_cdk::api::trap;
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
 use ic_stable_structures::{DefaultMemoryImpl, Memory};
+use serde::Deserialize;
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
 const WASM_PAGE_SIZE_IN_BYTES: u64 = 65536;
+
+/// The version of the stable memory schema written by this build.
+///
+/// Increase this whenever the data in stable memory changes.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// The oldest schema version that can read the stable memory written by this build.
+///
+/// Set this to `SCHEMA_VERSION` when older builds can no longer read the data, e.g. when a new encoding is written.
+/// Builds older than this refuse to be installed on top of the data, so that they cannot corrupt it.
+///
+/// Version 2 no longer writes heap data to the `Heap` partition.
+pub const MIN_COMPATIBLE_SCHEMA_VERSION: u32 = 2;
+
+/// The first schema version that keeps the certified canister hashes in the `CanistersHashes` partition up to date.
+///
+/// Older builds can still read the data, but leave the hashes stale, so they are computed again after upgrading from
+/// such a build.
+pub const CANISTERS_HASHES_SCHEMA_VERSION: u32 = 3;
 #[cfg(test)]
 pub mod tests;
 
@@ -50,12 +71,66 @@ pub enum PartitionType {
     Metadata = 0,
     /// The virtual memory containing heap data.
     ///
+    /// Heap data used to be serialized here on every upgrade.  All persistent data is now in stable structures in
+    /// partitions of their own, so this only ever holds data written by an older build, which is migrated on upgrade.
+    ///
     /// Note: This ID is guaranteed to be stable across deployments.
     Heap = 1,
-    /// The virtual memory containing accounts.
+    /// The virtual memory containing accounts keyed by variable-length account identifier bytes.
+    ///
+    /// Accounts are being moved from here to `AccountsByIdentifier`.
     ///
     /// Note: This ID is guaranteed to be stable across deployments.
     Accounts = 2,
+    /// The virtual memory containing the index from hardware wallets to the accounts that registered them.
+    ///
+    /// Note: This ID is guaranteed to be stable across deployments.
+    HardwareWalletIndex = 3,
+    /// The virtual memory containing the index from canister ids to the accounts that attached them.
+    ///
+    /// Note: This ID is guaranteed to be stable across deployments.
+    CanisterIndex = 4,
+    /// The virtual memory containing accounts keyed by fixed-size account identifiers.
+    ///
+    /// Note: This ID is guaranteed to be stable across deployments.
+    AccountsByIdentifier = 5,
+    /// The virtual memory containing the counters of sub-accounts and hardware wallets.
+    ///
+    /// Note: This ID is guaranteed to be stable across deployments.
+    AccountsDbStats = 6,
+    /// The virtual memory containing pending account transfers, keyed by the principal offering the account.
+    ///
+    /// Note: This ID is guaranteed to be stable across deployments.
+    PendingAccountTransfers = 7,
+    /// The virtual memory containing the limits on accounts and account data.
+    ///
+    /// Note: This ID is guaranteed to be stable across deployments.
+    Limits = 8,
+    /// The virtual memory containing the principals that may create accounts beyond the account limit.
+    ///
+    /// Note: This ID is guaranteed to be stable across deployments.
+    AccountLimitAllowlist = 9,
+    /// The virtual memory containing the assets that are persisted across upgrades.
+    ///
+    /// Note: This ID is guaranteed to be stable across deployments.
+    StableAssets = 10,
+    /// The virtual memory containing the TVL state.
+    ///
+    /// Note: This ID is guaranteed to be stable across deployments.
+    TvlState = 11,
+    /// The virtual memory containing the certified hashes of the canisters of every account.
+    ///
+    /// Note: This ID is guaranteed to be stable across deployments.
+    CanistersHashes = 12,
+    /// The virtual memory containing the progress of backfilling data derived from the accounts.
+    ///
+    /// Note: This ID is guaranteed to be stable across deployments.
+    Backfills = 13,
+    /// The virtual memory containing legacy accounts whose account identifier does not have the length of an account
+    /// identifier, and so cannot be migrated to `AccountsByIdentifier`.
+    ///
+    /// Note: This ID is guaranteed to be stable across deployments.
+    MalformedAccounts = 14,
 }
 impl PartitionType {
     /// The memory ID.
@@ -67,6 +142,54 @@ impl PartitionType {
     }
 }
 
+/// Identifies this build: by the git commit, if the `COMMIT` environment variable was set at build time, else by the
+/// crate version.
+fn wasm_build() -> String {
+    let build = match option_env!("COMMIT") {
+        Some(commit) if !commit.is_empty() => commit,
+        _ => env!("CARGO_PKG_VERSION"),
+    };
+    format!("{} {build}", env!("CARGO_PKG_NAME"))
+}
+
+/// Information about the schema of the data in stable memory, kept in the `Metadata` partition.
+#[derive(CandidType, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct SchemaInfo {
+    /// The version of the schema of the data in stable memory.
+    pub schema_version: u32,
+    /// The oldest schema version that can read the data in stable memory.
+    pub min_compatible_schema_version: u32,
+    /// The build of the wasm that last wrote the data: the git commit if it was known at build time, else the crate
+    /// version.
+    pub wasm_build: String,
+}
+
+impl SchemaInfo {
+    /// The schema information for data written by this build.
+    #[must_use]
+    pub fn current() -> Self {
+        SchemaInfo {
+            schema_version: SCHEMA_VERSION,
+            min_compatible_schema_version: MIN_COMPATIBLE_SCHEMA_VERSION,
+            wasm_build: wasm_build(),
+        }
+    }
+
+    /// Checks that a build supporting schemas up to `schema_version` can read the data.
+    ///
+    /// # Errors
+    /// - If the data was written with a newer schema that such a build cannot read.
+    pub fn check_readable_by(&self, schema_version: u32) -> Result<(), String> {
+        if self.min_compatible_schema_version > schema_version {
+            return Err(format!(
+                "Stable memory was written by {} with schema version {}, which requires at least schema version {} to read.  This build supports schema version {}.",
+                self.wasm_build, self.schema_version, self.min_compatible_schema_version, schema_version
+            ));
+        }
+        Ok(())
+    }
+}
+
 impl Partitions {
     /// Gets a partition.
     #[must_use]
@@ -103,11 +226,73 @@ impl Partitions {
         self.growing_write(PartitionType::Heap.memory_id(), 8, bytes);
     }
 
+    /// Reads the schema information from the `Metadata` partition, if any has been written.
+    ///
+    /// # Errors
+    /// - If the `Metadata` partition contains data that cannot be parsed as schema information.
+    pub fn schema_info(&self) -> Result<Option<SchemaInfo>, String> {
+        let memory = self.get(PartitionType::Metadata.memory_id());
+        if memory.size() == 0 {
+            return Ok(None);
+        }
+        let len = {
+            let mut length_field = [0u8; 8];
+            memory.read(0, &mut length_field);
+            usize::try_from(u64::from_be_bytes(length_field))
+                .map_err(|err| format!("The schema information is larger than usize: {err:?}"))?
+        };
+        if len == 0 {
+            return Ok(None);
+        }
+        if (len as u64).saturating_add(8) > memory.size() * WASM_PAGE_SIZE_IN_BYTES {
+            return Err(format!(
+                "The schema information length {len} exceeds the Metadata partition."
+            ));
+        }
+        let mut bytes = vec![0u8; len];
+        memory.read(8, &mut bytes);
+        candid::decode_one(&bytes)
+            .map(Some)
+            .map_err(|err| format!("Failed to parse the schema information: {err}"))
+    }
+
+    /// Writes the schema information to the `Metadata` partition.
+    ///
+    /// # Panics
+    /// - If the schema information cannot be serialized.
+    pub fn set_schema_info(&self, schema_info: &SchemaInfo) {
+        let bytes = candid::encode_one(schema_info).expect("Failed to serialize schema information");
+        self.growing_write(
+            PartitionType::Metadata.memory_id(),
+            0,
+            &(bytes.len() as u64).to_be_bytes(),
+        );
+        self.growing_write(PartitionType::Metadata.memory_id(), 8, &bytes);
+    }
+
+    /// Checks that this build can read the data in stable memory.
+    ///
+    /// Data written before the schema was recorded is readable.
+    ///
+    /// # Errors
+    /// - If the data was written with a newer schema that this build cannot read, or the schema is unrecognized.
+    pub fn check_schema_compatibility(&self) -> Result<(), String> {
+        match self.schema_info()? {
+            Some(schema_info) => schema_info.check_readable_by(SCHEMA_VERSION),
+            None => Ok(()),
+        }
+    }
+
     /// Reads bytes from the "managed memory", which is the stable memory section to store data that
     /// needs to be persisted across upgrades by serialization/deserialization.
+    ///
+    /// Returns no bytes if nothing has ever been written.
     #[must_use]
     pub fn read_bytes_from_managed_memory(&self) -> Vec<u8> {
         let memory = self.get(PartitionType::Heap.memory_id());
+        if memory.size() == 0 {
+            return Vec::new();
+        }
         let len = {
             let mut length_field = [0u8; 8];
             memory.read(0, &mut length_field);
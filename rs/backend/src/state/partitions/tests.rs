@@ -217,7 +217,7 @@ fn debug_should_portray_partitions_accurately() {
     partitions.get(PartitionType::Accounts.memory_id()).grow(2);
     assert_eq!(
         format!("{:?}", partitions),
-        "Partitions {\n  Metadata partition: 5 pages\n  Heap partition: 0 pages\n  Accounts partition: 2 pages\n}\n"
+        "Partitions {\n  Metadata partition: 5 pages\n  Heap partition: 0 pages\n  Accounts partition: 2 pages\n  HardwareWalletIndex partition: 0 pages\n  CanisterIndex partition: 0 pages\n  AccountsByIdentifier partition: 0 pages\n  AccountsDbStats partition: 0 pages\n  PendingAccountTransfers partition: 0 pages\n  Limits partition: 0 pages\n  AccountLimitAllowlist partition: 0 pages\n  StableAssets partition: 0 pages\n  TvlState partition: 0 pages\n  CanistersHashes partition: 0 pages\n  Backfills partition: 0 pages\n  MalformedAccounts partition: 0 pages\n}\n"
     );
 }
 
@@ -225,6 +225,9 @@ fn debug_should_portray_partitions_accurately() {
 fn write_to_and_read_from_managed_memory_should_work() {
     let partitions = Partitions::from(DefaultMemoryImpl::default());
 
+    // Nothing has been written yet.
+    assert_eq!(partitions.read_bytes_from_managed_memory(), Vec::<u8>::new());
+
     // Reading a previously written buffer should return the same bytes.
     let toy_bytes = b"foo_bar".to_vec();
     partitions.write_bytes_to_managed_memory(&toy_bytes);
@@ -252,3 +255,68 @@ fn write_to_and_read_from_managed_memory_should_work() {
         "Managed memory read did not return the expected bytes."
     );
 }
+
+#[test]
+fn schema_info_should_be_absent_until_written() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    assert_eq!(partitions.schema_info(), Ok(None));
+    assert_eq!(partitions.check_schema_compatibility(), Ok(()));
+}
+
+#[test]
+fn schema_info_should_be_read_back_after_writing() {
+    let toy_memory = DefaultMemoryImpl::default();
+    let partitions = Partitions::from(Rc::clone(&toy_memory));
+    partitions.set_schema_info(&SchemaInfo::current());
+    // The schema information survives an upgrade.
+    let partitions = Partitions::from(Rc::clone(&toy_memory));
+    assert_eq!(partitions.schema_info(), Ok(Some(SchemaInfo::current())));
+    assert_eq!(partitions.check_schema_compatibility(), Ok(()));
+
+    // A shorter build name overwrites the longer one completely.
+    let schema_info = SchemaInfo {
+        wasm_build: "x".to_string(),
+        ..SchemaInfo::current()
+    };
+    partitions.set_schema_info(&schema_info);
+    assert_eq!(partitions.schema_info(), Ok(Some(schema_info)));
+}
+
+#[test]
+fn newer_compatible_schema_should_be_accepted() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    partitions.set_schema_info(&SchemaInfo {
+        schema_version: SCHEMA_VERSION + 1,
+        min_compatible_schema_version: SCHEMA_VERSION,
+        wasm_build: "nns-dapp from the future".to_string(),
+    });
+    assert_eq!(partitions.check_schema_compatibility(), Ok(()));
+}
+
+#[test]
+fn newer_incompatible_schema_should_be_rejected() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    partitions.set_schema_info(&SchemaInfo {
+        schema_version: SCHEMA_VERSION + 1,
+        min_compatible_schema_version: SCHEMA_VERSION + 1,
+        wasm_build: "nns-dapp from the future".to_string(),
+    });
+    let error = partitions.check_schema_compatibility().unwrap_err();
+    assert!(error.contains("nns-dapp from the future"), "{error}");
+    assert!(
+        error.contains(&format!("This build supports schema version {SCHEMA_VERSION}")),
+        "{error}"
+    );
+}
+
+#[test]
+fn unrecognized_metadata_should_be_rejected() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    partitions.growing_write(PartitionType::Metadata.memory_id(), 0, &4u64.to_be_bytes());
+    partitions.growing_write(PartitionType::Metadata.memory_id(), 8, b"junk");
+    assert!(partitions.schema_info().is_err());
+    assert!(partitions.check_schema_compatibility().is_err());
+
+    partitions.growing_write(PartitionType::Metadata.memory_id(), 0, &u64::MAX.to_be_bytes());
+    assert!(partitions.schema_info().is_err());
+}
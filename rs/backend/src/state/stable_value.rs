@@ -0,0 +1,55 @@
+//! A single value kept in a stable memory partition of its own.
+//!
+//! Every update is written to stable memory straight away, so the value does not need to be saved in `pre_upgrade`.
+//! The cell also keeps a copy of the value on the heap, so reads are cheap.
+use ic_stable_structures::{memory_manager::VirtualMemory, DefaultMemoryImpl, StableCell, Storable};
+use std::fmt;
+
+#[cfg(test)]
+mod tests;
+
+pub struct StableValue<T: Storable> {
+    cell: StableCell<T, VirtualMemory<DefaultMemoryImpl>>,
+}
+
+impl<T: Storable + Clone> StableValue<T> {
+    /// Stores the value in the given memory, replacing anything that was there.
+    #[must_use]
+    pub fn new(memory: VirtualMemory<DefaultMemoryImpl>, value: T) -> Self {
+        Self {
+            cell: StableCell::new(memory, value),
+        }
+    }
+
+    /// Loads the value from the given memory, or stores `default` there if the memory is empty.
+    #[must_use]
+    pub fn init(memory: VirtualMemory<DefaultMemoryImpl>, default: T) -> Self {
+        Self {
+            cell: StableCell::init(memory, default),
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self) -> &T {
+        self.cell.get()
+    }
+
+    /// Replaces the value.
+    pub fn set(&mut self, value: T) {
+        self.cell.set(value);
+    }
+
+    /// Modifies the value and writes the result to stable memory.
+    pub fn update<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut value = self.get().clone();
+        let result = f(&mut value);
+        self.set(value);
+        result
+    }
+}
+
+impl<T: Storable + fmt::Debug> fmt::Debug for StableValue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.cell.get().fmt(f)
+    }
+}
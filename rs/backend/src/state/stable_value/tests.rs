@@ -0,0 +1,36 @@
+use super::*;
+use crate::state::partitions::{PartitionType, Partitions};
+use pretty_assertions::assert_eq;
+use std::rc::Rc;
+
+#[test]
+fn value_should_default_when_memory_is_empty() {
+    let partitions = Partitions::from(DefaultMemoryImpl::default());
+    let value = StableValue::init(partitions.get(PartitionType::Metadata.memory_id()), 7u64);
+    assert_eq!(*value.get(), 7);
+}
+
+#[test]
+fn updates_should_survive_an_upgrade() {
+    let toy_memory = DefaultMemoryImpl::default();
+    let partitions = Partitions::from(Rc::clone(&toy_memory));
+    let mut value = StableValue::init(partitions.get(PartitionType::Metadata.memory_id()), 7u64);
+    value.set(8);
+    assert_eq!(value.update(|value| std::mem::replace(value, 9)), 8);
+    assert_eq!(*value.get(), 9);
+
+    // The default is ignored when the memory already holds a value.
+    let partitions = Partitions::from(Rc::clone(&toy_memory));
+    let value = StableValue::init(partitions.get(PartitionType::Metadata.memory_id()), 7u64);
+    assert_eq!(*value.get(), 9);
+    assert_eq!(format!("{value:?}"), "9");
+}
+
+#[test]
+fn new_should_replace_any_stored_value() {
+    let toy_memory = DefaultMemoryImpl::default();
+    let partitions = Partitions::from(Rc::clone(&toy_memory));
+    let _stored = StableValue::init(partitions.get(PartitionType::Metadata.memory_id()), 9u64);
+    let value = StableValue::new(partitions.get(PartitionType::Metadata.memory_id()), 7u64);
+    assert_eq!(*value.get(), 7);
+}
@@ -1,7 +1,16 @@
 use crate::{
-    accounts_store::RegisterHardwareWalletRequest,
-    assets::{insert_asset_into_state, Asset},
-    state::{reset_partitions, PerformanceCounts, State},
+    accounts_store::{
+        limits::Limits, AccountsDbStats, AttachCanisterRequest, CreateAccountTransferRequest,
+        RegisterHardwareWalletRequest, UpdateAccountLimitAllowlistRequest,
+    },
+    assets::{insert_asset_into_state, Asset, StableAssets},
+    state::{
+        partitions::{PartitionType, SchemaInfo, CANISTERS_HASHES_SCHEMA_VERSION},
+        reset_partitions,
+        stable_value::StableValue,
+        with_partitions, PerformanceCounts, StableState, State,
+    },
+    stats::get_stats,
     tvl::state::TvlState,
 };
 use ic_base_types::PrincipalId;
@@ -9,6 +18,16 @@ use pretty_assertions::assert_eq;
 use proptest::proptest;
 
 pub(crate) fn populate_test_state(num_accounts: u64, state: &mut State) {
+    state.accounts_store.set_limits(Limits {
+        max_named_addresses: 7,
+        ..Limits::default()
+    });
+    state
+        .accounts_store
+        .update_account_limit_allowlist(UpdateAccountLimitAllowlistRequest {
+            add: vec![PrincipalId::new_user_test_id(u64::MAX)],
+            remove: vec![],
+        });
     for account_index in 0..num_accounts {
         let principal_id = PrincipalId::new_user_test_id(account_index);
         state.accounts_store.add_account(principal_id);
@@ -18,10 +37,18 @@ pub(crate) fn populate_test_state(num_accounts: u64, state: &mut State) {
         state
             .accounts_store
             .register_hardware_wallet(principal_id, RegisterHardwareWalletRequest::test_data());
+        state
+            .accounts_store
+            .attach_canister(principal_id, AttachCanisterRequest::test_data());
+        state.accounts_store.create_account_transfer(
+            principal_id,
+            CreateAccountTransferRequest::test_data(),
+            crate::time::time(),
+        );
     }
     insert_asset_into_state(state, "asset", Asset::new_stable(vec![0u8; 100]));
     state.performance = PerformanceCounts::test_data();
-    state.tvl_state = TvlState::test_data();
+    state.tvl_state.set(TvlState::test_data());
 }
 
 fn state_can_be_saved_and_recovered_from_stable_memory(num_accounts: u64) {
@@ -39,11 +66,17 @@ fn state_can_be_saved_and_recovered_from_stable_memory(num_accounts: u64) {
 
     // Now we examine the restored state against the original state:
 
-    // The content in the AccountsStore are either in stable structures, or serialized/deserialized during upgrades.
+    // Nothing is serialized to the heap partition during upgrades.
+    assert_eq!(
+        with_partitions(|p| p.read_bytes_from_managed_memory()),
+        Vec::<u8>::new()
+    );
+
+    // The content in the AccountsStore is in stable structures.
     assert_eq!(restored_state.accounts_store, state.accounts_store);
-    // The assets and tvl state are serialized/deserialized during upgrades.
+    // The stable assets and tvl state are in stable structures.
     assert_eq!(restored_state.assets, state.assets);
-    assert_eq!(restored_state.tvl_state, state.tvl_state);
+    assert_eq!(restored_state.tvl_state.get(), state.tvl_state.get());
     // The asset hashes are recomputed from assets during upgrades.
     assert_eq!(restored_state.asset_hashes, state.asset_hashes);
     // The performance counts are not persisted through upgrades, so they are reset after upgrades.
@@ -64,3 +97,67 @@ proptest! {
         state_can_be_saved_and_recovered_from_stable_memory(u64::from(num_accounts))
     }
 }
+
+/// Empties the partitions that hold data which older builds serialized to the heap partition.
+fn clear_partitions_of_heap_data() {
+    with_partitions(|p| {
+        let _ = StableValue::new(
+            p.get(PartitionType::AccountsDbStats.memory_id()),
+            AccountsDbStats::default(),
+        );
+        let _ = StableAssets::new(p.get(PartitionType::StableAssets.memory_id()));
+        let _ = StableValue::new(p.get(PartitionType::TvlState.memory_id()), TvlState::default());
+    });
+}
+
+#[test]
+fn state_saved_on_the_heap_by_an_older_build_is_moved_into_stable_structures() {
+    let mut state = State::new();
+    populate_test_state(3, &mut state);
+    let expected_stats = get_stats(&state);
+
+    // An older build serialized the heap data into the heap partition in `pre_upgrade` and had no partitions for it.
+    with_partitions(|p| p.write_bytes_to_managed_memory(&state.encode()));
+    clear_partitions_of_heap_data();
+
+    let restored_state = State::new_restored();
+
+    let restored_stats = get_stats(&restored_state);
+    assert_eq!(restored_stats.sub_accounts_count, expected_stats.sub_accounts_count);
+    assert_eq!(
+        restored_stats.hardware_wallet_accounts_count,
+        expected_stats.hardware_wallet_accounts_count
+    );
+    assert_eq!(restored_stats.account_limit, expected_stats.account_limit);
+    assert_eq!(restored_state.accounts_store, state.accounts_store);
+    assert_eq!(*restored_state.tvl_state.get(), TvlState::test_data());
+    assert_eq!(restored_state.assets, state.assets);
+    assert_eq!(restored_state.asset_hashes, state.asset_hashes);
+    // The heap data has been moved, so it is not migrated again on the next upgrade.
+    assert_eq!(
+        with_partitions(|p| p.read_bytes_from_managed_memory()),
+        Vec::<u8>::new()
+    );
+}
+
+#[test]
+fn canisters_hashes_are_computed_again_after_an_older_build_has_run() {
+    let mut state = State::new();
+    populate_test_state(3, &mut state);
+    let expected_root_hash = state.accounts_store.canisters_root_hash();
+
+    // A build that did not keep the canister hashes up to date wrote the stable memory last.
+    with_partitions(|p| {
+        p.set_schema_info(&SchemaInfo {
+            schema_version: CANISTERS_HASHES_SCHEMA_VERSION - 1,
+            min_compatible_schema_version: CANISTERS_HASHES_SCHEMA_VERSION - 1,
+            wasm_build: "nns-dapp older".to_string(),
+        });
+    });
+    let mut restored_state = State::new_restored();
+
+    assert!(!restored_state.accounts_store.backfills_complete());
+    assert_ne!(restored_state.accounts_store.canisters_root_hash(), expected_root_hash);
+    while !restored_state.accounts_store.step_backfills(1) {}
+    assert_eq!(restored_state.accounts_store.canisters_root_hash(), expected_root_hash);
+}
@@ -38,6 +38,11 @@ pub struct Stats {
     pub stable_memory_size_bytes: Option<u64>,
     pub wasm_memory_size_bytes: Option<u64>,
     pub migration_countdown: Option<u32>, // When non-zero, a migration is in progress.
+    pub account_limit: Option<u64>,
+    pub accounts_remaining: Option<u64>, // New accounts that may be created before the account limit is reached.
+    pub account_limit_near: Option<bool>, // True when few new accounts may be created before the account limit is reached.
+    pub principal_less_accounts_count: Option<u64>, // Accounts created before principals were stored.  None until counted.
+    pub malformed_accounts_count: Option<u64>,      // Legacy accounts with an invalid key, kept aside by the migration.
 }
 
 /// Encodes the metrics into the format scraped by the monitoring system.
@@ -86,6 +91,33 @@ pub fn encode_metrics(w: &mut MetricsEncoder<Vec<u8>>) -> std::io::Result<()> {
         f64::from(stats.migration_countdown.unwrap_or(0)),
         "When non-zero, a migration is in progress.",
     )?;
+    w.encode_gauge(
+        "nns_dapp_account_limit",
+        stats.account_limit.unwrap_or(0) as f64,
+        "The number of accounts beyond which only allowlisted principals may create accounts.",
+    )?;
+    w.encode_gauge(
+        "nns_dapp_accounts_remaining",
+        stats.accounts_remaining.unwrap_or(0) as f64,
+        "The number of accounts that may be created before the account limit is reached.",
+    )?;
+    w.encode_gauge(
+        "nns_dapp_account_limit_near",
+        f64::from(u8::from(stats.account_limit_near.unwrap_or(false))),
+        "1 when few accounts may be created before the account limit is reached, else 0.",
+    )?;
+    if let Some(principal_less_accounts_count) = stats.principal_less_accounts_count {
+        w.encode_gauge(
+            "nns_dapp_principal_less_accounts_count",
+            principal_less_accounts_count as f64,
+            "The number of accounts that have yet to be given their principal.",
+        )?;
+    }
+    w.encode_gauge(
+        "nns_dapp_malformed_accounts_count",
+        stats.malformed_accounts_count.unwrap_or(0) as f64,
+        "The number of legacy accounts quarantined because their key is not a valid account identifier.",
+    )?;
     Ok(())
 }
 
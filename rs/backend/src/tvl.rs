@@ -97,7 +97,7 @@ pub async fn update_exchange_rate() {
             with_state(|s| {
                 ic_cdk::println!(
                     "Keeping usd_e8s_per_icp for TVL at {} because of response error: {:?}",
-                    s.tvl_state.usd_e8s_per_icp,
+                    s.tvl_state.get().usd_e8s_per_icp,
                     err
                 );
             });
@@ -107,7 +107,7 @@ pub async fn update_exchange_rate() {
             with_state(|s| {
                 ic_cdk::println!(
                     "Keeping usd_e8s_per_icp for TVL at {} because of call error: {:?}",
-                    s.tvl_state.usd_e8s_per_icp,
+                    s.tvl_state.get().usd_e8s_per_icp,
                     err
                 );
             });
@@ -124,8 +124,10 @@ pub async fn update_exchange_rate() {
     let decimals = metadata.decimals;
     let usd_e8s_per_icp = convert_to_e8s(rate, decimals);
     with_state_mut(|s| {
-        s.tvl_state.usd_e8s_per_icp = usd_e8s_per_icp;
-        s.tvl_state.exchange_rate_timestamp_seconds = timestamp;
+        s.tvl_state.update(|tvl_state| {
+            tvl_state.usd_e8s_per_icp = usd_e8s_per_icp;
+            tvl_state.exchange_rate_timestamp_seconds = timestamp;
+        });
     });
     ic_cdk::println!("Updated usd_e8s_per_icp for TVL to {}", usd_e8s_per_icp);
 }
@@ -134,20 +136,21 @@ pub async fn update_locked_icp_e8s() {
     let metrics_result = governance::get_metrics().await;
     with_state_mut(|s| match metrics_result {
         Ok(Ok(metrics)) => {
-            s.tvl_state.total_locked_icp_e8s = metrics.total_locked_e8s;
+            s.tvl_state
+                .update(|tvl_state| tvl_state.total_locked_icp_e8s = metrics.total_locked_e8s);
             ic_cdk::println!("Updated total_locked_icp_e8s for TVL to {}", metrics.total_locked_e8s);
         }
         Ok(Err(err)) => {
             ic_cdk::println!(
                 "Keeping total_locked_icp_e8s for TVL at {} because of response error: {}",
-                s.tvl_state.total_locked_icp_e8s,
+                s.tvl_state.get().total_locked_icp_e8s,
                 err
             );
         }
         Err(err) => {
             ic_cdk::println!(
                 "Keeping total_locked_icp_e8s for TVL at {} because of call error: {}",
-                s.tvl_state.total_locked_icp_e8s,
+                s.tvl_state.get().total_locked_icp_e8s,
                 err
             );
         }
@@ -156,7 +159,7 @@ pub async fn update_locked_icp_e8s() {
 
 pub fn get_tvl() -> TvlResponse {
     with_state(|s| {
-        let state = &s.tvl_state;
+        let state = s.tvl_state.get();
         let locked_u128 = u128::from(state.total_locked_icp_e8s);
         let rate_u128 = u128::from(state.usd_e8s_per_icp);
         let e8s_per_unit = u128::from(E8S_PER_UNIT);